@@ -0,0 +1,13 @@
+// Prints the records of capture files recorded with `ServerConfig::capture`
+//
+// cargo run --example dump_capture -- captures/*.fcgi
+use vintage::Capture;
+
+fn main() {
+    for path in std::env::args().skip(1) {
+        match Capture::load(&path) {
+            Ok(capture) => println!("{path}\n{}", capture.dump()),
+            Err(err) => eprintln!("{path}: {err}"),
+        }
+    }
+}
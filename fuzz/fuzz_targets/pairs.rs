@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vintage::fuzzing::parse_pairs;
+
+fuzz_target!(|payload: Vec<u8>| {
+    let _ = parse_pairs(payload);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vintage::fuzzing::Record;
+
+// Record payloads come straight from the network
+fuzz_target!(|input: (u8, Vec<u8>)| {
+    let (type_id, payload) = input;
+    let _ = Record::from_bytes(type_id, payload);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vintage::fuzzing::Record;
+
+// Anything that parses should serialize back to an equivalent record
+fuzz_target!(|record: Record| {
+    let mut payload = vec![];
+    record.write_bytes(&mut payload).unwrap();
+    if let Ok(parsed) = Record::from_bytes(record.type_id(), payload) {
+        assert_eq!(parsed, record);
+    }
+});
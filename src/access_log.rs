@@ -0,0 +1,335 @@
+use crate::httpdate;
+use crate::sampler::Sampler;
+use crate::sync;
+use log::kv::{Source, Value};
+use std::fmt::{self, Display};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+pub type AccessLogCallback = Arc<dyn Fn(&AccessLogEntry) + Send + Sync>;
+
+/// A record of a request handled by the server
+///
+/// By default, every entry is logged at the info level through the [`log`] crate.
+/// See [`ServerConfig::access_log`](crate::ServerConfig::access_log) to send them elsewhere.
+///
+/// The [`Display`] implementation formats the entry as a single line:
+/// `<timestamp> <method> <path>[?<query>] <status> <elapsed>us[ <key>=<value>...]`,
+/// where the key-value pairs are the [log context](crate::Request::log_kv) of the request.
+/// The timestamp is in RFC 3339 format, in UTC.
+/// Values that contain whitespace or quotes are quoted.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AccessLogEntry<'a> {
+    /// When the response was sent
+    pub timestamp: SystemTime,
+    /// The request method
+    pub method: &'a str,
+    /// The request path
+    pub path: &'a str,
+    /// The raw query string of the request
+    pub query: &'a str,
+    /// The status code of the response
+    pub status: u16,
+    /// How long it took to handle the request
+    pub elapsed: Duration,
+    /// How long the connection waited in the request queue before a worker thread picked it up
+    pub queued: Duration,
+    /// How long it took to receive the params and body of the request
+    pub read: Duration,
+    /// How long the handler, middleware included, took to produce the response
+    pub handler: Duration,
+    /// The key-value pairs attached with [`Request::log_kv`](crate::Request::log_kv)
+    pub context: &'a [(String, String)],
+}
+
+impl AccessLogEntry<'_> {
+    pub(crate) fn log(&self) {
+        if !log::log_enabled!(log::Level::Info) {
+            return;
+        }
+
+        // The keys of the context are only known at runtime, so the record is built by hand
+        // instead of with `log::info!`
+        let fields: [(&str, Value); 9] = [
+            ("status", Value::from(self.status)),
+            ("method", Value::from(self.method)),
+            ("path", Value::from(self.path)),
+            ("query", Value::from(self.query)),
+            ("elapsed_milli", Value::from(self.elapsed.as_millis())),
+            ("elapsed_micro", Value::from(self.elapsed.as_micros())),
+            ("queued_micro", Value::from(self.queued.as_micros())),
+            ("read_micro", Value::from(self.read.as_micros())),
+            ("handler_micro", Value::from(self.handler.as_micros())),
+        ];
+        let key_values: [&dyn Source; 2] = [&fields, &self.context];
+
+        log::logger().log(
+            &log::Record::builder()
+                .level(log::Level::Info)
+                .target(module_path!())
+                .module_path_static(Some(module_path!()))
+                .file_static(Some(file!()))
+                .line(Some(line!()))
+                .key_values(&key_values)
+                .args(format_args!("fastcgi-request"))
+                .build(),
+        );
+    }
+}
+
+impl Display for AccessLogEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", httpdate::format_rfc3339(self.timestamp))?;
+
+        write!(f, "{} {}", self.method, self.path)?;
+
+        if !self.query.is_empty() {
+            write!(f, "?{}", self.query)?;
+        }
+
+        write!(f, " {} {}us", self.status, self.elapsed.as_micros())?;
+
+        for (key, value) in self.context {
+            if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+                write!(f, " {key}={value:?}")?;
+            } else {
+                write!(f, " {key}={value}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Rules that keep some entries out of the access log (e.g. the `404`s of vulnerability scanners
+/// probing for `/wp-admin` or `/.env`)
+///
+/// An entry is left out if it matches any of the rules.
+/// Path patterns are matched against the whole path, and `*` matches any sequence of characters
+/// (including `/`).
+///
+/// See [`ServerConfig::access_log_filter`](crate::ServerConfig::access_log_filter)
+///
+/// ```
+/// use vintage::{AccessLogFilter, ServerConfig};
+///
+/// let filter = AccessLogFilter::new()
+///     .skip_paths(["/wp-*", "*.php", "*/.env", "/.git/*"])
+///     .skip_status(300..=399)
+///     .sample_status(404..=404, 0.1);
+///
+/// let config = ServerConfig::new().access_log_filter(filter);
+/// ```
+#[derive(Debug, Default)]
+pub struct AccessLogFilter {
+    paths: Vec<String>,
+    statuses: Vec<RangeInclusive<u16>>,
+    samples: Vec<Sample>,
+}
+
+#[derive(Debug)]
+struct Sample {
+    statuses: RangeInclusive<u16>,
+    sampler: Sampler,
+}
+
+impl AccessLogFilter {
+    /// Creates a filter that keeps every entry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leaves out the requests to paths that match any of `patterns`
+    pub fn skip_paths<const N: usize>(mut self, patterns: [&str; N]) -> Self {
+        self.paths.extend(patterns.iter().map(|p| p.to_string()));
+        self
+    }
+
+    /// Leaves out the responses with a status in `statuses` (e.g. `300..=399` for redirects)
+    pub fn skip_status(mut self, statuses: RangeInclusive<u16>) -> Self {
+        self.statuses.push(statuses);
+        self
+    }
+
+    /// Only keeps a fraction `rate` of the responses with a status in `statuses`
+    ///
+    /// Kept entries are spread evenly: with `0.1`, every tenth one is kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not between 0 and 1
+    #[track_caller]
+    pub fn sample_status(mut self, statuses: RangeInclusive<u16>, rate: f64) -> Self {
+        self.samples.push(Sample {
+            statuses,
+            sampler: Sampler::new(rate),
+        });
+        self
+    }
+
+    // Whether `entry` should be logged
+    pub(crate) fn allows(&self, entry: &AccessLogEntry) -> bool {
+        if self.statuses.iter().any(|s| s.contains(&entry.status)) {
+            return false;
+        }
+        if self.paths.iter().any(|p| glob_matches(p, entry.path)) {
+            return false;
+        }
+
+        self.samples
+            .iter()
+            .filter(|sample| sample.statuses.contains(&entry.status))
+            .all(|sample| sample.sampler.sample())
+    }
+}
+
+// Matches `text` against `pattern`, where `*` matches any sequence of characters
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // There is always a first part, even if it is empty
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+// Creates an access log callback that writes each entry as a line to `writer`
+pub fn to_writer<W>(writer: W) -> AccessLogCallback
+where
+    W: Write + Send + 'static,
+{
+    let writer = Mutex::new(writer);
+    Arc::new(move |entry| {
+        // A panic while holding the lock does not leave the writer in an invalid state.
+        // At worst, a line is partially written.
+        let mut writer = sync::lock(&writer);
+        if let Err(err) = writeln!(writer, "{entry}").and_then(|_| writer.flush()) {
+            log::warn!(error:err = err; "Failed to write access log entry");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn entry(query: &str) -> AccessLogEntry<'_> {
+        AccessLogEntry {
+            timestamp: SystemTime::UNIX_EPOCH,
+            method: "GET",
+            path: "/about",
+            query,
+            status: 200,
+            elapsed: Duration::from_micros(1500),
+            queued: Duration::ZERO,
+            read: Duration::ZERO,
+            handler: Duration::from_micros(1000),
+            context: &[],
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            entry("").to_string(),
+            "1970-01-01T00:00:00Z GET /about 200 1500us"
+        );
+        assert_eq!(
+            entry("a=1").to_string(),
+            "1970-01-01T00:00:00Z GET /about?a=1 200 1500us"
+        );
+
+        let context = [
+            ("user_id".to_string(), "42".to_string()),
+            ("plan".to_string(), "free tier".to_string()),
+        ];
+        let entry = AccessLogEntry {
+            context: &context,
+            ..entry("")
+        };
+        assert_eq!(
+            entry.to_string(),
+            "1970-01-01T00:00:00Z GET /about 200 1500us user_id=42 plan=\"free tier\""
+        );
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("/wp-*", "/wp-admin/setup.php"));
+        assert!(glob_matches("*.php", "/index.php"));
+        assert!(glob_matches("*/.env", "/app/.env"));
+        assert!(glob_matches("/a*b*c", "/a-b-b-c"));
+        assert!(glob_matches("/exact", "/exact"));
+        assert!(!glob_matches("/exact", "/exact/more"));
+        assert!(!glob_matches("*.php", "/index.php5"));
+        assert!(!glob_matches("/ab*ba", "/aba"));
+    }
+
+    #[test]
+    fn filter() {
+        let filter = AccessLogFilter::new()
+            .skip_paths(["/wp-*"])
+            .skip_status(300..=399)
+            .sample_status(404..=404, 0.5);
+        let with = |path, status| AccessLogEntry {
+            path,
+            status,
+            ..entry("")
+        };
+
+        assert!(filter.allows(&with("/about", 200)));
+        assert!(!filter.allows(&with("/wp-login.php", 200)));
+        assert!(!filter.allows(&with("/about", 301)));
+        let kept: Vec<_> = (0..4)
+            .map(|_| filter.allows(&with("/missing", 404)))
+            .collect();
+        assert_eq!(kept, [false, true, false, true]);
+        assert!(filter.allows(&with("/about", 500)));
+    }
+
+    #[test]
+    fn writer_sink() {
+        let buffer = SharedBuffer::default();
+        let callback = to_writer(buffer.clone());
+
+        callback(&entry(""));
+        callback(&entry("a=1"));
+
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            written,
+            "1970-01-01T00:00:00Z GET /about 200 1500us\n\
+             1970-01-01T00:00:00Z GET /about?a=1 200 1500us\n"
+        );
+    }
+}
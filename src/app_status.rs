@@ -0,0 +1,25 @@
+//! FastCGI application status constants
+//!
+//! The application status is sent to the web server at the end of every request, in the
+//! `FCGI_END_REQUEST` record. It is the FastCGI equivalent of an exit code: `0` means the request
+//! was handled, anything else tells clients and log pipelines how it failed.
+//!
+//! Handlers set it with [`Response::set_app_status`](crate::Response::set_app_status).
+//! The server uses the constants of this module for the failures it handles on its own.
+//! Applications should use values of `256` and up for their own failure modes.
+
+/// The request was handled
+pub const OK: u32 = 0;
+/// The request could not be read (e.g. its params exceeded a limit), and was answered by the
+/// server itself
+pub const REQUEST_FAILED: u32 = 1;
+/// The request was turned away without being handled (e.g. the request queue was full, the
+/// memory budget could not fit it, the server was shutting down, or it was in maintenance mode)
+pub const REJECTED: u32 = 2;
+/// The streamed body of the response failed, so the response is incomplete
+pub const BODY_FAILED: u32 = 3;
+/// The web server took too long to send the request
+pub const TIMED_OUT: u32 = 4;
+/// The handler panicked, and the request was answered with a `500 Internal Server Error`.
+/// When the body stream of the response panics instead, the response is incomplete.
+pub const HANDLER_PANICKED: u32 = 5;
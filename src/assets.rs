@@ -0,0 +1,234 @@
+use crate::context::{Request, Response};
+use crate::file_server::FileServer;
+use crate::hash::fnv1a;
+use crate::headers::{self, CacheControl};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+/// Serves static assets under fingerprinted names
+///
+/// When created, every file under the asset directory is hashed.
+/// A file like `css/app.css` is then served as `css/app.<hash>.css` with headers telling clients to
+/// cache it forever.
+/// Since the name changes whenever the content of the file does, clients never use an outdated
+/// copy.
+///
+/// Templates should link to assets with [`Assets::asset_url`] instead of hard-coding paths.
+/// Files are only hashed once, so changes made to them while the server is running are not
+/// picked up.
+///
+/// Requests for the original names are also served, without the long-lived caching.
+///
+/// ```no_run
+/// use vintage::{Assets, Response, ServerConfig};
+///
+/// let assets = Assets::new("/assets", "./assets").unwrap();
+///
+/// let config = ServerConfig::new()
+///     .serve_assets(assets.clone())
+///     .on_get(["/"], move |_req, _params| {
+///         let css = assets.asset_url("app.css").unwrap();
+///         Response::html(format!(r#"<link rel="stylesheet" href="{css}">"#))
+///     });
+/// ```
+#[derive(Debug, Clone)]
+pub struct Assets {
+    request_prefix: String,
+    files: FileServer,
+    // Original name => Fingerprinted name
+    fingerprinted: Arc<BTreeMap<String, String>>,
+    // Fingerprinted name => Original name
+    originals: Arc<BTreeMap<String, String>>,
+}
+
+impl Assets {
+    /// Hashes the files under `path`, to be served at URLs starting with `prefix`.
+    ///
+    /// If `prefix` does not begin with a forward slash (e.g. `/assets`), it is implied.
+    /// An empty `path` implies the current working directory.
+    /// Files whose name is not valid utf8 are skipped.
+    pub fn new(prefix: &'static str, path: &'static str) -> Result<Self, io::Error> {
+        let files = FileServer::new(prefix, path);
+
+        let root = files.fs_path().canonicalize_utf8()?;
+        let mut names = vec![];
+        collect_files(&root, &mut names)?;
+
+        let mut fingerprinted = BTreeMap::new();
+        let mut originals = BTreeMap::new();
+
+        for name in names {
+            let content = fs::read(root.join(&name))?;
+            let hash = format!("{:016x}", fnv1a(&content));
+
+            let fingerprinted_name = match name.rsplit_once('.') {
+                // Hidden files (e.g. `.env`) have no extension.
+                // Neither do files in a directory that has a dot in its name (e.g. `v1.2/file`).
+                Some((stem, extension))
+                    if !stem.is_empty() && !stem.ends_with('/') && !extension.contains('/') =>
+                {
+                    format!("{stem}.{hash}.{extension}")
+                }
+                _ => format!("{name}.{hash}"),
+            };
+
+            originals.insert(fingerprinted_name.clone(), name.clone());
+            fingerprinted.insert(name, fingerprinted_name);
+        }
+
+        Ok(Self {
+            request_prefix: files.request_prefix().to_string(),
+            files,
+            fingerprinted: Arc::new(fingerprinted),
+            originals: Arc::new(originals),
+        })
+    }
+
+    /// Returns the URL path of the fingerprinted version of `name`
+    ///
+    /// `name` is the path of the file, relative to the asset directory (e.g. `css/app.css`).
+    /// For example, with a `/assets` prefix, this could return `/assets/css/app.0123456789abcdef.css`.
+    ///
+    /// Returns `None` if there is no such asset.
+    pub fn asset_url(&self, name: &str) -> Option<String> {
+        let fingerprinted = self.fingerprinted.get(name.trim_start_matches('/'))?;
+        let prefix = self.request_prefix.trim_end_matches('/');
+        Some(format!("{prefix}/{fingerprinted}"))
+    }
+
+    // The file server that serves the original files
+    pub(crate) fn files(&self) -> &FileServer {
+        &self.files
+    }
+
+    pub(crate) fn respond(&self, req: &Request) -> Option<Response> {
+        let path = self.files.strip_request_prefix(&req.path)?;
+
+        let Some(original) = self.originals.get(path.trim_start_matches('/')) else {
+            return self.files.respond(req);
+        };
+
+        let prefix = self.request_prefix.trim_end_matches('/');
+        let original_req = Request {
+            method: req.method.clone(),
+            path: format!("{prefix}/{original}"),
+            headers: req.headers.clone(),
+            ..Request::default()
+        };
+
+        let response = self.files.respond(&original_req)?;
+
+        // The content behind a fingerprinted name never changes
+        Some(response.set_header(
+            headers::CACHE_CONTROL,
+            CacheControl::new().public().max_age(31536000).immutable(),
+        ))
+    }
+}
+
+// Recursively collects the paths of all the files under `dir`, relative to `root`
+fn collect_files(root: &Utf8Path, names: &mut Vec<String>) -> Result<(), io::Error> {
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let Ok(path) = Utf8PathBuf::try_from(entry.path()) else {
+                continue;
+            };
+
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                // URLs always use forward slashes
+                let components: Vec<_> = relative.components().map(|c| c.as_str()).collect();
+                names.push(components.join("/"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{NOT_FOUND, OK};
+    use std::collections::BTreeMap;
+
+    fn get(path: &str) -> Request {
+        Request {
+            method: String::from("GET"),
+            path: path.to_string(),
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn asset_urls() {
+        let assets = Assets::new("/assets", "./src").unwrap();
+        let hash = format!(
+            "{:016x}",
+            fnv1a(&fs::read("./src/record/stdin.rs").unwrap())
+        );
+
+        assert_eq!(
+            assets.asset_url("record/stdin.rs").unwrap(),
+            format!("/assets/record/stdin.{hash}.rs")
+        );
+        assert_eq!(
+            assets.asset_url("/record/stdin.rs").unwrap(),
+            format!("/assets/record/stdin.{hash}.rs")
+        );
+        assert_eq!(assets.asset_url("nope.rs"), None);
+    }
+
+    #[test]
+    fn respond_to_fingerprinted_asset() {
+        let assets = Assets::new("/assets", "./src").unwrap();
+        let url = assets.asset_url("lib.rs").unwrap();
+
+        let res = assets.respond(&get(&url)).unwrap();
+        assert_eq!(res.status, OK);
+        assert_eq!(
+            res.headers["Cache-Control"],
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(res.body, fs::read("./src/lib.rs").unwrap());
+    }
+
+    #[test]
+    fn respond_to_original_asset() {
+        let assets = Assets::new("/assets", "./src").unwrap();
+
+        let res = assets.respond(&get("/assets/lib.rs")).unwrap();
+        assert_eq!(res.status, OK);
+        assert_eq!(res.headers["Cache-Control"], "no-cache");
+
+        let res = assets.respond(&get("/assets/lib.0000000000000000.rs"));
+        assert_eq!(res.unwrap().status, NOT_FOUND);
+
+        assert_eq!(assets.respond(&get("/other/lib.rs")), None);
+        assert_eq!(assets.respond(&get("/assetslib.rs")), None);
+        assert_eq!(assets.respond(&get("/assets-old/lib.rs")), None);
+    }
+
+    #[test]
+    fn conditional_headers_are_forwarded() {
+        let assets = Assets::new("/assets", "./src").unwrap();
+        let url = assets.asset_url("lib.rs").unwrap();
+
+        let etag = assets.respond(&get(&url)).unwrap().headers["ETag"].clone();
+        let req = Request {
+            headers: BTreeMap::from([("If-None-Match".to_string(), etag)]),
+            ..get(&url)
+        };
+
+        let res = assets.respond(&req).unwrap();
+        assert_eq!(res.status, crate::status::NOT_MODIFIED);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::context::{same_header_name, Request, Response};
+use crate::form;
+use crate::headers;
+use crate::middleware::Next;
+use crate::router::PathSet;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+// How much of each body is recorded when no cap is configured
+const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+// What redacted values are replaced with
+const REDACTED: &str = "REDACTED";
+
+type RedactCallback = Arc<dyn Fn(&mut AuditRecord) + Send + Sync>;
+
+/// A request and its response, as recorded by an [`AuditLog`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AuditRecord {
+    /// When the response was produced
+    pub timestamp: SystemTime,
+    /// The request method
+    pub method: String,
+    /// The request path
+    pub path: String,
+    /// The raw query string of the request, with redacted fields
+    pub query: String,
+    /// The request headers, with redacted values
+    pub request_headers: BTreeMap<String, String>,
+    /// The request body, up to the size cap
+    pub request_body: Vec<u8>,
+    /// The size of the whole request body
+    pub request_body_size: usize,
+    /// The status code of the response
+    pub status: u16,
+    /// The response headers, with redacted values
+    pub response_headers: BTreeMap<String, String>,
+    /// The response body, up to the size cap. Streamed bodies are not recorded.
+    pub response_body: Vec<u8>,
+    /// The size of the whole response body, or `None` if it was streamed
+    pub response_body_size: Option<usize>,
+    /// How long it took to handle the request
+    pub elapsed: Duration,
+    /// The key-value pairs attached with [`Request::log_kv`]
+    pub context: Vec<(String, String)>,
+}
+
+/// Where an [`AuditLog`] sends its records (e.g. a file, or a database)
+///
+/// Records are sent from the worker thread that handled the request, after the response is
+/// produced and before it is sent. Sinks that are slow to write should hand records off to
+/// another thread.
+///
+/// Closures that take an [`AuditRecord`] are sinks.
+pub trait AuditSink: Send + Sync {
+    /// Persists `record`
+    fn record(&self, record: AuditRecord);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(AuditRecord) + Send + Sync,
+{
+    fn record(&self, record: AuditRecord) {
+        self(record)
+    }
+}
+
+/// Records whole requests and responses, bodies included, for audit trails
+///
+/// Unlike the access log, which has a line per request, an audit log keeps what was sent and
+/// received. Since that includes credentials and personal data, values can be redacted before
+/// records reach the sink:
+/// - The `Authorization`, `Proxy-Authorization`, `Cookie` and `Set-Cookie` headers are always
+///   redacted. More can be added with [`AuditLog::redact_headers`].
+/// - Fields of the query string and of `application/x-www-form-urlencoded` request bodies can
+///   be redacted with [`AuditLog::redact_fields`].
+/// - Anything else (e.g. fields of JSON bodies) can be redacted with [`AuditLog::redact_with`].
+///
+/// Bodies are recorded up to a size cap (64KB by default). The size of the whole body is
+/// recorded too, so truncated bodies can be told apart.
+///
+/// See [`ServerConfig::audit_log`](crate::ServerConfig::audit_log)
+///
+/// ```
+/// use vintage::{AuditLog, AuditRecord, ServerConfig};
+///
+/// let audit = AuditLog::new(|record: AuditRecord| {
+///     // Write the record to durable storage
+///     println!("{} {} {}", record.method, record.path, record.status);
+/// })
+/// .paths(["/admin/{*rest}"])
+/// .max_body_size(16 * 1024)
+/// .redact_fields(["password"]);
+///
+/// let config = ServerConfig::new().audit_log(audit);
+/// ```
+pub struct AuditLog {
+    sink: Arc<dyn AuditSink>,
+    routes: Option<PathSet>,
+    max_body_size: usize,
+    redacted_headers: Vec<String>,
+    redacted_fields: Vec<String>,
+    redact: Option<RedactCallback>,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("max_body_size", &self.max_body_size)
+            .field("redacted_headers", &self.redacted_headers)
+            .field("redacted_fields", &self.redacted_fields)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AuditLog {
+    /// Creates an audit log that sends a record of every request to `sink`
+    pub fn new(sink: impl AuditSink + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            routes: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            redacted_headers: [
+                headers::AUTHORIZATION,
+                headers::PROXY_AUTHORIZATION,
+                headers::COOKIE,
+                headers::SET_COOKIE,
+            ]
+            .map(String::from)
+            .to_vec(),
+            redacted_fields: Vec::new(),
+            redact: None,
+        }
+    }
+
+    /// Only records requests to `paths`. By default, requests to any path are recorded.
+    ///
+    /// Paths use the same syntax as routes (see [`ServerConfig::on`](crate::ServerConfig::on)).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a path is not a valid route or is repeated
+    #[track_caller]
+    pub fn paths<const N: usize>(mut self, paths: [&str; N]) -> Self {
+        self.routes = Some(PathSet::new(paths));
+        self
+    }
+
+    /// Sets how many bytes of each body are recorded. The default is 64KB.
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.max_body_size = bytes;
+        self
+    }
+
+    /// Redacts the values of the request and response headers in `names`
+    pub fn redact_headers<const N: usize>(mut self, names: [&str; N]) -> Self {
+        self.redacted_headers
+            .extend(names.into_iter().map(String::from));
+        self
+    }
+
+    /// Redacts the values of the fields in `names`, in the query string and in
+    /// `application/x-www-form-urlencoded` request bodies
+    pub fn redact_fields<const N: usize>(mut self, names: [&str; N]) -> Self {
+        self.redacted_fields
+            .extend(names.into_iter().map(String::from));
+        self
+    }
+
+    /// Registers a callback that edits records before they are sent to the sink, after the other
+    /// redaction rules are applied
+    pub fn redact_with<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&mut AuditRecord) + Send + Sync + 'static,
+    {
+        self.redact = Some(Arc::new(callback));
+        self
+    }
+
+    // Runs the rest of the middleware chain, and records the request and its response
+    pub(crate) fn record(&self, req: &mut Request, next: Next<'_>) -> Response {
+        if let Some(routes) = &self.routes {
+            if !routes.matches(&req.path) {
+                return next.run(req);
+            }
+        }
+
+        // Handlers can take the body, so it is copied before they run
+        let request_body_size = req.body.len();
+        let mut request_body = self.capped(&req.body).to_vec();
+        if is_urlencoded(req) && !self.redacted_fields.is_empty() {
+            let redacted = self.redact_urlencoded(&String::from_utf8_lossy(&req.body));
+            request_body = self.capped(redacted.as_bytes()).to_vec();
+        }
+        let request_headers = self.redact_headers_of(&req.headers);
+
+        let response = next.run(req);
+
+        let mut record = AuditRecord {
+            timestamp: req.clock.now(),
+            method: req.method.clone(),
+            path: req.path.clone(),
+            query: self.redact_urlencoded(&req.query_string),
+            request_headers,
+            request_body,
+            request_body_size,
+            status: response.status,
+            response_headers: self.redact_headers_of(&headers_of(&response)),
+            response_body: self.capped(&response.body).to_vec(),
+            response_body_size: response.stream.is_none().then_some(response.body.len()),
+            elapsed: req.elapsed(),
+            context: req.log_context.clone(),
+        };
+        if let Some(redact) = &self.redact {
+            redact(&mut record);
+        }
+        self.sink.record(record);
+
+        response
+    }
+
+    fn capped<'a>(&self, body: &'a [u8]) -> &'a [u8] {
+        &body[..body.len().min(self.max_body_size)]
+    }
+
+    fn redact_headers_of(&self, headers: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let redacted = self
+                    .redacted_headers
+                    .iter()
+                    .any(|redacted| same_header_name(name, redacted));
+                let value = if redacted { REDACTED } else { value };
+                (name.clone(), value.to_string())
+            })
+            .collect()
+    }
+
+    // Replaces the values of redacted fields in urlencoded `input`, leaving the rest as it was
+    fn redact_urlencoded(&self, input: &str) -> String {
+        if self.redacted_fields.is_empty() {
+            return input.to_string();
+        }
+        input
+            .split('&')
+            .map(|pair| {
+                let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+                let decoded = form::parse(name).next().map(|(name, _)| name);
+                match decoded {
+                    Some(decoded) if self.redacted_fields.contains(&decoded) => {
+                        format!(
+                            "{}={REDACTED}",
+                            utf8_percent_encode(&decoded, NON_ALPHANUMERIC)
+                        )
+                    }
+                    _ => pair.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+// The headers of `response`. The cookies it adds are recorded as a single `Set-Cookie` header, one
+// cookie per line.
+fn headers_of(response: &Response) -> BTreeMap<String, String> {
+    let mut headers = response.headers.clone();
+    if !response.cookies.is_empty() {
+        let cookies = headers.entry(headers::SET_COOKIE.to_string()).or_default();
+        for cookie in &response.cookies {
+            if !cookies.is_empty() {
+                cookies.push('\n');
+            }
+            cookies.push_str(cookie);
+        }
+    }
+    headers
+}
+
+fn is_urlencoded(req: &Request) -> bool {
+    req.header(headers::CONTENT_TYPE)
+        .is_some_and(|content_type| {
+            let media_type = content_type.split(';').next().unwrap_or_default();
+            media_type
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{collector, receive_exactly};
+    use crate::ServerConfig;
+
+    #[test]
+    fn recording() {
+        let (collect, records) = collector();
+        let audit = AuditLog::new(collect)
+            .paths(["/admin/{*rest}"])
+            .max_body_size(24)
+            .redact_headers(["X-Api-Key"])
+            .redact_fields(["password"])
+            .redact_with(|record| record.context.clear());
+
+        let config = ServerConfig::new().audit_log(audit).on_post(
+            ["/admin/{*rest}", "/public"],
+            |req, _params| {
+                req.log_kv("user", "ada");
+                let body = req.take_body();
+                Response::text(format!("received {} bytes", body.len()))
+                    .set_header("Set-Cookie", "session=secret")
+            },
+        );
+        let server = crate::start(config, "localhost:0").unwrap();
+        let client = crate::Client::new(server.address());
+
+        let send = |path: &str| {
+            let req = Request {
+                method: "POST".into(),
+                path: path.into(),
+                query_string: "password=hunter2&next=%2Fhome".into(),
+                headers: BTreeMap::from([
+                    ("Authorization".into(), "Bearer token".into()),
+                    ("X-Api-Key".into(), "key".into()),
+                    (
+                        "Content-Type".into(),
+                        "application/x-www-form-urlencoded".into(),
+                    ),
+                ]),
+                body: b"user=ada&password=hunter2&remember=on".to_vec(),
+                ..Request::default()
+            };
+            client.send(&req).unwrap()
+        };
+
+        let response = send("/admin/users");
+        assert_eq!(response.body, b"received 37 bytes");
+        send("/public");
+
+        let [record] = receive_exactly(&records, 1).try_into().unwrap();
+        assert_eq!(record.path, "/admin/users");
+        assert_eq!(record.query, "password=REDACTED&next=%2Fhome");
+        assert_eq!(record.request_headers["Authorization"], "REDACTED");
+        assert_eq!(record.request_headers["X-Api-Key"], "REDACTED");
+        assert_eq!(record.request_body, b"user=ada&password=REDACT");
+        assert_eq!(record.request_body_size, 37);
+        assert_eq!(record.status, 200);
+        assert_eq!(record.response_headers["Set-Cookie"], "REDACTED");
+        assert_eq!(record.response_body, b"received 37 bytes");
+        assert_eq!(record.response_body_size, Some(17));
+        assert!(record.context.is_empty());
+
+        server.stop();
+    }
+}
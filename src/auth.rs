@@ -0,0 +1,429 @@
+//! The HTTP side of logging users in, with the checking of passwords left to the application
+//!
+//! Password hashing schemes come and go, so this module doesn't pick one. The application
+//! implements [`Authenticator`] with the library of its choice (e.g. `argon2`), and the middleware
+//! here takes care of reading credentials and challenging clients:
+//! - [`BasicAuth`] reads them from the `Authorization` header (HTTP Basic authentication).
+//! - `FormLogin` reads them from a submitted login form, and remembers the user in a signed
+//!   cookie (with the `signed-cookies` feature).
+//!
+//! Once a request is authenticated, the user is available to handlers as the `REMOTE_USER` CGI
+//! variable (see [`Request::remote_user`]), as if the web server had authenticated it.
+//! `AUTH_TYPE` is set to `Basic` or `Form`.
+//!
+//! ```
+//! use vintage::auth::{self, BasicAuth};
+//! use vintage::{Response, ServerConfig};
+//!
+//! let config = ServerConfig::new()
+//!     .basic_auth(BasicAuth::new("admin", |username: &str, password: &str| {
+//!         // Use a password hash in real applications
+//!         let valid = username == "admin"
+//!             && auth::constant_time_eq(password.as_bytes(), b"correct horse");
+//!         valid.then(|| username.to_string())
+//!     }))
+//!     .on_get(["/"], |req, _params| {
+//!         Response::text(format!("Hello {}", req.remote_user().unwrap()))
+//!     });
+//! ```
+
+use crate::context::{Request, Response};
+#[cfg(feature = "signed-cookies")]
+use crate::form::Form;
+use crate::headers;
+use crate::middleware::Next;
+#[cfg(feature = "signed-cookies")]
+use crate::query;
+use crate::router::PathSet;
+use crate::status;
+#[cfg(feature = "signed-cookies")]
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::fmt;
+use std::sync::Arc;
+#[cfg(feature = "signed-cookies")]
+use std::time::{Duration, SystemTime};
+
+// The cookie that remembers the user logged in with a `FormLogin`
+#[cfg(feature = "signed-cookies")]
+const LOGIN_COOKIE: &str = "login";
+// How long users stay logged in when no maximum age is configured
+#[cfg(feature = "signed-cookies")]
+const DEFAULT_LOGIN_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Checks the credentials of users
+///
+/// This is implemented by the application, which knows where users are stored and how their
+/// passwords are hashed. Closures taking a username and a password implement it too.
+pub trait Authenticator: Send + Sync {
+    /// Returns the identity of the user (e.g. their username or ID) if `password` is theirs
+    ///
+    /// The identity is what handlers see as the `REMOTE_USER` variable.
+    fn authenticate(&self, username: &str, password: &str) -> Option<String>;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&str, &str) -> Option<String> + Send + Sync,
+{
+    fn authenticate(&self, username: &str, password: &str) -> Option<String> {
+        self(username, password)
+    }
+}
+
+/// Returns true if `a` and `b` are equal, taking the same time whatever their contents
+///
+/// Comparing secrets (e.g. API tokens) with `==` stops at the first difference, so how long it
+/// takes tells an attacker how much of their guess was right. Only the length of the inputs can
+/// be learned from this function.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let difference = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    // Keeps the compiler from turning the loop into one that stops early
+    std::hint::black_box(difference) == 0
+}
+
+/// HTTP Basic authentication of every request
+///
+/// Requests without valid credentials get a `401 Unauthorized` response, which makes browsers
+/// prompt for a username and password. Basic authentication sends the password with every
+/// request, so it should only be used over `https`.
+///
+/// See [`ServerConfig::basic_auth`](crate::ServerConfig::basic_auth)
+#[derive(Clone)]
+pub struct BasicAuth {
+    realm: String,
+    authenticator: Arc<dyn Authenticator>,
+    except: PathSet,
+}
+
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("realm", &self.realm)
+            .field("except", &self.except)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BasicAuth {
+    /// Creates Basic authentication for `realm` (shown by some browsers in their prompt), with
+    /// credentials checked by `authenticator`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `realm` contains a `"`, a `\` or a line break
+    pub fn new(realm: impl Into<String>, authenticator: impl Authenticator + 'static) -> Self {
+        let realm = realm.into();
+        assert!(
+            !realm.contains(['"', '\\', '\r', '\n']),
+            "Invalid realm: '{}'",
+            realm.escape_debug()
+        );
+        Self {
+            realm,
+            authenticator: Arc::new(authenticator),
+            except: PathSet::default(),
+        }
+    }
+
+    /// Lets requests to `paths` through without credentials (e.g. a health check)
+    ///
+    /// Paths use the same syntax as routes (see [`ServerConfig::on`](crate::ServerConfig::on)).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a path is not a valid route, or was already excluded
+    #[track_caller]
+    pub fn except<const N: usize>(mut self, paths: [&str; N]) -> Self {
+        self.except.extend(paths);
+        self
+    }
+
+    /// Authenticates `req` before passing it to `next`, or challenges the client
+    pub fn respond(&self, req: &mut Request, next: Next<'_>) -> Response {
+        if self.except.matches(req.path()) {
+            return next.run(req);
+        }
+
+        let credentials = req
+            .header(headers::AUTHORIZATION)
+            .and_then(|value| {
+                let (scheme, token) = value.trim().split_once(' ')?;
+                scheme.eq_ignore_ascii_case("Basic").then_some(token)
+            })
+            .and_then(|token| decode_base64(token.trim()))
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        let user = credentials.as_deref().and_then(|credentials| {
+            let (username, password) = credentials.split_once(':')?;
+            self.authenticator.authenticate(username, password)
+        });
+
+        match user {
+            Some(user) => {
+                set_user(req, user, "Basic");
+                next.run(req)
+            }
+            None => Response::new().set_status(status::UNAUTHORIZED).set_header(
+                headers::WWW_AUTHENTICATE,
+                format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm),
+            ),
+        }
+    }
+}
+
+/// Logging in with a form, and staying logged in with a cookie
+///
+/// The application serves the login page at the login path (e.g. `/login`), with a form that is
+/// posted back to it. The form has a `username` and a `password` field.
+/// - When the credentials are valid, the user is remembered in a signed `login` cookie, and
+///   redirected to where they were going (or `/`).
+/// - When they are not, the user is redirected back to the login page, with `error=1` added to
+///   its query.
+///
+/// Requests for other paths without a valid cookie are redirected to the login page if they are
+/// `GET` or `HEAD` requests, and get a `401 Unauthorized` response otherwise. The path they were
+/// for is passed along in the `next` query parameter, which the form should post back.
+///
+/// The cookie is signed with the keys set with [`ServerConfig::keys`](crate::ServerConfig::keys),
+/// which are required. It holds the time the user logged in, and stops being accepted once it is
+/// older than [`FormLogin::max_age`]. Logging out is a matter of clearing the `login` cookie (e.g.
+/// `Set-Cookie: login=; Path=/; Max-Age=0`).
+///
+/// See [`ServerConfig::form_login`](crate::ServerConfig::form_login)
+#[cfg(feature = "signed-cookies")]
+#[derive(Clone)]
+pub struct FormLogin {
+    login_path: String,
+    authenticator: Arc<dyn Authenticator>,
+    except: PathSet,
+    max_age: Duration,
+}
+
+#[cfg(feature = "signed-cookies")]
+impl fmt::Debug for FormLogin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FormLogin")
+            .field("login_path", &self.login_path)
+            .field("except", &self.except)
+            .field("max_age", &self.max_age)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "signed-cookies")]
+impl FormLogin {
+    /// Creates a form login with the login page at `login_path`, and credentials checked by
+    /// `authenticator`
+    pub fn new(login_path: impl Into<String>, authenticator: impl Authenticator + 'static) -> Self {
+        Self {
+            login_path: login_path.into(),
+            authenticator: Arc::new(authenticator),
+            except: PathSet::default(),
+            max_age: DEFAULT_LOGIN_MAX_AGE,
+        }
+    }
+
+    /// Sets how long users stay logged in before they have to log in again. The default is 7
+    /// days.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Lets requests to `paths` through without logging in (e.g. stylesheets)
+    ///
+    /// Paths use the same syntax as routes (see [`ServerConfig::on`](crate::ServerConfig::on)).
+    /// The user is still made available to handlers if they are logged in.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a path is not a valid route, or was already excluded
+    #[track_caller]
+    pub fn except<const N: usize>(mut self, paths: [&str; N]) -> Self {
+        self.except.extend(paths);
+        self
+    }
+
+    /// Logs `req` in, or passes it to `next` if it is logged in already
+    pub fn respond(&self, req: &mut Request, next: Next<'_>) -> Response {
+        if req.keys.is_none() {
+            log::error!("ServerConfig::keys must be set to use FormLogin");
+            return Response::new().set_status(status::INTERNAL_SERVER_ERROR);
+        }
+
+        if req.path() == self.login_path {
+            if req.method() == "POST" {
+                return self.log_in(req);
+            }
+            return next.run(req);
+        }
+
+        if let Some(user) = self.logged_in_user(req) {
+            set_user(req, user, "Form");
+            return next.run(req);
+        }
+        if self.except.matches(req.path()) {
+            return next.run(req);
+        }
+
+        if !matches!(req.method(), "GET" | "HEAD") {
+            return Response::new().set_status(status::UNAUTHORIZED);
+        }
+        let mut next_path = req.path().to_string();
+        if !req.query_string().is_empty() {
+            next_path = format!("{next_path}?{}", req.query_string());
+        }
+        Response::new().set_status(status::SEE_OTHER).set_header(
+            headers::LOCATION,
+            query::append(&self.login_path, "next", &next_path),
+        )
+    }
+
+    // Checks the submitted credentials, and remembers the user if they are valid
+    fn log_in(&self, req: &Request) -> Response {
+        let form = match Form::from_request(req) {
+            Ok(form) => form,
+            Err(rejection) => return rejection,
+        };
+        let next = form.value("next").filter(|next| is_local(next));
+        let username = form.value("username").unwrap_or_default();
+        let password = form.value("password").unwrap_or_default();
+
+        let Some(user) = self.authenticator.authenticate(username, password) else {
+            log::info!(username = username; "Failed login");
+            let mut location = query::append(&self.login_path, "error", "1");
+            if let Some(next) = next {
+                location = query::append(&location, "next", next);
+            }
+            return Response::new()
+                .set_status(status::SEE_OTHER)
+                .set_header(headers::LOCATION, location);
+        };
+
+        // The user is encoded, so the `.` before the login time is the last one in the value
+        let user = utf8_percent_encode(&user, NON_ALPHANUMERIC);
+        let logged_in_at = unix_time(req.clock.now());
+        let value = req.sign_cookie(LOGIN_COOKIE, &format!("{user}.{logged_in_at}"));
+        let max_age = self.max_age.as_secs();
+        let secure = if req.is_secure() { "; Secure" } else { "" };
+        Response::new()
+            .set_status(status::SEE_OTHER)
+            .set_header(headers::LOCATION, next.unwrap_or("/"))
+            .add_cookie(format!(
+                "{LOGIN_COOKIE}={value}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}"
+            ))
+    }
+
+    // The user remembered by the login cookie of `req`, unless the cookie is invalid or expired
+    fn logged_in_user(&self, req: &Request) -> Option<String> {
+        let (user, logged_in_at) = req.signed_cookie(LOGIN_COOKIE)?.rsplit_once('.')?;
+        let age = unix_time(req.clock.now()).saturating_sub(logged_in_at.parse().ok()?);
+        if age >= self.max_age.as_secs() {
+            return None;
+        }
+        Some(percent_decode_str(user).decode_utf8_lossy().into_owned())
+    }
+}
+
+#[cfg(feature = "signed-cookies")]
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn set_user(req: &mut Request, user: String, auth_type: &str) {
+    req.vars.insert("REMOTE_USER".into(), user);
+    req.vars.insert("AUTH_TYPE".into(), auth_type.into());
+}
+
+// Returns true if `path` is on this site. Redirecting anywhere else after logging in would let
+// links to the login page send users to malicious sites.
+//
+// Browsers ignore tabs and line breaks in URLs, and treat `\` like `/`, so `/\t/evil.example` is
+// as external as `//evil.example`. Paths with these characters are rejected outright.
+#[cfg(feature = "signed-cookies")]
+fn is_local(path: &str) -> bool {
+    path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains(|c: char| c == '\\' || c.is_control())
+}
+
+// Decodes standard (padded) base64, as used by Basic authentication
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let sextet = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+
+    let input = input.as_bytes();
+    if !input.len().is_multiple_of(4) {
+        return None;
+    }
+    let padding = input.iter().rev().take_while(|&&c| c == b'=').count();
+    if padding > 2 {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    let mut buffer = 0u32;
+    for (i, &c) in input[..input.len() - padding].iter().enumerate() {
+        buffer = buffer << 6 | u32::from(sextet(c)?);
+        if i % 4 == 3 {
+            output.extend_from_slice(&buffer.to_be_bytes()[1..]);
+            buffer = 0;
+        }
+    }
+    match padding {
+        1 => output.extend_from_slice(&(buffer << 6).to_be_bytes()[1..3]),
+        2 => output.push((buffer << 12).to_be_bytes()[1]),
+        _ => {}
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        let decode = |input| decode_base64(input).map(|bytes| String::from_utf8(bytes).unwrap());
+        assert_eq!(decode("").as_deref(), Some(""));
+        assert_eq!(decode("YQ==").as_deref(), Some("a"));
+        assert_eq!(decode("YWI=").as_deref(), Some("ab"));
+        assert_eq!(decode("YWJj").as_deref(), Some("abc"));
+        assert_eq!(
+            decode("QWxhZGRpbjpvcGVuIHNlc2FtZQ==").as_deref(),
+            Some("Aladdin:open sesame")
+        );
+        for invalid in ["YQ", "Y===", "YQ=a", "Y!==", "YWJj\n"] {
+            assert_eq!(decode(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn comparison() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    #[test]
+    fn local_paths() {
+        assert!(is_local("/admin?tab=1"));
+        assert!(!is_local("//evil.example"));
+        assert!(!is_local("/\\evil.example"));
+        assert!(!is_local("https://evil.example"));
+        assert!(!is_local("/\t/evil.example"));
+        assert!(!is_local("/a\\b"));
+        assert!(!is_local("/a\r\nSet-Cookie: x=1"));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::connection::{Connection, Packet};
+use crate::record;
+use crate::sync;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+type Producer = Box<dyn FnOnce(&mut BodyWriter) -> io::Result<()> + Send>;
+
+// A response body that is produced while it is being sent.
+//
+// `Response` is `Clone`, but the producer can only run once. Whichever clone gets sent first runs
+// it. The others are sent without a body.
+#[derive(Clone)]
+pub struct BodyStream(Arc<Mutex<Option<Producer>>>);
+
+impl BodyStream {
+    pub fn new<F>(producer: F) -> Self
+    where
+        F: FnOnce(&mut BodyWriter) -> io::Result<()> + Send + 'static,
+    {
+        Self(Arc::new(Mutex::new(Some(Box::new(producer)))))
+    }
+
+    pub fn run(&self, writer: &mut BodyWriter) -> io::Result<()> {
+        let producer = sync::lock(&self.0).take();
+        match producer {
+            Some(producer) => producer(writer),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyStream")
+    }
+}
+
+impl PartialEq for BodyStream {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for BodyStream {}
+
+/// Writes the body of a streamed response to the FastCGI client
+///
+/// Written bytes are buffered, and sent once enough of them accumulate, or when
+/// [`flush()`](std::io::Write::flush) is called. A flush sends the buffered bytes all the way to
+/// the web server, so flushing after each event or progress update is enough for them to be
+/// delivered promptly. Interactive streams (e.g. server-sent events) can call
+/// [`BodyWriter::set_interactive`] instead, to send every write as soon as it is made.
+///
+/// Web servers may buffer responses too. Nginx, for example, can be told not to with the
+/// `X-Accel-Buffering: no` response header.
+///
+/// Before sending each chunk, the writer checks whether the client aborted the request, either
+/// by sending an `FCGI_ABORT_REQUEST` record or by closing the connection.
+/// Once that happens, every write fails with [`io::ErrorKind::ConnectionAborted`], so producers
+/// using the `?` operator stop early.
+///
+/// See [`Response::set_body_stream`](crate::Response::set_body_stream)
+pub struct BodyWriter<'a> {
+    conn: &'a mut Connection,
+    buffer: Vec<u8>,
+    aborted: bool,
+    // Why writing to the connection failed, if it did
+    write_error: Option<io::Error>,
+    sent: usize,
+    interactive: bool,
+}
+
+// Why a response was not completely sent
+#[derive(Debug)]
+pub(crate) enum Unsent {
+    // The client aborted the request
+    Aborted,
+    // Writing to the connection failed (e.g. the web server closed it)
+    WriteFailed(io::Error),
+}
+
+impl<'a> BodyWriter<'a> {
+    pub(crate) fn new(conn: &'a mut Connection) -> Self {
+        Self {
+            conn,
+            buffer: Vec::new(),
+            aborted: false,
+            write_error: None,
+            sent: 0,
+            interactive: false,
+        }
+    }
+
+    /// Returns true if the client aborted the request
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// Sends each write right away, in its own packet, instead of buffering it
+    ///
+    /// This also turns off Nagle's algorithm on TCP connections, so that small packets are not
+    /// held back by the operating system either. Use this for streams of small, latency sensitive
+    /// writes. Bulk transfers are more efficient with the default buffering.
+    pub fn set_interactive(&mut self, interactive: bool) {
+        if interactive && !self.interactive {
+            if let Err(err) = self.conn.set_nodelay() {
+                log::debug!(error:err = err; "Failed to disable Nagle's algorithm");
+            }
+        }
+        self.interactive = interactive;
+    }
+
+    fn aborted_error() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "The FastCGI client aborted the request",
+        )
+    }
+
+    // Sends the buffered bytes as a single `FCGI_STDOUT` packet
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.aborted {
+            return Err(Self::aborted_error());
+        }
+
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        if self.conn.poll_abort() {
+            self.aborted = true;
+            return Err(Self::aborted_error());
+        }
+
+        let packet = Packet {
+            type_id: record::FCGI_STDOUT,
+            content: std::mem::take(&mut self.buffer),
+        };
+
+        // A failed write most likely means the connection was closed (e.g. EPIPE).
+        // Either way, there is no point in producing more of the body.
+        if let Err(err) = self.conn.write_packet(&packet) {
+            self.aborted = true;
+            let returned = io::Error::new(err.kind(), err.to_string());
+            self.write_error = Some(err);
+            return Err(returned);
+        }
+        self.sent += packet.content.len();
+        Ok(())
+    }
+
+    // Sends whatever is left of the body, followed by the empty packet that terminates the
+    // `FCGI_STDOUT` stream.
+    //
+    // Returns how many bytes of `FCGI_STDOUT` content were sent, or why they were not all sent.
+    pub(crate) fn finish(mut self) -> Result<usize, Unsent> {
+        if self.send_buffer().is_err() {
+            return Err(self
+                .write_error
+                .map_or(Unsent::Aborted, Unsent::WriteFailed));
+        }
+
+        let terminator = Packet {
+            type_id: record::FCGI_STDOUT,
+            content: vec![],
+        };
+
+        self.conn
+            .write_packet(&terminator)
+            .map_err(Unsent::WriteFailed)?;
+        Ok(self.sent)
+    }
+}
+
+impl Write for BodyWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.aborted {
+            return Err(Self::aborted_error());
+        }
+
+        // The length of a packet must fit in two bytes
+        let available = u16::MAX as usize - self.buffer.len();
+        let len = buf.len().min(available);
+        self.buffer.extend_from_slice(&buf[..len]);
+
+        if self.interactive || self.buffer.len() == u16::MAX as usize {
+            self.send_buffer()?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use std::collections::VecDeque;
+
+    // The contents of the `FCGI_STDOUT` packets written to `conn`
+    fn packets(conn: &mut Connection) -> Vec<Vec<u8>> {
+        let mut packets = vec![];
+        while let Ok(packet) = conn.read_packet() {
+            packets.push(packet.content);
+        }
+        packets
+    }
+
+    #[test]
+    fn flushing() {
+        let mut conn = Connection::Test(VecDeque::new());
+        let mut writer = BodyWriter::new(&mut conn);
+        writer.write_all(b"a").unwrap();
+        writer.write_all(b"b").unwrap();
+        writer.flush().unwrap();
+        writer.write_all(b"c").unwrap();
+        assert_eq!(writer.finish().unwrap(), 3);
+        assert_eq!(packets(&mut conn), [&b"ab"[..], b"c", b""]);
+
+        let mut conn = Connection::Test(VecDeque::new());
+        let mut writer = BodyWriter::new(&mut conn);
+        writer.set_interactive(true);
+        writer.write_all(b"a").unwrap();
+        writer.write_all(b"b").unwrap();
+        writer.set_interactive(false);
+        writer.write_all(b"c").unwrap();
+        writer.write_all(b"d").unwrap();
+        assert_eq!(writer.finish().unwrap(), 4);
+        assert_eq!(packets(&mut conn), [&b"a"[..], b"b", b"cd", b""]);
+    }
+}
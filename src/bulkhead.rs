@@ -0,0 +1,92 @@
+use crate::router::PathSet;
+use std::fmt;
+
+// A group of routes whose requests are handled by a dedicated pool of worker threads.
+//
+// Requests are still read by the general workers, then handed off to the pool of the bulkhead
+// that matches their path. That way, a flood of slow requests to these routes only exhausts the
+// bulkhead's threads, and leaves the other routes responsive.
+#[derive(Clone)]
+pub struct Bulkhead {
+    routes: PathSet,
+    threads: usize,
+    // Created when the server starts, like `ServerConfig::stats`
+    pool: Option<threadpool::ThreadPool>,
+}
+
+impl fmt::Debug for Bulkhead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bulkhead")
+            .field("routes", &self.routes)
+            .field("threads", &self.threads)
+            .finish()
+    }
+}
+
+impl Bulkhead {
+    pub fn new<const N: usize>(paths: [&str; N], threads: usize) -> Self {
+        assert!(threads > 0, "A bulkhead needs at least one thread");
+
+        Self {
+            routes: PathSet::new(paths),
+            threads,
+            pool: None,
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.routes.matches(path)
+    }
+
+    pub fn start(&mut self) {
+        let pool = threadpool::Builder::new()
+            .num_threads(self.threads)
+            .thread_name(format!(
+                "vintage-bulkhead-{}",
+                self.routes.patterns().join(",")
+            ))
+            .build();
+        self.pool = Some(pool);
+    }
+
+    // Runs `job` on the threads of this bulkhead
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        match &self.pool {
+            Some(pool) => pool.execute(job),
+            // Only possible if the server was not started through `event_loop`
+            None => job(),
+        }
+    }
+
+    // Waits for the requests handed off to this bulkhead to be handled
+    pub fn join(&self) {
+        if let Some(pool) = &self.pool {
+            pool.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn execute() {
+        let mut bulkhead = Bulkhead::new(["/reports/{*path}"], 2);
+        assert!(bulkhead.matches("/reports/2024"));
+        assert!(!bulkhead.matches("/api/users"));
+
+        bulkhead.start();
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let count = count.clone();
+            bulkhead.execute(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        bulkhead.join();
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+    }
+}
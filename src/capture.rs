@@ -0,0 +1,365 @@
+use crate::record::{self, Record};
+use crate::server_config::ServerConfig;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Identifies capture files, and the version of their format
+const MAGIC: &[u8; 8] = b"FCGICAP1";
+
+// How long a replay waits on the server to respond
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Distinguishes captures of connections that were accepted during the same millisecond
+static CAPTURE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The direction bytes were flowing in when they were captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the FastCGI client to the server
+    Inbound,
+    /// From the server to the FastCGI client
+    Outbound,
+}
+
+/// The raw bytes exchanged over a single FastCGI connection
+///
+/// Captures are recorded with [`ServerConfig::capture`], and can be loaded back to inspect or
+/// replay the connection.
+/// This makes it possible to reproduce bugs that only happen with a specific web server.
+///
+/// ```no_run
+/// use vintage::{Capture, Response, ServerConfig};
+///
+/// let config = ServerConfig::new().unhandled(|_req| Response::text("hello"));
+///
+/// let capture = Capture::load("captures/1730000000000-0.fcgi").unwrap();
+/// let replayed = capture.replay(config).unwrap();
+///
+/// assert_eq!(replayed, capture.outbound());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capture {
+    /// Chunks of bytes in the order they were read or written
+    pub chunks: Vec<(Direction, Vec<u8>)>,
+}
+
+impl Capture {
+    /// Loads the capture file at `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a capture file"));
+        }
+
+        let mut chunks = vec![];
+        loop {
+            let mut header = [0u8; 5];
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+
+            let [direction, len @ ..] = header;
+            let direction = match direction {
+                0 => Direction::Inbound,
+                1 => Direction::Outbound,
+                _ => return Err(invalid_data("Invalid chunk direction")),
+            };
+
+            let mut bytes = vec![];
+            let len = u32::from_be_bytes(len) as u64;
+            reader.by_ref().take(len).read_to_end(&mut bytes)?;
+            if bytes.len() as u64 != len {
+                return Err(invalid_data("Truncated chunk"));
+            }
+            chunks.push((direction, bytes));
+        }
+
+        Ok(Self { chunks })
+    }
+
+    fn bytes(&self, direction: Direction) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|(d, _)| *d == direction)
+            .flat_map(|(_, bytes)| bytes.iter().copied())
+            .collect()
+    }
+
+    /// Returns everything the FastCGI client sent
+    pub fn inbound(&self) -> Vec<u8> {
+        self.bytes(Direction::Inbound)
+    }
+
+    /// Returns everything the server sent
+    pub fn outbound(&self) -> Vec<u8> {
+        self.bytes(Direction::Outbound)
+    }
+
+    /// Returns a human-readable description of the records in the capture, one per line
+    ///
+    /// Inbound records are prefixed with `->`, and outbound ones with `<-`.
+    /// Records split across multiple packets are shown once they are complete.
+    pub fn dump(&self) -> String {
+        let mut dump = String::new();
+        let mut inbound = Decoder::default();
+        let mut outbound = Decoder::default();
+
+        for (direction, bytes) in &self.chunks {
+            let (decoder, arrow) = match direction {
+                Direction::Inbound => (&mut inbound, "->"),
+                Direction::Outbound => (&mut outbound, "<-"),
+            };
+            decoder.buffer.extend(bytes);
+            while let Some(summary) = decoder.next_record() {
+                let _ = writeln!(dump, "{arrow} {summary}");
+            }
+        }
+
+        for (decoder, arrow) in [(inbound, "->"), (outbound, "<-")] {
+            if !decoder.buffer.is_empty() {
+                let len = decoder.buffer.len();
+                let hexdump = record::hexdump(&decoder.buffer);
+                let _ = writeln!(dump, "{arrow} {len} trailing bytes{hexdump}");
+            }
+        }
+
+        dump
+    }
+
+    /// Sends the captured inbound bytes to a server started with `config`, and returns
+    /// everything it sent back
+    ///
+    /// The server is started on a random local port, and stopped once the connection closes.
+    /// Responses carry a `Date` header, so they only match the captured ones byte for byte when
+    /// replayed within the same second.
+    pub fn replay(&self, mut config: ServerConfig) -> Result<Vec<u8>, io::Error> {
+        // Replays should not produce captures of their own
+        config.capture = None;
+
+        let server = crate::start(config, "localhost:0")?;
+        let result = (|| {
+            let mut stream = TcpStream::connect(server.address())?;
+            stream.set_read_timeout(Some(REPLAY_TIMEOUT))?;
+            stream.write_all(&self.inbound())?;
+
+            let mut outbound = vec![];
+            stream.read_to_end(&mut outbound)?;
+            Ok(outbound)
+        })();
+        server.stop();
+
+        result
+    }
+}
+
+// Splits a byte stream into records, for `Capture::dump`
+#[derive(Default)]
+struct Decoder {
+    buffer: Vec<u8>,
+    // Payloads of stream records that are not terminated yet, by type and request id
+    streams: BTreeMap<(u8, u16), Vec<u8>>,
+}
+
+impl Decoder {
+    // Returns the summary of the next complete record, if there is one
+    fn next_record(&mut self) -> Option<String> {
+        loop {
+            let [_, type_id, id_1, id_0, len_1, len_0, padding, _] =
+                *self.buffer.first_chunk::<8>()?;
+            let len = u16::from_be_bytes([len_1, len_0]) as usize;
+            let total = 8 + len + padding as usize;
+            if self.buffer.len() < total {
+                return None;
+            }
+
+            let content = self.buffer[8..8 + len].to_vec();
+            self.buffer.drain(..total);
+            let request_id = u16::from_be_bytes([id_1, id_0]);
+
+            let payload = if record::DISCRETE_RECORD_TYPES.contains(&type_id) {
+                content
+            } else if content.is_empty() {
+                self.streams
+                    .remove(&(type_id, request_id))
+                    .unwrap_or_default()
+            } else {
+                self.streams
+                    .entry((type_id, request_id))
+                    .or_default()
+                    .extend(content);
+                continue;
+            };
+
+            let summary = match Record::from_bytes(type_id, payload.clone()) {
+                Ok(record) => record.summary(),
+                Err(err) => format!("type {type_id}: {err}{}", record::hexdump(&payload)),
+            };
+            return Some(format!("request {request_id}: {summary}"));
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Writes the bytes flowing through a connection to a capture file
+#[derive(Debug)]
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+}
+
+impl CaptureWriter {
+    // Creates a new capture file in `dir`
+    pub fn create(dir: &Path) -> Result<Self, io::Error> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let count = CAPTURE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path: PathBuf = dir.join(format!("{millis}-{count}.fcgi"));
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        let direction = match direction {
+            Direction::Inbound => 0u8,
+            Direction::Outbound => 1u8,
+        };
+
+        // Chunk lengths are stored in 4 bytes
+        for chunk in bytes.chunks(u32::MAX as usize) {
+            let result = self
+                .file
+                .write_all(&[direction])
+                .and_then(|_| self.file.write_all(&(chunk.len() as u32).to_be_bytes()))
+                .and_then(|_| self.file.write_all(chunk));
+
+            if let Err(err) = result {
+                log::warn!(error:err = err; "Failed to write to capture file");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{encode_record, Connection};
+    use crate::httpdate;
+    use crate::record::*;
+    use crate::Response;
+
+    fn capture_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vintage-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn request_records() -> Vec<Record> {
+        vec![
+            BeginRequest::new(Role::Responder, false).into(),
+            Params::default()
+                .add("REQUEST_METHOD", "GET")
+                .add("PATH_INFO", "/hello")
+                .add("QUERY_STRING", "")
+                .into(),
+            Stdin(vec![]).into(),
+        ]
+    }
+
+    #[test]
+    fn capture_and_replay() {
+        let dir = capture_dir("capture");
+        let config = ServerConfig::new()
+            .unhandled(|req| Response::text(req.path().to_string()))
+            .capture(&dir);
+
+        let server = crate::start(config.clone(), "localhost:0").unwrap();
+        let socket = mio::net::TcpStream::connect(server.address()).unwrap();
+        let mut connection = Connection::try_from(socket).unwrap();
+        for record in request_records() {
+            connection.write_record(&record).unwrap();
+        }
+        let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+            panic!("expected a Stdout record");
+        };
+        assert!(stdout.0.ends_with(b"/hello"));
+        let _ = connection.read_record();
+        drop(connection);
+        server.stop();
+
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        let capture = Capture::load(entries[0].as_ref().unwrap().path()).unwrap();
+
+        let mut inbound = vec![];
+        for record in request_records() {
+            encode_record(&record, &mut inbound).unwrap();
+        }
+        assert_eq!(capture.inbound(), inbound);
+
+        let mut end = vec![];
+        let end_request = EndRequest::new(0, ProtocolStatus::RequestComplete);
+        encode_record(&end_request.into(), &mut end).unwrap();
+        assert!(capture.outbound().ends_with(&end));
+
+        let replayed = capture.replay(config).unwrap();
+        assert_eq!(
+            httpdate::without_date_header(&replayed),
+            httpdate::without_date_header(&capture.outbound())
+        );
+
+        let dump = capture.dump();
+        let lines: Vec<_> = dump.lines().filter(|l| !l.starts_with("  ")).collect();
+        assert_eq!(
+            lines,
+            [
+                "-> request 1: BEGIN_REQUEST role=Responder keep_conn=false",
+                r#"-> request 1: PARAMS 3 pairs PATH_INFO="/hello" QUERY_STRING="" REQUEST_METHOD="GET""#,
+                "-> request 1: STDIN 0 bytes",
+                "<- request 1: STDOUT 84 bytes",
+                "<- request 1: END_REQUEST app_status=0 protocol_status=RequestComplete",
+            ]
+        );
+        // The replay was not captured
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_capture() {
+        let dir = capture_dir("invalid-capture");
+        let path = dir.join("invalid.fcgi");
+
+        std::fs::write(&path, b"not a capture").unwrap();
+        assert!(Capture::load(&path).is_err());
+
+        let mut truncated = MAGIC.to_vec();
+        truncated.extend([0, 0, 0, 0, 10, b'a']);
+        std::fs::write(&path, truncated).unwrap();
+        assert!(Capture::load(&path).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use crate::sync;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The state of one of the circuits of a [`CircuitBreaker`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+    /// Calls are refused until the cooldown elapses
+    Open,
+    /// The cooldown elapsed, and a single probe call is going through.
+    /// Its outcome decides whether the circuit closes, or opens again.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Instant,
+}
+
+#[derive(Debug)]
+struct BreakerInner {
+    threshold: u32,
+    cooldown: Duration,
+    slow_call: Option<Duration>,
+    circuits: Mutex<BTreeMap<String, Circuit>>,
+}
+
+/// Stops calling something that keeps failing, until it had time to recover
+///
+/// A breaker keeps one circuit per key (e.g. a route, or the name of a downstream service).
+/// A circuit opens after a number of consecutive failures. While it is open, calls are refused
+/// right away instead of piling up on something that is down.
+/// Once the cooldown elapses, a single probe call goes through: the circuit closes if it
+/// succeeds, and opens again if it fails.
+///
+/// Breakers can guard routes with
+/// [`ServerConfig::circuit_breaker`](crate::ServerConfig::circuit_breaker), or calls to
+/// downstream services with [`CircuitBreaker::call`].
+/// The states of the breakers registered with a server are reported by
+/// [`ServerHandle::circuits`](crate::ServerHandle::circuits).
+///
+/// ```
+/// use std::time::Duration;
+/// use vintage::CircuitBreaker;
+///
+/// let breaker = CircuitBreaker::new(5, Duration::from_secs(30));
+///
+/// let balance = breaker.call("payments", || {
+///     // Call the payments service
+///     Ok::<_, std::io::Error>(42)
+/// });
+/// assert_eq!(balance.unwrap().unwrap(), 42);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    /// Creates a breaker whose circuits open after `failures` consecutive failures, and stay
+    /// open for `cooldown`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failures` is 0
+    pub fn new(failures: u32, cooldown: Duration) -> Self {
+        assert!(failures > 0, "A circuit must tolerate at least one failure");
+        Self {
+            inner: Arc::new(BreakerInner {
+                threshold: failures,
+                cooldown,
+                slow_call: None,
+                circuits: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    /// Counts calls that take longer than `duration` as failures, even if they succeed
+    ///
+    /// # Panics
+    ///
+    /// Panics if the breaker was cloned
+    pub fn slow_call(mut self, duration: Duration) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("circuit breaker should not be configured after being cloned")
+            .slow_call = Some(duration);
+        self
+    }
+
+    /// Calls `f`, unless the circuit of `key` is open
+    ///
+    /// Returns `None` without calling `f` if the circuit is open.
+    /// Otherwise, an `Err` counts as a failure.
+    pub fn call<T, E>(&self, key: &str, f: impl FnOnce() -> Result<T, E>) -> Option<Result<T, E>> {
+        let attempt = self.attempt(key)?;
+        let result = f();
+        attempt.finish(result.is_ok());
+        Some(result)
+    }
+
+    /// Returns the state of the circuit of `key`
+    pub fn state(&self, key: &str) -> CircuitState {
+        self.lock()
+            .get(key)
+            .map_or(CircuitState::Closed, |c| c.state)
+    }
+
+    /// Returns the state of every circuit that saw a call
+    pub fn states(&self) -> BTreeMap<String, CircuitState> {
+        self.lock()
+            .iter()
+            .map(|(key, circuit)| (key.clone(), circuit.state))
+            .collect()
+    }
+
+    // Starts a call through the circuit of `key`, or returns `None` if the circuit is open.
+    //
+    // The call counts as a failure if the attempt is dropped before it is finished (e.g. because
+    // the call panicked).
+    pub(crate) fn attempt(&self, key: &str) -> Option<Attempt<'_>> {
+        let mut circuits = self.lock();
+        let circuit = match circuits.get_mut(key) {
+            Some(circuit) => circuit,
+            None => circuits.entry(key.to_string()).or_insert(Circuit {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+            }),
+        };
+
+        match circuit.state {
+            CircuitState::Closed => {}
+            CircuitState::Open if circuit.opened_at.elapsed() >= self.inner.cooldown => {
+                circuit.state = CircuitState::HalfOpen;
+            }
+            CircuitState::Open | CircuitState::HalfOpen => return None,
+        }
+
+        Some(Attempt {
+            breaker: self,
+            key: key.to_string(),
+            started_at: Instant::now(),
+            finished: false,
+        })
+    }
+
+    // How long until the circuit of `key` lets a probe call through
+    pub(crate) fn retry_after(&self, key: &str) -> Duration {
+        self.lock().get(key).map_or(Duration::ZERO, |c| {
+            self.inner.cooldown.saturating_sub(c.opened_at.elapsed())
+        })
+    }
+
+    fn record(&self, key: &str, success: bool) {
+        let mut circuits = self.lock();
+        let Some(circuit) = circuits.get_mut(key) else {
+            return;
+        };
+
+        if success {
+            circuit.state = CircuitState::Closed;
+            circuit.consecutive_failures = 0;
+            return;
+        }
+
+        circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+        let failed_probe = circuit.state == CircuitState::HalfOpen;
+        if failed_probe || circuit.consecutive_failures >= self.inner.threshold {
+            if circuit.state == CircuitState::Closed {
+                log::warn!(circuit = key, failures = circuit.consecutive_failures; "Circuit opened");
+            }
+            circuit.state = CircuitState::Open;
+            circuit.opened_at = Instant::now();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Circuit>> {
+        sync::lock(&self.inner.circuits)
+    }
+}
+
+// A call going through a circuit
+pub(crate) struct Attempt<'a> {
+    breaker: &'a CircuitBreaker,
+    key: String,
+    started_at: Instant,
+    finished: bool,
+}
+
+impl Attempt<'_> {
+    pub fn finish(mut self, success: bool) {
+        self.finished = true;
+        let slow = self
+            .breaker
+            .inner
+            .slow_call
+            .is_some_and(|limit| self.started_at.elapsed() > limit);
+        self.breaker.record(&self.key, success && !slow);
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.breaker.record(&self.key, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_and_recovers() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        let fail = || Err::<(), ()>(());
+
+        assert_eq!(breaker.call("db", fail), Some(Err(())));
+        assert_eq!(breaker.state("db"), CircuitState::Closed);
+        assert_eq!(breaker.call("db", fail), Some(Err(())));
+        assert_eq!(breaker.state("db"), CircuitState::Open);
+        assert_eq!(breaker.call("db", || Ok::<_, ()>(())), None);
+
+        // Other circuits are not affected
+        assert_eq!(breaker.call("cache", || Ok::<_, ()>(1)), Some(Ok(1)));
+
+        // A failed probe opens the circuit again right away
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(breaker.call("db", fail), Some(Err(())));
+        assert_eq!(breaker.state("db"), CircuitState::Open);
+
+        std::thread::sleep(Duration::from_millis(60));
+        let probe = breaker.attempt("db").unwrap();
+        assert_eq!(breaker.state("db"), CircuitState::HalfOpen);
+        assert!(breaker.attempt("db").is_none());
+        probe.finish(true);
+        assert_eq!(breaker.state("db"), CircuitState::Closed);
+
+        assert_eq!(
+            breaker.states(),
+            BTreeMap::from([
+                ("cache".to_string(), CircuitState::Closed),
+                ("db".to_string(), CircuitState::Closed),
+            ])
+        );
+    }
+
+    #[test]
+    fn slow_calls() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60)).slow_call(Duration::ZERO);
+        let result = breaker.call("slow", || {
+            std::thread::sleep(Duration::from_millis(1));
+            Ok::<_, ()>(())
+        });
+        assert_eq!(result, Some(Ok(())));
+        assert_eq!(breaker.state("slow"), CircuitState::Open);
+    }
+}
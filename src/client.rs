@@ -0,0 +1,712 @@
+use crate::connection::{encode_record, read_packet};
+use crate::context::{Request, Response};
+use crate::error::Error;
+use crate::record::{self, *};
+use crate::status;
+use crate::sync;
+use std::fmt::{self, Display};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The address of a FastCGI server that requests can be forwarded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// A FastCGI server listening on a TCP socket
+    Tcp(SocketAddr),
+    /// A FastCGI server listening on a unix socket
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for Backend {
+    fn from(value: SocketAddr) -> Self {
+        Self::Tcp(value)
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{address}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The reason a request forwarded with a [`Client`] failed
+#[derive(Debug)]
+pub enum ClientError {
+    /// Connecting to the backend failed
+    Connect(io::Error),
+    /// The connection failed while the request was in flight
+    Io(io::Error),
+    /// The backend sent something that is not valid FastCGI (or CGI) output
+    Protocol(String),
+    /// The backend is too busy to handle the request
+    Overloaded,
+    /// The backend refused to handle the request
+    Rejected,
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(err) => write!(f, "Failed to connect to the FastCGI backend: {err}"),
+            Self::Io(err) => write!(f, "The connection to the FastCGI backend failed: {err}"),
+            Self::Protocol(message) => {
+                write!(
+                    f,
+                    "The FastCGI backend sent a malformed response: {message}"
+                )
+            }
+            Self::Overloaded => write!(f, "The FastCGI backend is overloaded"),
+            Self::Rejected => write!(f, "The FastCGI backend rejected the request"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Connect(err) | Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for ClientError {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::UnexpectedSocketClose(err) => Self::Io(err),
+            err => Self::Protocol(err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Self::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Self::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Self::Unix(s) => s.flush(),
+        }
+    }
+}
+
+impl Stream {
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => {
+                s.set_read_timeout(Some(timeout))?;
+                s.set_write_timeout(Some(timeout))
+            }
+            #[cfg(unix)]
+            Self::Unix(s) => {
+                s.set_read_timeout(Some(timeout))?;
+                s.set_write_timeout(Some(timeout))
+            }
+        }
+    }
+
+    // Checks, without blocking, that the backend did not close the connection while it was idle.
+    // An idle connection should not have anything to read either.
+    fn is_healthy(&self) -> bool {
+        let mut byte = [0u8; 1];
+        let peeked = match self {
+            Self::Tcp(s) => s.set_nonblocking(true).and_then(|_| {
+                let peeked = s.peek(&mut byte);
+                s.set_nonblocking(false).and(peeked)
+            }),
+            // `UnixStream::peek` is not stable. Reading is fine: a healthy idle connection has
+            // nothing to read, and an unhealthy one is discarded anyway.
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_nonblocking(true).and_then(|_| {
+                let peeked = (&mut &*s).read(&mut byte);
+                s.set_nonblocking(false).and(peeked)
+            }),
+        };
+        matches!(peeked, Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+    }
+}
+
+#[derive(Debug)]
+struct IdleConnection {
+    stream: Stream,
+    since: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PoolSettings {
+    size: usize,
+    max_idle_time: Duration,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+// How a request exchange with the backend went wrong
+enum Failure {
+    // Nothing was received from the backend, so the request can safely be sent again
+    BeforeResponse(ClientError),
+    // The backend started responding
+    DuringResponse(ClientError),
+}
+
+impl Failure {
+    fn into_error(self) -> ClientError {
+        match self {
+            Self::BeforeResponse(err) | Self::DuringResponse(err) => err,
+        }
+    }
+}
+
+/// A FastCGI client that forwards requests to a backend FastCGI server
+///
+/// Connecting to the backend on every request adds latency, so the client keeps a pool of
+/// connections open and reuses them across requests.
+/// Connections are checked out for the duration of a single request, and are health checked before
+/// being reused.
+///
+/// Clones share the same pool.
+///
+/// ```no_run
+/// use vintage::{status, Client, Response, ServerConfig};
+///
+/// let client = Client::tcp("localhost:9000").unwrap().pool_size(8);
+///
+/// let config = ServerConfig::new()
+///     .unhandled(move |req| {
+///         client
+///             .send(req)
+///             .unwrap_or_else(|_err| Response::default().set_status(status::BAD_GATEWAY))
+///     });
+/// ```
+#[derive(Debug, Clone)]
+pub struct Client {
+    backend: Backend,
+    settings: PoolSettings,
+    idle: Arc<Mutex<Vec<IdleConnection>>>,
+    // Whether the backend agreed to keep connections open.
+    // Some backends (like this crate) refuse to, in which case pooling is pointless.
+    keep_alive: Arc<AtomicBool>,
+}
+
+impl Client {
+    /// Creates a client for the FastCGI server at `backend`
+    pub fn new(backend: impl Into<Backend>) -> Self {
+        Self {
+            backend: backend.into(),
+            settings: PoolSettings {
+                size: 4,
+                max_idle_time: Duration::from_secs(60),
+                connect_timeout: Duration::from_secs(3),
+                timeout: Duration::from_secs(30),
+            },
+            idle: Arc::default(),
+            keep_alive: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Creates a client for the FastCGI server listening at the TCP socket `address`
+    ///
+    /// If `address` yields multiple addresses, only the first one is considered.
+    pub fn tcp(address: impl ToSocketAddrs) -> Result<Self, io::Error> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(Self::new(address))
+    }
+
+    /// Creates a client for the FastCGI server listening at the unix socket `path`
+    #[cfg(unix)]
+    pub fn unix(path: impl AsRef<Path>) -> Self {
+        Self::new(Backend::Unix(path.as_ref().to_path_buf()))
+    }
+
+    /// Sets how many idle connections are kept open. The default is 4.
+    ///
+    /// Setting this to 0 disables pooling; a new connection is opened for every request.
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.settings.size = size;
+        self
+    }
+
+    /// Sets how long a connection may stay idle before it is closed. The default is 60 seconds.
+    pub fn max_idle_time(mut self, duration: Duration) -> Self {
+        self.settings.max_idle_time = duration;
+        self
+    }
+
+    /// Sets how long to wait when connecting to a TCP backend. The default is 3 seconds.
+    pub fn connect_timeout(mut self, duration: Duration) -> Self {
+        self.settings.connect_timeout = duration;
+        self
+    }
+
+    /// Sets how long to wait on each read from and write to the backend. The default is 30
+    /// seconds.
+    pub fn timeout(mut self, duration: Duration) -> Self {
+        self.settings.timeout = duration;
+        self
+    }
+
+    /// Returns the backend this client forwards requests to
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    /// Opens connections until the pool is full, so that the first requests do not pay for
+    /// connecting
+    pub fn warm_up(&self) -> Result<(), ClientError> {
+        loop {
+            let missing = self.settings.size.saturating_sub(self.lock_idle().len());
+            if missing == 0 || !self.keep_alive.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let stream = self.connect()?;
+            self.checkin(stream);
+        }
+    }
+
+    /// Forwards `req` to the backend, and returns its response
+    pub fn send(&self, req: &Request) -> Result<Response, ClientError> {
+        let keep_alive = self.settings.size > 0 && self.keep_alive.load(Ordering::Relaxed);
+
+        if keep_alive {
+            if let Some(mut stream) = self.checkout() {
+                match self.exchange(&mut stream, req, true) {
+                    Ok(Some(response)) => {
+                        self.checkin(stream);
+                        return Ok(response);
+                    }
+                    // The backend might have closed the connection right as it was checked out.
+                    // Try again with a fresh connection.
+                    Ok(None) | Err(Failure::BeforeResponse(_)) => {}
+                    Err(failure) => return Err(failure.into_error()),
+                }
+            }
+        }
+
+        let mut stream = self.connect()?;
+        match self.exchange(&mut stream, req, keep_alive) {
+            Ok(Some(response)) => {
+                if keep_alive {
+                    self.checkin(stream);
+                }
+                Ok(response)
+            }
+            Ok(None) => {
+                log::info!(backend:% = self.backend; "FastCGI backend does not support keep-alive. Connections will not be pooled");
+                self.keep_alive.store(false, Ordering::Relaxed);
+                self.lock_idle().clear();
+                // The backend waits for the refused connection to be closed
+                drop(stream);
+
+                let mut stream = self.connect()?;
+                match self.exchange(&mut stream, req, false) {
+                    Ok(Some(response)) => Ok(response),
+                    Ok(None) => Err(ClientError::Rejected),
+                    Err(failure) => Err(failure.into_error()),
+                }
+            }
+            Err(failure) => Err(failure.into_error()),
+        }
+    }
+
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, Vec<IdleConnection>> {
+        sync::lock(&self.idle)
+    }
+
+    fn connect(&self) -> Result<Stream, ClientError> {
+        let stream = match &self.backend {
+            Backend::Tcp(address) => {
+                TcpStream::connect_timeout(address, self.settings.connect_timeout).map(Stream::Tcp)
+            }
+            #[cfg(unix)]
+            Backend::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+        };
+        let stream = stream.map_err(ClientError::Connect)?;
+        stream
+            .set_timeout(self.settings.timeout)
+            .map_err(ClientError::Connect)?;
+        Ok(stream)
+    }
+
+    // Takes the most recently used healthy connection out of the pool.
+    // Connections that have been idle for too long are closed.
+    fn checkout(&self) -> Option<Stream> {
+        let mut idle = self.lock_idle();
+        let max_idle_time = self.settings.max_idle_time;
+        idle.retain(|c| c.since.elapsed() < max_idle_time);
+
+        while let Some(connection) = idle.pop() {
+            if connection.stream.is_healthy() {
+                return Some(connection.stream);
+            }
+        }
+        None
+    }
+
+    // Returns a connection to the pool, unless the pool is full
+    fn checkin(&self, stream: Stream) {
+        let mut idle = self.lock_idle();
+        if idle.len() < self.settings.size {
+            idle.push(IdleConnection {
+                stream,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    // Sends the request, and reads the response.
+    //
+    // Returns `None` if the request asked to keep the connection open, and the backend refused to.
+    fn exchange(
+        &self,
+        stream: &mut Stream,
+        req: &Request,
+        keep_alive: bool,
+    ) -> Result<Option<Response>, Failure> {
+        let mut bytes = vec![];
+        for record in request_records(req, keep_alive) {
+            // Writing to a `Vec` cannot fail
+            let _ = encode_record(&record, &mut bytes);
+        }
+
+        stream
+            .write_all(&bytes)
+            .and_then(|_| stream.flush())
+            .map_err(|e| Failure::BeforeResponse(ClientError::Io(e)))?;
+
+        let mut reader = BufReader::new(stream);
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        let mut received = false;
+
+        let end = loop {
+            let packet = match read_packet(&mut reader) {
+                Ok(packet) => packet,
+                Err(err) if received => return Err(Failure::DuringResponse(err.into())),
+                Err(err) => return Err(Failure::BeforeResponse(err.into())),
+            };
+            received = true;
+
+            match packet.type_id {
+                record::FCGI_STDOUT => stdout.extend(packet.content),
+                record::FCGI_STDERR => stderr.extend(packet.content),
+                record::FCGI_END_REQUEST => {
+                    break EndRequest::from_record_bytes(packet.content)
+                        .map_err(|e| Failure::DuringResponse(e.into()))?;
+                }
+                t => {
+                    let err = ClientError::Protocol(format!("Unexpected record type: '{t}'"));
+                    return Err(Failure::DuringResponse(err));
+                }
+            }
+        };
+
+        if !stderr.is_empty() {
+            log::warn!(backend:% = self.backend; "FastCGI backend: {}", String::from_utf8_lossy(&stderr));
+        }
+
+        match end.protocol_status() {
+            ProtocolStatus::RequestComplete => {}
+            ProtocolStatus::MultiplexingUnsupported if keep_alive && stdout.is_empty() => {
+                return Ok(None)
+            }
+            ProtocolStatus::Overloaded => {
+                return Err(Failure::DuringResponse(ClientError::Overloaded))
+            }
+            _ => return Err(Failure::DuringResponse(ClientError::Rejected)),
+        }
+
+        parse_cgi_response(stdout)
+            .map(Some)
+            .map_err(Failure::DuringResponse)
+    }
+}
+
+// Converts `req` back into the records a web server would have sent
+fn request_records(req: &Request, keep_alive: bool) -> [Record; 3] {
+    let mut params = Params::default()
+        .add("REQUEST_METHOD", &req.method)
+        .add("PATH_INFO", &req.path)
+        .add("QUERY_STRING", &req.query_string);
+
+    for (key, value) in &req.vars {
+        params = params.add(key, value);
+    }
+
+    for (key, value) in &req.headers {
+        let key = key.to_uppercase().replace('-', "_");
+        params = params.add(format!("HTTP_{key}"), value);
+    }
+
+    [
+        BeginRequest::new(Role::Responder, keep_alive).into(),
+        params.into(),
+        Stdin(req.body.clone()).into(),
+    ]
+}
+
+// Parses the output of a CGI script into a response.
+// https://datatracker.ietf.org/doc/html/rfc3875#section-6
+fn parse_cgi_response(stdout: Vec<u8>) -> Result<Response, ClientError> {
+    let lf = stdout.windows(2).position(|w| w == b"\n\n").map(|i| (i, 2));
+    let crlf = stdout
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| (i, 4));
+
+    let (header_len, separator_len) = match (lf, crlf) {
+        (Some(lf), Some(crlf)) => std::cmp::min_by_key(lf, crlf, |(i, _)| *i),
+        (Some(found), None) | (None, Some(found)) => found,
+        (None, None) => (stdout.len(), 0),
+    };
+
+    let header = std::str::from_utf8(&stdout[..header_len])
+        .map_err(|_| ClientError::Protocol("Response headers are not valid utf8".into()))?;
+
+    let mut response = Response::default();
+    let mut status = None;
+
+    for line in header.lines().filter(|l| !l.is_empty()) {
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(ClientError::Protocol(format!("Malformed header: '{line}'")));
+        };
+        let value = value.trim();
+
+        if key.eq_ignore_ascii_case("Status") {
+            let (code, reason) = value.split_once(' ').unwrap_or((value, ""));
+            let Ok(code) = code.parse::<u16>() else {
+                return Err(ClientError::Protocol(format!(
+                    "Malformed status: '{value}'"
+                )));
+            };
+            let reason = reason.trim();
+            let valid_reason = !reason.is_empty() && !reason.contains('\0');
+            status = Some((code, valid_reason.then(|| reason.to_string())));
+        } else if key.eq_ignore_ascii_case("Set-Cookie") && response.has_header(key) {
+            // Other headers have a single value, but every cookie has its own header
+            response.cookies.push(value.to_string());
+        } else {
+            response = response
+                .try_set_header(key, value)
+                .map_err(|err| ClientError::Protocol(err.to_string()))?;
+        }
+    }
+
+    // A script that only sets the location is asking for a redirect
+    let response = match status {
+        Some((code, Some(reason))) => response.set_status_with_reason(code, reason),
+        Some((code, None)) => response.set_status(code),
+        None if response.headers.contains_key("Location") => response.set_status(status::FOUND),
+        None => response.set_status(status::OK),
+    };
+
+    let body = stdout[(header_len + separator_len).min(stdout.len())..].to_vec();
+
+    Ok(response.set_raw_body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use crate::ServerConfig;
+    use assert_matches::assert_matches;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    fn request(path: &str) -> Request {
+        Request {
+            method: "GET".into(),
+            path: path.into(),
+            ..Request::default()
+        }
+    }
+
+    // A backend that keeps connections open for `requests_per_connection` requests.
+    // Returns its address and the number of connections it accepted so far.
+    fn keep_alive_backend(requests_per_connection: usize) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        thread::spawn({
+            let accepted = accepted.clone();
+            move || {
+                for stream in listener.incoming() {
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                    let stream = mio::net::TcpStream::from_std(stream.unwrap());
+                    let mut conn = Connection::try_from(stream).unwrap();
+                    thread::spawn(move || {
+                        for _ in 0..requests_per_connection {
+                            let Ok(Record::BeginRequest(_)) = conn.read_record() else {
+                                return;
+                            };
+                            let Ok(Record::Params(mut params)) = conn.read_record() else {
+                                return;
+                            };
+                            let Ok(Record::Stdin(_)) = conn.read_record() else {
+                                return;
+                            };
+                            let path = params.take().remove("PATH_INFO").unwrap_or_default();
+                            let stdout = Stdout(format!("Status: 200\n\n{path}").into_bytes());
+                            let end = EndRequest::new(0, ProtocolStatus::RequestComplete);
+                            let _ = conn.write_record(&stdout.into());
+                            let _ = conn.write_record(&end.into());
+                        }
+                    });
+                }
+            }
+        });
+
+        (address, accepted)
+    }
+
+    #[test]
+    fn reuses_connections() {
+        let (address, accepted) = keep_alive_backend(usize::MAX);
+        let client = Client::new(address);
+
+        for path in ["/one", "/two", "/three"] {
+            let response = client.send(&request(path)).unwrap();
+            assert_eq!(response.body, path.as_bytes());
+        }
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn discards_closed_connections() {
+        let (address, accepted) = keep_alive_backend(1);
+        let client = Client::new(address);
+
+        for path in ["/one", "/two"] {
+            // Give the backend time to close the connection
+            thread::sleep(Duration::from_millis(50));
+            let response = client.send(&request(path)).unwrap();
+            assert_eq!(response.body, path.as_bytes());
+        }
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn evicts_idle_connections() {
+        let (address, accepted) = keep_alive_backend(usize::MAX);
+        let client = Client::new(address).max_idle_time(Duration::ZERO);
+
+        client.send(&request("/one")).unwrap();
+        client.send(&request("/two")).unwrap();
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn warm_up() {
+        let (address, accepted) = keep_alive_backend(usize::MAX);
+        let client = Client::new(address).pool_size(3);
+
+        client.warm_up().unwrap();
+        // Connections complete before the backend gets around to accepting them
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+
+        client.send(&request("/one")).unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn backend_without_keep_alive() {
+        let config = ServerConfig::new().on_get(["/hello/{name}"], |_req, params| {
+            Response::text(format!("Hello {}", params["name"])).set_header("X-Custom", "1")
+        });
+        let server = crate::start(config, "localhost:0").unwrap();
+        let client = Client::new(server.address());
+
+        for _ in 0..2 {
+            let response = client.send(&request("/hello/world")).unwrap();
+            assert_eq!(response.status, status::OK);
+            assert_eq!(response.headers["Content-Type"], "text/plain");
+            assert_eq!(response.headers["X-Custom"], "1");
+            assert_eq!(response.body, b"Hello world");
+        }
+
+        assert!(!client.keep_alive.load(Ordering::Relaxed));
+        server.stop();
+    }
+
+    #[test]
+    fn connect_error() {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let result = Client::new(address).send(&request("/"));
+        assert_matches!(result, Err(ClientError::Connect(_)));
+    }
+
+    #[test]
+    fn cgi_response() {
+        let response = parse_cgi_response(b"Status: 404 Not Found\r\nX-A: b\r\n\r\nbody".to_vec());
+        let response = response.unwrap();
+        assert_eq!(response.status, status::NOT_FOUND);
+        assert_eq!(response.reason.as_deref(), Some("Not Found"));
+        assert_eq!(response.headers["X-A"], "b");
+        assert_eq!(response.body, b"body");
+
+        let response = parse_cgi_response(b"Set-Cookie: a=1\nSet-Cookie: b=2\n\n".to_vec());
+        let response = response.unwrap();
+        assert_eq!(response.headers["Set-Cookie"], "a=1");
+        assert_eq!(response.cookies, ["b=2"]);
+
+        let response = parse_cgi_response(b"Location: /elsewhere\n\n".to_vec()).unwrap();
+        assert_eq!(response.status, status::FOUND);
+        assert_eq!(response.reason, None);
+        assert!(response.body.is_empty());
+
+        let response = parse_cgi_response(b"Content-Type: text/plain".to_vec()).unwrap();
+        assert_eq!(response.status, status::OK);
+
+        assert_matches!(
+            parse_cgi_response(b"garbage\n\n".to_vec()),
+            Err(ClientError::Protocol(_))
+        );
+    }
+}
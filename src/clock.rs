@@ -0,0 +1,141 @@
+use crate::sync;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Where the server gets the current time from
+///
+/// The server reads it to date responses (the `Date` header), to timestamp access log entries,
+/// to measure how long requests take, and to expire the responses kept by [`Idempotency`].
+/// Tests can set a [`TestClock`] with [`ServerConfig::clock`] to control all of these.
+///
+/// Timers that make the server wait (e.g. schedules and timeouts) always use the real time.
+///
+/// [`Idempotency`]: crate::Idempotency
+/// [`ServerConfig::clock`]: crate::ServerConfig::clock
+pub trait Clock: Send + Sync {
+    /// Returns the current date and time
+    fn now(&self) -> SystemTime;
+
+    /// Returns the current instant, to measure durations with
+    fn instant(&self) -> Instant;
+}
+
+/// The clock of the system. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to
+///
+/// Clones share the same time, so a test can keep one to advance the clock of a running server.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use vintage::{Clock, ServerConfig, TestClock};
+///
+/// let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+/// let config = ServerConfig::new().clock(clock.clone());
+///
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(clock.now(), SystemTime::UNIX_EPOCH + Duration::from_secs(60));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TestClock {
+    inner: Arc<TestClockState>,
+}
+
+#[derive(Debug)]
+struct TestClockState {
+    // The real instant at which the clock was created, since `Instant`s can only be made by
+    // adding to another one
+    start: Instant,
+    time: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl TestClock {
+    /// Creates a clock stopped at `time`
+    pub fn new(time: SystemTime) -> Self {
+        Self {
+            inner: Arc::new(TestClockState {
+                start: Instant::now(),
+                time,
+                elapsed: Mutex::new(Duration::ZERO),
+            }),
+        }
+    }
+
+    /// Moves the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed() += duration;
+    }
+
+    fn elapsed(&self) -> std::sync::MutexGuard<'_, Duration> {
+        sync::lock(&self.inner.elapsed)
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> SystemTime {
+        self.inner.time + *self.elapsed()
+    }
+
+    fn instant(&self) -> Instant {
+        self.inner.start + *self.elapsed()
+    }
+}
+
+// The clock of a server, shared by its configuration and its requests
+#[derive(Clone)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self(Arc::new(clock))
+    }
+
+    // How much time passed since `earlier`
+    pub fn since(&self, earlier: Instant) -> Duration {
+        self.instant().saturating_duration_since(earlier)
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+// Requests compare equal whatever clock they use
+impl PartialEq for SharedClock {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SharedClock {}
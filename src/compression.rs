@@ -0,0 +1,273 @@
+use crate::context::{same_header_name, Request, Response};
+use crate::headers;
+use crate::status;
+use flate2::write::GzEncoder;
+use std::io::Write;
+
+// The suffix of the entity tags of compressed bodies
+const ETAG_SUFFIX: &str = "-gzip";
+
+// Request headers that hold entity tags, which may have been given the suffix
+const CONDITIONAL_HEADERS: &[&str] =
+    &[headers::IF_MATCH, headers::IF_NONE_MATCH, headers::IF_RANGE];
+
+// Content types that are worth compressing. Most other types (e.g. images) already are.
+const COMPRESSIBLE_TYPES: &[&str] = &[
+    "application/javascript",
+    "application/json",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// Gzip compression of generated responses
+///
+/// Responses produced by route handlers, middleware, the [`ServerConfig::unhandled`] callback
+/// and the server itself (e.g. the default `404` page) are compressed when the client accepts it.
+/// Static files and assets are served as they are on disk.
+///
+/// Only bodies of textual content types that are at least [`Compression::min_size`] bytes long
+/// are compressed.
+/// Caching headers are adjusted to match: `Vary: Accept-Encoding` is added, and entity tags are
+/// given a `-gzip` suffix so they don't collide with those of the uncompressed body.
+/// The suffix is removed from the entity tags of conditional request headers (e.g.
+/// `If-None-Match`), so handlers can compare them with the tags they produce.
+///
+/// See [`ServerConfig::compression`]
+///
+/// [`ServerConfig::unhandled`]: crate::ServerConfig::unhandled
+/// [`ServerConfig::compression`]: crate::ServerConfig::compression
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: usize,
+    level: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            level: 6,
+        }
+    }
+}
+
+impl Compression {
+    /// Creates the default compression settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size under which bodies are not compressed. The default is 1024 bytes.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Sets the compression level, from 0 (none) to 9 (best). The default is 6.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = level.min(9);
+        self
+    }
+
+    // Removes the suffix given to entity tags by `compress()` from the conditional headers of
+    // `req`, so they match the entity tags of the uncompressed body
+    pub(crate) fn strip_etag_suffixes(&self, req: &mut Request) {
+        for (name, value) in req.headers.iter_mut() {
+            if !CONDITIONAL_HEADERS
+                .iter()
+                .any(|header| same_header_name(name, header))
+            {
+                continue;
+            }
+            if !value.contains(ETAG_SUFFIX) {
+                continue;
+            }
+
+            // e.g. If-None-Match: "v1-gzip", W/"v2-gzip"
+            *value = value
+                .split(',')
+                .map(|tag| {
+                    let tag = tag.trim();
+                    match tag
+                        .strip_suffix('"')
+                        .and_then(|t| t.strip_suffix(ETAG_SUFFIX))
+                    {
+                        Some(tag) => format!("{tag}\""),
+                        None => tag.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+        }
+    }
+
+    /// Compresses `response` if it is eligible, and `req` accepts gzip
+    pub fn compress(&self, req: &Request, mut response: Response) -> Response {
+        if !self.is_eligible(&response) {
+            return response;
+        }
+
+        // The body depends on `Accept-Encoding` whether or not this client accepts gzip
+        let vary = match response.headers.get(headers::VARY) {
+            Some(vary) if vary.split(',').any(|v| v.trim() == "*") => return response,
+            Some(vary) => format!("{vary}, Accept-Encoding"),
+            None => "Accept-Encoding".to_string(),
+        };
+        response = response.set_header(headers::VARY, vary);
+
+        if !req
+            .header(headers::ACCEPT_ENCODING)
+            .is_some_and(accepts_gzip)
+        {
+            return response;
+        }
+
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::new(self.level));
+        let compressed = match encoder
+            .write_all(&response.body)
+            .and_then(|_| encoder.finish())
+        {
+            Ok(compressed) => compressed,
+            Err(err) => {
+                log::warn!(error:err = err; "Failed to compress response");
+                return response;
+            }
+        };
+
+        if let Some(etag) = response.headers.get(headers::ETAG) {
+            let etag = match etag.strip_suffix('"') {
+                Some(tag) => format!("{tag}{ETAG_SUFFIX}\""),
+                None => etag.clone(),
+            };
+            response = response.set_header(headers::ETAG, etag);
+        }
+
+        response
+            .set_header(headers::CONTENT_ENCODING, "gzip")
+            .set_raw_body(compressed)
+    }
+
+    fn is_eligible(&self, response: &Response) -> bool {
+        if response.stream.is_some()
+            || response.body.len() < self.min_size
+            || response.headers.contains_key(headers::CONTENT_ENCODING)
+            || matches!(
+                response.status,
+                status::NO_CONTENT | status::PARTIAL_CONTENT | status::NOT_MODIFIED
+            )
+        {
+            return false;
+        }
+
+        let Some(mime) = response.media_type() else {
+            return false;
+        };
+
+        mime.starts_with("text/") || COMPRESSIBLE_TYPES.contains(&mime.as_str())
+    }
+}
+
+// Checks whether an `Accept-Encoding` header value allows gzip
+fn accepts_gzip(header: &str) -> bool {
+    let mut wildcard = false;
+
+    for entry in header.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default();
+        let quality: f32 = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse().ok())
+            .unwrap_or(1.0);
+
+        if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            return quality > 0.0;
+        }
+        if coding == "*" {
+            wildcard = quality > 0.0;
+        }
+    }
+
+    wildcard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn request(accept_encoding: &str) -> Request {
+        Request {
+            headers: [("Accept-Encoding".to_string(), accept_encoding.to_string())].into(),
+            ..Request::default()
+        }
+    }
+
+    fn gunzip(bytes: &[u8]) -> String {
+        let mut body = String::new();
+        GzDecoder::new(bytes).read_to_string(&mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn compress() {
+        let compression = Compression::new();
+        let body = "<p>hello</p>".repeat(100);
+        let response = Response::html(body.clone()).set_header("ETag", r#""v1""#);
+
+        let compressed = compression.compress(&request("deflate, gzip"), response.clone());
+        assert_eq!(compressed.headers["Content-Encoding"], "gzip");
+        assert_eq!(compressed.headers["Vary"], "Accept-Encoding");
+        assert_eq!(compressed.headers["ETag"], r#""v1-gzip""#);
+        assert_eq!(gunzip(&compressed.body), body);
+
+        let uncompressed = compression.compress(&request("gzip;q=0, *"), response.clone());
+        assert_eq!(uncompressed.headers.get("Content-Encoding"), None);
+        assert_eq!(uncompressed.headers["Vary"], "Accept-Encoding");
+        assert_eq!(uncompressed.headers["ETag"], r#""v1""#);
+        assert_eq!(uncompressed.body, body.as_bytes());
+
+        let compressed = compression.compress(&request("br;q=1, *;q=0.5"), response);
+        assert_eq!(compressed.headers["Content-Encoding"], "gzip");
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let compression = Compression::new();
+        let mut req = Request {
+            headers: [
+                ("if-none-match", r#""v1-gzip", W/"v2-gzip", "v3""#),
+                ("If-Range", r#""v1-gzip""#),
+                ("If-Match", "*"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .into(),
+            ..Request::default()
+        };
+        compression.strip_etag_suffixes(&mut req);
+        assert_eq!(req.header("If-None-Match"), Some(r#""v1", W/"v2", "v3""#));
+        assert_eq!(req.header("If-Range"), Some(r#""v1""#));
+        assert_eq!(req.header("If-Match"), Some("*"));
+    }
+
+    #[test]
+    fn ineligible_responses() {
+        let compression = Compression::new();
+        let req = request("gzip");
+        let large = "a".repeat(2000);
+
+        let small = Response::text("hello");
+        assert_eq!(compression.compress(&req, small.clone()), small);
+
+        let binary = Response::new()
+            .set_header("Content-Type", "image/png")
+            .set_body(large.clone());
+        assert_eq!(compression.compress(&req, binary.clone()), binary);
+
+        let encoded = Response::text(large.clone()).set_header("Content-Encoding", "br");
+        assert_eq!(compression.compress(&req, encoded.clone()), encoded);
+
+        let partial = Response::text(large).set_status(status::PARTIAL_CONTENT);
+        assert_eq!(compression.compress(&req, partial.clone()), partial);
+    }
+}
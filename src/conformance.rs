@@ -0,0 +1,471 @@
+//! A protocol conformance suite for FastCGI servers
+//!
+//! The suite acts as a FastCGI client, and runs a battery of scenarios derived from the
+//! [spec](https://www.mit.edu/~yandros/doc/specs/fcgi-spec.html) against a running server.
+//! Each scenario sends requests that are valid, but that real FastCGI clients rarely produce (e.g.
+//! unusual padding, or params split across many records), and checks that the response is well
+//! formed.
+//!
+//! The scenarios only rely on the server answering `GET /`, so they can be pointed at any FastCGI
+//! server, not just ones written with this crate.
+//!
+//! ```
+//! use vintage::{Response, ServerConfig};
+//!
+//! let config = ServerConfig::new().unhandled(|_req| Response::text("hello"));
+//! let server = vintage::start(config, "localhost:0").unwrap();
+//!
+//! let report = vintage::conformance::run(server.address()).unwrap();
+//! assert!(report.passed(), "{report}");
+//!
+//! server.stop();
+//! ```
+
+use crate::record::{self, pairs};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+// How long to wait on the server before failing a scenario
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+// The request id used for application records.
+// Management records always use 0.
+const REQUEST_ID: u16 = 1;
+
+/// The outcome of a single scenario
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioResult {
+    /// A short, unique name for the scenario
+    pub name: &'static str,
+    /// What the scenario checks
+    pub description: &'static str,
+    /// Why the scenario failed, if it did
+    pub failure: Option<String>,
+}
+
+/// The outcome of running the conformance suite
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The outcome of every scenario, in the order they ran
+    pub results: Vec<ScenarioResult>,
+}
+
+impl Report {
+    /// Returns true if every scenario passed
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.failure.is_none())
+    }
+
+    /// Returns the scenarios that failed
+    pub fn failures(&self) -> impl Iterator<Item = &ScenarioResult> {
+        self.results.iter().filter(|r| r.failure.is_some())
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.failure {
+                None => writeln!(f, "PASS {}", result.name)?,
+                Some(failure) => writeln!(f, "FAIL {}: {failure}", result.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Scenario {
+    name: &'static str,
+    description: &'static str,
+    run: fn(SocketAddr) -> Result<(), String>,
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "minimal_request",
+        description: "A request with minimal padding and an empty stdin stream",
+        run: minimal_request,
+    },
+    Scenario {
+        name: "no_padding",
+        description: "Records that are not padded to a multiple of 8 bytes",
+        run: no_padding,
+    },
+    Scenario {
+        name: "maximum_padding",
+        description: "Records that carry 255 bytes of padding",
+        run: maximum_padding,
+    },
+    Scenario {
+        name: "split_params",
+        description: "Params split across one record per byte",
+        run: split_params,
+    },
+    Scenario {
+        name: "split_stdin",
+        description: "A request body split across many records",
+        run: split_stdin,
+    },
+    Scenario {
+        name: "fragmented_writes",
+        description: "A request written one byte at a time",
+        run: fragmented_writes,
+    },
+    Scenario {
+        name: "delayed_stdin",
+        description: "A pause between the params and stdin streams",
+        run: delayed_stdin,
+    },
+    Scenario {
+        name: "get_values",
+        description: "An FCGI_GET_VALUES query answered with FCGI_GET_VALUES_RESULT",
+        run: get_values,
+    },
+    Scenario {
+        name: "unknown_management_record",
+        description: "An unknown management record answered with FCGI_UNKNOWN_TYPE",
+        run: unknown_management_record,
+    },
+    Scenario {
+        name: "unknown_role",
+        description: "An unknown role answered with FCGI_UNKNOWN_ROLE",
+        run: unknown_role,
+    },
+];
+
+/// Runs every scenario against the FastCGI server at `address`
+///
+/// If `address` yields multiple addresses, only the first one is considered.
+/// Each scenario opens its own connection.
+pub fn run(address: impl ToSocketAddrs) -> Result<Report, io::Error> {
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+
+    let results = SCENARIOS
+        .iter()
+        .map(|scenario| ScenarioResult {
+            name: scenario.name,
+            description: scenario.description,
+            failure: (scenario.run)(address).err(),
+        })
+        .collect();
+
+    Ok(Report { results })
+}
+
+#[derive(Debug)]
+struct Packet {
+    type_id: u8,
+    request_id: u16,
+    content: Vec<u8>,
+}
+
+fn connect(address: SocketAddr) -> Result<TcpStream, String> {
+    let stream = TcpStream::connect_timeout(&address, TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    Ok(stream)
+}
+
+// Encodes a single packet with exactly `padding` bytes of padding
+fn encode(type_id: u8, request_id: u16, content: &[u8], padding: u8) -> Vec<u8> {
+    let mut bytes = vec![1, type_id];
+    bytes.extend(request_id.to_be_bytes());
+    bytes.extend((content.len() as u16).to_be_bytes());
+    bytes.extend([padding, 0]);
+    bytes.extend(content);
+    bytes.extend(vec![0; padding as usize]);
+    bytes
+}
+
+// How padding is chosen for each packet
+#[derive(Clone, Copy)]
+enum Padding {
+    // Pads to the next multiple of 8, as recommended by the spec
+    Aligned,
+    None,
+    Maximum,
+}
+
+impl Padding {
+    fn for_content(self, content: &[u8]) -> u8 {
+        match self {
+            Self::Aligned => ((8 - content.len() % 8) % 8) as u8,
+            Self::None => 0,
+            Self::Maximum => u8::MAX,
+        }
+    }
+}
+
+// Builds the bytes of a `GET /` request, splitting the params and stdin streams into chunks of
+// at most `chunk_size` bytes
+fn request_bytes(padding: Padding, chunk_size: usize, body: &[u8]) -> Vec<u8> {
+    let mut params = BTreeMap::new();
+    for (key, value) in [
+        ("REQUEST_METHOD", "GET"),
+        ("PATH_INFO", "/"),
+        ("QUERY_STRING", ""),
+        ("REQUEST_URI", "/"),
+        ("SCRIPT_NAME", ""),
+        ("SERVER_PROTOCOL", "HTTP/1.1"),
+        ("GATEWAY_INTERFACE", "CGI/1.1"),
+        ("CONTENT_LENGTH", &body.len().to_string()),
+    ] {
+        params.insert(key.to_string(), value.to_string());
+    }
+    let mut params_bytes = vec![];
+    // Writing to a `Vec` cannot fail
+    let _ = pairs::to_record_bytes(&params, &mut params_bytes);
+
+    let mut bytes = vec![];
+    let mut push = |type_id: u8, content: &[u8]| {
+        bytes.extend(encode(
+            type_id,
+            REQUEST_ID,
+            content,
+            padding.for_content(content),
+        ));
+    };
+
+    // Role = Responder, Flags = 0 (Close the connection when done)
+    push(record::FCGI_BEGIN_REQUEST, &[0, 1, 0, 0, 0, 0, 0, 0]);
+    for chunk in params_bytes.chunks(chunk_size) {
+        push(record::FCGI_PARAMS, chunk);
+    }
+    push(record::FCGI_PARAMS, &[]);
+    for chunk in body.chunks(chunk_size) {
+        push(record::FCGI_STDIN, chunk);
+    }
+    push(record::FCGI_STDIN, &[]);
+
+    bytes
+}
+
+fn read_packet(stream: &mut TcpStream) -> Result<Packet, String> {
+    let mut header = [0u8; 8];
+    stream
+        .read_exact(&mut header)
+        .map_err(|e| format!("Failed to read record header: {e}"))?;
+
+    let [version, type_id, id_1, id_0, length_1, length_0, padding, _] = header;
+    if version != 1 {
+        return Err(format!("Unexpected version: {version}"));
+    }
+
+    let mut content = vec![0u8; u16::from_be_bytes([length_1, length_0]) as usize];
+    let mut padding = vec![0u8; padding as usize];
+    stream
+        .read_exact(&mut content)
+        .and_then(|_| stream.read_exact(&mut padding))
+        .map_err(|e| format!("Failed to read record content: {e}"))?;
+
+    Ok(Packet {
+        type_id,
+        request_id: u16::from_be_bytes([id_1, id_0]),
+        content,
+    })
+}
+
+// Reads a response to a request, checking that:
+// + Every record has the right request id
+// + The stdout stream is non-empty, and terminated before the end of the request
+// + The request completed
+fn expect_response(stream: &mut TcpStream) -> Result<(), String> {
+    let mut stdout_len = 0;
+    let mut stdout_closed = false;
+
+    loop {
+        let packet = read_packet(stream)?;
+        if packet.request_id != REQUEST_ID {
+            return Err(format!("Unexpected request id: {}", packet.request_id));
+        }
+
+        match packet.type_id {
+            record::FCGI_STDOUT if stdout_closed => {
+                return Err("Stdout record after the stdout stream was closed".into())
+            }
+            record::FCGI_STDOUT if packet.content.is_empty() => stdout_closed = true,
+            record::FCGI_STDOUT => stdout_len += packet.content.len(),
+            record::FCGI_STDERR => {}
+            record::FCGI_END_REQUEST => {
+                if !stdout_closed {
+                    return Err("The stdout stream was not closed".into());
+                }
+                if stdout_len == 0 {
+                    return Err("The response was empty".into());
+                }
+                return expect_protocol_status(&packet, 0);
+            }
+            t => return Err(format!("Unexpected record type: {t}")),
+        }
+    }
+}
+
+fn expect_protocol_status(packet: &Packet, expected: u8) -> Result<(), String> {
+    match packet.content.get(4) {
+        Some(status) if *status == expected => Ok(()),
+        Some(status) => Err(format!("Expected protocol status {expected}, got {status}")),
+        None => Err("Malformed FCGI_END_REQUEST record".into()),
+    }
+}
+
+fn send(stream: &mut TcpStream, bytes: &[u8]) -> Result<(), String> {
+    stream
+        .write_all(bytes)
+        .map_err(|e| format!("Failed to send request: {e}"))
+}
+
+fn exchange(address: SocketAddr, bytes: &[u8]) -> Result<(), String> {
+    let mut stream = connect(address)?;
+    send(&mut stream, bytes)?;
+    expect_response(&mut stream)
+}
+
+fn minimal_request(address: SocketAddr) -> Result<(), String> {
+    exchange(address, &request_bytes(Padding::Aligned, usize::MAX, &[]))
+}
+
+fn no_padding(address: SocketAddr) -> Result<(), String> {
+    exchange(address, &request_bytes(Padding::None, usize::MAX, b"hello"))
+}
+
+fn maximum_padding(address: SocketAddr) -> Result<(), String> {
+    exchange(
+        address,
+        &request_bytes(Padding::Maximum, usize::MAX, b"hello"),
+    )
+}
+
+fn split_params(address: SocketAddr) -> Result<(), String> {
+    exchange(address, &request_bytes(Padding::Aligned, 1, &[]))
+}
+
+fn split_stdin(address: SocketAddr) -> Result<(), String> {
+    let body = vec![b'A'; 4096];
+    exchange(address, &request_bytes(Padding::Aligned, 100, &body))
+}
+
+fn fragmented_writes(address: SocketAddr) -> Result<(), String> {
+    let mut stream = connect(address)?;
+    stream.set_nodelay(true).map_err(|e| e.to_string())?;
+    for byte in request_bytes(Padding::Aligned, usize::MAX, b"hello") {
+        send(&mut stream, &[byte])?;
+    }
+    expect_response(&mut stream)
+}
+
+fn delayed_stdin(address: SocketAddr) -> Result<(), String> {
+    let bytes = request_bytes(Padding::Aligned, usize::MAX, &[]);
+    // The empty stdin record is the last packet
+    let (head, tail) = bytes.split_at(bytes.len() - 8);
+
+    let mut stream = connect(address)?;
+    send(&mut stream, head)?;
+    thread::sleep(Duration::from_millis(500));
+    send(&mut stream, tail)?;
+    expect_response(&mut stream)
+}
+
+fn get_values(address: SocketAddr) -> Result<(), String> {
+    let mut query = BTreeMap::new();
+    query.insert("FCGI_MPXS_CONNS".to_string(), String::new());
+    let mut content = vec![];
+    let _ = pairs::to_record_bytes(&query, &mut content);
+
+    let mut stream = connect(address)?;
+    send(
+        &mut stream,
+        &encode(record::FCGI_GET_VALUES, 0, &content, 0),
+    )?;
+
+    let packet = read_packet(&mut stream)?;
+    if packet.type_id != record::FCGI_GET_VALUES_RESULT {
+        return Err(format!("Unexpected record type: {}", packet.type_id));
+    }
+    if packet.request_id != 0 {
+        return Err(format!("Unexpected request id: {}", packet.request_id));
+    }
+    pairs::from_record_bytes(packet.content)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn unknown_management_record(address: SocketAddr) -> Result<(), String> {
+    let mut stream = connect(address)?;
+    send(&mut stream, &encode(200, 0, &[], 0))?;
+
+    let packet = read_packet(&mut stream)?;
+    if packet.type_id != record::FCGI_UNKNOWN_TYPE {
+        return Err(format!("Unexpected record type: {}", packet.type_id));
+    }
+    match packet.content.first() {
+        Some(200) => Ok(()),
+        _ => Err("FCGI_UNKNOWN_TYPE did not echo the unknown type".into()),
+    }
+}
+
+fn unknown_role(address: SocketAddr) -> Result<(), String> {
+    let mut stream = connect(address)?;
+    let begin = encode(
+        record::FCGI_BEGIN_REQUEST,
+        REQUEST_ID,
+        &[0, 99, 0, 0, 0, 0, 0, 0],
+        0,
+    );
+    send(&mut stream, &begin)?;
+
+    let packet = read_packet(&mut stream)?;
+    if packet.type_id != record::FCGI_END_REQUEST {
+        return Err(format!("Unexpected record type: {}", packet.type_id));
+    }
+    // FCGI_UNKNOWN_ROLE
+    expect_protocol_status(&packet, 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Response, ServerConfig};
+    use std::net::TcpListener;
+
+    #[test]
+    fn vintage_conforms() {
+        let config = ServerConfig::new().unhandled(|req| Response::text(req.path()));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let report = run(server.address()).unwrap();
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.results.len(), SCENARIOS.len());
+
+        server.stop();
+    }
+
+    #[test]
+    fn reports_failures() {
+        // Nothing listens on this address
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let result = unknown_role(address);
+        assert!(result.is_err());
+
+        let report = Report {
+            results: vec![ScenarioResult {
+                name: "unknown_role",
+                description: "",
+                failure: result.err(),
+            }],
+        };
+        assert!(!report.passed());
+        assert_eq!(report.failures().count(), 1);
+        assert!(report.to_string().starts_with("FAIL unknown_role: "));
+    }
+}
@@ -1,21 +1,177 @@
+use crate::capture::{CaptureWriter, Direction};
 use crate::error::Error;
+use crate::memory_budget::Reservation;
+#[cfg(windows)]
+use crate::named_pipe::PipeStream;
+use crate::record::pairs::{self, PairLimits};
 use crate::record::{self, *};
-#[cfg(test)]
+#[cfg(feature = "tls")]
+use crate::tls::{ClientIdentity, TlsConfig, TlsStream};
 use std::collections::VecDeque;
 use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+// The size of the read and write buffers of connections, unless configured otherwise.
+// Same as the default of `BufReader` and `BufWriter`.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+// How much input `Connection::close_gracefully()` discards before giving up on the client
+const MAX_DISCARDED_BYTES: u64 = 1024 * 1024;
 
 #[derive(Debug)]
 pub enum Connection {
-    Tcp(BufReader<TcpStream>, BufWriter<TcpStream>),
+    Socket(BufReader<Stream>, BufWriter<Stream>, Option<CaptureWriter>),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream>, Option<CaptureWriter>),
     #[cfg(test)]
     Test(VecDeque<u8>),
 }
 
+// The socket of an accepted connection, in blocking mode
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(windows)]
+    NamedPipe(PipeStream),
+}
+
+impl Stream {
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(s) => s.try_clone().map(Self::Tcp),
+            #[cfg(unix)]
+            Self::Unix(s) => s.try_clone().map(Self::Unix),
+            #[cfg(windows)]
+            Self::NamedPipe(s) => Ok(Self::NamedPipe(s.clone())),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_nonblocking(nonblocking),
+            #[cfg(windows)]
+            Self::NamedPipe(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_read_timeout(timeout),
+            #[cfg(windows)]
+            Self::NamedPipe(s) => s.set_read_timeout(timeout),
+        }
+    }
+
+    // Disables Nagle's algorithm, so that small writes are sent right away.
+    // Unix sockets and pipes don't delay small writes.
+    fn set_nodelay(&self) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_nodelay(true),
+            #[cfg(unix)]
+            Self::Unix(_) => Ok(()),
+            #[cfg(windows)]
+            Self::NamedPipe(_) => Ok(()),
+        }
+    }
+
+    // Peeks at the next bytes without consuming them.
+    // Returns 0 if the peer closed the connection.
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(s) => s.peek(buf),
+            // `UnixStream::peek` is not stable
+            #[cfg(unix)]
+            Self::Unix(s) => {
+                use std::os::fd::AsRawFd;
+                // SAFETY: `buf` is valid for writes of `buf.len()` bytes
+                let peeked = unsafe {
+                    libc::recv(
+                        s.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        libc::MSG_PEEK,
+                    )
+                };
+                usize::try_from(peeked).map_err(|_| io::Error::last_os_error())
+            }
+            #[cfg(windows)]
+            Self::NamedPipe(s) => s.peek(buf),
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.shutdown(how),
+            #[cfg(unix)]
+            Self::Unix(s) => s.shutdown(how),
+            // Pipes can't be half-closed
+            #[cfg(windows)]
+            Self::NamedPipe(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Self::Unix(s) => s.read(buf),
+            #[cfg(windows)]
+            Self::NamedPipe(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Self::Unix(s) => s.write(buf),
+            #[cfg(windows)]
+            Self::NamedPipe(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Self::Unix(s) => s.flush(),
+            #[cfg(windows)]
+            Self::NamedPipe(s) => s.flush(),
+        }
+    }
+}
+
 impl Write for Connection {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         match self {
-            Connection::Tcp(_, w) => w.write(buf),
+            Connection::Socket(_, w, capture) => {
+                let n = w.write(buf)?;
+                if let Some(capture) = capture {
+                    capture.record(Direction::Outbound, &buf[..n]);
+                }
+                Ok(n)
+            }
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls, capture) => {
+                let n = tls.write(buf)?;
+                if let Some(capture) = capture {
+                    capture.record(Direction::Outbound, &buf[..n]);
+                }
+                Ok(n)
+            }
             #[cfg(test)]
             Connection::Test(w) => w.write(buf),
         }
@@ -23,7 +179,9 @@ impl Write for Connection {
 
     fn flush(&mut self) -> std::io::Result<()> {
         match self {
-            Connection::Tcp(_, w) => w.flush(),
+            Connection::Socket(_, w, _) => w.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls, _) => tls.flush(),
             #[cfg(test)]
             Connection::Test(w) => w.flush(),
         }
@@ -33,30 +191,281 @@ impl Write for Connection {
 impl Read for Connection {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         match self {
-            Connection::Tcp(r, _) => r.read(buf),
+            Connection::Socket(r, _, capture) => {
+                let n = r.read(buf)?;
+                if let Some(capture) = capture {
+                    capture.record(Direction::Inbound, &buf[..n]);
+                }
+                Ok(n)
+            }
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls, capture) => {
+                let n = tls.read(buf)?;
+                if let Some(capture) = capture {
+                    capture.record(Direction::Inbound, &buf[..n]);
+                }
+                Ok(n)
+            }
             #[cfg(test)]
             Connection::Test(r) => r.read(buf),
         }
     }
 }
 
+impl Connection {
+    // Sets up a connection whose read and write buffers hold `read_buffer` and `write_buffer`
+    // bytes
+    pub fn with_buffer_sizes(
+        stream: Stream,
+        read_buffer: usize,
+        write_buffer: usize,
+    ) -> io::Result<Self> {
+        set_blocking(&stream)?;
+        let writer = stream.try_clone()?;
+        Ok(Connection::Socket(
+            BufReader::with_capacity(read_buffer, stream),
+            BufWriter::with_capacity(write_buffer, writer),
+            None,
+        ))
+    }
+
+    // Sets up a connection that is served over TLS. Plaintext is encrypted once `write_buffer`
+    // bytes are buffered.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(stream: Stream, config: &TlsConfig, write_buffer: usize) -> io::Result<Self> {
+        set_blocking(&stream)?;
+        let tls = TlsStream::new(stream, config, write_buffer)?;
+        Ok(Connection::Tls(Box::new(tls), None))
+    }
+}
+
+// How long a read waits for the peer to send something
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+
+fn set_blocking(stream: &Stream) -> io::Result<()> {
+    // Convert to a regular blocking stream here, since it would be annoying to manage a mio
+    // event loop for every call to read/write/flush
+    // Additionally add a timeout for io operations so that an idle connection is not kept open
+    // indefinitely
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))
+}
+
+// Reads from a connection, failing with `TimedOut` once `deadline` passes.
+//
+// The read timeout only bounds each read, so a peer that trickles bytes in can make a record take
+// any amount of time. This bounds the whole record.
+struct Deadline<'a> {
+    conn: &'a mut Connection,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        self.conn.set_read_timeout(remaining.min(READ_TIMEOUT))?;
+        self.conn.read(buf)
+    }
+}
+
+impl TryFrom<Stream> for Connection {
+    type Error = io::Error;
+
+    fn try_from(stream: Stream) -> Result<Self, Self::Error> {
+        Self::with_buffer_sizes(stream, DEFAULT_BUFFER_SIZE, DEFAULT_BUFFER_SIZE)
+    }
+}
+
 impl TryFrom<mio::net::TcpStream> for Connection {
     type Error = io::Error;
 
     fn try_from(value: mio::net::TcpStream) -> Result<Self, Self::Error> {
-        // Convert to a regular blocking TcpStream here, since it would be annoying to manage a mio
-        // event loop for every call to read/write/flush
-        // Additionally add a timeout for io operations so that an idle connection is not kept open
-        // indefinitely
-        let stream = TcpStream::from(value);
-        let timeout = std::time::Duration::from_secs(3);
-        stream.set_nonblocking(false)?;
-        stream.set_read_timeout(Some(timeout))?;
-        let writer = stream.try_clone()?;
-        Ok(Connection::Tcp(
-            BufReader::new(stream),
-            BufWriter::new(writer),
-        ))
+        Self::try_from(Stream::Tcp(TcpStream::from(value)))
+    }
+}
+
+#[cfg(unix)]
+impl TryFrom<mio::net::UnixStream> for Connection {
+    type Error = io::Error;
+
+    fn try_from(value: mio::net::UnixStream) -> Result<Self, Self::Error> {
+        Self::try_from(Stream::Unix(UnixStream::from(value)))
+    }
+}
+
+// Bytes that could not be written to a connection without blocking
+#[derive(Debug)]
+pub struct PendingWrite {
+    stream: PendingStream,
+    bytes: Vec<u8>,
+    written: usize,
+}
+
+// The socket of a connection with a pending write, in non-blocking mode
+#[derive(Debug)]
+enum PendingStream {
+    Tcp(mio::net::TcpStream),
+    #[cfg(unix)]
+    Unix(mio::net::UnixStream),
+}
+
+impl PendingWrite {
+    // Writes `bytes` to `stream` without blocking, and returns what is left to write, if anything
+    fn start(stream: Stream, bytes: Vec<u8>) -> Result<Option<Self>, io::Error> {
+        stream.set_nonblocking(true)?;
+        let stream = match stream {
+            Stream::Tcp(s) => PendingStream::Tcp(mio::net::TcpStream::from_std(s)),
+            #[cfg(unix)]
+            Stream::Unix(s) => PendingStream::Unix(mio::net::UnixStream::from_std(s)),
+            // The event loop can't wait for pipes to become writable, so this blocks instead
+            #[cfg(windows)]
+            Stream::NamedPipe(mut s) => {
+                s.set_write_timeout(Some(READ_TIMEOUT))?;
+                s.write_all(&bytes)?;
+                return Ok(None);
+            }
+        };
+
+        let mut pending = PendingWrite {
+            stream,
+            bytes,
+            written: 0,
+        };
+
+        if pending.write()? {
+            Ok(None)
+        } else {
+            Ok(Some(pending))
+        }
+    }
+
+    pub fn stream(&mut self) -> &mut dyn mio::event::Source {
+        match &mut self.stream {
+            PendingStream::Tcp(s) => s,
+            #[cfg(unix)]
+            PendingStream::Unix(s) => s,
+        }
+    }
+
+    // Writes as much as possible without blocking.
+    // Returns true once everything has been written.
+    pub fn write(&mut self) -> Result<bool, io::Error> {
+        while self.written < self.bytes.len() {
+            let remaining = &self.bytes[self.written..];
+            let result = match &mut self.stream {
+                PendingStream::Tcp(s) => s.write(remaining),
+                #[cfg(unix)]
+                PendingStream::Unix(s) => s.write(remaining),
+            };
+            match result {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    // Writes the rest of the bytes, blocking for at most `timeout` on each write
+    pub fn finish_blocking(self, timeout: Duration) -> Result<(), io::Error> {
+        let remaining = &self.bytes[self.written..];
+        match self.stream {
+            PendingStream::Tcp(s) => {
+                let mut stream = TcpStream::from(s);
+                stream.set_nonblocking(false)?;
+                stream.set_write_timeout(Some(timeout))?;
+                stream.write_all(remaining)
+            }
+            #[cfg(unix)]
+            PendingStream::Unix(s) => {
+                let mut stream = UnixStream::from(s);
+                stream.set_nonblocking(false)?;
+                stream.set_write_timeout(Some(timeout))?;
+                stream.write_all(remaining)
+            }
+        }
+    }
+}
+
+// Bounds the resources a peer can make a connection use while its records are read.
+//
+// A single packet can carry at most 64KiB, but a record can be split into any number of packets.
+#[derive(Debug)]
+pub struct ReadLimits {
+    pub max_packets_per_record: usize,
+    // How many more bytes of record content can be read on the connection
+    pub remaining_bytes: usize,
+    // The part of the server's memory budget held by the connection, if there is a budget
+    pub reservation: Option<Reservation>,
+    // When the record must be completely read by, however slowly the peer sends it
+    pub deadline: Option<Instant>,
+    // Bounds the params of the request, which end up as request headers and variables
+    pub params: PairLimits,
+    // How many packets of other records can arrive while a stream record is assembled. Past that,
+    // the stream is malformed.
+    pub max_interleaved_packets: usize,
+    // Packets of other records that arrived while a record was assembled, in the order they
+    // arrived. They are read before anything else on the connection.
+    pub interleaved: VecDeque<Packet>,
+    // Whether packets of other requests are skipped. Before a request begins, there is no
+    // request to serve instead, so they fail the read.
+    pub skip_other_requests: bool,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        Self {
+            max_packets_per_record: usize::MAX,
+            remaining_bytes: usize::MAX,
+            reservation: None,
+            deadline: None,
+            params: PairLimits::default(),
+            max_interleaved_packets: usize::MAX,
+            interleaved: VecDeque::new(),
+            skip_other_requests: true,
+        }
+    }
+}
+
+impl ReadLimits {
+    fn consume(&mut self, packet: &Packet) -> Result<(), Error> {
+        self.remaining_bytes = self
+            .remaining_bytes
+            .checked_sub(packet.content.len())
+            .ok_or(Error::LimitExceeded("connection memory"))?;
+        if let Some(reservation) = &mut self.reservation {
+            if !reservation.grow(packet.content.len()) {
+                return Err(Error::MemoryBudgetExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    // Keeps a packet of another record that arrived in the middle of a record of `expected` type
+    fn interleave(&mut self, expected: u8, packet: Packet) -> Result<(), Error> {
+        let interleaved = self.interleaved.len();
+        if interleaved >= self.max_interleaved_packets {
+            return Err(Error::MalformedRecordStream {
+                expected,
+                received: packet.type_id,
+            });
+        }
+        log::debug!(expected = expected, received = packet.type_id, interleaved = interleaved; "Buffered a packet that arrived in the middle of another record");
+        self.interleaved.push_back(packet);
+        Ok(())
+    }
+
+    // Decodes the content of a complete record
+    fn decode(&self, type_id: u8, content: Vec<u8>) -> Result<Record, Error> {
+        if type_id == record::FCGI_PARAMS {
+            pairs::check_limits(&content, &self.params)?;
+        }
+        Record::from_bytes(type_id, content)
     }
 }
 
@@ -87,40 +496,15 @@ impl Packet {
     fn is_empty(&self) -> bool {
         self.content.is_empty()
     }
-}
-
-impl Connection {
-    pub fn read_packet(&mut self) -> Result<Packet, Error> {
-        let mut header = [0u8; 8];
-        self.read_exact(&mut header)
-            .map_err(Error::UnexpectedSocketClose)?;
-
-        let [version, type_id, req_id_1, req_id_0, length_1, length_0, padding_length, _] = header;
-
-        if version != 1 {
-            return Err(Error::UnsuportedVersion(version));
-        }
-
-        let req_id = u16::from_be_bytes([req_id_1, req_id_0]);
 
-        if req_id > 1 {
-            return Err(Error::MultiplexingUnsupported);
-        }
-
-        let length = u16::from_be_bytes([length_1, length_0]);
-        let mut content = vec![0u8; length as usize];
-        let mut padding = vec![0u8; padding_length as usize];
-
-        self.read_exact(&mut content)
-            .map_err(Error::UnexpectedSocketClose)?;
-        self.read_exact(&mut padding)
-            .map_err(Error::UnexpectedSocketClose)?;
-
-        Ok(Packet { type_id, content })
+    pub fn encode<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let request_id = if self.is_management_record() { 0 } else { 1 };
+        self.encode_for(request_id, writer)
     }
 
-    pub fn write_packet(&mut self, packet: &Packet) -> Result<(), io::Error> {
-        let payload = &packet.content;
+    // Encodes the packet as part of the request `request_id`
+    pub fn encode_for<W: Write>(&self, request_id: u16, writer: &mut W) -> Result<(), io::Error> {
+        let payload = &self.content;
 
         // Length of Header + Length of Payload
         let unpadded_len = 8 + payload.len();
@@ -131,93 +515,444 @@ impl Connection {
         // The amount of padding is the difference between those numers
         let padding = (padded_len - unpadded_len) as u8;
 
-        let request_id = if packet.is_management_record() {
-            [0, 0]
-        } else {
-            [0, 1]
-        };
-
         // Version + Record type
-        self.write_all(&[1, packet.type_id])?;
+        writer.write_all(&[1, self.type_id])?;
         // Request ID
-        self.write_all(&request_id)?;
+        writer.write_all(&request_id.to_be_bytes())?;
         // Payload length
-        self.write_all(&(payload.len() as u16).to_be_bytes())?;
+        writer.write_all(&(payload.len() as u16).to_be_bytes())?;
         // Padding length + Reserved field
-        self.write_all(&[padding, 0])?;
+        writer.write_all(&[padding, 0])?;
         // Payload
-        self.write_all(payload)?;
+        writer.write_all(payload)?;
         // Padding
-        self.write_all(&vec![0u8; padding as usize])?;
+        writer.write_all(&vec![0u8; padding as usize])
+    }
+}
+
+impl Connection {
+    // Starts recording everything read from and written to this connection
+    pub fn capture(&mut self, writer: CaptureWriter) {
+        match self {
+            Connection::Socket(_, _, capture) => *capture = Some(writer),
+            #[cfg(feature = "tls")]
+            Connection::Tls(_, capture) => *capture = Some(writer),
+            #[cfg(test)]
+            Connection::Test(_) => {}
+        }
+    }
+
+    // Reads a single packet.
+    //
+    // Packets of other requests than the one being served are skipped, and reported with
+    // `Error::OtherRequest`. If one begins a new request, that request is rejected.
+    pub fn read_packet(&mut self) -> Result<Packet, Error> {
+        self.read_packet_before(None)
+    }
+
+    // Reads a single packet like `read_packet`, failing once `deadline` passes
+    fn read_packet_before(&mut self, deadline: Option<Instant>) -> Result<Packet, Error> {
+        let result = self.read_any_packet(deadline);
+        if let Err(Error::OtherRequest {
+            request_id,
+            type_id: record::FCGI_BEGIN_REQUEST,
+        }) = result
+        {
+            log::warn!(request_id = request_id; "FastCGI client began another request on the same connection. Rejecting it");
+            if let Err(err) = self.reject_other_request(request_id) {
+                log::warn!(error:err = err; "Failed to reject the other request");
+            }
+        }
+        result
+    }
+
+    // Reads a single packet, failing once `deadline` passes. Packets of other requests are
+    // reported with `Error::OtherRequest`, without answering them.
+    fn read_any_packet(&mut self, deadline: Option<Instant>) -> Result<Packet, Error> {
+        match deadline {
+            Some(deadline) => read_packet(&mut Deadline {
+                conn: self,
+                deadline,
+            }),
+            None => read_packet(self),
+        }
+    }
+
+    // Reads a single packet of the request being served
+    fn read_own_packet(&mut self, deadline: Option<Instant>) -> Result<Packet, Error> {
+        loop {
+            match self.read_packet_before(deadline) {
+                Err(Error::OtherRequest { .. }) => {}
+                result => return result,
+            }
+        }
+    }
+
+    // Tells the client that connections can't carry concurrent requests, ending the request
+    // `request_id` without disturbing the one being served
+    fn reject_other_request(&mut self, request_id: u16) -> Result<(), io::Error> {
+        let mut content = vec![];
+        Record::EndRequest(EndRequest::new(0, ProtocolStatus::MultiplexingUnsupported))
+            .write_bytes(&mut content)?;
+        let packet = Packet {
+            type_id: record::FCGI_END_REQUEST,
+            content,
+        };
+        packet.encode_for(request_id, self)?;
+        self.flush()
+    }
+
+    pub fn write_packet(&mut self, packet: &Packet) -> Result<(), io::Error> {
+        packet.encode(self)?;
         // Don't forget to flush.
         self.flush()
     }
 
-    pub fn read_record(&mut self) -> Result<Record, Error> {
-        let first = self.read_packet()?;
-        let expected_type_id = first.type_id;
+    // The identity of the client, if the connection is served over TLS and the client presented
+    // a certificate
+    #[cfg(feature = "tls")]
+    pub fn client_identity(&self) -> Option<ClientIdentity> {
+        match self {
+            Connection::Tls(tls, _) => tls.client_identity(),
+            _ => None,
+        }
+    }
 
-        if first.is_discrete() || first.is_empty() {
-            let record = Record::from_bytes(expected_type_id, first.content)?;
-            return Ok(record);
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Connection::Socket(reader, _, _) => reader.get_ref().set_read_timeout(Some(timeout)),
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls, _) => tls.stream().set_read_timeout(Some(timeout)),
+            #[cfg(test)]
+            Connection::Test(_) => Ok(()),
         }
+    }
 
-        let mut packets = vec![first];
+    // Sends small writes right away, instead of waiting to coalesce them with the next ones
+    pub fn set_nodelay(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Socket(_, writer, _) => writer.get_ref().set_nodelay(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls, _) => tls.stream().set_nodelay(),
+            #[cfg(test)]
+            Connection::Test(_) => Ok(()),
+        }
+    }
 
-        loop {
-            let packet = self.read_packet()?;
+    // Checks, without blocking, whether the client aborted the request, either by sending an
+    // `FCGI_ABORT_REQUEST` record or by closing the connection.
+    //
+    // This is only meaningful after the request has been fully read. Past that point, a
+    // well-behaved client does not send anything else.
+    pub fn poll_abort(&mut self) -> bool {
+        let peeked = match self {
+            Connection::Socket(reader, _, _) => peek_abort(reader.buffer(), reader.get_ref()),
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls, _) => match tls.peek_input() {
+                Ok((start, available)) if start.len() >= 8 && available >= packet_len(start) => {
+                    None
+                }
+                Ok(_) => Some(false),
+                Err(_) => Some(true),
+            },
+            // Test connections read back what was written to them, so there is no way to tell
+            // what the "client" sent.
+            #[cfg(test)]
+            Connection::Test(_) => return false,
+        };
+        if let Some(aborted) = peeked {
+            return aborted;
+        }
+
+        match self.read_packet() {
+            Ok(packet) => packet.type_id == record::FCGI_ABORT_REQUEST,
+            Err(Error::OtherRequest { .. }) => false,
+            Err(_) => true,
+        }
+    }
 
-            if packet.type_id != expected_type_id {
-                return Err(Error::MalformedRecordStream);
+    // Closes the connection, without destroying a response the client has not read yet.
+    //
+    // Closing a socket with unread input makes the OS reset the connection. This is a problem
+    // when a request is answered before it is fully read, so the write side is shut down first,
+    // then whatever the client still sends is discarded until it closes its side.
+    // Named pipes can't be half-closed, but closing them doesn't discard what was written either.
+    pub fn close_gracefully(mut self) {
+        let _ = self.flush();
+        match self {
+            Connection::Socket(mut reader, _, _) => {
+                if reader.get_ref().shutdown(Shutdown::Write).is_ok() {
+                    let _ = io::copy(
+                        &mut (&mut reader).take(MAX_DISCARDED_BYTES),
+                        &mut io::sink(),
+                    );
+                }
+            }
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls, _) => {
+                // The rest of the input is discarded without decrypting it
+                let mut stream = tls.close();
+                if stream.shutdown(Shutdown::Write).is_ok() {
+                    let _ = io::copy(
+                        &mut (&mut stream).take(MAX_DISCARDED_BYTES),
+                        &mut io::sink(),
+                    );
+                }
             }
+            #[cfg(test)]
+            Connection::Test(_) => {}
+        }
+    }
+
+    #[cfg(test)]
+    pub fn read_record(&mut self) -> Result<Record, Error> {
+        self.read_record_limited(&mut ReadLimits::default())
+    }
+
+    // Reads a record, failing once it exceeds `limits`
+    pub fn read_record_limited(&mut self, limits: &mut ReadLimits) -> Result<Record, Error> {
+        let result = self.read_record_inner(limits);
+        // Reads after this one wait as long as usual
+        if limits.deadline.is_some() {
+            self.set_read_timeout(READ_TIMEOUT)
+                .map_err(Error::UnexpectedSocketClose)?;
+        }
+        let record = result?;
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Received {}", record.summary());
+        }
+        Ok(record)
+    }
 
-            if packet.is_empty() {
-                break;
+    fn read_record_inner(&mut self, limits: &mut ReadLimits) -> Result<Record, Error> {
+        let mut packet = self.next_packet(limits, None)?;
+        let type_id = packet.type_id;
+        if packet.is_discrete() {
+            return limits.decode(type_id, packet.content);
+        }
+
+        let mut content = vec![];
+        // The packets that followed the first one, the one that ends the record included
+        let mut packets = 0;
+        while !packet.is_empty() {
+            content.extend(packet.content);
+            packet = loop {
+                let next = self.next_packet(limits, Some(type_id))?;
+                if next.type_id == type_id {
+                    break next;
+                }
+                limits.interleave(type_id, next)?;
+            };
+
+            packets += 1;
+            if packets > limits.max_packets_per_record {
+                return Err(Error::LimitExceeded("packets per record"));
             }
-            packets.push(packet);
         }
 
-        let content = packets
-            .into_iter()
-            .flat_map(|r| r.content)
-            .collect::<Vec<_>>();
+        limits.decode(type_id, content)
+    }
 
-        let record = Record::from_bytes(expected_type_id, content)?;
+    // Returns the next packet of the request being served: the first interleaved packet of
+    // `type_id` (or of any type), or else the next one on the connection
+    fn next_packet(
+        &mut self,
+        limits: &mut ReadLimits,
+        type_id: Option<u8>,
+    ) -> Result<Packet, Error> {
+        let read_ahead = limits
+            .interleaved
+            .iter()
+            .position(|packet| type_id.is_none_or(|t| packet.type_id == t))
+            .and_then(|i| limits.interleaved.remove(i));
+        if let Some(packet) = read_ahead {
+            return Ok(packet);
+        }
 
-        Ok(record)
+        let packet = match limits.skip_other_requests {
+            true => self.read_own_packet(limits.deadline)?,
+            false => self.read_any_packet(limits.deadline)?,
+        };
+        limits.consume(&packet)?;
+        Ok(packet)
     }
 
     pub fn write_record(&mut self, record: &Record) -> Result<(), io::Error> {
-        let mut payload = vec![];
-        record.write_bytes(&mut payload)?;
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Sending {}", record.summary());
+        }
+        encode_record(record, self)?;
+        self.flush()
+    }
 
-        // The length of the payload must be able to fit in two bytes.
-        let mut payload_chunks: Vec<Vec<_>> = payload
-            .chunks(u16::MAX as usize)
-            .map(<[u8]>::to_vec)
-            .collect();
+    // Writes `bytes` without blocking.
+    //
+    // If the client is not ready to receive all of them, whatever is left is returned so that it
+    // can be written later, once the socket becomes writable.
+    pub fn write_nonblocking(self, bytes: Vec<u8>) -> Result<Option<PendingWrite>, io::Error> {
+        match self {
+            Connection::Socket(_, writer, mut capture) => {
+                if let Some(capture) = &mut capture {
+                    capture.record(Direction::Outbound, &bytes);
+                }
+                let stream = writer.into_inner().map_err(|e| e.into_error())?;
+                PendingWrite::start(stream, bytes)
+            }
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls, mut capture) => {
+                if let Some(capture) = &mut capture {
+                    capture.record(Direction::Outbound, &bytes);
+                }
+                let (stream, encrypted) = tls.close_after(&bytes)?;
+                PendingWrite::start(stream, encrypted)
+            }
+            #[cfg(test)]
+            Connection::Test(mut w) => {
+                w.write_all(&bytes)?;
+                Ok(None)
+            }
+        }
+    }
+}
 
-        // Always write an empty chunk.
-        // + For stream records, this will be used to terminate the stream
-        // + For empty discrete records, this will be the only chunk written
-        payload_chunks.push(vec![]);
+// Checks without blocking whether the client closed `stream`, whose input starts with `buffered`.
+//
+// Returns `None` if a whole packet is waiting to be read, which tells whether the client aborted
+// the request. Part of a packet is left alone until the rest arrives.
+fn peek_abort(buffered: &[u8], stream: &Stream) -> Option<bool> {
+    if stream.set_nonblocking(true).is_err() {
+        return Some(false);
+    }
+    let peeked = peek_packet(buffered, stream);
+    let _ = stream.set_nonblocking(false);
+
+    match peeked {
+        Ok(true) => None,
+        Ok(false) => Some(false),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Some(false),
+        // The client closed the connection
+        Err(_) => Some(true),
+    }
+}
 
-        for chunk in payload_chunks {
-            let packet = Packet {
-                type_id: record.type_id(),
-                content: chunk,
-            };
-            self.write_packet(&packet)?;
+// Whether `buffered`, followed by what already arrived on `stream`, holds a whole packet
+fn peek_packet(buffered: &[u8], stream: &Stream) -> io::Result<bool> {
+    let mut input = buffered.to_vec();
+    if !peek_until(stream, buffered.len(), &mut input, 8)? {
+        return Ok(false);
+    }
+    let len = packet_len(&input);
+    peek_until(stream, buffered.len(), &mut input, len)
+}
 
-            // Discrete records should always fit in a single packet. So write exactly one, and
-            // break out
-            if packet.is_discrete() {
-                break;
-            }
+// Peeks at `stream` until `input` holds `len` bytes, the first `buffered` of which are not on
+// `stream` anymore. Returns whether it does.
+fn peek_until(
+    stream: &Stream,
+    buffered: usize,
+    input: &mut Vec<u8>,
+    len: usize,
+) -> io::Result<bool> {
+    if input.len() >= len {
+        return Ok(true);
+    }
+    input.resize(len, 0);
+    let peeked = stream.peek(&mut input[buffered..])?;
+    if peeked == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    input.truncate(buffered + peeked);
+    Ok(input.len() == len)
+}
+
+// The length of the packet that `header` starts, header included
+fn packet_len(header: &[u8]) -> usize {
+    let content_length = u16::from_be_bytes([header[4], header[5]]);
+    8 + usize::from(content_length) + usize::from(header[6])
+}
+
+// Reads a single packet from `reader`
+pub fn read_packet<R: Read>(reader: &mut R) -> Result<Packet, Error> {
+    let mut header = [0u8; 8];
+    reader
+        .read_exact(&mut header)
+        .map_err(Error::UnexpectedSocketClose)?;
+
+    let [version, type_id, req_id_1, req_id_0, length_1, length_0, padding_length, _] = header;
+
+    if version != 1 {
+        return Err(Error::UnsuportedVersion(version));
+    }
+
+    let req_id = u16::from_be_bytes([req_id_1, req_id_0]);
+    let length = u16::from_be_bytes([length_1, length_0]);
+
+    // The rest of the packet is read anyway, so that the next one can be
+    if req_id > 1 {
+        let skipped = length as u64 + padding_length as u64;
+        let read = io::copy(&mut reader.take(skipped), &mut io::sink())
+            .map_err(Error::UnexpectedSocketClose)?;
+        if read != skipped {
+            return Err(Error::UnexpectedSocketClose(io::Error::from(
+                io::ErrorKind::UnexpectedEof,
+            )));
         }
+        return Err(Error::OtherRequest {
+            request_id: req_id,
+            type_id,
+        });
+    }
 
-        Ok(())
+    let mut content = vec![0u8; length as usize];
+
+    reader
+        .read_exact(&mut content)
+        .map_err(Error::UnexpectedSocketClose)?;
+
+    // Padding is discarded without being buffered
+    let padding_length = padding_length as u64;
+    let skipped = io::copy(&mut reader.take(padding_length), &mut io::sink())
+        .map_err(Error::UnexpectedSocketClose)?;
+    if skipped != padding_length {
+        return Err(Error::UnexpectedSocketClose(io::Error::from(
+            io::ErrorKind::UnexpectedEof,
+        )));
     }
+
+    Ok(Packet { type_id, content })
+}
+
+// Writes `record` as one or more packets
+pub fn encode_record<W: Write>(record: &Record, writer: &mut W) -> Result<(), io::Error> {
+    let mut payload = vec![];
+    record.write_bytes(&mut payload)?;
+
+    // The length of the payload must be able to fit in two bytes.
+    let mut payload_chunks: Vec<Vec<_>> = payload
+        .chunks(u16::MAX as usize)
+        .map(<[u8]>::to_vec)
+        .collect();
+
+    // Always write an empty chunk.
+    // + For stream records, this will be used to terminate the stream
+    // + For empty discrete records, this will be the only chunk written
+    payload_chunks.push(vec![]);
+
+    for chunk in payload_chunks {
+        let packet = Packet {
+            type_id: record.type_id(),
+            content: chunk,
+        };
+        packet.encode(writer)?;
+
+        // Discrete records should always fit in a single packet. So write exactly one, and
+        // break out
+        if packet.is_discrete() {
+            break;
+        }
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -366,4 +1101,190 @@ mod stream_parsing_tests {
         let result = result.unwrap();
         assert_eq!(result, Record::from(Stdout(payload)));
     }
+
+    #[test]
+    fn params_split_into_many_packets() {
+        let params = (0..50).fold(Params::default(), |params, i| {
+            params.add(format!("HTTP_X_{i}"), "v".repeat(i))
+        });
+        let mut bytes = vec![];
+        params.write_record_bytes(&mut bytes).unwrap();
+
+        // Pairs are split between packets too
+        let mut connection = Connection::Test(VecDeque::new());
+        for chunk in bytes.chunks(37).chain([&[][..]]) {
+            let packet = Packet {
+                type_id: record::FCGI_PARAMS,
+                content: chunk.to_vec(),
+            };
+            connection.write_packet(&packet).unwrap();
+        }
+
+        assert_eq!(connection.read_record().unwrap(), Record::from(params));
+    }
+
+    // Packets framed the way nginx sends them: request id 1, every packet padded to a multiple
+    // of 8 bytes, all the params in a single packet, and the body in packets of up to 32KiB
+    #[test]
+    fn nginx_framing() {
+        fn packet(type_id: u8, content: &[u8]) -> Vec<u8> {
+            let padding = (8 - content.len() % 8) % 8;
+            let len = (content.len() as u16).to_be_bytes();
+            let mut bytes = vec![1, type_id, 0, 1, len[0], len[1], padding as u8, 0];
+            bytes.extend(content);
+            bytes.extend(vec![0; padding]);
+            bytes
+        }
+
+        let params = Params::default()
+            .add("QUERY_STRING", "")
+            .add("REQUEST_METHOD", "POST")
+            .add("CONTENT_TYPE", "application/octet-stream")
+            .add("CONTENT_LENGTH", "40001")
+            .add("PATH_INFO", "/upload");
+        let mut params_bytes = vec![];
+        params.write_record_bytes(&mut params_bytes).unwrap();
+        let body = vec![b'x'; 40001];
+
+        let mut bytes = packet(record::FCGI_BEGIN_REQUEST, &[0, 1, 1, 0, 0, 0, 0, 0]);
+        bytes.extend(packet(record::FCGI_PARAMS, &params_bytes));
+        bytes.extend(packet(record::FCGI_PARAMS, &[]));
+        for chunk in body.chunks(32768) {
+            bytes.extend(packet(record::FCGI_STDIN, chunk));
+        }
+        bytes.extend(packet(record::FCGI_STDIN, &[]));
+
+        let mut connection = Connection::Test(VecDeque::from(bytes));
+        assert_eq!(
+            connection.read_record().unwrap(),
+            Record::from(BeginRequest::new(Role::Responder, true))
+        );
+        assert_eq!(connection.read_record().unwrap(), Record::from(params));
+        assert_eq!(connection.read_record().unwrap(), Record::from(Stdin(body)));
+    }
+
+    #[test]
+    fn interleaved_packets() {
+        let packet = |type_id, content: &[u8]| Packet {
+            type_id,
+            content: content.to_vec(),
+        };
+        let write_request = |connection: &mut Connection| {
+            let mut first = vec![];
+            Params::default()
+                .add("A", "1")
+                .write_record_bytes(&mut first)
+                .unwrap();
+            let mut second = vec![];
+            Params::default()
+                .add("B", "2")
+                .write_record_bytes(&mut second)
+                .unwrap();
+            for packet in [
+                packet(record::FCGI_PARAMS, &first),
+                packet(record::FCGI_STDIN, b"hello "),
+                packet(record::FCGI_STDIN, b""),
+                packet(record::FCGI_PARAMS, &second),
+                packet(record::FCGI_PARAMS, b""),
+            ] {
+                connection.write_packet(&packet).unwrap();
+            }
+        };
+
+        // The body arrived before the params ended, and ended with them
+        let mut connection = Connection::Test(VecDeque::new());
+        write_request(&mut connection);
+        let mut limits = ReadLimits::default();
+        assert_eq!(
+            connection.read_record_limited(&mut limits).unwrap(),
+            Record::from(Params::default().add("A", "1").add("B", "2"))
+        );
+        assert_eq!(
+            connection.read_record_limited(&mut limits).unwrap(),
+            Record::from(Stdin(b"hello ".to_vec()))
+        );
+        assert!(limits.interleaved.is_empty());
+
+        let mut connection = Connection::Test(VecDeque::new());
+        write_request(&mut connection);
+        let mut limits = ReadLimits {
+            max_interleaved_packets: 1,
+            ..ReadLimits::default()
+        };
+        assert_matches!(
+            connection.read_record_limited(&mut limits),
+            Err(Error::MalformedRecordStream {
+                expected: record::FCGI_PARAMS,
+                received: record::FCGI_STDIN,
+            })
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let write_packets = |connection: &mut Connection, count: usize| {
+            for _ in 0..count {
+                let packet = Packet {
+                    type_id: record::FCGI_STDIN,
+                    content: b"AB".to_vec(),
+                };
+                connection.write_packet(&packet).unwrap();
+            }
+            let end = Packet {
+                type_id: record::FCGI_STDIN,
+                content: vec![],
+            };
+            connection.write_packet(&end).unwrap();
+        };
+
+        let mut connection = Connection::Test(VecDeque::new());
+        write_packets(&mut connection, 3);
+        let mut limits = ReadLimits {
+            max_packets_per_record: 2,
+            ..ReadLimits::default()
+        };
+        assert_matches!(
+            connection.read_record_limited(&mut limits),
+            Err(Error::LimitExceeded("packets per record"))
+        );
+
+        // The memory budget is shared by all the records of a connection
+        let mut connection = Connection::Test(VecDeque::new());
+        write_packets(&mut connection, 3);
+        write_packets(&mut connection, 3);
+        let mut limits = ReadLimits {
+            remaining_bytes: 10,
+            ..ReadLimits::default()
+        };
+        assert_matches!(connection.read_record_limited(&mut limits), Ok(_));
+        assert_eq!(limits.remaining_bytes, 4);
+        assert_matches!(
+            connection.read_record_limited(&mut limits),
+            Err(Error::LimitExceeded("connection memory"))
+        );
+
+        let params = Params::default().add("A", "1").add("LONG_NAME", "1234");
+        let mut limits = ReadLimits {
+            params: PairLimits {
+                max_pairs: 2,
+                max_name_len: 9,
+                max_value_len: 4,
+            },
+            ..ReadLimits::default()
+        };
+        let mut connection = Connection::Test(VecDeque::new());
+        connection.write_record(&params.clone().into()).unwrap();
+        assert_matches!(connection.read_record_limited(&mut limits), Ok(_));
+        for (params, limit) in [
+            (params.clone().add("B", "2"), "number of params"),
+            (params.clone().add("LONGER_NAME", "1"), "param name size"),
+            (params.add("B", "12345"), "param value size"),
+        ] {
+            connection.write_record(&params.into()).unwrap();
+            assert_matches!(
+                connection.read_record_limited(&mut limits),
+                Err(Error::ParamLimitExceeded(l)) if l == limit
+            );
+        }
+    }
 }
@@ -1,8 +1,40 @@
+use crate::app_status;
+use crate::body::{BodyStream, BodyWriter};
+use crate::clock::SharedClock;
+use crate::extensions::Extensions;
+#[cfg(feature = "fs")]
+use crate::file_server::FileServer;
+use crate::form;
+use crate::headers;
+use crate::httpdate;
+use crate::ip::IpRange;
+use crate::query;
+use crate::response_builder::ResponseBuilder;
+use crate::router::ParamError;
+#[cfg(feature = "signed-cookies")]
+use crate::signing::Keys;
 use crate::status;
+#[cfg(feature = "tls")]
+use crate::tls::ClientIdentity;
+#[cfg(feature = "fs")]
+use camino::Utf8PathBuf;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::borrow::Cow;
 use std::cell::OnceCell;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::io::{self, Write};
-use std::time::Instant;
+use std::net::IpAddr;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// The cookie that carries flash messages from one request to the next
+const FLASH_COOKIE: &str = "flash";
+
+// Marks requests whose flash message was read, so that the response clears it
+struct FlashTaken;
 
 /// A FastCGI request
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -11,9 +43,26 @@ pub struct Request {
     pub(crate) path: String,
     pub(crate) query_string: String,
     pub(crate) headers: BTreeMap<String, String>,
+    pub(crate) vars: BTreeMap<String, String>,
     pub(crate) body: Vec<u8>,
     pub(crate) created_at: Instant,
+    // How long the connection waited for a worker thread, then how long it took to read the
+    // request
+    pub(crate) queued: Duration,
+    pub(crate) read: Duration,
+    // When the handler started producing the response
+    pub(crate) handler_started_at: Instant,
+    pub(crate) clock: SharedClock,
     pub(crate) query: OnceCell<BTreeMap<String, String>>,
+    #[cfg(feature = "csp-nonce")]
+    pub(crate) csp_nonce: OnceCell<String>,
+    pub(crate) trusted_proxies: Arc<Vec<IpRange>>,
+    #[cfg(feature = "signed-cookies")]
+    pub(crate) keys: Option<Arc<Keys>>,
+    pub(crate) extensions: Extensions,
+    pub(crate) log_context: Vec<(String, String)>,
+    #[cfg(feature = "tls")]
+    pub(crate) client_identity: Option<Arc<ClientIdentity>>,
 }
 
 impl Default for Request {
@@ -23,9 +72,23 @@ impl Default for Request {
             path: String::new(),
             query_string: String::new(),
             headers: BTreeMap::new(),
+            vars: BTreeMap::new(),
             body: Vec::new(),
             created_at: Instant::now(),
+            queued: Duration::ZERO,
+            read: Duration::ZERO,
+            handler_started_at: Instant::now(),
+            clock: SharedClock::default(),
             query: OnceCell::new(),
+            #[cfg(feature = "csp-nonce")]
+            csp_nonce: OnceCell::new(),
+            trusted_proxies: Arc::default(),
+            #[cfg(feature = "signed-cookies")]
+            keys: None,
+            extensions: Extensions::default(),
+            log_context: Vec::new(),
+            #[cfg(feature = "tls")]
+            client_identity: None,
         }
     }
 }
@@ -42,8 +105,89 @@ impl Request {
     }
 
     /// Looks up the header value associated with `key`, if any
+    ///
+    /// The lookup ignores case, and treats `-` and `_` the same, so it works regardless of the
+    /// configured [`HeaderCase`].
     pub fn header(&self, key: &str) -> Option<&str> {
-        self.headers.get(key).map(String::as_str)
+        if let Some(value) = self.headers.get(key) {
+            return Some(value.as_str());
+        }
+        self.headers
+            .iter()
+            .find(|(name, _)| same_header_name(name, key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Looks up the value of the cookie `name` sent with the request, if any
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.header(headers::COOKIE)?
+            .split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.trim_matches('"'))
+    }
+
+    /// Looks up the value of the cookie `name`, if it was signed with one of the keys set with
+    /// [`ServerConfig::keys`](crate::ServerConfig::keys)
+    ///
+    /// Returns `None` if the cookie is missing, if its signature is invalid, or if no keys are
+    /// set.
+    #[cfg(feature = "signed-cookies")]
+    pub fn signed_cookie(&self, name: &str) -> Option<&str> {
+        self.keys.as_ref()?.verify(name, self.cookie(name)?)
+    }
+
+    /// Signs `value` to be sent as the value of the cookie `name`, for
+    /// [`Request::signed_cookie`] to read back
+    ///
+    /// The signature is appended to `value`, which is still readable by the client. `value`
+    /// should only contain characters that are allowed in cookies (e.g. percent-encoded text).
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .keys("secret", [])
+    ///     .on_post(["/login"], |req, _params| {
+    ///         let value = req.sign_cookie("user", "42");
+    ///         Response::new().set_header("Set-Cookie", format!("user={value}; HttpOnly"))
+    ///     })
+    ///     .on_get(["/me"], |req, _params| match req.signed_cookie("user") {
+    ///         Some(user) => Response::text(format!("user {user}")),
+    ///         None => Response::new().set_status(401),
+    ///     });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if no keys were set with [`ServerConfig::keys`](crate::ServerConfig::keys)
+    #[cfg(feature = "signed-cookies")]
+    #[track_caller]
+    pub fn sign_cookie(&self, name: &str, value: &str) -> String {
+        let keys = self
+            .keys
+            .as_ref()
+            .expect("ServerConfig::keys must be set to sign cookies");
+        keys.sign(name, value)
+    }
+
+    /// Looks up the value of the CGI variable `name` sent by the web server, if any
+    ///
+    /// These are the FastCGI params that are not HTTP headers (e.g. `REMOTE_ADDR`, `SERVER_PORT`).
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    /// Returns the user the request was authenticated as, if any
+    ///
+    /// This is the `REMOTE_USER` CGI variable. It is set when the web server authenticated the
+    /// request (e.g. with Basic authentication configured in the web server, or a FastCGI
+    /// Authorizer whose `Variable-REMOTE_USER` response header the web server passes on), or by
+    /// the middleware of the [`auth`](crate::auth) module.
+    ///
+    /// The value is not taken from request headers, which the client controls.
+    pub fn remote_user(&self) -> Option<&str> {
+        self.var("REMOTE_USER").filter(|user| !user.is_empty())
     }
 
     /// Returns a reference to the request body
@@ -57,18 +201,140 @@ impl Request {
     pub fn take_body(&mut self) -> Vec<u8> {
         std::mem::take(&mut self.body)
     }
+
+    /// Attaches `value` to the request, replacing any previous value of the same type
+    ///
+    /// This lets code that runs before a handler (e.g. authentication) pass data along to it.
+    ///
+    /// ```
+    /// use vintage::Request;
+    ///
+    /// struct User(String);
+    ///
+    /// let mut req = Request::default();
+    /// req.insert_ext(User("ferris".into()));
+    /// assert_eq!(req.ext::<User>().unwrap().0, "ferris");
+    /// ```
+    pub fn insert_ext<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.extensions.insert(value);
+    }
+
+    /// Attaches a key-value pair to the log context of the request, replacing any previous value
+    /// of `key`
+    ///
+    /// The context is included in the request's [`AccessLogEntry`](crate::AccessLogEntry), so
+    /// identifiers known to handlers (e.g. a user ID) show up in the access log.
+    ///
+    /// ```
+    /// use vintage::Request;
+    ///
+    /// let mut req = Request::default();
+    /// req.log_kv("user_id", 42);
+    /// assert_eq!(req.log_context(), [("user_id".to_string(), "42".to_string())]);
+    /// ```
+    pub fn log_kv(&mut self, key: impl Into<String>, value: impl ToString) {
+        let key = key.into();
+        let value = value.to_string();
+        match self.log_context.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.log_context.push((key, value)),
+        }
+    }
+
+    /// Returns the key-value pairs attached with [`Request::log_kv`], in the order they were
+    /// first attached
+    pub fn log_context(&self) -> &[(String, String)] {
+        &self.log_context
+    }
+
+    /// Returns the value of type `T` attached with [`Request::insert_ext`], if any
+    pub fn ext<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get()
+    }
+
+    /// Returns a random nonce for the inline scripts and styles of the response
+    ///
+    /// The nonce is generated the first time it is requested, and stays the same for the rest of
+    /// the request. It is added to the `Content-Security-Policy` header of the response (see
+    /// [`ServerConfig::content_security_policy`](crate::ServerConfig::content_security_policy)),
+    /// so that only the inline scripts that carry it run.
+    ///
+    /// ```
+    /// use vintage::Request;
+    ///
+    /// let req = Request::default();
+    /// let script = format!("<script nonce=\"{}\">init()</script>", req.csp_nonce());
+    /// assert_eq!(req.csp_nonce().len(), 32);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the operating system can't provide random bytes
+    #[cfg(feature = "csp-nonce")]
+    pub fn csp_nonce(&self) -> &str {
+        self.csp_nonce.get_or_init(|| {
+            let mut bytes = [0; 16];
+            getrandom::fill(&mut bytes).expect("the OS should provide random bytes");
+            bytes.iter().map(|b| format!("{b:02x}")).collect()
+        })
+    }
+
+    /// Returns the flash message set by the previous response (see [`Response::with_flash`]), if
+    /// any
+    ///
+    /// The message is cleared from the client once it is taken: later calls, and later requests,
+    /// return `None`. Requests that don't take it (e.g. for a stylesheet) leave it for the next
+    /// one.
+    ///
+    /// The message comes from a cookie, so the client can change it unless it is signed (see
+    /// `ServerConfig::keys`, with the `signed-cookies` feature). Escape it before including it
+    /// in a page.
+    pub fn take_flash(&mut self) -> Option<String> {
+        if self.ext::<FlashTaken>().is_some() {
+            return None;
+        }
+        #[cfg(feature = "signed-cookies")]
+        let value = match &self.keys {
+            Some(_) => self.signed_cookie(FLASH_COOKIE),
+            None => self.cookie(FLASH_COOKIE),
+        };
+        #[cfg(not(feature = "signed-cookies"))]
+        let value = self.cookie(FLASH_COOKIE);
+        let value = value.filter(|v| !v.is_empty())?;
+        let message = percent_decode_str(value).decode_utf8_lossy().into_owned();
+        self.insert_ext(FlashTaken);
+        Some(message)
+    }
 }
 
 impl Request {
     fn parse_query(qs: &str) -> BTreeMap<String, String> {
         let mut query = BTreeMap::new();
-        for (k, v) in form_urlencoded::parse(qs.as_bytes()) {
-            query.insert(k.to_string(), v.to_string());
+        for (k, v) in form::parse(qs) {
+            query.insert(k, v);
         }
 
         query
     }
 
+    // How long ago the request was received, according to the server's clock
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.clock.since(self.created_at)
+    }
+
+    /// Returns how long each phase of handling the request took so far, according to the
+    /// server's clock
+    ///
+    /// [`Timings::handler`] is how long the request has been handled so far. The access log
+    /// records the complete timings once the response is produced.
+    pub fn timings(&self) -> Timings {
+        Timings {
+            queued: self.queued,
+            read: self.read,
+            handler: self.clock.since(self.handler_started_at),
+        }
+    }
+
     /// Returns the value of `key` from the parsed query string
     pub fn query(&self, key: &str) -> Option<&str> {
         let map = self
@@ -77,14 +343,472 @@ impl Request {
 
         map.get(key).map(String::as_str)
     }
+
+    /// Returns the query string of the request, as it was received (i.e. still encoded)
+    pub fn query_string(&self) -> &str {
+        &self.query_string
+    }
+}
+
+impl Request {
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|range| range.contains(ip))
+    }
+
+    // Returns the address of the peer that connected to the web server, if it is a trusted proxy
+    fn trusted_peer(&self) -> Option<IpAddr> {
+        let peer = self.var("REMOTE_ADDR")?.parse().ok()?;
+        self.is_trusted_proxy(peer).then_some(peer)
+    }
+
+    // Returns the chain of client addresses recorded by proxies, from the original client to the
+    // last proxy.
+    // An entry is `None` if the proxy did not disclose a valid address (e.g. `for=unknown`)
+    fn forwarded_for(&self) -> Vec<Option<IpAddr>> {
+        if let Some(forwarded) = self.header(headers::FORWARDED) {
+            return parse_forwarded(forwarded, "for")
+                .map(parse_forwarded_node)
+                .collect();
+        }
+
+        if let Some(forwarded) = self.header(headers::X_FORWARDED_FOR) {
+            return forwarded
+                .split(',')
+                .map(|node| parse_forwarded_node(node.trim()))
+                .collect();
+        }
+
+        vec![]
+    }
+
+    /// Returns the certificate the web server presented, on a TLS listener that asks for one
+    ///
+    /// This identifies the web server (the FastCGI client) that forwarded the request, not the
+    /// user. It is `None` on listeners without [`TlsConfig::client_auth`], and for web servers
+    /// that were let in without a certificate by [`ClientAuth::Optional`].
+    ///
+    /// Only available with the `tls` feature.
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new().on_get(["/admin"], |req, _params| {
+    ///     match req.client_identity() {
+    ///         Some(client) => {
+    ///             log::info!(fingerprint = client.fingerprint(); "Admin request");
+    ///             Response::text("welcome")
+    ///         }
+    ///         None => Response::text("forbidden").set_status(403),
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`TlsConfig::client_auth`]: crate::TlsConfig::client_auth
+    /// [`ClientAuth::Optional`]: crate::ClientAuth::Optional
+    #[cfg(feature = "tls")]
+    pub fn client_identity(&self) -> Option<&ClientIdentity> {
+        self.client_identity.as_deref()
+    }
+
+    /// Returns the IP address of the client that made the request
+    ///
+    /// If the request was forwarded by one of the proxies configured with
+    /// [`ServerConfig::trust_proxies`](crate::ServerConfig::trust_proxies), the `Forwarded` or
+    /// `X-Forwarded-For` headers are used to find the original client.
+    /// The headers are never consulted when the request did not come from a trusted proxy, since
+    /// anyone can set them.
+    ///
+    /// Returns `None` if the web server did not send the `REMOTE_ADDR` variable.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        let peer = self.var("REMOTE_ADDR")?.parse::<IpAddr>().ok()?;
+
+        if !self.is_trusted_proxy(peer) {
+            return Some(peer);
+        }
+
+        // Walk the chain backwards, starting from the proxy closest to us.
+        // The first address that is not a trusted proxy is the client.
+        let mut client = peer;
+        for node in self.forwarded_for().into_iter().rev() {
+            match node {
+                Some(ip) if self.is_trusted_proxy(ip) => client = ip,
+                Some(ip) => return Some(ip),
+                // We can't see past a proxy that hides the address of its client
+                None => break,
+            }
+        }
+
+        Some(client)
+    }
+
+    /// Returns the scheme (e.g. `http` or `https`) used by the client to make the request
+    ///
+    /// If the request was forwarded by one of the proxies configured with
+    /// [`ServerConfig::trust_proxies`](crate::ServerConfig::trust_proxies), the `Forwarded` or
+    /// `X-Forwarded-Proto` headers are used.
+    /// Otherwise, this relies on the `HTTPS` and `REQUEST_SCHEME` variables of the web server.
+    pub fn scheme(&self) -> &str {
+        if self.trusted_peer().is_some() {
+            // Only the last value was added by the trusted proxy. The ones before it come from
+            // the client, which can send anything.
+            let forwarded = self.header(headers::FORWARDED).and_then(|value| {
+                let last = value.rsplit(',').next()?;
+                parse_forwarded(last, "proto").next()
+            });
+            if let Some(proto) = forwarded {
+                return normalize_scheme(proto);
+            }
+
+            let forwarded = self
+                .header(headers::X_FORWARDED_PROTO)
+                .and_then(|value| value.rsplit(',').next());
+            if let Some(proto) = forwarded {
+                return normalize_scheme(proto.trim());
+            }
+        }
+
+        let https = self.var("HTTPS").unwrap_or_default();
+        if https.eq_ignore_ascii_case("on") || https == "1" {
+            return "https";
+        }
+
+        self.var("REQUEST_SCHEME").map_or("http", normalize_scheme)
+    }
+
+    /// Returns true if the client made the request over `https`
+    ///
+    /// See [`Request::scheme`]
+    pub fn is_secure(&self) -> bool {
+        self.scheme() == "https"
+    }
+
+    // Returns the host (and port, if any) the client used to make the request
+    fn host(&self) -> Option<String> {
+        if self.trusted_peer().is_some() {
+            // Like the scheme, only the last value can be trusted. See `Request::scheme`
+            let forwarded = self
+                .header(headers::FORWARDED)
+                .and_then(|value| parse_forwarded(value.rsplit(',').next()?, "host").next())
+                .or_else(|| {
+                    self.header(headers::X_FORWARDED_HOST)
+                        .and_then(|value| value.rsplit(',').next())
+                })
+                .map(str::trim)
+                .filter(|host| !host.is_empty());
+
+            if let Some(host) = forwarded {
+                return Some(host.to_string());
+            }
+        }
+
+        if let Some(host) = self.header(headers::HOST).filter(|host| !host.is_empty()) {
+            return Some(host.to_string());
+        }
+
+        let name = self.var("SERVER_NAME").filter(|name| !name.is_empty())?;
+        let default_port = if self.is_secure() { "443" } else { "80" };
+
+        match self.var("SERVER_PORT") {
+            Some(port) if port != default_port => Some(format!("{name}:{port}")),
+            _ => Some(name.to_string()),
+        }
+    }
+
+    /// Returns the URL of the site the client made the request to (e.g. `https://example.com`)
+    ///
+    /// The URL is built from the `Host` header, or the `SERVER_NAME` and `SERVER_PORT`
+    /// variables of the web server.
+    /// If the request was forwarded by one of the proxies configured with
+    /// [`ServerConfig::trust_proxies`](crate::ServerConfig::trust_proxies), the `Forwarded` or
+    /// `X-Forwarded-Host` headers take precedence.
+    /// The scheme is determined by [`Request::scheme`].
+    ///
+    /// Returns `None` if the host can't be determined.
+    pub fn base_url(&self) -> Option<String> {
+        let host = self.host()?;
+        Some(format!("{}://{host}", self.scheme()))
+    }
+
+    /// Returns the absolute URL of `path` on the site the client made the request to
+    ///
+    /// For example, `req.absolute_url("/login")` could return `https://example.com/login`.
+    ///
+    /// See [`Request::base_url`]
+    pub fn absolute_url(&self, path: &str) -> Option<String> {
+        let base = self.base_url()?;
+        Some(format!("{base}/{}", path.trim_start_matches('/')))
+    }
+}
+
+impl Request {
+    /// Returns the entity tags listed in the `If-Match` header, if any
+    ///
+    /// Tags are returned as they appear in the header (e.g. `"v1"`, `W/"v1"` or `*`).
+    pub fn if_match(&self) -> Option<Vec<&str>> {
+        self.header(headers::IF_MATCH).map(parse_entity_tags)
+    }
+
+    /// Returns the date in the `If-Unmodified-Since` header, if any
+    ///
+    /// Returns `None` if the header is missing or is not a valid HTTP date.
+    pub fn if_unmodified_since(&self) -> Option<SystemTime> {
+        self.header(headers::IF_UNMODIFIED_SINCE)
+            .and_then(httpdate::parse)
+    }
+
+    /// Evaluates the `If-Match` and `If-Unmodified-Since` preconditions of the request against the
+    /// current state of a resource.
+    ///
+    /// This is used to implement optimistic concurrency: A client that wants to update (e.g.
+    /// `PUT`) or delete a resource sends the `ETag` or `Last-Modified` value it last saw.
+    /// If the resource has changed since then, the request should not be carried out.
+    ///
+    /// `etag` is the current entity tag of the resource, formatted as in the `ETag` header (e.g.
+    /// `"v1"`). It should be `None` if the resource does not exist.
+    /// `last_modified` is when the resource was last changed, if known.
+    ///
+    /// Returns a `412 Precondition Failed` response if a precondition fails.
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .on_put(["/doc"], |req, _params| {
+    ///         if let Err(response) = req.check_preconditions(Some("\"v1\""), None) {
+    ///             return response;
+    ///         }
+    ///         // Update the document ...
+    ///         Response::new()
+    ///     });
+    /// ```
+    pub fn check_preconditions(
+        &self,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Result<(), Response> {
+        let failed = || Err(Response::new().set_status(status::PRECONDITION_FAILED));
+
+        // If-Unmodified-Since is ignored when If-Match is present
+        // See: https://www.rfc-editor.org/rfc/rfc9110#section-13.2.2
+        if let Some(tags) = self.if_match() {
+            let matches = match etag {
+                // Weak tags never match when using the strong comparison
+                Some(etag) if !etag.starts_with("W/") => {
+                    tags.iter().any(|tag| *tag == "*" || *tag == etag)
+                }
+                Some(_) => tags.contains(&"*"),
+                None => false,
+            };
+
+            if !matches {
+                return failed();
+            }
+        } else if let (Some(since), Some(modified)) = (self.if_unmodified_since(), last_modified) {
+            // HTTP dates are only precise to the second
+            let modified = modified
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let since = since
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+
+            if modified > since {
+                return failed();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Splits a list of entity tags, as found in the `If-Match` and `If-None-Match` headers.
+//
+// The header can look like:
+// If-Match: "<etag_value>"
+// If-Match: "<etag_value>", W/"<etag_value>", …
+// If-Match: *
+fn parse_entity_tags(value: &str) -> Vec<&str> {
+    let mut tags = vec![];
+    let mut rest = value.trim_start_matches([' ', '\t', ',']);
+
+    while !rest.is_empty() {
+        let opaque_start = if rest.starts_with("W/") { 2 } else { 0 };
+
+        let end = if rest[opaque_start..].starts_with('"') {
+            // Commas are allowed inside of the quotes
+            rest[opaque_start + 1..]
+                .find('"')
+                .map(|i| opaque_start + i + 2)
+                .unwrap_or(rest.len())
+        } else {
+            rest.find(',').unwrap_or(rest.len())
+        };
+
+        tags.push(rest[..end].trim());
+        rest = rest[end..].trim_start_matches([' ', '\t', ',']);
+    }
+
+    tags
+}
+
+fn normalize_scheme(scheme: &str) -> &str {
+    if scheme.eq_ignore_ascii_case("https") {
+        "https"
+    } else if scheme.eq_ignore_ascii_case("http") {
+        "http"
+    } else {
+        scheme
+    }
+}
+
+// Returns the values of `key` in each element of a `Forwarded` header, in order.
+//
+// The header looks like:
+// Forwarded: for=192.0.2.60;proto=http;by=203.0.113.43, for="[2001:db8:cafe::17]:4711"
+fn parse_forwarded<'a>(value: &'a str, key: &'a str) -> impl Iterator<Item = &'a str> {
+    value.split(',').filter_map(move |element| {
+        element.split(';').find_map(|pair| {
+            let (k, v) = pair.split_once('=')?;
+            if !k.trim().eq_ignore_ascii_case(key) {
+                return None;
+            }
+            Some(v.trim().trim_matches('"'))
+        })
+    })
+}
+
+// Parses a node of the forwarding chain, which may include a port (e.g. `[::1]:80`, `1.2.3.4:80`)
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, _) = rest.split_once(']')?;
+        return ip.parse().ok();
+    }
+
+    let (ip, _port) = node.rsplit_once(':')?;
+    ip.parse().ok()
+}
+
+/// How the names of request headers are spelled
+///
+/// Web servers pass headers to FastCGI servers as params like `HTTP_X_API_KEY`, so the original
+/// spelling of header names is lost. This determines how they are spelled again.
+///
+/// [`Request::header`] finds headers regardless of this setting.
+///
+/// See [`ServerConfig::header_case`](crate::ServerConfig::header_case)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeaderCase {
+    /// `X-Api-Key`, `Dnt`
+    #[default]
+    Train,
+    /// `x-api-key`, `dnt`
+    Lower,
+    /// The spelling used by the web server, with dashes: `X-API-KEY`, `DNT`
+    Original,
+}
+
+impl HeaderCase {
+    // Converts the suffix of an `HTTP_*` param into a header name
+    pub(crate) fn header_name(&self, param_suffix: &str) -> String {
+        match self {
+            Self::Train => param_suffix
+                .split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => {
+                            first.to_ascii_uppercase().to_string()
+                                + &chars.as_str().to_ascii_lowercase()
+                        }
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("-"),
+            Self::Lower => param_suffix.to_ascii_lowercase().replace('_', "-"),
+            Self::Original => param_suffix.replace('_', "-"),
+        }
+    }
+}
+
+// Compares header names, ignoring case and the difference between `-` and `_`
+pub(crate) fn same_header_name(a: &str, b: &str) -> bool {
+    let normalize = |c: u8| match c {
+        b'_' => b'-',
+        c => c.to_ascii_lowercase(),
+    };
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .all(|(a, b)| normalize(a) == normalize(b))
+}
+
+/// How long the phases of handling a request took
+///
+/// A slow client (or web server) shows up in [`read`](Timings::read), an overloaded server in
+/// [`queued`](Timings::queued), and a slow handler in [`handler`](Timings::handler).
+///
+/// See [`Request::timings`] and [`AccessLogEntry`](crate::AccessLogEntry)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Timings {
+    /// How long the connection waited in the request queue before a worker thread picked it up
+    pub queued: Duration,
+    /// How long it took to receive the params and body of the request, once a worker thread
+    /// picked it up
+    pub read: Duration,
+    /// How long the handler, middleware included, took to produce the response
+    pub handler: Duration,
+}
+
+/// The line ending used between the headers of a response
+///
+/// See [`ServerConfig::line_ending`](crate::ServerConfig::line_ending)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\r\n`, as expected by HTTP clients
+    #[default]
+    CrLf,
+    /// `\n`, which the CGI specification allows.
+    /// This is how responses were serialized before `CrLf` became the default.
+    Lf,
+}
+
+impl LineEnding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::CrLf => "\r\n",
+            Self::Lf => "\n",
+        }
+    }
 }
 
 /// A FastCGI response
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Response {
     pub(crate) status: u16,
+    pub(crate) reason: Option<Box<str>>,
     pub(crate) headers: BTreeMap<String, String>,
+    // The `Set-Cookie` headers added with `Response::add_cookie`, each sent on its own line
+    pub(crate) cookies: Vec<String>,
     pub(crate) body: Vec<u8>,
+    pub(crate) stream: Option<BodyStream>,
+    // Whether `stream` writes the whole response, headers included. See `Response::raw`.
+    pub(crate) raw: bool,
+    // Sent in `FCGI_END_REQUEST`. See `Response::set_app_status`.
+    pub(crate) app_status: u32,
+    // The error this response answers, for `ServerConfig::on_param_error` to replace it
+    pub(crate) param_error: Option<Box<ParamError>>,
+    // A file to serve as the body, once the handler returns. See `Response::file`. Boxed to keep
+    // responses small, since they are returned as errors.
+    #[cfg(feature = "fs")]
+    pub(crate) file: Option<Box<Utf8PathBuf>>,
 }
 
 impl Default for Response {
@@ -92,8 +816,16 @@ impl Default for Response {
         Self {
             // The CGI RFC says this is the default if no status is provided
             status: 200,
+            reason: None,
             headers: BTreeMap::new(),
+            cookies: Vec::new(),
             body: Vec::new(),
+            stream: None,
+            raw: false,
+            app_status: app_status::OK,
+            param_error: None,
+            #[cfg(feature = "fs")]
+            file: None,
         }
     }
 }
@@ -104,17 +836,143 @@ impl Response {
         Self::default()
     }
 
+    /// Returns a builder that checks the response for common mistakes once it is complete
+    ///
+    /// See [`ResponseBuilder`]
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder::new()
+    }
+
     /// Sets the response header `key` to `value`
     ///
     /// If `key` was already present in the map, the value is updated
-    pub fn set_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
-        self.headers.insert(key.into(), value.into());
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is not a valid header name, or if `value` contains a line break.
+    /// Use [`Response::try_set_header`] for values that come from user input.
+    #[track_caller]
+    pub fn set_header(self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        match self.try_set_header(key, value) {
+            Ok(response) => response,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Adds a `Set-Cookie` header to the response, keeping the cookies it already sets
+    ///
+    /// Headers set with [`Response::set_header`] have a single value, but each cookie needs a
+    /// `Set-Cookie` header of its own.
+    ///
+    /// ```
+    /// use vintage::Response;
+    ///
+    /// let response = Response::text("Welcome")
+    ///     .add_cookie("session=abc; Path=/; HttpOnly")
+    ///     .add_cookie("theme=dark; Path=/");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cookie` contains a line break
+    #[track_caller]
+    pub fn add_cookie(mut self, cookie: impl Into<String>) -> Self {
+        let cookie = cookie.into();
+        if let Err(err) = check_header(headers::SET_COOKIE, &cookie) {
+            panic!("{err}");
+        }
+        self.cookies.push(cookie);
         self
     }
 
+    /// Sets the response header `key` to `time`, formatted as an HTTP date (e.g.
+    /// `Wed, 21 Oct 2015 07:28:00 GMT`)
+    ///
+    /// This is the format of headers like `Last-Modified`, `Expires` and `Retry-After`.
+    /// Fractions of seconds are dropped.
+    ///
+    /// ```
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use vintage::Response;
+    ///
+    /// let time = UNIX_EPOCH + Duration::from_secs(1445412480);
+    /// let response = Response::new().set_date_header("Expires", time);
+    /// assert_eq!(
+    ///     response,
+    ///     Response::new().set_header("Expires", "Wed, 21 Oct 2015 07:28:00 GMT")
+    /// );
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is not a valid header name
+    #[track_caller]
+    pub fn set_date_header(self, key: impl Into<String>, time: SystemTime) -> Self {
+        self.set_header(key, httpdate::format(time))
+    }
+
+    /// Sets the response header `key` to `value`, unless either of them is invalid
+    ///
+    /// Line breaks in a header value would let whoever controls it inject headers of their own
+    /// (i.e. response splitting).
+    pub fn try_set_header(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self, InvalidHeader> {
+        let key = key.into();
+        let value = value.into();
+        check_header(&key, &value)?;
+        self.headers.insert(key, value);
+        Ok(self)
+    }
+
     /// Sets the status code of the response to `code`
     pub fn set_status(mut self, code: u16) -> Self {
         self.status = code;
+        self.reason = None;
+        self
+    }
+
+    /// Sets the status code of the response to `code`, followed by a reason phrase
+    ///
+    /// This is sent as e.g. `Status: 404 Not Found`.
+    /// Some FastCGI clients forward the reason phrase to the HTTP client.
+    /// [`status::reason_phrase`] returns the canonical phrase of common status codes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reason` contains a line break
+    #[track_caller]
+    pub fn set_status_with_reason(mut self, code: u16, reason: impl Into<String>) -> Self {
+        let reason = reason.into();
+        assert!(
+            !reason.contains(['\r', '\n', '\0']),
+            "Invalid reason phrase: '{}'",
+            reason.escape_debug()
+        );
+        self.status = code;
+        self.reason = Some(reason.into_boxed_str());
+        self
+    }
+
+    /// Sets the application status sent to the web server once the response is complete
+    ///
+    /// This is the FastCGI equivalent of an exit code, which web servers usually log. It lets
+    /// clients that understand it tell failure modes apart without parsing the body.
+    /// See [`app_status`] for the values the server uses itself.
+    ///
+    /// ```
+    /// use vintage::{status, Response};
+    ///
+    /// // Upstream failures are told apart from other 502s in the logs of the web server
+    /// const UPSTREAM_DOWN: u32 = 256;
+    /// let response = Response::new()
+    ///     .set_status(status::BAD_GATEWAY)
+    ///     .set_app_status(UPSTREAM_DOWN);
+    /// ```
+    pub fn set_app_status(mut self, app_status: u32) -> Self {
+        self.app_status = app_status;
         self
     }
 
@@ -126,12 +984,171 @@ impl Response {
     /// Sets the response body in bytes
     pub fn set_raw_body(mut self, body: Vec<u8>) -> Self {
         self.body = body;
+        self.stream = None;
+        self.raw = false;
+        #[cfg(feature = "fs")]
+        {
+            self.file = None;
+        }
         self
     }
 
+    /// Sets the response body to be produced by `producer` while it is being sent
+    ///
+    /// This is useful for large bodies that should not be held in memory all at once.
+    /// The producer runs on the worker thread after the handler returns, and receives a writer
+    /// that sends the body to the FastCGI client.
+    ///
+    /// If the client aborts the request, writes start failing.
+    /// The producer should stop (e.g. by propagating the error with `?`) so that expensive work can
+    /// be cancelled.
+    /// See also [`ServerConfig::on_abort`](crate::ServerConfig::on_abort).
+    ///
+    /// The body is buffered before being sent. Streams of small updates (e.g. server-sent events)
+    /// should flush after each one, or use
+    /// [`BodyWriter::set_interactive`](crate::BodyWriter::set_interactive).
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .on_get(["/numbers"], |_req, _params| {
+    ///         Response::new()
+    ///             .set_header("Content-Type", "text/plain")
+    ///             .set_body_stream(|writer| {
+    ///                 for i in 0..1_000_000 {
+    ///                     writeln!(writer, "{i}")?;
+    ///                 }
+    ///                 Ok(())
+    ///             })
+    ///     });
+    /// ```
+    pub fn set_body_stream<F>(mut self, producer: F) -> Self
+    where
+        F: FnOnce(&mut BodyWriter) -> io::Result<()> + Send + 'static,
+    {
+        self.body = Vec::new();
+        self.stream = Some(BodyStream::new(producer));
+        self.raw = false;
+        #[cfg(feature = "fs")]
+        {
+            self.file = None;
+        }
+        self
+    }
+
+    /// Returns a new response that is entirely written by `producer`, headers included
+    ///
+    /// This is an escape hatch for what the rest of the API can't express (e.g. CGI response
+    /// fields other than headers). Whatever `producer` writes is sent as is on the `FCGI_STDOUT`
+    /// stream, and the request is ended once it returns. Nothing is checked, so it is up to the
+    /// producer to write the header section, the blank line that ends it, and the body.
+    ///
+    /// Headers and bodies set on the response are not sent. The status code is only used by
+    /// the server itself (e.g. in stats and access logs), so it should be set to the one that
+    /// `producer` writes. Otherwise, the producer runs like the one of
+    /// [`set_body_stream`](Response::set_body_stream).
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .on_get(["/legacy"], |_req, _params| {
+    ///         Response::raw(|writer| {
+    ///             writer.write_all(b"Status: 200 OK\r\nContent-Type: text/plain\r\n\r\n")?;
+    ///             writer.write_all(b"hello")
+    ///         })
+    ///     });
+    /// ```
+    pub fn raw<F>(producer: F) -> Self
+    where
+        F: FnOnce(&mut BodyWriter) -> io::Result<()> + Send + 'static,
+    {
+        Self {
+            raw: true,
+            ..Self::default().set_body_stream(producer)
+        }
+    }
+
+    /// Returns a new response that serves the file at `path`
+    ///
+    /// The file is served like the files of a [`FileServer`](crate::FileServer), once the
+    /// handler returns: its content type is guessed from its extension, and conditional
+    /// (`If-None-Match`) and range requests are answered. A `Content-Type` set on the response
+    /// replaces the guessed one, and other headers (e.g. `Content-Disposition`) are sent along.
+    ///
+    /// Fails if `path` is not an existing file with a UTF-8 path. Relative paths are resolved
+    /// against the current directory right away.
+    ///
+    /// ```no_run
+    /// use std::io;
+    /// use vintage::{Request, Response, RouteParams, ServerConfig};
+    ///
+    /// fn report(_req: &mut Request, params: RouteParams) -> io::Result<Response> {
+    ///     let response = Response::file(format!("reports/{}.pdf", params["id"]))?;
+    ///     Ok(response.set_header("Content-Disposition", "attachment"))
+    /// }
+    ///
+    /// let config = ServerConfig::new()
+    ///     .on_get(["/reports/{id}"], |req, params| {
+    ///         report(req, params).unwrap_or_else(|_| Response::new().set_status(404))
+    ///     });
+    /// ```
+    #[cfg(feature = "fs")]
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = Utf8PathBuf::try_from(path.as_ref().to_path_buf())
+            .map_err(|err| err.into_io_error())?
+            .canonicalize_utf8()?;
+        if !path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{path}' is not a file"),
+            ));
+        }
+        Ok(Self {
+            file: Some(Box::new(path)),
+            ..Self::default()
+        })
+    }
+
+    // Reads the file set with `Response::file` into the response, answering `req`'s conditional
+    // and range headers
+    #[cfg(feature = "fs")]
+    pub(crate) fn resolve_file(mut self, req: &Request) -> Self {
+        let Some(path) = self.file.take() else {
+            return self;
+        };
+
+        let content_type = self
+            .headers
+            .iter()
+            .find(|(name, _)| same_header_name(name, headers::CONTENT_TYPE))
+            .map(|(_, value)| value.as_str());
+        let served = FileServer::new("/", "").respond_with_file(req, &path, content_type);
+
+        // The headers set by the handler are kept, except those that depend on what part of the
+        // file is sent
+        let mut response = self.set_status(served.status).set_raw_body(served.body);
+        for (name, value) in served.headers {
+            let replaced = same_header_name(&name, headers::CONTENT_TYPE)
+                || same_header_name(&name, headers::CONTENT_RANGE);
+            if replaced {
+                response
+                    .headers
+                    .retain(|key, _| !same_header_name(key, &name));
+            }
+            if replaced || !response.has_header(&name) {
+                response.headers.insert(name, value);
+            }
+        }
+        response
+    }
+
     fn of_content_type(content_type: &str, value: impl Into<String>) -> Self {
         Response::default()
-            .set_header("Content-Type", content_type)
+            .set_header(headers::CONTENT_TYPE, content_type)
             .set_body(value)
     }
 
@@ -150,6 +1167,23 @@ impl Response {
         Self::of_content_type("text/html", html)
     }
 
+    /// Returns a new `204 No Content` response
+    pub fn no_content() -> Self {
+        Response::default().set_status(status::NO_CONTENT)
+    }
+
+    /// Returns a new `201 Created` response, with `location` pointing to the new resource
+    pub fn created(location: impl Into<String>) -> Self {
+        Response::default()
+            .set_header(headers::LOCATION, location)
+            .set_status(status::CREATED)
+    }
+
+    /// Returns a new `202 Accepted` response, for requests that will be processed later
+    pub fn accepted() -> Self {
+        Response::default().set_status(status::ACCEPTED)
+    }
+
     /// Returns a new response that will trigger a temporary redirect
     ///
     /// The browser receiving the request will re-make the request with `path` as the new target
@@ -159,7 +1193,7 @@ impl Response {
     /// new resource, meaning no SEO value is transferred to the new URL.
     pub fn temporary_redirect(path: impl Into<String>) -> Self {
         Response::default()
-            .set_header("Location", path)
+            .set_header(headers::LOCATION, path)
             .set_status(status::TEMPORARY_REDIRECT)
     }
 
@@ -172,16 +1206,710 @@ impl Response {
     /// redirected resource, passing the SEO ranking to the new URL.
     pub fn permanent_redirect(path: impl Into<String>) -> Self {
         Response::default()
-            .set_header("Location", path)
+            .set_header(headers::LOCATION, path)
             .set_status(status::PERMANENT_REDIRECT)
     }
 
-    pub(crate) fn write_stdout_bytes<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+    /// Returns a new response that will trigger a temporary redirect to `path`, keeping the query
+    /// string of `req`
+    ///
+    /// Parameters that `path` sets itself replace those of the same name in the query of `req`.
+    /// Use [`set_status`](Response::set_status) for another kind of redirect.
+    /// See [`query`](crate::query) for building the query of other URLs.
+    ///
+    /// ```
+    /// use vintage::{status, Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     // `/search?q=fish` redirects to `/find?q=fish`
+    ///     .on_get(["/search"], |req, _params| {
+    ///         Response::redirect_preserving_query(req, "/find")
+    ///             .set_status(status::PERMANENT_REDIRECT)
+    ///     });
+    /// ```
+    pub fn redirect_preserving_query(req: &Request, path: impl AsRef<str>) -> Self {
+        Self::temporary_redirect(query::merge(path.as_ref(), &req.query_string))
+    }
+
+    /// Sets a one-shot message for the next request of the client to read with
+    /// [`Request::take_flash`]
+    ///
+    /// This is meant for the post/redirect/get pattern: a form submission is answered with a
+    /// redirect that carries a message (e.g. "Saved!"), and the page it redirects to displays it
+    /// once.
+    ///
+    /// The message is stored in a cookie, so it should stay short (browsers cap cookies at about
+    /// 4KB). It replaces a flash message set before, and keeps the other cookies of the response
+    /// (see [`Response::add_cookie`]). The cookie is signed if `ServerConfig::keys` are set.
+    ///
+    /// ```
+    /// use vintage::{status, Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .on_post(["/settings"], |_req, _params| {
+    ///         Response::new()
+    ///             .set_status(status::SEE_OTHER)
+    ///             .set_header("Location", "/settings")
+    ///             .with_flash("Settings saved")
+    ///     })
+    ///     .on_get(["/settings"], |req, _params| {
+    ///         let notice = req.take_flash().unwrap_or_default();
+    ///         Response::text(notice)
+    ///     });
+    /// ```
+    pub fn with_flash(mut self, message: impl AsRef<str>) -> Self {
+        let value = utf8_percent_encode(message.as_ref(), NON_ALPHANUMERIC);
+        self.cookies.retain(|cookie| !is_flash_cookie(cookie));
+        self.add_cookie(format!(
+            "{FLASH_COOKIE}={value}; Path=/; HttpOnly; SameSite=Lax"
+        ))
+    }
+
+    // Clears the flash message from the client if `req` took it, unless the response sets a new
+    // one
+    pub(crate) fn clear_taken_flash(self, req: &Request) -> Self {
+        if req.ext::<FlashTaken>().is_none() || self.cookies.iter().any(|c| is_flash_cookie(c)) {
+            return self;
+        }
+        self.add_cookie(format!("{FLASH_COOKIE}=; Path=/; Max-Age=0"))
+    }
+
+    // Signs the flash message set by the response, if keys are set
+    #[cfg(feature = "signed-cookies")]
+    pub(crate) fn sign_flash(mut self, req: &Request) -> Self {
+        let Some(keys) = &req.keys else {
+            return self;
+        };
+        let Some(cookie) = self.cookies.iter_mut().find(|c| is_flash_cookie(c)) else {
+            return self;
+        };
+        let rest = &cookie[FLASH_COOKIE.len() + 1..];
+        let (value, attributes) = rest.split_once(';').unwrap_or((rest, ""));
+        if !value.is_empty() {
+            let value = keys.sign(FLASH_COOKIE, value);
+            *cookie = format!("{FLASH_COOKIE}={value};{attributes}");
+        }
+        self
+    }
+
+    // Returns true if the header `key` is set, whatever its spelling
+    pub(crate) fn has_header(&self, key: &str) -> bool {
+        self.headers.keys().any(|name| same_header_name(name, key))
+    }
+
+    // Returns the lowercased `Content-Type`, without its parameters (e.g. `text/html`)
+    pub(crate) fn media_type(&self) -> Option<String> {
+        let content_type = self.headers.get(headers::CONTENT_TYPE)?;
+        let media_type = content_type.split(';').next().unwrap_or_default();
+        Some(media_type.trim().to_ascii_lowercase())
+    }
+
+    pub(crate) fn write_stdout_bytes<W: Write>(
+        &self,
+        writer: &mut W,
+        line_ending: LineEnding,
+    ) -> Result<(), io::Error> {
+        if self.raw {
+            return Ok(());
+        }
+        let eol = line_ending.as_str();
+
+        // There is nothing to describe without a body.
+        // Some clients get confused by a `Content-Type` on e.g. `204` and `304` responses.
+        let has_body = !self.body.is_empty() || self.stream.is_some();
+
         for (key, value) in self.headers.iter() {
-            writeln!(writer, "{key}: {value}")?;
+            if !has_body && key.eq_ignore_ascii_case("Content-Type") {
+                continue;
+            }
+            // Line breaks would let header values (which might come from user input) inject
+            // headers of their own, or even a body
+            let key = sanitize_header(key);
+            let value = sanitize_header(value);
+            write!(writer, "{key}: {value}{eol}")?;
+        }
+        for cookie in &self.cookies {
+            let cookie = sanitize_header(cookie);
+            write!(writer, "{}: {cookie}{eol}", headers::SET_COOKIE)?;
+        }
+        match &self.reason {
+            Some(reason) => write!(writer, "Status: {} {reason}{eol}{eol}", self.status)?,
+            None => write!(writer, "Status: {}{eol}{eol}", self.status)?,
         }
-        writeln!(writer, "Status: {}", self.status)?;
-        writeln!(writer)?;
         writer.write_all(&self.body)
     }
 }
+
+/// The error returned by [`Response::try_set_header`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidHeader {
+    /// The header name is empty, or contains characters that are not allowed in HTTP tokens
+    Name(String),
+    /// The value of the named header contains a line break or a null byte
+    Value(String),
+}
+
+impl fmt::Display for InvalidHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "Invalid header name: '{}'", name.escape_debug()),
+            Self::Value(name) => write!(f, "Invalid value for header '{name}'"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidHeader {}
+
+// Checks that a response header can be sent as is
+pub(crate) fn check_header(key: &str, value: &str) -> Result<(), InvalidHeader> {
+    if !is_token(key) {
+        return Err(InvalidHeader::Name(key.to_string()));
+    }
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(InvalidHeader::Value(key.to_string()));
+    }
+    Ok(())
+}
+
+// Checks that `s` is a `token`, as defined by RFC 9110.
+// Header names and request methods are tokens.
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// Returns true if the `Set-Cookie` value `cookie` sets the flash message
+fn is_flash_cookie(cookie: &str) -> bool {
+    cookie
+        .strip_prefix(FLASH_COOKIE)
+        .is_some_and(|rest| rest.starts_with('='))
+}
+
+// Replaces the line breaks in a header name or value with spaces
+fn sanitize_header(s: &str) -> Cow<'_, str> {
+    if s.contains(['\r', '\n']) {
+        Cow::Owned(s.replace(['\r', '\n'], " "))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(vars: &[(&str, &str)], headers: &[(&str, &str)]) -> Request {
+        let to_map = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        Request {
+            vars: to_map(vars),
+            headers: to_map(headers),
+            trusted_proxies: Arc::new(vec!["127.0.0.1".parse().unwrap()]),
+            ..Request::default()
+        }
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn client_ip_from_untrusted_peer() {
+        let req = request(
+            &[("REMOTE_ADDR", "10.0.0.1")],
+            &[("X-Forwarded-For", "1.1.1.1")],
+        );
+        assert_eq!(req.client_ip(), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn client_ip_from_x_forwarded_for() {
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[("X-Forwarded-For", "6.6.6.6, 1.1.1.1, 127.0.0.1")],
+        );
+        assert_eq!(req.client_ip(), ip("1.1.1.1"));
+    }
+
+    #[test]
+    fn client_ip_from_forwarded() {
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[
+                (
+                    "Forwarded",
+                    r#"for="[2001:db8::17]:4711";proto=https, for=127.0.0.1"#,
+                ),
+                ("X-Forwarded-For", "1.1.1.1"),
+            ],
+        );
+        assert_eq!(req.client_ip(), ip("2001:db8::17"));
+    }
+
+    #[test]
+    fn client_ip_behind_obfuscated_proxy() {
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[("Forwarded", "for=1.1.1.1, for=_hidden")],
+        );
+        assert_eq!(req.client_ip(), ip("127.0.0.1"));
+    }
+
+    #[test]
+    fn client_ip_without_remote_addr() {
+        assert_eq!(request(&[], &[]).client_ip(), None);
+    }
+
+    #[test]
+    fn scheme_from_web_server() {
+        assert_eq!(request(&[], &[]).scheme(), "http");
+        assert_eq!(request(&[("HTTPS", "on")], &[]).scheme(), "https");
+        assert_eq!(
+            request(&[("REQUEST_SCHEME", "https")], &[]).scheme(),
+            "https"
+        );
+    }
+
+    #[test]
+    fn scheme_from_trusted_proxy() {
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[("X-Forwarded-Proto", "HTTPS")],
+        );
+        assert_eq!(req.scheme(), "https");
+        assert!(req.is_secure());
+
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1"), ("HTTPS", "on")],
+            &[("Forwarded", "for=1.1.1.1;proto=http")],
+        );
+        assert_eq!(req.scheme(), "http");
+    }
+
+    #[test]
+    fn scheme_with_spoofed_values() {
+        // The client sent its own headers, and the proxy added its values after them
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[("X-Forwarded-Proto", "https, http")],
+        );
+        assert_eq!(req.scheme(), "http");
+
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[(
+                "Forwarded",
+                "for=6.6.6.6;proto=https, for=1.1.1.1;proto=http",
+            )],
+        );
+        assert_eq!(req.scheme(), "http");
+
+        // The proxy didn't say which scheme was used
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[("Forwarded", "for=6.6.6.6;proto=https, for=1.1.1.1")],
+        );
+        assert_eq!(req.scheme(), "http");
+    }
+
+    #[test]
+    fn scheme_from_untrusted_proxy() {
+        let req = request(
+            &[("REMOTE_ADDR", "10.0.0.1")],
+            &[("X-Forwarded-Proto", "https")],
+        );
+        assert_eq!(req.scheme(), "http");
+    }
+
+    #[test]
+    fn base_url_from_host_header() {
+        let req = request(
+            &[("SERVER_NAME", "internal")],
+            &[("Host", "example.com:8080")],
+        );
+        assert_eq!(req.base_url().unwrap(), "http://example.com:8080");
+    }
+
+    #[test]
+    fn base_url_from_server_variables() {
+        let req = request(
+            &[("SERVER_NAME", "example.com"), ("SERVER_PORT", "80")],
+            &[],
+        );
+        assert_eq!(req.base_url().unwrap(), "http://example.com");
+
+        let req = request(
+            &[
+                ("SERVER_NAME", "example.com"),
+                ("SERVER_PORT", "8443"),
+                ("HTTPS", "on"),
+            ],
+            &[],
+        );
+        assert_eq!(req.base_url().unwrap(), "https://example.com:8443");
+
+        assert_eq!(request(&[], &[]).base_url(), None);
+    }
+
+    #[test]
+    fn base_url_behind_trusted_proxy() {
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[
+                ("Host", "backend:9000"),
+                ("X-Forwarded-Host", "example.com"),
+                ("X-Forwarded-Proto", "https"),
+            ],
+        );
+        assert_eq!(req.base_url().unwrap(), "https://example.com");
+
+        let req = request(
+            &[("REMOTE_ADDR", "10.0.0.1")],
+            &[
+                ("Host", "backend:9000"),
+                ("X-Forwarded-Host", "example.com"),
+            ],
+        );
+        assert_eq!(req.base_url().unwrap(), "http://backend:9000");
+
+        // The first values come from the client
+        let req = request(
+            &[("REMOTE_ADDR", "127.0.0.1")],
+            &[
+                ("X-Forwarded-Host", "evil.example, example.com"),
+                (
+                    "Forwarded",
+                    "host=evil.example;proto=https, host=example.com;proto=http",
+                ),
+            ],
+        );
+        assert_eq!(req.base_url().unwrap(), "http://example.com");
+    }
+
+    #[test]
+    fn absolute_url() {
+        let req = request(&[], &[("Host", "example.com")]);
+        assert_eq!(
+            req.absolute_url("/login").unwrap(),
+            "http://example.com/login"
+        );
+        assert_eq!(
+            req.absolute_url("login").unwrap(),
+            "http://example.com/login"
+        );
+        assert_eq!(req.absolute_url("").unwrap(), "http://example.com/");
+    }
+
+    #[test]
+    fn entity_tag_parsing() {
+        assert_eq!(parse_entity_tags("*"), ["*"]);
+        assert_eq!(parse_entity_tags(r#""a""#), [r#""a""#]);
+        assert_eq!(
+            parse_entity_tags(r#""a", W/"b" ,"c,d""#),
+            [r#""a""#, r#"W/"b""#, r#""c,d""#]
+        );
+        assert!(parse_entity_tags("").is_empty());
+    }
+
+    #[test]
+    fn if_match_preconditions() {
+        let req = request(&[], &[("If-Match", r#""v1", "v2""#)]);
+        assert_eq!(req.check_preconditions(Some(r#""v2""#), None), Ok(()));
+
+        let failed = Err(Response::new().set_status(status::PRECONDITION_FAILED));
+        assert_eq!(req.check_preconditions(Some(r#""v3""#), None), failed);
+        assert_eq!(req.check_preconditions(None, None), failed);
+
+        let req = request(&[], &[("If-Match", r#"W/"v1""#)]);
+        assert_eq!(req.check_preconditions(Some(r#"W/"v1""#), None), failed);
+
+        let req = request(&[], &[("If-Match", "*")]);
+        assert_eq!(req.check_preconditions(Some(r#""v1""#), None), Ok(()));
+        assert_eq!(req.check_preconditions(None, None), failed);
+    }
+
+    #[test]
+    fn if_unmodified_since_preconditions() {
+        let req = request(
+            &[],
+            &[("If-Unmodified-Since", "Thu, 01 Jan 1970 00:01:40 GMT")],
+        );
+        let at = |secs| Some(UNIX_EPOCH + std::time::Duration::from_secs(secs));
+
+        assert_eq!(req.if_unmodified_since(), at(100));
+        assert_eq!(req.check_preconditions(None, at(100)), Ok(()));
+        assert_eq!(req.check_preconditions(None, None), Ok(()));
+        assert_eq!(
+            req.check_preconditions(None, at(101)),
+            Err(Response::new().set_status(status::PRECONDITION_FAILED))
+        );
+
+        // If-Match takes precedence
+        let req = request(
+            &[],
+            &[
+                ("If-Unmodified-Since", "Thu, 01 Jan 1970 00:01:40 GMT"),
+                ("If-Match", "*"),
+            ],
+        );
+        assert_eq!(req.check_preconditions(Some(r#""v1""#), at(101)), Ok(()));
+    }
+
+    #[test]
+    fn header_lookup_ignores_casing() {
+        for case in [HeaderCase::Train, HeaderCase::Lower, HeaderCase::Original] {
+            let name = case.header_name("X_API_KEY");
+            let req = request(&[], &[(&name, "secret")]);
+            assert_eq!(req.header("X-Api-Key"), Some("secret"));
+            assert_eq!(req.header("x-api-key"), Some("secret"));
+            assert_eq!(req.header("X_API_KEY"), Some("secret"));
+            assert_eq!(req.header("X-Api"), None);
+        }
+
+        assert_eq!(HeaderCase::Train.header_name("DNT"), "Dnt");
+        assert_eq!(
+            HeaderCase::Train.header_name("X_B3_TRACEID"),
+            "X-B3-Traceid"
+        );
+        assert_eq!(HeaderCase::Lower.header_name("DNT"), "dnt");
+        assert_eq!(HeaderCase::Original.header_name("DNT"), "DNT");
+    }
+
+    #[test]
+    fn extensions() {
+        #[derive(Debug, PartialEq)]
+        struct User(&'static str);
+        struct Locale(&'static str);
+
+        let mut req = Request::default();
+        assert_eq!(req.ext::<User>(), None);
+
+        req.insert_ext(User("a"));
+        req.insert_ext(Locale("fr"));
+        req.insert_ext(User("b"));
+        assert_eq!(req.ext::<User>(), Some(&User("b")));
+        assert_eq!(req.ext::<Locale>().map(|l| l.0), Some("fr"));
+
+        let clone = req.clone();
+        assert_eq!(clone.ext::<User>(), Some(&User("b")));
+        assert_eq!(clone.extensions, req.extensions);
+        req.insert_ext(User("b"));
+        assert_ne!(clone.extensions, req.extensions);
+    }
+
+    fn stdout(response: &Response) -> String {
+        let mut bytes = vec![];
+        response
+            .write_stdout_bytes(&mut bytes, LineEnding::Lf)
+            .unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn bodiless_responses() {
+        assert_eq!(stdout(&Response::no_content()), "Status: 204\n\n");
+        assert_eq!(stdout(&Response::accepted()), "Status: 202\n\n");
+        assert_eq!(
+            stdout(&Response::created("/items/1")),
+            "Location: /items/1\nStatus: 201\n\n"
+        );
+
+        // Content-Type is only sent along with a body
+        let not_modified = Response::html("").set_status(status::NOT_MODIFIED);
+        assert_eq!(stdout(&not_modified), "Status: 304\n\n");
+        assert_eq!(
+            stdout(&Response::text("hi")),
+            "Content-Type: text/plain\nStatus: 200\n\nhi"
+        );
+        let streamed = Response::text("").set_body_stream(|_| Ok(()));
+        assert_eq!(
+            stdout(&streamed),
+            "Content-Type: text/plain\nStatus: 200\n\n"
+        );
+    }
+
+    #[test]
+    fn line_endings() {
+        let response = Response::text("hi").set_header("X-A", "b");
+
+        let mut crlf = vec![];
+        response
+            .write_stdout_bytes(&mut crlf, LineEnding::CrLf)
+            .unwrap();
+        assert_eq!(
+            crlf,
+            b"Content-Type: text/plain\r\nX-A: b\r\nStatus: 200\r\n\r\nhi"
+        );
+
+        // Values are validated when they are set, so this can only happen through a bug
+        let mut injected = Response::text("hi");
+        injected
+            .headers
+            .insert("X-A".into(), "b\r\nSet-Cookie: c=d\n\nevil".into());
+        assert_eq!(
+            stdout(&injected),
+            "Content-Type: text/plain\nX-A: b  Set-Cookie: c=d  evil\nStatus: 200\n\nhi"
+        );
+    }
+
+    #[test]
+    fn header_validation() {
+        let response = Response::new().try_set_header("X-A", "b\tc").unwrap();
+        assert_eq!(response.headers["X-A"], "b\tc");
+
+        assert_eq!(
+            Response::new().try_set_header("X-A", "b\r\nSet-Cookie: c=d"),
+            Err(InvalidHeader::Value("X-A".into()))
+        );
+        assert_eq!(
+            Response::new().try_set_header("X-A", "b\0"),
+            Err(InvalidHeader::Value("X-A".into()))
+        );
+        for name in ["", "X A", "X-A:", "X\nA", "é"] {
+            assert_eq!(
+                Response::new().try_set_header(name, "b"),
+                Err(InvalidHeader::Name(name.into()))
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid value for header 'Location'")]
+    fn set_header_panics_on_injection() {
+        let _ = Response::temporary_redirect("/a\r\nSet-Cookie: c=d");
+    }
+
+    #[test]
+    fn remote_user() {
+        assert_eq!(
+            request(&[("REMOTE_USER", "ferris")], &[]).remote_user(),
+            Some("ferris")
+        );
+        assert_eq!(request(&[("REMOTE_USER", "")], &[]).remote_user(), None);
+        // Clients can't claim to be someone
+        let req = request(
+            &[],
+            &[("Remote-User", "admin"), ("Variable-Remote-User", "admin")],
+        );
+        assert_eq!(req.remote_user(), None);
+    }
+
+    #[test]
+    fn redirect_preserving_query() {
+        let req = Request {
+            query_string: "q=fish&page=3".into(),
+            ..Request::default()
+        };
+        let response = Response::redirect_preserving_query(&req, "/find?page=1#results");
+        assert_eq!(response.status, status::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers["Location"], "/find?page=1&q=fish#results");
+
+        let response = Response::redirect_preserving_query(&Request::default(), "/find");
+        assert_eq!(response.headers["Location"], "/find");
+    }
+
+    #[test]
+    fn status_reason() {
+        let response = Response::new().set_status_with_reason(404, "Not Found");
+        assert_eq!(stdout(&response), "Status: 404 Not Found\n\n");
+
+        let reason = status::reason_phrase(status::TEAPOT).unwrap();
+        let response = Response::new().set_status_with_reason(status::TEAPOT, reason);
+        assert_eq!(stdout(&response), "Status: 418 I'm a teapot\n\n");
+        assert_eq!(status::reason_phrase(299), None);
+
+        // The reason does not outlive the status it was set with
+        let response = response.set_status(status::OK);
+        assert_eq!(stdout(&response), "Status: 200\n\n");
+    }
+
+    #[test]
+    fn flash_messages() {
+        let redirect = Response::new().with_flash("Saved; 100% done");
+        let cookie = &redirect.cookies[0];
+        assert!(cookie.starts_with("flash=Saved%3B%20100%25%20done;"));
+
+        let (pair, _) = cookie.split_once(';').unwrap();
+        let mut req = request(&[], &[("Cookie", &format!("theme=dark; {pair}"))]);
+        assert_eq!(req.take_flash().as_deref(), Some("Saved; 100% done"));
+        assert_eq!(req.take_flash(), None);
+
+        let response = Response::new().clear_taken_flash(&req);
+        assert_eq!(response.cookies, ["flash=; Path=/; Max-Age=0"]);
+        let response = Response::new().with_flash("Again").clear_taken_flash(&req);
+        assert_eq!(response.cookies.len(), 1);
+        assert!(response.cookies[0].starts_with("flash=Again;"));
+
+        // Other cookies of the response are kept, and don't keep the message from being cleared
+        let response = Response::new()
+            .add_cookie("session=1; Path=/")
+            .clear_taken_flash(&req);
+        assert_eq!(
+            response.cookies,
+            ["session=1; Path=/", "flash=; Path=/; Max-Age=0"]
+        );
+        let response = Response::new()
+            .with_flash("First")
+            .add_cookie("flashy=1")
+            .with_flash("Second");
+        assert_eq!(response.cookies.len(), 2);
+        assert!(response.cookies[1].starts_with("flash=Second;"));
+        assert_eq!(
+            stdout(&response.set_header("X-A", "b")),
+            "X-A: b\nSet-Cookie: flashy=1\nSet-Cookie: flash=Second; Path=/; HttpOnly; SameSite=Lax\nStatus: 200\n\n"
+        );
+
+        // Requests that don't take the message leave it alone
+        let req = request(&[], &[("Cookie", pair)]);
+        assert!(Response::new().clear_taken_flash(&req).cookies.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "signed-cookies")]
+    fn signed_flash_messages() {
+        let keyed = |cookie: &str, keys: &[&str]| Request {
+            keys: Some(Arc::new(Keys::new(
+                keys[0].into(),
+                keys[1..]
+                    .iter()
+                    .map(|key| key.as_bytes().to_vec())
+                    .collect(),
+            ))),
+            ..request(&[], &[("Cookie", cookie)])
+        };
+
+        let req = keyed("", &["old"]);
+        let redirect = Response::new()
+            .add_cookie("session=1")
+            .with_flash("Saved")
+            .sign_flash(&req);
+        assert_eq!(redirect.cookies[0], "session=1");
+        let cookie = &redirect.cookies[1];
+        assert!(cookie.starts_with("flash=Saved."));
+        assert!(cookie.ends_with("; Path=/; HttpOnly; SameSite=Lax"));
+
+        // Messages signed with a previous key are still accepted
+        let (pair, _) = cookie.split_once(';').unwrap();
+        let mut taken = keyed(pair, &["new", "old"]);
+        assert_eq!(taken.signed_cookie("flash"), Some("Saved"));
+        assert_eq!(taken.take_flash().as_deref(), Some("Saved"));
+
+        // Unsigned or tampered messages are not
+        let mut req = keyed("flash=Saved", &["new", "old"]);
+        assert_eq!(req.take_flash(), None);
+        let mut req = keyed(&pair.replacen("Saved", "Owned", 1), &["new", "old"]);
+        assert_eq!(req.take_flash(), None);
+        let mut req = keyed(pair, &["new"]);
+        assert_eq!(req.take_flash(), None);
+
+        // Clearing the message is left alone
+        let response = Response::new().clear_taken_flash(&taken).sign_flash(&taken);
+        assert_eq!(response.cookies, ["flash=; Path=/; Max-Age=0"]);
+    }
+}
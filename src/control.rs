@@ -0,0 +1,219 @@
+use crate::log_filter;
+use crate::stats::Stats;
+use mio::Waker;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+// What the commands of the control socket act on
+pub struct Controls {
+    pub stats: Arc<Stats>,
+    // The method and pattern of every route
+    pub routes: Vec<(String, String)>,
+    pub maintenance: Arc<AtomicBool>,
+    pub shutdown_requested: Arc<AtomicBool>,
+    pub server_waker: Arc<Waker>,
+}
+
+// The control socket of a server (see `ServerConfig::control_socket`).
+//
+// Connections are accepted on a thread of their own, and each gets a thread to read its commands
+// from. Dropping the socket stops accepting connections and removes the socket file. Connections
+// that are still open are left to finish on their own.
+pub struct ControlSocket {
+    path: PathBuf,
+    stopping: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ControlSocket {
+    pub fn bind(path: &Path, controls: Controls) -> io::Result<Self> {
+        let listener = UnixListener::bind(path)?;
+        // Only the user the server runs as may send commands
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        log::info!(path:? = path; "Control socket listening");
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        let controls = Arc::new(controls);
+        let thread = thread::spawn({
+            let stopping = stopping.clone();
+            move || {
+                for stream in listener.incoming() {
+                    if stopping.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    match stream {
+                        Ok(stream) => {
+                            let controls = controls.clone();
+                            thread::spawn(move || serve(stream, &controls));
+                        }
+                        Err(err) => {
+                            log::warn!(error:err = err; "Failed to accept a control connection")
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            stopping,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // Wakes up the thread blocked on `accept()`, which then sees that it must stop
+        if UnixStream::connect(&self.path).is_ok() {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Runs the commands sent on `stream`, one per line, until the peer closes it
+fn serve(stream: UnixStream, controls: &Controls) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(err) => {
+            log::warn!(error:err = err; "Failed to set up a control connection");
+            return;
+        }
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if writer
+            .write_all(execute(&line, controls).as_bytes())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+// Runs `command` and returns the reply: the lines of its output (if any), followed by `ok` or
+// by `error: ` and what went wrong.
+fn execute(command: &str, controls: &Controls) -> String {
+    log::info!(command = command; "Control command received");
+
+    let mut words = command.split_whitespace();
+    let mut reply = String::new();
+    let result = match (words.next().unwrap_or(""), words.next(), words.next()) {
+        ("stats", None, _) => {
+            for (route, stats) in controls.stats.routes() {
+                let _ = writeln!(
+                    reply,
+                    "route {route} requests={} bytes_in={} bytes_out={}",
+                    stats.requests, stats.bytes_in, stats.bytes_out
+                );
+            }
+            let _ = writeln!(reply, "write_errors {}", controls.stats.write_errors());
+            Ok(())
+        }
+        ("routes", None, _) => {
+            for (method, pattern) in &controls.routes {
+                let _ = writeln!(reply, "{method} {pattern}");
+            }
+            Ok(())
+        }
+        ("drain", None, _) => {
+            controls.shutdown_requested.store(true, Ordering::SeqCst);
+            controls
+                .server_waker
+                .wake()
+                .map_err(|err| format!("failed to wake up the server: {err}"))
+        }
+        ("maintenance", None, _) => {
+            let on = controls.maintenance.load(Ordering::SeqCst);
+            let _ = writeln!(reply, "maintenance {}", if on { "on" } else { "off" });
+            Ok(())
+        }
+        ("maintenance", Some(state @ ("on" | "off")), None) => {
+            controls.maintenance.store(state == "on", Ordering::SeqCst);
+            Ok(())
+        }
+        ("log-level", None, _) => {
+            let _ = writeln!(reply, "log-level {}", log_filter::current());
+            Ok(())
+        }
+        ("log-level", Some(filter), None) => log_filter::set(filter).map_err(|err| err.to_string()),
+        ("help", None, _) => {
+            reply.push_str(HELP);
+            Ok(())
+        }
+        _ => Err(format!("unknown command '{}' (try 'help')", command.trim())),
+    };
+
+    match result {
+        Ok(()) => reply.push_str("ok\n"),
+        Err(err) => {
+            let _ = writeln!(reply, "error: {err}");
+        }
+    }
+    reply
+}
+
+const HELP: &str = "\
+stats                    traffic of every route since the server started
+routes                   the method and pattern of every route
+drain                    finish in-flight requests, then stop the server
+maintenance [on|off]     answer every request with 503 Service Unavailable, or stop doing so
+log-level [filter]       show or change the log filter (e.g. info or warn,vintage=debug)
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let poll = mio::Poll::new().unwrap();
+        let stats = Arc::new(Stats::default());
+        stats.record("/users/{id}", 10, 20);
+        let controls = Controls {
+            stats,
+            routes: vec![("GET".to_string(), "/users/{id}".to_string())],
+            maintenance: Arc::default(),
+            shutdown_requested: Arc::default(),
+            server_waker: Arc::new(Waker::new(poll.registry(), mio::Token(0)).unwrap()),
+        };
+
+        assert_eq!(
+            execute("stats", &controls),
+            "route /users/{id} requests=1 bytes_in=10 bytes_out=20\nwrite_errors 0\nok\n"
+        );
+        assert_eq!(execute(" routes ", &controls), "GET /users/{id}\nok\n");
+
+        assert_eq!(execute("maintenance on", &controls), "ok\n");
+        assert!(controls.maintenance.load(Ordering::SeqCst));
+        assert_eq!(execute("maintenance", &controls), "maintenance on\nok\n");
+        assert_eq!(
+            execute("maintenance maybe", &controls),
+            "error: unknown command 'maintenance maybe' (try 'help')\n"
+        );
+
+        assert_eq!(
+            execute("log-level vintage=loud", &controls),
+            "error: Invalid log filter directive: 'vintage=loud'\n"
+        );
+
+        assert_eq!(execute("drain", &controls), "ok\n");
+        assert!(controls.shutdown_requested.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::context::{self, Request, Response};
+use crate::headers;
+use crate::router::PathSet;
+use crate::status;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use std::fmt;
+use std::io::{self, Read};
+
+/// Decompression of request bodies sent with a `Content-Encoding`
+///
+/// Bodies encoded with `gzip` or `deflate` are decompressed before they reach route handlers,
+/// middleware registered after it, and the [`ServerConfig::unhandled`] callback.
+/// The `Content-Encoding` header is removed, and the `CONTENT_LENGTH` variable is updated to
+/// the decompressed size.
+///
+/// Requests are rejected with:
+/// - `413 Content Too Large` if the decompressed body is larger than [`Decompression::max_size`].
+///   This guards against "zip bombs".
+/// - `415 Unsupported Media Type` if the encoding is not supported.
+/// - `400 Bad Request` if the body can't be decompressed.
+///
+/// See [`ServerConfig::decompression`]
+///
+/// [`ServerConfig::unhandled`]: crate::ServerConfig::unhandled
+/// [`ServerConfig::decompression`]: crate::ServerConfig::decompression
+#[derive(Clone)]
+pub struct Decompression {
+    max_size: usize,
+    except: PathSet,
+}
+
+impl Default for Decompression {
+    fn default() -> Self {
+        Self {
+            max_size: 64 * 1024 * 1024,
+            except: PathSet::default(),
+        }
+    }
+}
+
+impl fmt::Debug for Decompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decompression")
+            .field("max_size", &self.max_size)
+            .field("except", &self.except)
+            .finish()
+    }
+}
+
+impl Decompression {
+    /// Creates the default decompression settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the largest decompressed body accepted. The default is 64MiB.
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Leaves the bodies of requests to `paths` as they were sent
+    ///
+    /// Paths use the same syntax as routes (see [`ServerConfig::on`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a path is not a valid route, or was already excluded
+    ///
+    /// [`ServerConfig::on`]: crate::ServerConfig::on
+    #[track_caller]
+    pub fn except<const N: usize>(mut self, paths: [&str; N]) -> Self {
+        self.except.extend(paths);
+        self
+    }
+
+    /// Decompresses the body of `req` in place
+    ///
+    /// Returns an error response if the request should be rejected.
+    pub fn decompress(&self, req: &mut Request) -> Option<Response> {
+        let encoding = req.header(headers::CONTENT_ENCODING)?.to_string();
+        if self.except.matches(req.path()) {
+            return None;
+        }
+
+        // Encodings are listed in the order they were applied
+        let mut body = req.take_body();
+        for coding in encoding.rsplit(',').map(str::trim) {
+            let result = match coding.to_ascii_lowercase().as_str() {
+                "" | "identity" => continue,
+                "gzip" | "x-gzip" => self.read(GzDecoder::new(body.as_slice())),
+                "deflate" => self.read(ZlibDecoder::new(body.as_slice())),
+                _ => {
+                    log::debug!(encoding = coding; "Unsupported request body encoding");
+                    return Some(
+                        Response::new()
+                            .set_status(status::UNSUPPORTED_MEDIA_TYPE)
+                            .set_header(headers::ACCEPT_ENCODING, "gzip, deflate"),
+                    );
+                }
+            };
+
+            body = match result {
+                Ok(Some(decompressed)) => decompressed,
+                Ok(None) => return Some(Response::new().set_status(status::CONTENT_TOO_LARGE)),
+                Err(err) => {
+                    log::debug!(error:err = err; "Failed to decompress request body");
+                    return Some(Response::new().set_status(status::BAD_REQUEST));
+                }
+            };
+        }
+
+        req.headers
+            .retain(|name, _| !context::same_header_name(name, headers::CONTENT_ENCODING));
+        req.vars
+            .insert("CONTENT_LENGTH".to_string(), body.len().to_string());
+        req.body = body;
+        None
+    }
+
+    // Reads all of `decoder`, or returns `None` if that is more than `max_size` bytes
+    fn read<R: Read>(&self, decoder: R) -> io::Result<Option<Vec<u8>>> {
+        let mut body = vec![];
+        let limit = self.max_size as u64 + 1;
+        decoder.take(limit).read_to_end(&mut body)?;
+        if body.len() > self.max_size {
+            return Ok(None);
+        }
+        Ok(Some(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use std::io::Write;
+
+    fn request(path: &str, encoding: &str, body: Vec<u8>) -> Request {
+        Request {
+            path: path.into(),
+            headers: [("Content-Encoding".to_string(), encoding.to_string())].into(),
+            body,
+            ..Request::default()
+        }
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn deflate(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decompress() {
+        let decompression = Decompression::new();
+
+        let mut req = request("/", "gzip", gzip(b"hello"));
+        assert_eq!(decompression.decompress(&mut req), None);
+        assert_eq!(req.body(), b"hello");
+        assert_eq!(req.header("Content-Encoding"), None);
+        assert_eq!(req.var("CONTENT_LENGTH"), Some("5"));
+
+        let mut req = request("/", "deflate, gzip", gzip(&deflate(b"hello")));
+        assert_eq!(decompression.decompress(&mut req), None);
+        assert_eq!(req.body(), b"hello");
+
+        let mut req = Request {
+            body: b"plain".to_vec(),
+            ..Request::default()
+        };
+        assert_eq!(decompression.decompress(&mut req), None);
+        assert_eq!(req.body(), b"plain");
+    }
+
+    #[test]
+    fn rejections() {
+        let decompression = Decompression::new().max_size(1024);
+
+        let mut req = request("/", "gzip", gzip(&[0; 1025]));
+        let response = decompression.decompress(&mut req).unwrap();
+        assert_eq!(response.status, status::CONTENT_TOO_LARGE);
+
+        let mut req = request("/", "gzip", gzip(&[0; 1024]));
+        assert_eq!(decompression.decompress(&mut req), None);
+
+        let mut req = request("/", "br", vec![1, 2, 3]);
+        let response = decompression.decompress(&mut req).unwrap();
+        assert_eq!(response.status, status::UNSUPPORTED_MEDIA_TYPE);
+
+        let mut req = request("/", "gzip", b"not gzip".to_vec());
+        let response = decompression.decompress(&mut req).unwrap();
+        assert_eq!(response.status, status::BAD_REQUEST);
+    }
+
+    #[test]
+    fn except() {
+        let decompression = Decompression::new().except(["/raw/{*path}"]);
+        let compressed = gzip(b"hello");
+
+        let mut req = request("/raw/upload", "gzip", compressed.clone());
+        assert_eq!(decompression.decompress(&mut req), None);
+        assert_eq!(req.body(), compressed);
+        assert_eq!(req.header("Content-Encoding"), Some("gzip"));
+    }
+}
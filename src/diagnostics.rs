@@ -0,0 +1,145 @@
+use crate::fastcgi_responder;
+use crate::listener::ListenerInfo;
+use crate::server_config::{ServerConfig, WorkerModel};
+use std::fmt;
+
+/// How a running server is set up, for bug reports and admin pages
+///
+/// Its `Display` implementation is a one-line summary, which is also logged when the server
+/// starts if [`ServerConfig::startup_banner`] is enabled.
+///
+/// See [`ServerHandle::diagnostics`](crate::ServerHandle::diagnostics)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Diagnostics {
+    /// The version of `vintage`
+    pub version: &'static str,
+    /// The operating system the server runs on (e.g. `linux`)
+    pub os: &'static str,
+    /// The CPU architecture the server was compiled for (e.g. `x86_64`)
+    pub arch: &'static str,
+    /// The optional features of `vintage` that were compiled in (e.g. `fs`)
+    pub features: Vec<&'static str>,
+    /// Every socket the server is listening on, as returned by
+    /// [`ServerHandle::listeners`](crate::ServerHandle::listeners)
+    pub listeners: Vec<ListenerInfo>,
+    /// How connections are spread between threads
+    pub worker_model: WorkerModel,
+    /// How many threads handle connections
+    pub workers: usize,
+    /// How many accepted connections can wait for a worker thread, if that is bounded
+    pub queue_capacity: Option<usize>,
+    /// How many packets a single FastCGI record can be split into
+    pub max_record_packets: usize,
+    /// How many bytes of params and request body can be buffered for a single connection
+    pub max_connection_memory: usize,
+    /// How many bytes of params and request bodies all requests can buffer together, if that is
+    /// bounded
+    pub memory_budget: Option<usize>,
+    /// The capacity, in bytes, of the read and write buffers of each connection
+    pub buffer_sizes: (usize, usize),
+}
+
+impl Diagnostics {
+    pub(crate) fn new(config: &ServerConfig, listeners: Vec<ListenerInfo>, workers: usize) -> Self {
+        let limits = fastcgi_responder::read_limits(config);
+        let features = [
+            ("fs", cfg!(feature = "fs")),
+            ("serde", cfg!(feature = "serde")),
+            ("tls", cfg!(feature = "tls")),
+            ("arbitrary", cfg!(feature = "arbitrary")),
+        ];
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            features: features
+                .into_iter()
+                .filter_map(|(feature, enabled)| enabled.then_some(feature))
+                .collect(),
+            listeners,
+            worker_model: config.worker_model,
+            workers,
+            queue_capacity: config.queue_capacity,
+            max_record_packets: limits.max_packets_per_record,
+            max_connection_memory: limits.remaining_bytes,
+            memory_budget: config.memory_budget.as_ref().map(|budget| budget.limit()),
+            buffer_sizes: config.connection_buffer_sizes(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vintage {} ({}-{}", self.version, self.os, self.arch)?;
+        if !self.features.is_empty() {
+            write!(f, ", features: {}", self.features.join(", "))?;
+        }
+        write!(f, ") listening on ")?;
+        for (i, listener) in self.listeners.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{listener}")?;
+        }
+
+        write!(f, "; {} workers ({:?})", self.workers, self.worker_model)?;
+        match self.queue_capacity {
+            Some(capacity) => write!(f, ", queue of {capacity}")?,
+            None => write!(f, ", unbounded queue")?,
+        }
+        write!(
+            f,
+            "; limits: {} packets per record, {} bytes per connection",
+            self.max_record_packets, self.max_connection_memory
+        )?;
+        if let Some(budget) = self.memory_budget {
+            write!(f, ", {budget} bytes for all requests")?;
+        }
+        let (read, write) = self.buffer_sizes;
+        write!(f, "; buffers: {read} bytes read, {write} bytes write")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listener::SocketOptions;
+    use crate::MemoryPolicy;
+
+    #[test]
+    fn summary() {
+        let config = ServerConfig::new()
+            .max_connection_memory(1024)
+            .memory_budget(4096, MemoryPolicy::Shed);
+        let listener = ListenerInfo::Tcp {
+            address: "127.0.0.1:9000".parse().unwrap(),
+            options: SocketOptions {
+                backlog: Some(128),
+                reuse_address: true,
+            },
+        };
+        let diagnostics = Diagnostics {
+            features: vec!["fs"],
+            ..Diagnostics::new(&config, vec![listener], 4)
+        };
+
+        let expected = format!(
+            "vintage {} ({}-{}, features: fs) listening on tcp://127.0.0.1:9000; 4 workers \
+             (ThreadPool), unbounded queue; limits: 65536 packets per record, 1024 bytes per \
+             connection, 4096 bytes for all requests; buffers: 8192 bytes read, 8192 bytes write",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+        );
+        assert_eq!(diagnostics.to_string(), expected);
+    }
+
+    #[test]
+    fn features() {
+        let diagnostics = Diagnostics::new(&ServerConfig::new(), vec![], 1);
+        assert_eq!(diagnostics.features.contains(&"tls"), cfg!(feature = "tls"));
+        assert_eq!(diagnostics.features.contains(&"fs"), cfg!(feature = "fs"));
+    }
+}
@@ -6,12 +6,18 @@ pub enum Error {
     UnexpectedSocketClose(io::Error),
     UnsuportedVersion(u8),
     UnknownRecordType(u8),
-    MultiplexingUnsupported,
+    // A packet of a request other than the one being served, which was skipped
+    OtherRequest { request_id: u16, type_id: u8 },
     MalformedRecordPayload(&'static str),
     UnsupportedRole(u16),
     UnspportedProtocolStatus(u8),
     InvalidUtf8KeyValuePair,
-    MalformedRecordStream,
+    // A packet of another type arrived in the middle of a stream record
+    MalformedRecordStream { expected: u8, received: u8 },
+    LimitExceeded(&'static str),
+    // The params of a request are too many or too large. The request can still be answered.
+    ParamLimitExceeded(&'static str),
+    MemoryBudgetExceeded,
 }
 
 impl Display for Error {
@@ -26,10 +32,10 @@ impl Display for Error {
             Self::UnknownRecordType(t) => {
                 write!(f, "Unknown record type: '{t}'")
             }
-            Self::MultiplexingUnsupported => {
+            Self::OtherRequest { request_id, .. } => {
                 write!(
                     f,
-                    "Multiplexing multiple requests unto a single connection is not supported"
+                    "Received a record for request {request_id}, but multiplexing multiple requests unto a single connection is not supported"
                 )
             }
             Self::MalformedRecordPayload(s) => {
@@ -42,8 +48,20 @@ impl Display for Error {
             Self::InvalidUtf8KeyValuePair => {
                 write!(f, "Detected invalid utf8 in a key-value pair")
             }
-            Self::MalformedRecordStream => {
-                write!(f, "Web server sent a malformed record stream")
+            Self::MalformedRecordStream { expected, received } => {
+                write!(
+                    f,
+                    "Web server sent a malformed record stream: a packet of type {received} in the middle of a record of type {expected}"
+                )
+            }
+            Self::LimitExceeded(limit) => {
+                write!(f, "Web server exceeded the limit on {limit}")
+            }
+            Self::ParamLimitExceeded(limit) => {
+                write!(f, "The request params exceed the limit on {limit}")
+            }
+            Self::MemoryBudgetExceeded => {
+                write!(f, "The server's memory budget can't fit the request")
             }
         }
     }
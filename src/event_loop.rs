@@ -1,28 +1,642 @@
-use crate::connection::Connection;
+use crate::connection::{Connection, PendingWrite, Stream};
+#[cfg(unix)]
+use crate::control::{ControlSocket, Controls};
+use crate::diagnostics::Diagnostics;
 use crate::fastcgi_responder;
-use crate::server_config::ServerConfig;
-use crate::server_handle::{ServerExitReason, ServerHandle};
+use crate::listener::{self, ListenerInfo, Socket, SocketOptions, DEFAULT_BACKLOG};
+use crate::panic_report;
+use crate::queue::{Queued, RequestQueue};
+use crate::scheduler::Scheduler;
+use crate::server_config::{ServerConfig, WorkerModel};
+use crate::server_handle::{Listening, ServerExitReason, ServerHandle, ServerOperation};
+use crate::start_error::{StartError, StartFailure, StartStep};
+use crate::stats::Stats;
 use mio::event::Events;
 use mio::net::TcpListener;
 use mio::{Interest, Poll, Token, Waker};
+use std::collections::BTreeMap;
 use std::io;
 use std::net::SocketAddr;
-use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 // Tokens used for the MIO event loop
 const SERVER: Token = Token(0);
-const SHUTDOWN: Token = Token(1);
+const WAKER: Token = Token(1);
+// Additional listeners are assigned tokens starting from this one.
+// Connections with pending writes are assigned the tokens that follow them.
+const FIRST_LISTENER: usize = 2;
+
+// How long to wait on slow clients when flushing pending writes during shutdown
+const SHUTDOWN_WRITE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Lets worker threads hand off the rest of a response to the event loop when the client is too
+// slow to receive it. This way, slow clients don't hold on to worker threads.
+#[derive(Clone)]
+pub struct WriteHandoff {
+    sender: Sender<PendingWrite>,
+    waker: Arc<Waker>,
+}
+
+impl WriteHandoff {
+    // Wakes up the event loop, so that it accepts connections again once the request queue has
+    // room for them
+    pub fn wake(&self) {
+        if let Err(err) = self.waker.wake() {
+            log::warn!(error:err = err; "Failed to wake up the server loop");
+        }
+    }
+
+    pub fn send(&self, pending: PendingWrite) {
+        // If the event loop is gone, the pending write is dropped, which closes the connection.
+        if self.sender.send(pending).is_ok() {
+            if let Err(err) = self.waker.wake() {
+                log::warn!(error:err = err; "Failed to wake up the server loop for a pending write");
+            }
+        }
+    }
+}
+
+// How many shed connections can wait to be rejected. Past that, they are closed outright.
+const MAX_PENDING_REJECTIONS: usize = 64;
+
+// Runs the connections accepted by an event loop
+enum Executor {
+    // Connections wait in a queue for a pool of worker threads.
+    // Connections shed from the queue are rejected by a dedicated thread, so that shedding load
+    // does not take workers away from the pool.
+    Pool {
+        pool: threadpool::ThreadPool,
+        queue: Arc<RequestQueue>,
+        rejections: SyncSender<(Connection, ServerConfig)>,
+        // Set once the server is shutting down. Connections still waiting in the queue then are
+        // turned away, with a `Retry-After` of `drain_retry_after`.
+        draining: Arc<AtomicBool>,
+        drain_retry_after: Duration,
+    },
+    // Connections are handled on the event loop thread itself
+    Inline,
+}
+
+impl Executor {
+    // `draining` is set when the server starts shutting down
+    fn new(config: &ServerConfig, draining: Arc<AtomicBool>) -> Self {
+        match config.worker_model {
+            WorkerModel::ThreadPool => {
+                let (rejections, rejected) = sync_channel(MAX_PENDING_REJECTIONS);
+                // Exits once the executor is dropped
+                thread::spawn(move || {
+                    for (connection, config) in rejected {
+                        fastcgi_responder::reject_connection(connection, &config, None);
+                    }
+                });
+
+                Self::Pool {
+                    pool: threadpool::Builder::new()
+                        .num_threads(worker_threads())
+                        .build(),
+                    queue: Arc::new(RequestQueue::new(
+                        config.queue_capacity,
+                        config.queue_policy,
+                    )),
+                    rejections,
+                    draining,
+                    drain_retry_after: config.drain_retry_after_or_default(),
+                }
+            }
+            #[cfg(unix)]
+            WorkerModel::ThreadPerCore => Self::Inline,
+        }
+    }
+
+    fn execute(
+        &self,
+        connection: Connection,
+        accepted_at: Instant,
+        config: &ServerConfig,
+        handoff: &WriteHandoff,
+    ) {
+        let Self::Pool {
+            pool,
+            queue,
+            rejections,
+            draining,
+            drain_retry_after,
+        } = self
+        else {
+            fastcgi_responder::handle_connection(
+                connection,
+                config.clone(),
+                handoff.clone(),
+                Duration::ZERO,
+            );
+            return;
+        };
+
+        let queued = Queued {
+            connection,
+            config: config.clone(),
+            accepted_at,
+        };
+        if let Some(shed) = queue.push(queued) {
+            let waited = shed.config.clock.since(shed.accepted_at);
+            log::warn!(policy:? = queue.policy(), waited_micro = waited.as_micros(); "Request queue is full. Rejecting a connection");
+            if rejections.try_send((shed.connection, shed.config)).is_err() {
+                log::warn!("Too many connections waiting to be rejected. Closing connection");
+            }
+        }
+
+        // Every job takes whichever connection is at the front of the queue.
+        // There can be more jobs than queued connections when some are shed, in which case the
+        // extra jobs have nothing to do.
+        pool.execute({
+            let queue = queue.clone();
+            let handoff = handoff.clone();
+            let draining = draining.clone();
+            let retry_after = *drain_retry_after;
+            move || {
+                if let Some(queued) = queue.pop(|| handoff.wake()) {
+                    if draining.load(Ordering::SeqCst) {
+                        log::info!("The server is shutting down. Rejecting a queued connection");
+                        fastcgi_responder::reject_connection(
+                            queued.connection,
+                            &queued.config,
+                            Some(retry_after),
+                        );
+                        return;
+                    }
+                    let waited = queued.config.clock.since(queued.accepted_at);
+                    fastcgi_responder::handle_connection(
+                        queued.connection,
+                        queued.config,
+                        handoff,
+                        waited,
+                    );
+                }
+            }
+        });
+    }
+
+    // Whether new connections can be accepted, or must wait in the listen backlog until the
+    // request queue has room for them
+    fn accepts(&self) -> bool {
+        match self {
+            Self::Pool { queue, .. } => queue.accepts(),
+            Self::Inline => true,
+        }
+    }
+
+    // Waits for in-flight connections to be handled, unless `abort` is true.
+    // `configs` are the configurations of every listener of the event loop.
+    //
+    // This should always be called before an event loop exits, regardless of cause.
+    fn shutdown<'a>(self, abort: bool, configs: impl Iterator<Item = &'a ServerConfig>) {
+        match self {
+            // Dropping the pool without joining it detaches the worker threads.
+            // In-flight requests are left to finish on their own.
+            Self::Pool { pool, .. } if abort => drop(pool),
+            Self::Pool { pool, .. } => {
+                pool.join();
+                drop(pool);
+            }
+            Self::Inline => {}
+        }
+
+        // Requests handed off to bulkheads by the workers are only complete once handled there
+        if !abort {
+            for bulkhead in configs.flat_map(|config| &config.bulkheads) {
+                bulkhead.join();
+            }
+        }
+    }
+}
+
+// An event loop running on another thread, in the thread-per-core model.
+// It is owned by the event loop of the server thread, which stops it on shutdown.
+struct Peer {
+    waker: Arc<Waker>,
+    thread: thread::JoinHandle<ServerExitReason>,
+}
 
 struct EventLoop {
-    socket: TcpListener,
+    socket: Socket,
+    // See `ServerHandle::address`
+    address: SocketAddr,
     config: ServerConfig,
+    // The additional listeners, with the configuration their connections are handled with
+    listeners: Vec<(Socket, ServerConfig)>,
     poll: Poll,
     events: Events,
-    signal_shutdown: SyncSender<()>,
+    shutdown_requested: Arc<AtomicBool>,
+    abort_requested: Arc<AtomicBool>,
+    // Only the event loop of the server thread rendezvous with `ServerHandle` on shutdown
+    signal_shutdown: Option<SyncSender<()>>,
+    handoff: WriteHandoff,
+    handoffs: Receiver<PendingWrite>,
+    pending_writes: BTreeMap<Token, PendingWrite>,
+    next_token: usize,
+    // Set when connections were left in the listen backlogs because the request queue was full.
+    // Listeners only signal new connections, so they are all accepted from again once there is
+    // room.
+    accept_paused: bool,
+    peers: Vec<Peer>,
+    scheduler: Option<Scheduler>,
+    #[cfg(unix)]
+    control: Option<ControlSocket>,
+}
+
+impl EventLoop {
+    fn new(
+        mut socket: Socket,
+        config: ServerConfig,
+        mut listeners: Vec<(Socket, ServerConfig)>,
+        shutdown_requested: Arc<AtomicBool>,
+        abort_requested: Arc<AtomicBool>,
+        signal_shutdown: Option<SyncSender<()>>,
+    ) -> Result<(Self, Arc<Waker>), io::Error> {
+        let address = socket.local_addr()?;
+        let poll = Poll::new()?;
+        let events = Events::with_capacity(128);
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+
+        socket.register(poll.registry(), SERVER, &waker)?;
+        for (i, (listener, _)) in listeners.iter_mut().enumerate() {
+            listener.register(poll.registry(), Token(FIRST_LISTENER + i), &waker)?;
+        }
+        let next_token = FIRST_LISTENER + listeners.len();
+
+        let (sender, handoffs) = channel();
+
+        let event_loop = EventLoop {
+            socket,
+            address,
+            config,
+            listeners,
+            poll,
+            events,
+            shutdown_requested,
+            abort_requested,
+            signal_shutdown,
+            handoff: WriteHandoff {
+                sender,
+                waker: waker.clone(),
+            },
+            handoffs,
+            pending_writes: BTreeMap::new(),
+            next_token,
+            accept_paused: false,
+            peers: vec![],
+            scheduler: None,
+            #[cfg(unix)]
+            control: None,
+        };
+
+        Ok((event_loop, waker))
+    }
+
+    // The configurations of the main listener and of the additional ones
+    fn configs(&self) -> impl Iterator<Item = &ServerConfig> {
+        std::iter::once(&self.config).chain(self.listeners.iter().map(|(_, config)| config))
+    }
+
+    // Accepts the pending connections of the listener registered with `token`, and hands them to
+    // `executor`.
+    //
+    // Returns `false` if it stopped because the request queue is full, leaving connections in
+    // the listen backlog.
+    fn accept(&self, token: Token, executor: &Executor) -> Result<bool, ServerExitReason> {
+        let (socket, config) = match token {
+            SERVER => (&self.socket, &self.config),
+            Token(i) => {
+                let (socket, config) = &self.listeners[i - FIRST_LISTENER];
+                (socket, config)
+            }
+        };
+
+        loop {
+            if !executor.accepts() {
+                return Ok(false);
+            }
+            match socket.accept() {
+                Ok(stream) => {
+                    let connection = set_up_connection(stream, config).map_err(|err| {
+                        log::warn!(error:err = err; "Failed to setup accepted connection. Server loop will exit");
+                        self.error(ServerOperation::Accept, err)
+                    })?;
+                    executor.execute(connection, config.clock.instant(), config, &self.handoff);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(err) => {
+                    log::warn!(error:err = err; "Socket accept call failed. Server loop will exit");
+                    return Err(self.error(ServerOperation::Accept, err));
+                }
+            }
+        }
+    }
+
+    // Accepts from the listener registered with `token`, pausing when the request queue is full
+    fn accept_from(&mut self, token: Token, executor: &Executor) -> Result<(), ServerExitReason> {
+        if !self.accept(token, executor)? {
+            self.accept_paused = true;
+        }
+        Ok(())
+    }
+
+    // Accepts the connections that the threads of the named pipes woke the loop up for
+    #[cfg(windows)]
+    fn accept_from_pipes(&mut self, executor: &Executor) -> Result<(), ServerExitReason> {
+        for i in 0..self.listeners.len() {
+            if matches!(self.listeners[i].0, Socket::NamedPipe(_)) {
+                self.accept_from(Token(FIRST_LISTENER + i), executor)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Accepts the connections left in the listen backlogs, once the request queue has room for
+    // them again
+    fn resume_accepting(&mut self, executor: &Executor) -> Result<(), ServerExitReason> {
+        if !self.accept_paused || !executor.accepts() {
+            return Ok(());
+        }
+        self.accept_paused = false;
+        let tokens = std::iter::once(SERVER)
+            .chain((0..self.listeners.len()).map(|i| Token(FIRST_LISTENER + i)));
+        for token in tokens {
+            self.accept_from(token, executor)?;
+            if self.accept_paused {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Stops the scheduled jobs and the event loops of the other threads, and waits for them to exit
+    fn stop_threads(&mut self) {
+        #[cfg(unix)]
+        drop(self.control.take());
+
+        if let Some(scheduler) = self.scheduler.take() {
+            // A job that is running when the server is aborted is left to finish on its own
+            let aborted = self.abort_requested.load(Ordering::SeqCst);
+            scheduler.stop(!aborted);
+        }
+
+        if self.peers.is_empty() {
+            return;
+        }
+        // The other threads only exit when woken up for a shutdown. The server may be exiting
+        // because of an error instead, which they would not know about.
+        self.shutdown_requested.store(true, Ordering::SeqCst);
+        for peer in &self.peers {
+            if let Err(err) = peer.waker.wake() {
+                log::warn!(error:err = err; "Failed to wake up a worker thread for shutdown");
+            }
+        }
+
+        for peer in std::mem::take(&mut self.peers) {
+            match peer.thread.join() {
+                Ok(ServerExitReason::Normal | ServerExitReason::Aborted) => {}
+                Ok(ServerExitReason::Panic(report)) => {
+                    log::warn!(panic:% = report, backtrace:% = report.backtrace(); "Worker thread panicked")
+                }
+                Ok(reason) => log::warn!(reason:% = reason; "Worker thread exited abnormally"),
+                Err(_) => log::warn!("Worker thread panicked"),
+            }
+        }
+    }
+
+    // Starts watching the connections that were handed off by worker threads
+    fn register_pending_writes(&mut self) {
+        while let Ok(mut pending) = self.handoffs.try_recv() {
+            let token = Token(self.next_token);
+            self.next_token += 1;
+
+            let registered =
+                self.poll
+                    .registry()
+                    .register(pending.stream(), token, Interest::WRITABLE);
+
+            match registered {
+                Ok(()) => {
+                    self.pending_writes.insert(token, pending);
+                }
+                Err(err) => {
+                    log::warn!(error:err = err; "Failed to watch connection with a pending write. Closing connection");
+                }
+            }
+        }
+    }
+
+    // Continues writing to a connection that became writable
+    fn resume_pending_write(&mut self, token: Token) {
+        let Some(pending) = self.pending_writes.get_mut(&token) else {
+            return;
+        };
+
+        match pending.write() {
+            Ok(false) => return,
+            Ok(true) => {}
+            Err(err) => {
+                log::warn!(error:err = err; "Failed to write response. Closing connection");
+                self.config.stats.record_write_error();
+            }
+        }
+
+        if let Some(mut pending) = self.pending_writes.remove(&token) {
+            let _ = self.poll.registry().deregister(pending.stream());
+        }
+    }
+
+    // Blocks until all pending writes complete (or time out)
+    fn finish_pending_writes(&mut self) {
+        self.register_pending_writes();
+        for (_, mut pending) in std::mem::take(&mut self.pending_writes) {
+            let _ = self.poll.registry().deregister(pending.stream());
+            if let Err(err) = pending.finish_blocking(SHUTDOWN_WRITE_TIMEOUT) {
+                log::warn!(error:err = err; "Failed to write response during shutdown. Closing connection");
+                self.config.stats.record_write_error();
+            }
+        }
+    }
+
+    fn error(&self, operation: ServerOperation, error: io::Error) -> ServerExitReason {
+        ServerExitReason::Err {
+            operation,
+            address: self.address,
+            timestamp: SystemTime::now(),
+            error,
+        }
+    }
+}
+
+// The other threads are stopped however the event loop exits (e.g. if it panics), so that they
+// don't keep serving on their own
+impl Drop for EventLoop {
+    fn drop(&mut self) {
+        self.stop_threads();
+    }
+}
+
+// A server whose sockets are bound, ready to run on the thread of the caller's choice
+struct Prepared {
+    spec: ServerConfig,
+    event_loop: EventLoop,
+    executor: Executor,
+    peer_loops: Vec<(EventLoop, Arc<Waker>, Executor)>,
+    address: SocketAddr,
+    listener: ListenerInfo,
+    listener_infos: Vec<ListenerInfo>,
+    server_waker: Arc<Waker>,
+    shutdown_requested: Arc<AtomicBool>,
+    abort_requested: Arc<AtomicBool>,
+    observe_shutdown: Receiver<()>,
+    stats: Arc<Stats>,
+    maintenance: Arc<AtomicBool>,
+}
+
+impl Prepared {
+    fn diagnostics(&self) -> Diagnostics {
+        let listeners = std::iter::once(&self.listener).chain(&self.listener_infos);
+        Diagnostics::new(&self.spec, listeners.cloned().collect(), worker_threads())
+    }
+}
+
+pub fn create_handle(spec: ServerConfig, address: SocketAddr) -> Result<ServerHandle, StartError> {
+    let prepared = prepare(spec, address)?;
+    let diagnostics = prepared.diagnostics();
+    let Prepared {
+        spec,
+        event_loop,
+        executor,
+        peer_loops,
+        address,
+        listener,
+        listener_infos,
+        server_waker,
+        shutdown_requested,
+        abort_requested,
+        observe_shutdown,
+        stats,
+        maintenance,
+    } = prepared;
+
+    // Only used to observe the server thread exiting. See `ServerHandle::join_timeout()`
+    let (signal_exit, observe_exit) = sync_channel(0);
+    // Reports whether the `before_serve` hooks succeeded
+    let (signal_ready, observe_ready) = sync_channel(1);
+
+    let handle = thread::spawn(move || {
+        let _signal_exit: SyncSender<()> = signal_exit;
+        panic_report::catch(|| {
+            serve(event_loop, executor, peer_loops, |result| {
+                let _ = signal_ready.send(result);
+            })
+        })
+        .unwrap_or_else(ServerExitReason::Panic)
+    });
+
+    match observe_ready.recv() {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            let _ = handle.join();
+            return Err(StartError::single(StartStep::BeforeServe, err));
+        }
+        Err(_) => {
+            let mut message = String::from("The server thread panicked before serving requests");
+            if let Ok(ServerExitReason::Panic(report)) = handle.join() {
+                message = format!("{message}: {report}");
+            }
+            return Err(setup_failed(io::Error::other(message)));
+        }
+    }
+
+    if spec.startup_banner {
+        log::info!("{diagnostics}");
+    }
+
+    Ok(ServerHandle {
+        listener: Box::new(Listening {
+            address,
+            info: listener,
+            others: listener_infos,
+        }),
+        server_loop: handle,
+        server_waker,
+        shutdown_requested,
+        abort_requested,
+        observe_shutdown,
+        observe_exit,
+        stats,
+        maintenance,
+        diagnostics: Box::new(diagnostics),
+    })
+}
+
+// Runs a server on the calling thread until it exits. See `crate::run()`
+pub fn run(spec: ServerConfig, address: SocketAddr) -> Result<ServerExitReason, StartError> {
+    let prepared = prepare(spec, address)?;
+    let diagnostics = prepared.diagnostics();
+    let startup_banner = prepared.spec.startup_banner;
+    // Nobody waits for the server to stop, but the server thread still reports it
+    let _observe_shutdown = prepared.observe_shutdown;
+
+    let mut failed = None;
+    let reason = panic_report::catch(|| {
+        serve(
+            prepared.event_loop,
+            prepared.executor,
+            prepared.peer_loops,
+            |result| match result {
+                Ok(()) if startup_banner => log::info!("{diagnostics}"),
+                Ok(()) => {}
+                Err(err) => failed = Some(err),
+            },
+        )
+    })
+    .unwrap_or_else(ServerExitReason::Panic);
+    match failed {
+        Some(err) => Err(StartError::single(StartStep::BeforeServe, err)),
+        None => Ok(reason),
+    }
+}
+
+// Runs the `before_serve` hooks, starts the other threads, then runs the event loop until the
+// server exits.
+//
+// `ready` is told whether the hooks succeeded, before any connection is accepted.
+fn serve(
+    mut event_loop: EventLoop,
+    executor: Executor,
+    peer_loops: Vec<(EventLoop, Arc<Waker>, Executor)>,
+    ready: impl FnOnce(io::Result<()>),
+) -> ServerExitReason {
+    for hook in &event_loop.config.before_serve {
+        if let Err(err) = hook() {
+            ready(Err(err));
+            // Nobody observes this. The error is reported by `ready` instead.
+            return ServerExitReason::Normal;
+        }
+    }
+
+    for (peer_loop, waker, executor) in peer_loops {
+        let thread = thread::spawn(move || {
+            panic_report::catch(|| start(peer_loop, executor))
+                .unwrap_or_else(ServerExitReason::Panic)
+        });
+        event_loop.peers.push(Peer { waker, thread });
+    }
+    event_loop.scheduler = Scheduler::start(event_loop.config.schedules.clone());
+
+    ready(Ok(()));
+    start(event_loop, executor)
 }
 
-pub fn create_handle(spec: ServerConfig, address: SocketAddr) -> Result<ServerHandle, io::Error> {
+// Binds the sockets of a server, and sets up everything it needs to run
+fn prepare(spec: ServerConfig, address: SocketAddr) -> Result<Prepared, StartError> {
     // One of the requirements is that the user of the library be able to shutdown the server
     // gracefully. This means that there should be some way for the user to say "finish all
     // in-flight work, then stop the thread pool".
@@ -44,115 +658,335 @@ pub fn create_handle(spec: ServerConfig, address: SocketAddr) -> Result<ServerHa
     // This gives us a nice way to implement graceful shutdown:
     // 1) Wake up the server thread from the `poll()` call with a Waker.
     // 2) On the server thread, join the thread pool, and drop it.
-    // 3) Use a bounded channel of size 0 to "rendezvous" the main thread and the server
-    //    thread. (A bounded channel of size 0 acts as a barrier. But allows timeouts.)
+    // 3) Use a bounded channel to tell the main thread that the server thread is done.
+    //    (It has room for one message, so that the server thread doesn't block when the
+    //    shutdown was requested from the control socket and nobody is waiting.)
     //
     // That said, working with mio requires some care.
     // Familiarize yourself with this section of its documentation as any comments that follow
     // assume a baseline understanding of the workflow:
     // https://docs.rs/mio/latest/mio/struct.Poll.html#portability
 
-    let mut socket = TcpListener::bind(address)?;
+    for task in &spec.on_start {
+        if let Err(err) = task() {
+            log::error!(error:% = err; "Start-up task failed. The server will not start");
+            return Err(StartError::single(
+                StartStep::StartTask,
+                io::Error::other(err),
+            ));
+        }
+    }
 
-    let address = socket.local_addr()?;
+    // Every server gets its own bulkhead threads
+    let mut spec = spec;
+    for bulkhead in &mut spec.bulkheads {
+        bulkhead.start();
+    }
+    let breakers = std::iter::once(&spec)
+        .chain(spec.listeners.iter().map(|(_, config)| config))
+        .flat_map(|config| config.circuit_breakers.iter().cloned())
+        .collect();
+    spec.stats = Arc::new(Stats::new(breakers));
+    let stats = spec.stats.clone();
+    spec.memory_budget = spec
+        .memory_budget
+        .as_ref()
+        .map(|budget| Arc::new(budget.renewed()));
+    spec.maintenance = Arc::default();
+    let maintenance = spec.maintenance.clone();
 
-    log::info!("FastCGI Server listening on {address}");
+    let reuse_port = spec.worker_model != WorkerModel::ThreadPool;
+    let backlog = spec.backlog.unwrap_or(DEFAULT_BACKLOG);
 
-    let poll = Poll::new()?;
+    #[cfg(unix)]
+    let inherited = match spec.inherited_listener {
+        true => listener::inherited(listener::LISTENSOCK_FILENO)
+            .map_err(|err| StartError::single(StartStep::InheritListener, err))?,
+        false => None,
+    };
+    #[cfg(not(unix))]
+    let inherited: Option<(Socket, ListenerInfo)> = None;
 
-    let events = Events::with_capacity(128);
+    // Every socket is bound before giving up, so that all of the failures are reported at once
+    let mut failures = vec![];
+    let main = match &inherited {
+        #[cfg(unix)]
+        Some((socket, info)) => {
+            log::info!("Using the listening socket inherited from the parent process");
+            let socket = socket
+                .try_clone()
+                .map_err(|err| StartError::single(StartStep::InheritListener, err))?;
+            Some((socket, info.clone()))
+        }
+        _ => match bind_main(address, reuse_port, backlog) {
+            Ok(bound) => Some(bound),
+            Err(error) => {
+                let step = StartStep::Bind(address.into());
+                failures.push(StartFailure { step, error });
+                None
+            }
+        },
+    };
+
+    let mut listeners = vec![];
+    let mut listener_infos = vec![];
+    for (address, mut config) in std::mem::take(&mut spec.listeners) {
+        match Socket::bind(&address, &config) {
+            Ok((socket, info)) => {
+                log::info!("FastCGI Server also listening on {info}");
+                for bulkhead in &mut config.bulkheads {
+                    bulkhead.start();
+                }
+                config.stats = stats.clone();
+                config.memory_budget = spec.memory_budget.clone();
+                config.maintenance = maintenance.clone();
+                listeners.push((socket, config));
+                listener_infos.push(info);
+            }
+            Err(error) => failures.push(StartFailure {
+                step: StartStep::Bind(address),
+                error,
+            }),
+        }
+    }
 
-    let server_waker = Waker::new(poll.registry(), SHUTDOWN)?;
+    let Some((socket, listener)) = main.filter(|_| failures.is_empty()) else {
+        for failure in &failures {
+            log::error!(error:err = failure.error; "Failed to bind a socket ({}). The server will not start", failure.step);
+        }
+        return Err(StartError::new(failures));
+    };
+    let address = socket.local_addr().map_err(setup_failed)?;
+    log::info!("FastCGI Server listening on {listener}");
 
-    poll.registry()
-        .register(&mut socket, SERVER, Interest::READABLE)?;
+    let (signal_shutdown, observe_shutdown) = sync_channel(1);
 
-    let (signal_shutdown, observe_shutdown) = sync_channel(0);
+    let shutdown_requested = Arc::new(AtomicBool::new(false));
+    let abort_requested = Arc::new(AtomicBool::new(false));
 
-    let event_loop = EventLoop {
+    let (mut event_loop, server_waker) = EventLoop::new(
         socket,
-        config: spec,
-        poll,
-        events,
-        signal_shutdown,
-    };
+        spec.clone(),
+        listeners,
+        shutdown_requested.clone(),
+        abort_requested.clone(),
+        Some(signal_shutdown),
+    )
+    .map_err(setup_failed)?;
 
-    let handle = thread::spawn(move || start(event_loop));
+    #[cfg(unix)]
+    if let Some(path) = &spec.control_socket {
+        let controls = Controls {
+            stats: stats.clone(),
+            routes: spec
+                .routes()
+                .into_iter()
+                .map(|(method, pattern)| (method.to_string(), pattern.to_string()))
+                .collect(),
+            maintenance: maintenance.clone(),
+            shutdown_requested: shutdown_requested.clone(),
+            server_waker: server_waker.clone(),
+        };
+        let control = ControlSocket::bind(path, controls)
+            .map_err(|err| StartError::single(StartStep::ControlSocket(path.clone()), err))?;
+        event_loop.control = Some(control);
+    }
 
-    Ok(ServerHandle {
+    // In the thread-per-core model, every thread gets its own socket bound to the same address.
+    // The kernel spreads incoming connections between them.
+    // An inherited socket can't be bound again, so the threads share it instead.
+    //
+    // The sockets are all bound now, but the threads only start accepting once the
+    // `before_serve` hooks have run.
+    let mut peer_loops = vec![];
+    if reuse_port {
+        for _ in 1..worker_threads() {
+            let socket = match &inherited {
+                #[cfg(unix)]
+                Some((socket, _)) => socket.try_clone().map_err(setup_failed)?,
+                _ => {
+                    let backlog = listener.options().backlog.unwrap_or(backlog);
+                    bind(address, true)
+                        .and_then(|socket| listener::listen(&socket, backlog).map(|_| socket))
+                        .map(Socket::Tcp)
+                        .map_err(|err| StartError::single(StartStep::Bind(address.into()), err))?
+                }
+            };
+            let (peer_loop, waker) = EventLoop::new(
+                socket,
+                spec.clone(),
+                vec![],
+                shutdown_requested.clone(),
+                abort_requested.clone(),
+                None,
+            )
+            .map_err(setup_failed)?;
+            peer_loops.push((
+                peer_loop,
+                waker,
+                Executor::new(&spec, shutdown_requested.clone()),
+            ));
+        }
+    }
+
+    let executor = Executor::new(&spec, shutdown_requested.clone());
+    Ok(Prepared {
+        spec,
+        event_loop,
+        executor,
+        peer_loops,
         address,
-        server_loop: handle,
+        listener,
+        listener_infos,
         server_waker,
+        shutdown_requested,
+        abort_requested,
         observe_shutdown,
+        stats,
+        maintenance,
     })
 }
 
-fn start(mut evloop: EventLoop) -> ServerExitReason {
-    // `shutdown_threadpool` should always be called before exiting this function, regardless of
-    // cause.
-    // This will ensure active threads finish their work.
-    let pool = threadpool::Builder::new().build();
+// Binds the main listening socket of a server to `address`
+fn bind_main(
+    address: SocketAddr,
+    reuse_port: bool,
+    backlog: u32,
+) -> Result<(Socket, ListenerInfo), io::Error> {
+    let socket = bind(address, reuse_port)?;
+    let backlog = listener::listen(&socket, backlog)?;
+    let info = ListenerInfo::Tcp {
+        address: socket.local_addr()?,
+        options: SocketOptions {
+            backlog,
+            // mio sets it everywhere but on Windows, where it would allow hijacking the socket
+            reuse_address: cfg!(not(windows)),
+        },
+    };
+    Ok((Socket::Tcp(socket), info))
+}
+
+fn setup_failed(err: io::Error) -> StartError {
+    StartError::single(StartStep::Setup, err)
+}
+
+// Sets up an accepted connection, with the buffer sizes (and TLS certificate) of `config`
+fn set_up_connection(stream: Stream, config: &ServerConfig) -> io::Result<Connection> {
+    let (read_buffer, write_buffer) = config.connection_buffer_sizes();
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &config.tls {
+        return Connection::with_tls(stream, tls, write_buffer);
+    }
+    Connection::with_buffer_sizes(stream, read_buffer, write_buffer)
+}
+
+// How many threads handle connections: one per available CPU core, in both worker models
+fn worker_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// Binds a listening socket to `address`
+fn bind(address: SocketAddr, reuse_port: bool) -> Result<TcpListener, io::Error> {
+    if !reuse_port {
+        return TcpListener::bind(address);
+    }
+
+    #[cfg(unix)]
+    {
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        let socket = Socket::new(
+            Domain::for_address(address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&address.into())?;
+        Ok(TcpListener::from_std(socket.into()))
+    }
+
+    #[cfg(not(unix))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is only available on unix",
+    ))
+}
 
+fn start(mut evloop: EventLoop, executor: Executor) -> ServerExitReason {
     loop {
         match evloop.poll.poll(&mut evloop.events, None) {
             Ok(_) => {}
             Err(err) => {
                 log::warn!(error:err = err; "Poll call failed. Server loop will exit");
-                shutdown_threadpool(pool);
-                return ServerExitReason::Err(err);
+                executor.shutdown(false, evloop.configs());
+                evloop.stop_threads();
+                return evloop.error(ServerOperation::Poll, err);
             }
         };
 
-        for event in evloop.events.iter() {
-            match event.token() {
-                SERVER => loop {
-                    match evloop.socket.accept() {
-                        Ok((stream, _)) => {
-                            let connection = match Connection::try_from(stream) {
-                                Ok(c) => c,
-                                Err(err) => return ServerExitReason::Err(err),
-                            };
-                            pool.execute({
-                                let spec = evloop.config.clone();
-                                move || {
-                                    fastcgi_responder::handle_connection(connection, spec);
-                                }
-                            });
-                        }
-                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
-                        Err(err) => {
-                            log::warn!(error:err = err; "Socket accept call failed. Server loop will exit");
-                            shutdown_threadpool(pool);
-                            return ServerExitReason::Err(err);
+        let tokens: Vec<Token> = evloop.events.iter().map(|e| e.token()).collect();
+
+        for token in tokens {
+            match token {
+                WAKER => {
+                    // The waker is used for shutting down, for handing off pending writes, for
+                    // accepting connections again once the request queue has room, and for
+                    // accepting connections to named pipes.
+                    evloop.register_pending_writes();
+
+                    if !evloop.shutdown_requested.load(Ordering::SeqCst) {
+                        #[cfg(windows)]
+                        let accepted = evloop.accept_from_pipes(&executor);
+                        #[cfg(not(windows))]
+                        let accepted = Ok(());
+                        if let Err(reason) =
+                            accepted.and_then(|()| evloop.resume_accepting(&executor))
+                        {
+                            executor.shutdown(false, evloop.configs());
+                            evloop.stop_threads();
+                            return reason;
                         }
+                        continue;
                     }
-                },
-                SHUTDOWN => {
-                    shutdown_threadpool(pool);
-                    if evloop.signal_shutdown.send(()).is_err() {
-                        // The only way this happens is if the main thread called
-                        // `Server::server_waker.wake()` then immediately dropped
-                        // the `Server::observe_shutdown` receiver such that this fails to
-                        // send.
-                        //
-                        // But that cannot be, since we don't do that ... and those properties
-                        // are not part of the public API.
-                        //
-                        // That said if somehow, it does happen, I do still want to know
-                        log::error!(
-                            "unreachable code reached! failed to notify main thread of shutdown."
-                        );
-                        unreachable!("failed to notify main thread of shutdown");
+
+                    let aborted = evloop.abort_requested.load(Ordering::SeqCst);
+                    executor.shutdown(aborted, evloop.configs());
+                    if !aborted {
+                        // In-flight requests are only complete once their responses are sent
+                        evloop.finish_pending_writes();
+                    }
+                    evloop.stop_threads();
+
+                    let Some(signal_shutdown) = &evloop.signal_shutdown else {
+                        // This is the event loop of another thread. The server thread is the one
+                        // that reports back to the handle.
+                        return ServerExitReason::Normal;
+                    };
+                    // The additional listeners are closed first, so that their addresses can be
+                    // bound again as soon as `stop()` returns (abstract unix sockets can't be
+                    // reused while they are open).
+                    evloop.listeners.clear();
+                    // Nobody waits for this when the shutdown was requested from the control
+                    // socket, and the handle may even be gone. The channel has room for the
+                    // message so that this doesn't block either way.
+                    let _ = signal_shutdown.send(());
+                    if aborted {
+                        return ServerExitReason::Aborted;
                     }
                     return ServerExitReason::Normal;
                 }
-                _ => unreachable!(),
+                // The main listener, or one of the additional ones
+                Token(i) if i < FIRST_LISTENER + evloop.listeners.len() => {
+                    if let Err(reason) = evloop.accept_from(token, &executor) {
+                        executor.shutdown(false, evloop.configs());
+                        evloop.stop_threads();
+                        return reason;
+                    }
+                }
+                token => evloop.resume_pending_write(token),
             }
         }
     }
 }
-
-fn shutdown_threadpool(pool: threadpool::ThreadPool) {
-    pool.join();
-    drop(pool);
-}
@@ -1,30 +1,71 @@
-use crate::connection::Connection;
-use crate::context::{Request, Response};
+use crate::access_log::AccessLogEntry;
+use crate::app_status;
+use crate::body::{BodyWriter, Unsent};
+use crate::capture::CaptureWriter;
+use crate::connection::{encode_record, Connection, ReadLimits};
+use crate::context::{LineEnding, Request, Response};
 use crate::error::Error;
+use crate::event_loop::WriteHandoff;
+use crate::headers;
+use crate::memory_budget::MemoryBudget;
+use crate::middleware::Next;
+use crate::panic_report::{self, PanicReport};
+use crate::path_mapping;
+use crate::record::pairs::PairLimits;
 use crate::record::*;
-use crate::server_config::ServerConfig;
+use crate::server_config::{ParamStrictness, ServerConfig, UnreadBody};
+use crate::stats::{ActiveConnection, Stats};
 use crate::status;
-use convert_case::{Case, Casing};
+use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_RECORD_PACKETS: usize = 65536;
+const DEFAULT_MAX_CONNECTION_MEMORY: usize = 64 * 1024 * 1024;
+const DEFAULT_MAX_PARAMS: usize = 1024;
+const DEFAULT_MAX_INTERLEAVED_PACKETS: usize = 64;
+const DEFAULT_MAX_PARAM_SIZES: (usize, usize) = (1024, 64 * 1024);
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// How much of a request is read before rejecting it. See `reject_connection`
+const MAX_REJECTED_BYTES: usize = 64 * 1024;
 
 // Handles a FastCGI Connection.
 //
 // There are two expected flows;
 // + We receive a `GetValues` request to which we respond.
 // + We receive a `BeginRequest` request followed by Params and Stdin. Respond using Stdout followed by EndRequest
-pub fn handle_connection(mut conn: Connection, config: ServerConfig) {
-    let begin = match conn.read_record() {
+//
+// `queued` is how long the connection waited for a worker thread.
+pub fn handle_connection(
+    mut conn: Connection,
+    config: ServerConfig,
+    handoff: WriteHandoff,
+    queued: Duration,
+) {
+    let active = ActiveConnection::new(config.stats.clone());
+    if let Some(dir) = &config.capture {
+        match CaptureWriter::create(dir) {
+            Ok(writer) => conn.capture(writer),
+            Err(err) => log::warn!(error:err = err; "Failed to create capture file"),
+        }
+    }
+
+    let read_started_at = config.clock.instant();
+    let begin = match conn.read_record_limited(&mut handshake_limits(&config)) {
         Ok(Record::GetValues(r)) => {
-            handle_get_values(&mut conn, r);
+            handle_get_values(&mut conn, r, &config);
             return;
         }
         Ok(Record::BeginRequest(r)) => r,
         Ok(_) => {
             log::error!("FastCGI connection began with unexpected record. Closing connection");
+            config.stats.record_protocol_error();
             return;
         }
         Err(e) => {
-            handle_error(&mut conn, e);
+            handle_error(&mut conn, e, &config.stats);
             return;
         }
     };
@@ -34,138 +75,745 @@ pub fn handle_connection(mut conn: Connection, config: ServerConfig) {
             Record::EndRequest(EndRequest::new(0, ProtocolStatus::MultiplexingUnsupported));
         let _ = conn.write_record(&response);
         log::warn!("FastCGI client wanted keep-alive. It is not supported. Closing connection");
+        config.stats.record_protocol_error();
+        conn.close_gracefully();
         return;
     }
 
-    let mut params = match conn.read_record() {
+    let mut limits = read_limits(&config);
+    limits.reservation = config.memory_budget.as_ref().map(MemoryBudget::reservation);
+    let memory = limits.remaining_bytes;
+
+    let mut params = match read_request_record(&mut conn, &mut limits) {
         Ok(Record::Params(r)) => r,
         Ok(_) => {
             log::error!("FastCGI connection missing Params record. Closing connection");
+            config.stats.record_protocol_error();
+            let reason = "The web server did not send FCGI_PARAMS";
+            fail_request(conn, &config, status::INTERNAL_SERVER_ERROR, reason);
             return;
         }
         Err(e) => {
-            handle_error(&mut conn, e);
+            let failure = failure_status(&e).map(|status| (status, e.to_string()));
+            handle_error(&mut conn, e, &config.stats);
+            if let Some((status, reason)) = failure {
+                // There is no point waiting for the rest of a request that stalled
+                if status != status::REQUEST_TIMEOUT {
+                    drain_stdin(&mut conn, &mut limits, config.unread_body);
+                }
+                fail_request(conn, &config, status, &reason);
+            }
             return;
         }
     };
 
-    let mut stdin = match conn.read_record() {
+    let mut stdin = match read_after_params(&mut conn, &mut limits, &mut params) {
         Ok(Record::Stdin(r)) => r,
         Ok(_) => {
             log::error!("FastCGI connection missing Stdin record. Closing connection");
+            config.stats.record_protocol_error();
+            let reason = "The web server did not send FCGI_STDIN";
+            fail_request(conn, &config, status::INTERNAL_SERVER_ERROR, reason);
             return;
         }
         Err(e) => {
-            handle_error(&mut conn, e);
+            let failure = failure_status(&e).map(|status| (status, e.to_string()));
+            handle_error(&mut conn, e, &config.stats);
+            if let Some((status, reason)) = failure {
+                // There is no point waiting for the rest of a request that stalled
+                if status != status::REQUEST_TIMEOUT {
+                    drain_stdin(&mut conn, &mut limits, config.unread_body);
+                }
+                fail_request(conn, &config, status, &reason);
+            }
             return;
         }
     };
 
+    let bytes_in = memory - limits.remaining_bytes;
+    // The request holds on to its part of the memory budget until it is handled
+    let reservation = limits.reservation.take();
     let mut vars = params.take();
 
-    let Some(method) = vars.remove("REQUEST_METHOD") else {
-        log::error!("FastCGI request missing REQUEST_METHOD header. Closing connection.");
-        return;
-    };
+    let lenient = config.param_strictness == ParamStrictness::Lenient;
 
-    let Some(path) = vars.remove("PATH_INFO") else {
-        log::error!("FastCGI request missing PATH_INFO header. Closing connection.");
-        return;
+    let method = match vars.remove("REQUEST_METHOD") {
+        Some(method) => method,
+        None if lenient => String::from("GET"),
+        None => {
+            log::error!("FastCGI request missing REQUEST_METHOD header. Closing connection.");
+            let reason = "The REQUEST_METHOD param is missing";
+            fail_request(conn, &config, status::INTERNAL_SERVER_ERROR, reason);
+            return;
+        }
     };
 
-    let Some(query_string) = vars.remove("QUERY_STRING") else {
-        log::error!("FastCGI request missing QUERY_STRING header. Closing connection.");
-        return;
+    let path = match config.path_mapping.resolve(&vars) {
+        Some(path) => path,
+        None if lenient => String::from("/"),
+        None => {
+            log::error!(mapping:? = config.path_mapping; "FastCGI request params don't contain the request path. Closing connection.");
+            let reason = "The params don't contain the request path (e.g. SCRIPT_NAME, PATH_INFO or REQUEST_URI)";
+            fail_request(conn, &config, status::INTERNAL_SERVER_ERROR, reason);
+            return;
+        }
     };
+    vars.remove("PATH_INFO");
+
+    // Some web servers leave out `QUERY_STRING` when there is no query
+    let query_string = vars
+        .remove("QUERY_STRING")
+        .or_else(|| {
+            let uri = vars.get("REQUEST_URI")?;
+            Some(
+                path_mapping::request_uri_query(uri)
+                    .unwrap_or_default()
+                    .to_string(),
+            )
+        })
+        .unwrap_or_default();
 
     let mut headers = BTreeMap::new();
+    let mut cgi_vars = BTreeMap::new();
     for (k, v) in vars {
         if let Some(suffix) = k.strip_prefix("HTTP_") {
-            headers.insert(suffix.to_case(Case::Train), v);
+            headers.insert(config.header_case.header_name(suffix), v);
+        } else {
+            cgi_vars.insert(k, v);
         }
     }
 
-    let mut req = Request {
+    let req = Request {
         method,
         path,
         query_string,
         headers,
+        vars: cgi_vars,
         body: stdin.take(),
+        trusted_proxies: config.trusted_proxies.clone(),
+        #[cfg(feature = "signed-cookies")]
+        keys: config.keys.clone(),
+        created_at: config.clock.instant(),
+        queued,
+        read: config.clock.since(read_started_at),
+        clock: config.clock.clone(),
+        #[cfg(feature = "tls")]
+        client_identity: conn.client_identity().map(std::sync::Arc::new),
         ..Request::default()
     };
 
-    let mut response: Option<Response> = None;
+    // Requests to routes that have their own worker threads are handled on those threads
+    if let Some(bulkhead) = config.bulkheads.iter().find(|b| b.matches(&req.path)) {
+        let config = config.clone();
+        bulkhead.execute(move || {
+            respond(conn, req, config, handoff, bytes_in);
+            drop(reservation);
+            drop(active);
+        });
+        return;
+    }
+
+    respond(conn, req, config, handoff, bytes_in);
+    drop(reservation);
+    drop(active);
+}
 
-    if let Some(fs) = config.file_server {
-        response = fs.respond(&req);
+// Produces the response to a request, and sends it
+fn respond(
+    mut conn: Connection,
+    mut req: Request,
+    config: ServerConfig,
+    handoff: WriteHandoff,
+    bytes_in: usize,
+) {
+    req.handler_started_at = config.clock.instant();
+    let response = maintenance(&config)
+        .or_else(|| filter_ip(&req, &config))
+        .or_else(|| serve_static(&req, &config));
+
+    // The pattern of the route that produced the response, if any
+    let route = RefCell::new(None);
+    // Responses generated by the server itself (e.g. the default 404) are produced at the end of
+    // the middleware chain, so that middleware (e.g. compression) applies to them too
+    let handler = |req: &mut Request| {
+        let mut response = config.list_routes(req);
+
+        if response.is_none() {
+            if let Some(found) = config.find_route(req) {
+                *route.borrow_mut() = Some(found.pattern.to_string());
+                response = Some(config.call_route(req, found));
+            }
+        }
+
+        if response.is_none() {
+            if let Some(fallback) = &config.fallback {
+                response = Some(fallback(req));
+            }
+        }
+
+        if response.is_none() {
+            response = config.respond_to_other_method(req);
+        }
+
+        let response = response
+            .or_else(|| config.default_index_page(req))
+            .unwrap_or(Response::default().set_status(status::NOT_FOUND));
+        config.transform_body_of(req, resolve_file(response, req))
     };
 
-    if response.is_none() {
-        if let Some(router) = config.router {
-            response = router.respond(&mut req);
+    let response = match response {
+        Some(response) => response,
+        // Middleware may respond with a file too
+        None => {
+            let chain = Next::new(&config.middleware, &handler);
+            match panic_report::catch(|| chain.run(&mut req)) {
+                Ok(response) => resolve_file(response, &req),
+                Err(report) => handler_panicked(&req, &config, &report),
+            }
         }
+    };
+    let response = response.clear_taken_flash(&req);
+    #[cfg(feature = "signed-cookies")]
+    let response = response.sign_flash(&req);
+    let response = add_server_headers(response, &config);
+
+    config.stats.record_status(response.status);
+    let entry = AccessLogEntry {
+        timestamp: config.clock.now(),
+        method: &req.method,
+        path: &req.path,
+        query: &req.query_string,
+        status: response.status,
+        elapsed: req.elapsed(),
+        queued: req.queued,
+        read: req.read,
+        handler: req.timings().handler,
+        context: &req.log_context,
+    };
+
+    let logged = config
+        .access_log_filter
+        .as_ref()
+        .is_none_or(|filter| filter.allows(&entry));
+    match &config.access_log {
+        Some(callback) if logged => callback(&entry),
+        None if logged => entry.log(),
+        _ => {}
     }
 
-    if response.is_none() {
-        if let Some(fallback) = config.fallback {
-            response = Some(fallback(&mut req));
+    let end_request = |app_status| {
+        Record::EndRequest(EndRequest::new(app_status, ProtocolStatus::RequestComplete))
+    };
+
+    let route = route.into_inner();
+    let record_stats = |bytes_out: usize| {
+        if let Some(route) = &route {
+            config.stats.record(route, bytes_in, bytes_out);
+        }
+    };
+    let write_failed = |err: io::Error| {
+        log::warn!(error:err = err, method = req.method, path = req.path, route:? = route; "Failed to write the response");
+        config.stats.record_write_error();
+        if let Some(callback) = &config.on_write_error {
+            callback(&req, &err);
+        }
+    };
+
+    // Don't bother sending anything if the client went away while the handler was running
+    if conn.poll_abort() {
+        notify_abort(&config, &req);
+        record_stats(0);
+        if let Err(err) = conn.write_record(&end_request(response.app_status)) {
+            log::debug!(error:err = err; "Failed to end the aborted request");
+        }
+        return;
+    }
+
+    // Streamed bodies are produced while they are being sent, so they are written from this
+    // thread, however long it takes.
+    if response.stream.is_some() {
+        let panicked = |report: &PanicReport| report_panic(&req, &config, report);
+        let written = write_response(&mut conn, &response, config.line_ending, panicked);
+        let app_status = match written {
+            Ok((bytes_out, app_status)) => {
+                record_stats(bytes_out);
+                app_status
+            }
+            Err(Unsent::Aborted) => {
+                notify_abort(&config, &req);
+                record_stats(0);
+                response.app_status
+            }
+            Err(Unsent::WriteFailed(err)) => {
+                record_stats(0);
+                write_failed(err);
+                return;
+            }
+        };
+        if let Err(err) = conn.write_record(&end_request(app_status)) {
+            write_failed(err);
+        }
+        return;
+    }
+
+    // Other responses are written without blocking.
+    // If the client is too slow to receive all of it, the rest is handed off to the event loop.
+    // That way, this worker thread is free to handle other connections.
+    // Encoding into a `Vec` can't fail.
+    let mut stdout = Stdout(vec![]);
+    let _ = response.write_stdout_bytes(&mut stdout.0, config.line_ending);
+    record_stats(stdout.0.len());
+
+    let mut bytes = vec![];
+    let _ = encode_record(&Record::Stdout(stdout), &mut bytes);
+    let _ = encode_record(&end_request(response.app_status), &mut bytes);
+
+    match conn.write_nonblocking(bytes) {
+        Ok(None) => {}
+        Ok(Some(pending)) => handoff.send(pending),
+        Err(err) => write_failed(err),
+    }
+}
+
+// Turns the request away while the server is in maintenance mode
+fn maintenance(config: &ServerConfig) -> Option<Response> {
+    if !config.maintenance.load(Ordering::Relaxed) {
+        return None;
+    }
+    let response = Response::new()
+        .set_status(status::SERVICE_UNAVAILABLE)
+        .set_app_status(app_status::REJECTED);
+    Some(set_retry_after(
+        response,
+        config.drain_retry_after_or_default(),
+    ))
+}
+
+fn set_retry_after(response: Response, retry_after: Duration) -> Response {
+    // The header is in whole seconds, and waiting a bit longer is harmless
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    response.set_header(headers::RETRY_AFTER, seconds.to_string())
+}
+
+// Rejects the request if one of the IP filters says so
+fn filter_ip(req: &Request, config: &ServerConfig) -> Option<Response> {
+    if config.ip_filters.is_empty() {
+        return None;
+    }
+
+    let peer = req.var("REMOTE_ADDR").and_then(|addr| addr.parse().ok());
+    let client = req.client_ip();
+    let status = config
+        .ip_filters
+        .iter()
+        .find_map(|filter| filter(peer, client))?;
+    log::info!(status = status, peer:? = peer, client:? = client; "Request rejected by IP filter");
+    Some(Response::new().set_status(status))
+}
+
+// Serves the request from the fingerprinted assets or the file server, if it is one of theirs.
+//
+// Static files are served without going through middleware.
+#[cfg(feature = "fs")]
+fn serve_static(req: &Request, config: &ServerConfig) -> Option<Response> {
+    use crate::server_config::DispatchOrder;
+
+    if let Some(response) = config.assets.as_ref().and_then(|a| a.respond(req)) {
+        return Some(response);
+    }
+
+    let fs = config.file_server.as_ref()?;
+    // Routes are looked up again once the request goes through middleware, so they are only
+    // looked up here when the file server could answer the request, and the order depends on
+    // them
+    let files_first = match config.dispatch_order {
+        DispatchOrder::FilesFirst => true,
+        _ if fs.strip_request_prefix(&req.path).is_none() => return None,
+        order => match (order, config.find_route(req)) {
+            (_, None) => true,
+            (DispatchOrder::MostSpecific, Some(route)) => {
+                fs.request_prefix().len() >= route.literal_len
+            }
+            (_, Some(_)) => false,
+        },
+    };
+    if files_first {
+        return fs.respond(req);
+    }
+    None
+}
+
+#[cfg(not(feature = "fs"))]
+fn serve_static(_req: &Request, _config: &ServerConfig) -> Option<Response> {
+    None
+}
+
+// Reads the file of a response made with `Response::file`, so that middleware sees its body
+#[cfg(feature = "fs")]
+fn resolve_file(response: Response, req: &Request) -> Response {
+    response.resolve_file(req)
+}
+
+#[cfg(not(feature = "fs"))]
+fn resolve_file(response: Response, _req: &Request) -> Response {
+    response
+}
+
+// Responds to a connection with a `503 Service Unavailable`, without handling its request.
+//
+// Used to shed load when the request queue is full, and to turn away the connections still
+// waiting for a worker when the server shuts down. `retry_after` tells the client when to try
+// again.
+pub fn reject_connection(
+    mut conn: Connection,
+    config: &ServerConfig,
+    retry_after: Option<Duration>,
+) {
+    // Rejections are handled one at a time, so a client that sends its request slowly must not
+    // hold up the others
+    let mut limits = ReadLimits {
+        deadline: handshake_deadline(config),
+        remaining_bytes: MAX_REJECTED_BYTES,
+        ..read_limits(config)
+    };
+
+    // The request is read anyway, since closing a connection with unread input resets it.
+    // That could destroy the response before the client gets to read it.
+    loop {
+        match conn.read_record_limited(&mut limits) {
+            Ok(Record::GetValues(r)) => {
+                handle_get_values(&mut conn, r, config);
+                return;
+            }
+            Ok(Record::Stdin(_)) => break,
+            Ok(_) => {}
+            // The rest of the request is left unread, and may reset the connection
+            Err(Error::LimitExceeded(_)) => break,
+            Err(e) => {
+                handle_error(&mut conn, e, &config.stats);
+                return;
+            }
         }
     }
 
-    let response = response.unwrap_or(Response::default().set_status(status::NOT_FOUND));
+    let mut response = Response::new().set_status(status::SERVICE_UNAVAILABLE);
+    if let Some(retry_after) = retry_after {
+        response = set_retry_after(response, retry_after);
+    }
+    let response = add_server_headers(response, config);
+    config.stats.record_status(response.status);
+    let end_request = EndRequest::new(app_status::REJECTED, ProtocolStatus::RequestComplete);
+    let _ = write_response(&mut conn, &response, config.line_ending, |_| {});
+    let _ = conn.write_record(&Record::EndRequest(end_request));
+}
 
-    let elapsed = req.created_at.elapsed();
+// Reads and discards the rest of the request body, if `policy` says so.
+//
+// Some web servers only read the response once they are done sending the request, so a request
+// rejected before its body is read would otherwise never get its response.
+fn drain_stdin(conn: &mut Connection, limits: &mut ReadLimits, policy: UnreadBody) {
+    let UnreadBody::Drain(mut remaining) = policy else {
+        return;
+    };
+    // Some of the body may have arrived in the middle of the params
+    let mut read_ahead = std::mem::take(&mut limits.interleaved);
+    loop {
+        let packet = match read_ahead.pop_front() {
+            Some(packet) => Ok(packet),
+            None => conn.read_packet(),
+        };
+        match packet {
+            Ok(packet) if packet.type_id == FCGI_STDIN && packet.content.is_empty() => return,
+            Ok(packet) => match remaining.checked_sub(packet.content.len()) {
+                Some(left) => remaining = left,
+                None => {
+                    log::debug!("Too much of the request body is left unread. Closing connection after responding");
+                    return;
+                }
+            },
+            Err(Error::OtherRequest { .. }) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+// The response to a request whose handler panicked
+fn handler_panicked(req: &Request, config: &ServerConfig, report: &PanicReport) -> Response {
+    report_panic(req, config, report);
+    let body = status::reason_phrase(status::INTERNAL_SERVER_ERROR).unwrap_or_default();
+    Response::text(body)
+        .set_status(status::INTERNAL_SERVER_ERROR)
+        .set_app_status(app_status::HANDLER_PANICKED)
+}
 
-    log::info!(
-        status = response.status,
+// Logs the panic of a handler, or of the body stream it returned, and passes it on to the
+// application
+fn report_panic(req: &Request, config: &ServerConfig, report: &PanicReport) {
+    log::error!(
+        panic:% = report,
+        backtrace:% = report.backtrace(),
         method = req.method,
-        path = req.path,
-        query = req.query_string,
-        elapsed_milli = elapsed.as_millis(),
-        elapsed_micro = elapsed.as_micros();
-        "fastcgi-request"
+        path = req.path;
+        "Handler panicked"
     );
+    if let Some(callback) = &config.on_panic {
+        callback(req, report);
+    }
+}
 
-    let mut stdout = Stdout(vec![]);
-    let _ = response.write_stdout_bytes(&mut stdout.0);
-    let _ = conn.write_record(&Record::Stdout(stdout));
+// Responds to a request the web server did not send properly, then closes the connection.
+//
+// Without a response, web servers only report that the connection was closed (e.g. nginx logs a
+// `502` with "upstream prematurely closed connection"). `reason` is sent on the `FCGI_STDERR`
+// stream, so that it ends up in the web server's error log.
+fn fail_request(mut conn: Connection, config: &ServerConfig, status: u16, reason: &str) {
+    let stderr = format!("vintage: {reason}\n").into_bytes();
+    let _ = conn.write_record(&Record::Stderr(Stderr(stderr)));
+    let _ = conn.write_record(&Record::Stderr(Stderr(vec![])));
 
-    let _ = conn.write_record(&Record::EndRequest(EndRequest::new(
-        0,
-        ProtocolStatus::RequestComplete,
-    )));
+    let body = status::reason_phrase(status).unwrap_or_default();
+    let response = add_server_headers(Response::text(body).set_status(status), config);
+    config.stats.record_status(status);
+    let _ = write_response(&mut conn, &response, config.line_ending, |_| {});
+    let app_status = match status {
+        status::REQUEST_TIMEOUT => app_status::TIMED_OUT,
+        // The request was shed, not failed
+        status::SERVICE_UNAVAILABLE => app_status::REJECTED,
+        _ => app_status::REQUEST_FAILED,
+    };
+    let end_request = EndRequest::new(app_status, ProtocolStatus::RequestComplete);
+    let _ = conn.write_record(&Record::EndRequest(end_request));
+
+    // Whatever is left of the request is unread
+    conn.close_gracefully();
+}
+
+// The status of the response to send when the request can't be read because of `error`, if the
+// connection is still usable
+fn failure_status(error: &Error) -> Option<u16> {
+    match error {
+        // Usually a client sending a header that is not valid utf8
+        Error::InvalidUtf8KeyValuePair => Some(status::BAD_REQUEST),
+        Error::MalformedRecordPayload(_) | Error::MalformedRecordStream { .. } => {
+            Some(status::INTERNAL_SERVER_ERROR)
+        }
+        Error::MemoryBudgetExceeded => Some(status::SERVICE_UNAVAILABLE),
+        Error::ParamLimitExceeded(_) => Some(status::REQUEST_HEADER_FIELDS_TOO_LARGE),
+        e if timed_out(e) => Some(status::REQUEST_TIMEOUT),
+        _ => None,
+    }
+}
+
+// Whether the web server stalled in the middle of the request
+fn timed_out(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::UnexpectedSocketClose(e)
+            if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+    )
+}
+
+// Adds the headers sent with every response, unless the handler set them.
+//
+// Many FastCGI clients don't add a `Date` header of their own, even though HTTP requires it.
+fn add_server_headers(mut response: Response, config: &ServerConfig) -> Response {
+    if !response.has_header(headers::DATE) {
+        response = response.set_date_header(headers::DATE, config.clock.now());
+    }
+    for (name, value) in &config.identity_headers {
+        if !response.has_header(name) {
+            response = response.set_header(*name, value);
+        }
+    }
+    response
 }
 
-fn handle_error(conn: &mut Connection, e: Error) {
+// Reads the next record of the request being served.
+//
+// Clients that get confused sometimes begin the same request again. Since the request already
+// began, the repeated `FCGI_BEGIN_REQUEST` is skipped.
+fn read_request_record(conn: &mut Connection, limits: &mut ReadLimits) -> Result<Record, Error> {
+    loop {
+        match conn.read_record_limited(limits) {
+            Ok(Record::BeginRequest(_)) => {
+                log::warn!("FastCGI client began the request being served again. Ignoring it");
+            }
+            result => return result,
+        }
+    }
+}
+
+// Reads the record that follows the params, which should be the request body.
+//
+// Some clients end the params more than once (e.g. with an empty record used as a flush), and send
+// the rest of them after that. Those are added to `params`.
+fn read_after_params(
+    conn: &mut Connection,
+    limits: &mut ReadLimits,
+    params: &mut Params,
+) -> Result<Record, Error> {
+    loop {
+        match read_request_record(conn, limits)? {
+            Record::Params(more) => {
+                log::debug!(
+                    "FastCGI client sent more params after ending them. Adding them to the request"
+                );
+                params.extend(more);
+                if params.pairs().len() > limits.params.max_pairs {
+                    return Err(Error::ParamLimitExceeded("number of params"));
+                }
+            }
+            record => return Ok(record),
+        }
+    }
+}
+
+pub fn read_limits(config: &ServerConfig) -> ReadLimits {
+    ReadLimits {
+        max_packets_per_record: config
+            .max_record_packets
+            .unwrap_or(DEFAULT_MAX_RECORD_PACKETS),
+        remaining_bytes: config
+            .max_connection_memory
+            .unwrap_or(DEFAULT_MAX_CONNECTION_MEMORY),
+        reservation: None,
+        deadline: None,
+        params: {
+            let (max_name_len, max_value_len) =
+                config.max_param_sizes.unwrap_or(DEFAULT_MAX_PARAM_SIZES);
+            PairLimits {
+                max_pairs: config.max_params.unwrap_or(DEFAULT_MAX_PARAMS),
+                max_name_len,
+                max_value_len,
+            }
+        },
+        max_interleaved_packets: config
+            .max_interleaved_packets
+            .unwrap_or(DEFAULT_MAX_INTERLEAVED_PACKETS),
+        ..ReadLimits::default()
+    }
+}
+
+// The limits on reading the record that begins a connection.
+//
+// Until then, the connection holds on to a worker thread without doing anything useful.
+fn handshake_limits(config: &ServerConfig) -> ReadLimits {
+    ReadLimits {
+        deadline: handshake_deadline(config),
+        // A connection that begins with another request than the one it can serve is closed,
+        // rather than left waiting for a request that never comes
+        skip_other_requests: false,
+        ..ReadLimits::default()
+    }
+}
+
+// When a new connection must be done with the records it begins with. See
+// `ServerConfig::handshake_timeout`
+fn handshake_deadline(config: &ServerConfig) -> Option<Instant> {
+    let timeout = config
+        .handshake_timeout
+        .unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT);
+    (!timeout.is_zero()).then(|| Instant::now() + timeout)
+}
+
+fn notify_abort(config: &ServerConfig, req: &Request) {
+    log::info!(method = req.method, path = req.path; "FastCGI client aborted the request");
+    if let Some(callback) = &config.on_abort {
+        callback(req);
+    }
+}
+
+// Sends the response as a `FCGI_STDOUT` stream.
+//
+// A panic of the body stream is passed to `panicked`, and the response is ended where the stream
+// left it.
+//
+// Returns how many bytes were sent and the app status to end the request with, or why the
+// response was not completely sent.
+fn write_response(
+    conn: &mut Connection,
+    response: &Response,
+    line_ending: LineEnding,
+    panicked: impl FnOnce(&PanicReport),
+) -> Result<(usize, u32), Unsent> {
+    let mut writer = BodyWriter::new(conn);
+    let mut result = response.write_stdout_bytes(&mut writer, line_ending);
+
+    let mut app_status = response.app_status;
+    if let (Ok(()), Some(stream)) = (&result, &response.stream) {
+        result = match panic_report::catch(|| stream.run(&mut writer)) {
+            Ok(result) => result,
+            Err(report) => {
+                panicked(&report);
+                app_status = app_status::HANDLER_PANICKED;
+                Ok(())
+            }
+        };
+    }
+
+    if let Err(err) = result {
+        if !writer.is_aborted() {
+            log::warn!(error:err = err; "Failed to produce response body");
+            app_status = app_status::BODY_FAILED;
+        }
+    }
+
+    Ok((writer.finish()?, app_status))
+}
+
+// Logs `e`, and counts it in `stats` if it is a protocol error
+fn handle_error(conn: &mut Connection, e: Error, stats: &Stats) {
+    // Requests rejected because of what the client sent are counted by their response instead
+    let answered = matches!(
+        e,
+        Error::InvalidUtf8KeyValuePair | Error::ParamLimitExceeded(_) | Error::MemoryBudgetExceeded
+    ) || timed_out(&e);
+    if !answered {
+        stats.record_protocol_error();
+    }
     match e {
         Error::UnsupportedRole(_) => {
             let response = EndRequest::new(0, ProtocolStatus::UnknownRole);
             let _ = conn.write_record(&response.into());
             log::warn!("FastCGI client requested an unknown role. Closing connection");
         }
-        Error::MultiplexingUnsupported => {
-            let response = EndRequest::new(0, ProtocolStatus::MultiplexingUnsupported);
-            let _ = conn.write_record(&response.into());
-            log::warn!("FastCGI client requested connection multiplixing. It is not supported. Closing connection");
-        }
         Error::UnknownRecordType(t) => {
             let response = UnknownType(t);
             let _ = conn.write_record(&response.into());
             log::warn!("Unknown record type: {t}. Closing connection");
         }
+        Error::LimitExceeded(limit) => {
+            log::warn!(limit = limit; "FastCGI client exceeded a resource limit. Closing connection");
+        }
+        Error::UnexpectedSocketClose(e) if e.kind() == io::ErrorKind::TimedOut => {
+            log::warn!("FastCGI client took too long to send a record. Closing connection");
+        }
+        Error::ParamLimitExceeded(limit) => {
+            log::warn!(limit = limit; "Request params exceeded a limit. Rejecting the request");
+        }
+        Error::MemoryBudgetExceeded => {
+            log::warn!("The memory budget can't fit the request. Shedding it");
+        }
         e => {
             log::warn!(error:err = e; "Error reading FastCGI record. Closing connection");
         }
     }
 }
 
-fn handle_get_values(conn: &mut Connection, record: GetValues) {
+fn handle_get_values(conn: &mut Connection, record: GetValues, config: &ServerConfig) {
     let mut response = GetValuesResult::default();
     for variable in record.get_variables() {
         // If the client cares, tell it we do not want to multiplex connections
         if variable == "FCGI_MPXS_CONNS" {
             response = response.add("FCGI_MPXS_CONNS", "0");
-            break;
+            continue;
+        }
+        let provider = config
+            .management_values
+            .iter()
+            .find(|(name, _)| name == variable);
+        if let Some((name, provider)) = provider {
+            response = response.add(name, provider());
         }
     }
     let _ = conn.write_record(&Record::GetValuesResult(response));
@@ -1,16 +1,46 @@
 use crate::context::{Request, Response};
-use crate::status::{NOT_FOUND, NOT_MODIFIED, OK};
-use camino::Utf8PathBuf;
+use crate::headers;
+use crate::status::{NOT_FOUND, NOT_MODIFIED, OK, PARTIAL_CONTENT, RANGE_NOT_SATISFIABLE};
+use camino::{Utf8Path, Utf8PathBuf};
 use filetime::FileTime;
+use percent_encoding::percent_decode_str;
+use std::borrow::Cow;
 use std::fs;
+use std::ops::Range;
+use std::time::{Duration, UNIX_EPOCH};
 
+// How many bytes at the start of a file are examined when sniffing its content type
+const SNIFF_LEN: usize = 512;
+
+/// Serves static files from a directory
+///
+/// Use this with [`ServerConfig::file_server`](crate::ServerConfig::file_server) when the defaults
+/// of [`ServerConfig::serve_files`](crate::ServerConfig::serve_files) need tweaking.
+///
+/// ```
+/// use vintage::{FileServer, ServerConfig};
+///
+/// let config = ServerConfig::new()
+///     .file_server(
+///         FileServer::new("/static", "./assets")
+///             .sniff_content_type(true)
+///             .default_charset("utf-8")
+///     );
+/// ```
 #[derive(Debug, Clone)]
 pub struct FileServer {
     request_prefix: String,
     fs_path: Utf8PathBuf,
+    sniff_content_type: bool,
+    default_charset: Option<String>,
 }
 
 impl FileServer {
+    /// Creates a file server that matches requests that start with `prefix` and uses the rest of
+    /// the path to lookup a file at `path`.
+    ///
+    /// If `prefix` does not begin with a forward slash (e.g. `/static`), it is implied.
+    /// An empty `path` implies the current working directory.
     pub fn new(prefix: &'static str, path: &'static str) -> Self {
         let request_prefix = if prefix.starts_with('/') {
             prefix.to_string()
@@ -29,16 +59,68 @@ impl FileServer {
         Self {
             request_prefix,
             fs_path,
+            sniff_content_type: false,
+            default_charset: None,
         }
     }
 
-    pub fn respond(&self, req: &Request) -> Option<Response> {
+    /// Determines the content type of files without an extension by looking at their first few
+    /// bytes.
+    ///
+    /// When disabled (the default), such files are served as `application/octet-stream`.
+    pub fn sniff_content_type(mut self, enabled: bool) -> Self {
+        self.sniff_content_type = enabled;
+        self
+    }
+
+    /// Sets the `charset` parameter of the `Content-Type` header for text files (e.g. `utf-8`)
+    ///
+    /// This replaces any charset that would otherwise be sent.
+    pub fn default_charset(mut self, charset: impl Into<String>) -> Self {
+        self.default_charset = Some(charset.into());
+        self
+    }
+
+    pub(crate) fn request_prefix(&self) -> &str {
+        &self.request_prefix
+    }
+
+    // The rest of `path`, if `path` is under the request prefix.
+    // A `/static` prefix covers `/static` and `/static/app.css`, but not `/staticfile`.
+    pub(crate) fn strip_request_prefix<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(&self.request_prefix)?;
+        let at_boundary =
+            rest.is_empty() || rest.starts_with('/') || self.request_prefix.ends_with('/');
+        at_boundary.then_some(rest)
+    }
+
+    pub(crate) fn fs_path(&self) -> &Utf8Path {
+        &self.fs_path
+    }
+
+    fn content_type(&self, path: &Utf8Path, bytes: &[u8]) -> String {
+        let extension = path.extension();
+        let mime = match extension {
+            None if self.sniff_content_type => sniff_mime(&bytes[..bytes.len().min(SNIFF_LEN)]),
+            _ => extension_to_mime_impl(extension),
+        };
+
+        match &self.default_charset {
+            Some(charset) if is_textual(mime) => {
+                let essence = mime.split(';').next().unwrap_or_default().trim();
+                format!("{essence}; charset={charset}")
+            }
+            _ => mime.to_string(),
+        }
+    }
+
+    pub(crate) fn respond(&self, req: &Request) -> Option<Response> {
         if req.method != "GET" {
             return None;
         }
 
         // Ignore the request if its prefix is different from what was configured
-        let path = req.path.strip_prefix(&self.request_prefix)?;
+        let path = self.strip_request_prefix(&req.path)?;
 
         // First, validate that the base path exists.
         // The user could have provided a relative path.
@@ -46,28 +128,45 @@ impl FileServer {
             return Some(Response::new().set_status(NOT_FOUND));
         };
 
-        // Create the full path: <base>/<path>
+        // Create the full path: <base>/<path>, for the first candidate that exists
         // For this to work though, we need to strip any leading forward slashes from `path`
         // If we do not do this, `Path::join()` will assume it is an absolute path
-        let full_path = base.join(path.trim_start_matches('/'));
+        let full_path = file_names(path).find_map(|name| {
+            let full_path = base.join(name.trim_start_matches('/'));
+            full_path.canonicalize_utf8().ok()
+        });
 
         // Ensure the path exists
-        let Ok(full_path) = full_path.canonicalize_utf8() else {
+        let Some(full_path) = full_path else {
             return Some(Response::new().set_status(NOT_FOUND));
         };
 
         // Ensure the canonical form still points to a directory inside `base`
         // This prevents things like `GET ../../blah.txt`
-        if !full_path.starts_with(&base) {
+        if !is_within(&full_path, &base) {
             return Some(Response::new().set_status(NOT_FOUND));
         };
 
-        // Ensure the path points to a file (and not a directory)
+        Some(self.respond_with_file(req, &full_path, None))
+    }
+
+    // Serves the file at `full_path`, which is known to be fine to serve.
+    //
+    // `content_type` replaces the one guessed from the file.
+    pub(crate) fn respond_with_file(
+        &self,
+        req: &Request,
+        full_path: &Utf8Path,
+        content_type: Option<&str>,
+    ) -> Response {
+        // Ensure the path points to a file (and not a directory).
+        // Files modified before 1970 are rare enough to not be worth a negative offset, so their
+        // modification time is clamped to the epoch, for the ETag and Last-Modified alike.
         let mtime = match full_path.metadata() {
-            Ok(meta) if meta.is_file() => {
-                FileTime::from_last_modification_time(&meta).unix_seconds()
-            }
-            _ => return Some(Response::new().set_status(NOT_FOUND)),
+            Ok(meta) if meta.is_file() => FileTime::from_last_modification_time(&meta)
+                .unix_seconds()
+                .max(0) as u64,
+            _ => return Response::new().set_status(NOT_FOUND),
         };
 
         // Caching approach:
@@ -87,167 +186,343 @@ impl FileServer {
         // The filetime as unix seconds is used as the etag
 
         let current_etag_value = format!("\"{}\"", mtime);
-        let mut res = Response::new()
-            .set_header("Cache-Control", "no-cache")
-            .set_header("ETag", &current_etag_value);
-
-        if let Ok(mtime) = jiff::Timestamp::from_second(mtime) {
-            // e.g. Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT
-            let last_modified = mtime.strftime("%a, %d %b %Y %H:%M:%S GMT");
-            res = res.set_header("Last-Modified", last_modified.to_string());
-        }
+        let modified = UNIX_EPOCH + Duration::from_secs(mtime);
+        let res = Response::new()
+            .set_header(headers::CACHE_CONTROL, "no-cache")
+            .set_header(headers::ETAG, &current_etag_value)
+            .set_date_header(headers::LAST_MODIFIED, modified);
 
-        if let Some(request_etag) = req.headers.get("If-None-Match") {
+        if let Some(request_etag) = req.header(headers::IF_NONE_MATCH) {
             // This header can look like:
             // If-None-Match: "<etag_value>"
             // If-None-Match: "<etag_value>", "<etag_value>", …
             // If-None-Match: *
 
             if request_etag.contains(&current_etag_value) {
-                return Some(res.set_status(NOT_MODIFIED));
+                return res.set_status(NOT_MODIFIED);
             }
         }
 
-        let bytes = match fs::read(&full_path) {
+        let bytes = match fs::read(full_path) {
             Ok(bytes) => bytes,
-            Err(_) => return Some(Response::new().set_status(NOT_FOUND)),
+            Err(_) => return Response::new().set_status(NOT_FOUND),
         };
 
-        let extension = full_path.extension();
-        let content_type = extension_to_mime_impl(extension);
+        let content_type = match content_type {
+            Some(content_type) => content_type.to_string(),
+            None => self.content_type(full_path, &bytes),
+        };
 
-        Some(
-            res.set_status(OK)
-                .set_header("Content-Type", content_type)
-                .set_raw_body(bytes),
-        )
+        let res = res
+            .set_header(headers::ACCEPT_RANGES, "bytes")
+            .set_header(headers::CONTENT_TYPE, content_type);
+
+        // Range requests are used to resume downloads.
+        // `If-Range` makes the `Range` conditional: If the file changed since the client
+        // downloaded the first part, the client needs to start over, so the whole file is sent.
+        // Stitching parts of two different versions of the file would corrupt the download.
+        let range = req
+            .header(headers::RANGE)
+            .filter(|_| match req.header(headers::IF_RANGE) {
+                Some(validator) => {
+                    let validator = validator.trim();
+                    validator == current_etag_value
+                        || res.headers.get(headers::LAST_MODIFIED).map(String::as_str)
+                            == Some(validator)
+                }
+                None => true,
+            })
+            .and_then(|range| parse_range(range, bytes.len()));
+
+        match range {
+            None => res.set_status(OK).set_raw_body(bytes),
+            Some(ByteRange::Satisfiable(ranges)) if ranges.len() == 1 => {
+                let range = ranges[0].clone();
+                let content_range =
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, bytes.len());
+                res.set_status(PARTIAL_CONTENT)
+                    .set_header(headers::CONTENT_RANGE, content_range)
+                    .set_raw_body(bytes[range].to_vec())
+            }
+            // Each range is sent in its own part, with its own `Content-Range`
+            Some(ByteRange::Satisfiable(ranges)) => {
+                let (boundary, body) =
+                    multipart_byteranges(&bytes, &ranges, &res.headers[headers::CONTENT_TYPE]);
+                res.set_status(PARTIAL_CONTENT)
+                    .set_header(
+                        headers::CONTENT_TYPE,
+                        format!("multipart/byteranges; boundary={boundary}"),
+                    )
+                    .set_raw_body(body)
+            }
+            Some(ByteRange::Unsatisfiable) => Response::new()
+                .set_status(RANGE_NOT_SATISFIABLE)
+                .set_header(headers::CONTENT_RANGE, format!("bytes */{}", bytes.len())),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use camino::Utf8Path;
-    use std::collections::BTreeMap;
+// Returns the names of the file that the part of a request path after the prefix could refer to.
+//
+// Some web servers pass the path percent-encoded (e.g. `caf%C3%A9.png`), and others decode it
+// first. The decoded name is tried first, then the name as it was received, in case it was
+// already decoded and the file name contains a `%`.
+//
+// Names that could reach outside the served directory are left out: encoded separators
+// (`%2F`, `%5C`), and the names rejected by `is_safe_file_name`.
+fn file_names(path: &str) -> impl Iterator<Item = String> {
+    let is_safe = |name: &str| is_safe_file_name(name, cfg!(windows));
 
-    struct FileInfo {
-        etag: String,
-        last_modified: String,
-        content: Vec<u8>,
+    let lowercase = path.to_ascii_lowercase();
+    let decoded = if lowercase.contains("%2f") || lowercase.contains("%5c") {
+        None
+    } else {
+        percent_decode_str(path)
+            .decode_utf8()
+            .ok()
+            .map(|name| name.into_owned())
+            .filter(|name| is_safe(name))
+    };
+    let received =
+        Some(path.to_string()).filter(|name| is_safe(name) && decoded.as_ref() != Some(name));
+
+    decoded.into_iter().chain(received)
+}
+
+// Returns false for names with `..` components or NUL bytes.
+//
+// On Windows, names with a `:` are rejected too. They could name another drive (`C:/x`), or an
+// alternate data stream of a file (`index.html::$DATA`).
+fn is_safe_file_name(name: &str, windows: bool) -> bool {
+    if name.contains('\0') || (windows && name.contains(':')) {
+        return false;
     }
+    !name.split(['/', '\\']).any(|component| component == "..")
+}
 
-    fn file_info(path: &str) -> FileInfo {
-        let metadata = Utf8Path::new(path).metadata().unwrap();
-        let mtime = FileTime::from_last_modification_time(&metadata).unix_seconds();
-        let timestamp = jiff::Timestamp::from_second(mtime).unwrap();
-        let last_modified = timestamp.strftime("%a, %d %b %Y %H:%M:%S GMT").to_string();
-        FileInfo {
-            etag: format!("\"{mtime}\""),
-            last_modified,
-            content: fs::read(path).unwrap(),
-        }
+// Returns true if `path` is `base` or inside it.
+//
+// On Windows, canonical paths are "verbatim" paths (e.g. `\\?\C:\static`), which don't compare
+// equal to the usual form of the same path. They are converted back before comparing, in case
+// only one of the paths is verbatim.
+fn is_within(path: &Utf8Path, base: &Utf8Path) -> bool {
+    if !cfg!(windows) {
+        return path.starts_with(base);
     }
+    let path = without_verbatim_prefix(path.as_str());
+    let base = without_verbatim_prefix(base.as_str());
+    Utf8Path::new(path.as_ref()).starts_with(Utf8Path::new(base.as_ref()))
+}
 
-    #[test]
-    fn empty_prefix_and_path() {
-        let fs = FileServer::new("", "");
-        assert_eq!(fs.request_prefix, "/");
-        assert_eq!(fs.fs_path, ".");
+fn without_verbatim_prefix(path: &str) -> Cow<'_, str> {
+    if let Some(share) = path.strip_prefix(r"\\?\UNC\") {
+        return Cow::Owned(format!(r"\\{share}"));
     }
+    Cow::Borrowed(path.strip_prefix(r"\\?\").unwrap_or(path))
+}
 
-    #[test]
-    fn respond_to_request_with_no_path() {
-        let req = Request::default();
-        let fs = FileServer::new("", "");
+// How many ranges a request can ask for before its `Range` header is ignored.
+// Requests for many small ranges are costly to serve, and are a known denial of service vector.
+const MAX_RANGES: usize = 32;
 
-        // An empty prefix defaults to `/`..which is not a prefix of a path
-        // that does not begin with `/`.
-        assert_eq!(fs.respond(&req), None);
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    // The ranges to send, in the order they were requested (or sorted, if some overlapped)
+    Satisfiable(Vec<Range<usize>>),
+    Unsatisfiable,
+}
+
+// Parses the value of a `Range` header for a file of size `len`.
+//
+// The header can look like:
+// Range: bytes=<start>-<end>
+// Range: bytes=<start>-
+// Range: bytes=-<suffix-length>
+// Range: bytes=<range>, <range>, …
+//
+// Returns `None` if the header should be ignored, either because it is invalid or because it asks
+// for something we don't support (e.g. a unit other than bytes, or too many ranges).
+// Ranges that start past the end of the file are left out. Overlapping ranges are merged, as
+// RFC 9110 allows, so that no byte is sent twice.
+fn parse_range(header: &str, len: usize) -> Option<ByteRange> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    if specs.split(',').count() > MAX_RANGES {
+        return None;
     }
 
-    #[test]
-    fn respond_to_request_with_path_outside_prefix() {
-        let mut req = Request::default();
-        req.path = String::from("/about");
-        let fs = FileServer::new("/static", ".");
+    let mut ranges = vec![];
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        // Empty elements are allowed in HTTP lists (e.g. `bytes=0-1,,3-4`)
+        if spec.is_empty() {
+            continue;
+        }
+        if let Some(range) = parse_range_spec(spec, len)? {
+            ranges.push(range);
+        }
+    }
 
-        assert_eq!(fs.respond(&req), None);
+    if ranges.is_empty() {
+        return Some(ByteRange::Unsatisfiable);
     }
 
-    #[test]
-    fn respond_to_request_with_non_existing_file() {
-        let fs = FileServer::new("/static", "./src");
+    let overlaps = ranges.iter().enumerate().any(|(i, a)| {
+        ranges[i + 1..]
+            .iter()
+            .any(|b| a.start < b.end && b.start < a.end)
+    });
+    if overlaps {
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = vec![];
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        ranges = merged;
+    }
 
-        let mut req = Request::default();
-        req.method = String::from("GET");
-        req.path = String::from("/static/../file.txt");
+    Some(ByteRange::Satisfiable(ranges))
+}
 
-        assert_eq!(
-            fs.respond(&req),
-            Some(Response::new().set_status(NOT_FOUND))
-        );
+// Parses one range of a `Range` header.
+//
+// Returns `None` if it is invalid, and `Some(None)` if it is valid but starts past the end of the
+// file.
+fn parse_range_spec(spec: &str, len: usize) -> Option<Option<Range<usize>>> {
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let parse = |s: &str| -> Option<usize> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        // Numbers too large to fit are still valid; they are past the end of any file.
+        Some(s.parse().unwrap_or(usize::MAX))
+    };
+
+    if start.is_empty() {
+        // The last `suffix` bytes of the file
+        let suffix = parse(end)?;
+        if suffix == 0 || len == 0 {
+            return Some(None);
+        }
+        return Some(Some(len.saturating_sub(suffix)..len));
     }
 
-    #[test]
-    fn respond_to_request_trying_to_escape_file_hierarchy() {
-        let fs = FileServer::new("/static", "./src");
+    let start = parse(start)?;
+    let end = if end.is_empty() {
+        usize::MAX
+    } else {
+        parse(end)?
+    };
+
+    if start > end {
+        return None;
+    }
 
-        let mut req = Request::default();
-        req.method = String::from("GET");
-        req.path = String::from("/static/../README.md");
+    if start >= len {
+        return Some(None);
+    }
 
-        assert_eq!(
-            fs.respond(&req),
-            Some(Response::new().set_status(NOT_FOUND))
+    Some(Some(start..end.saturating_add(1).min(len)))
+}
+
+// Builds a `multipart/byteranges` body with a part for each range of `bytes`.
+// Returns the boundary that separates the parts, and the body.
+//
+// https://www.rfc-editor.org/rfc/rfc9110#name-media-type-multipart-byteran
+fn multipart_byteranges(
+    bytes: &[u8],
+    ranges: &[Range<usize>],
+    content_type: &str,
+) -> (String, Vec<u8>) {
+    // The boundary must not appear in the body. A random one is unlikely to.
+    let mut random = [0; 12];
+    getrandom::fill(&mut random).expect("the OS should provide random bytes");
+    let boundary: String = random.iter().map(|b| format!("{b:02x}")).collect();
+
+    let mut body = vec![];
+    for range in ranges {
+        let header = format!(
+            "--{boundary}\r\n{}: {content_type}\r\n{}: bytes {}-{}/{}\r\n\r\n",
+            headers::CONTENT_TYPE,
+            headers::CONTENT_RANGE,
+            range.start,
+            range.end - 1,
+            bytes.len()
         );
+        body.extend_from_slice(header.as_bytes());
+        body.extend_from_slice(&bytes[range.clone()]);
+        body.extend_from_slice(b"\r\n");
     }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
 
-    #[test]
-    fn respond_to_uncached_file() {
-        let fs = FileServer::new("/static", ".");
-        let FileInfo {
-            etag,
-            last_modified,
-            content,
-        } = file_info("./README.md");
+    (boundary, body)
+}
 
-        let mut req = Request::default();
-        req.method = String::from("GET");
-        req.path = String::from("/static/README.md");
+// Returns true if `mime` describes textual content, for which a charset is meaningful
+fn is_textual(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || essence.ends_with("+json")
+        || matches!(
+            essence,
+            "application/json" | "application/javascript" | "application/xml"
+        )
+}
 
-        assert_eq!(
-            fs.respond(&req).unwrap(),
-            Response::new()
-                .set_header("Last-Modified", last_modified)
-                .set_header("ETag", etag)
-                .set_header("Cache-Control", "no-cache")
-                .set_header("Content-Type", "text/markdown")
-                .set_raw_body(content)
-        );
+/// Returns the mime type of a file based on its first few bytes.
+fn sniff_mime(head: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/x-gzip"),
+        (b"\0asm", "application/wasm"),
+    ];
+
+    for (signature, mime) in SIGNATURES {
+        if head.starts_with(signature) {
+            return mime;
+        }
     }
 
-    #[test]
-    fn respond_to_cached_file() {
-        let fs = FileServer::new("/static", ".");
-        let FileInfo {
-            etag,
-            last_modified,
-            ..
-        } = file_info("./README.md");
+    if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        return "image/webp";
+    }
 
-        let mut req = Request::default();
-        req.method = String::from("GET");
-        req.path = String::from("/static/README.md");
-        req.headers = BTreeMap::from([("If-None-Match".to_string(), etag.clone())]);
+    // Past this point, only text is recognized
+    let text = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let text = match std::str::from_utf8(text) {
+        Ok(text) => text,
+        // The file may have been cut in the middle of a character, which is fine.
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&text[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return "application/octet-stream",
+    };
 
-        assert_eq!(
-            fs.respond(&req).unwrap(),
-            Response::new()
-                .set_status(NOT_MODIFIED)
-                .set_header("Last-Modified", last_modified)
-                .set_header("ETag", etag)
-                .set_header("Cache-Control", "no-cache")
-        );
+    if text.contains('\0') {
+        return "application/octet-stream";
+    }
+
+    let start = text.trim_start().get(..14).unwrap_or(text.trim_start());
+    let start = start.to_ascii_lowercase();
+
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html"
+    } else if start.starts_with("<svg") {
+        "image/svg+xml"
+    } else if start.starts_with("<?xml") {
+        "application/xml"
+    } else {
+        "text/plain"
     }
 }
 
@@ -828,3 +1103,468 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         _ => "application/octet-stream",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino::Utf8Path;
+    use std::collections::BTreeMap;
+
+    struct FileInfo {
+        etag: String,
+        last_modified: String,
+        content: Vec<u8>,
+    }
+
+    fn file_info(path: &str) -> FileInfo {
+        let metadata = Utf8Path::new(path).metadata().unwrap();
+        let mtime = FileTime::from_last_modification_time(&metadata).unix_seconds();
+        let modified = UNIX_EPOCH + Duration::from_secs(mtime as u64);
+        let last_modified = crate::httpdate::format(modified);
+        FileInfo {
+            etag: format!("\"{mtime}\""),
+            last_modified,
+            content: fs::read(path).unwrap(),
+        }
+    }
+
+    #[test]
+    fn empty_prefix_and_path() {
+        let fs = FileServer::new("", "");
+        assert_eq!(fs.request_prefix, "/");
+        assert_eq!(fs.fs_path, ".");
+    }
+
+    #[test]
+    fn respond_to_request_with_no_path() {
+        let req = Request::default();
+        let fs = FileServer::new("", "");
+
+        // An empty prefix defaults to `/`..which is not a prefix of a path
+        // that does not begin with `/`.
+        assert_eq!(fs.respond(&req), None);
+    }
+
+    #[test]
+    fn respond_to_request_with_path_outside_prefix() {
+        let req = Request {
+            path: String::from("/about"),
+            ..Request::default()
+        };
+        let fs = FileServer::new("/static", ".");
+
+        assert_eq!(fs.respond(&req), None);
+
+        // The prefix only matches whole path segments
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/staticsrc/lib.rs"),
+            ..Request::default()
+        };
+        assert_eq!(fs.respond(&req), None);
+    }
+
+    #[test]
+    fn respond_to_request_with_non_existing_file() {
+        let fs = FileServer::new("/static", "./src");
+
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/../file.txt"),
+            ..Request::default()
+        };
+
+        assert_eq!(
+            fs.respond(&req),
+            Some(Response::new().set_status(NOT_FOUND))
+        );
+    }
+
+    #[test]
+    fn respond_to_request_trying_to_escape_file_hierarchy() {
+        let fs = FileServer::new("/static", "./src");
+
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/../README.md"),
+            ..Request::default()
+        };
+
+        assert_eq!(
+            fs.respond(&req),
+            Some(Response::new().set_status(NOT_FOUND))
+        );
+    }
+
+    #[test]
+    fn respond_to_uncached_file() {
+        let fs = FileServer::new("/static", ".");
+        let FileInfo {
+            etag,
+            last_modified,
+            content,
+        } = file_info("./README.md");
+
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/README.md"),
+            ..Request::default()
+        };
+
+        assert_eq!(
+            fs.respond(&req).unwrap(),
+            Response::new()
+                .set_header("Last-Modified", last_modified)
+                .set_header("ETag", etag)
+                .set_header("Cache-Control", "no-cache")
+                .set_header("Accept-Ranges", "bytes")
+                .set_header("Content-Type", "text/markdown")
+                .set_raw_body(content)
+        );
+    }
+
+    #[test]
+    fn respond_to_range_request() {
+        let fs = FileServer::new("/static", ".");
+        let FileInfo { content, .. } = file_info("./README.md");
+
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/README.md"),
+            headers: BTreeMap::from([("Range".to_string(), "bytes=2-5".to_string())]),
+            ..Request::default()
+        };
+
+        let res = fs.respond(&req).unwrap();
+        assert_eq!(res.status, PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers["Content-Range"],
+            format!("bytes 2-5/{}", content.len())
+        );
+        assert_eq!(res.body, content[2..6]);
+    }
+
+    #[test]
+    fn respond_to_multiple_range_request() {
+        let fs = FileServer::new("/static", ".");
+        let FileInfo { content, .. } = file_info("./README.md");
+        let len = content.len();
+
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/README.md"),
+            headers: BTreeMap::from([("Range".to_string(), "bytes=0-1, -3".to_string())]),
+            ..Request::default()
+        };
+
+        let res = fs.respond(&req).unwrap();
+        assert_eq!(res.status, PARTIAL_CONTENT);
+        assert!(!res.headers.contains_key("Content-Range"));
+        let boundary = res.headers["Content-Type"]
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+
+        let mut expected = format!(
+            "--{boundary}\r\nContent-Type: text/markdown\r\nContent-Range: bytes 0-1/{len}\r\n\r\n"
+        )
+        .into_bytes();
+        expected.extend_from_slice(&content[..2]);
+        expected.extend_from_slice(
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: text/markdown\r\nContent-Range: bytes {}-{}/{len}\r\n\r\n",
+                len - 3,
+                len - 1
+            )
+            .as_bytes(),
+        );
+        expected.extend_from_slice(&content[len - 3..]);
+        expected.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+        assert_eq!(res.body, expected);
+    }
+
+    #[test]
+    fn respond_to_unsatisfiable_range_request() {
+        let fs = FileServer::new("/static", ".");
+        let FileInfo { content, .. } = file_info("./README.md");
+
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/README.md"),
+            headers: BTreeMap::from([("Range".to_string(), format!("bytes={}-", content.len()))]),
+            ..Request::default()
+        };
+
+        let res = fs.respond(&req).unwrap();
+        assert_eq!(res.status, RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            res.headers["Content-Range"],
+            format!("bytes */{}", content.len())
+        );
+        assert!(res.body.is_empty());
+    }
+
+    #[test]
+    fn respond_to_if_range_request() {
+        let fs = FileServer::new("/static", ".");
+        let FileInfo {
+            etag,
+            last_modified,
+            content,
+        } = file_info("./README.md");
+
+        let request = |if_range: &str| Request {
+            method: String::from("GET"),
+            path: String::from("/static/README.md"),
+            headers: BTreeMap::from([
+                ("Range".to_string(), "bytes=-3".to_string()),
+                ("If-Range".to_string(), if_range.to_string()),
+            ]),
+            ..Request::default()
+        };
+
+        // The file did not change, so the range is honored
+        for validator in [etag.as_str(), last_modified.as_str()] {
+            let res = fs.respond(&request(validator)).unwrap();
+            assert_eq!(res.status, PARTIAL_CONTENT);
+            assert_eq!(res.body, content[content.len() - 3..]);
+        }
+
+        // The file changed, so the whole file is sent
+        for validator in ["\"1\"", "Thu, 01 Jan 1970 00:00:01 GMT", "W/\"1\""] {
+            let res = fs.respond(&request(validator)).unwrap();
+            assert_eq!(res.status, OK);
+            assert_eq!(res.body, content);
+            assert!(!res.headers.contains_key("Content-Range"));
+        }
+    }
+
+    #[test]
+    // A single range is a vec with one element, not a vec of the numbers in the range
+    #[allow(clippy::single_range_in_vec_init)]
+    fn range_parsing() {
+        use ByteRange::*;
+
+        assert_eq!(parse_range("bytes=0-0", 10), Some(Satisfiable(vec![0..1])));
+        assert_eq!(parse_range("bytes=2-", 10), Some(Satisfiable(vec![2..10])));
+        assert_eq!(
+            parse_range("bytes=2-100", 10),
+            Some(Satisfiable(vec![2..10]))
+        );
+        assert_eq!(parse_range("bytes=-3", 10), Some(Satisfiable(vec![7..10])));
+        assert_eq!(
+            parse_range("bytes=-100", 10),
+            Some(Satisfiable(vec![0..10]))
+        );
+        assert_eq!(
+            parse_range("bytes=99999999999999999999999-", 10),
+            Some(Unsatisfiable)
+        );
+        assert_eq!(parse_range("bytes=10-", 10), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=-0", 10), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=-1", 0), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=5-2", 10), None);
+        assert_eq!(
+            parse_range("bytes=6-7, 0-1,,20-30", 10),
+            Some(Satisfiable(vec![6..8, 0..2]))
+        );
+        assert_eq!(
+            parse_range("bytes=4-6,0-1,-5,2-2", 10),
+            Some(Satisfiable(vec![0..3, 4..10]))
+        );
+        assert_eq!(parse_range("bytes=10-,20-", 10), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-1,x", 10), None);
+        assert_eq!(
+            parse_range(&format!("bytes={}", ["0-0"; 33].join(",")), 10),
+            None
+        );
+        assert_eq!(parse_range("items=0-1", 10), None);
+        assert_eq!(parse_range("bytes=a-b", 10), None);
+        assert_eq!(parse_range("bytes=-", 10), None);
+    }
+
+    #[test]
+    fn respond_to_extensionless_file() {
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/LICENSE"),
+            ..Request::default()
+        };
+
+        let fs = FileServer::new("/static", ".");
+        let res = fs.respond(&req).unwrap();
+        assert_eq!(res.headers["Content-Type"], "application/octet-stream");
+
+        let fs = FileServer::new("/static", ".").sniff_content_type(true);
+        let res = fs.respond(&req).unwrap();
+        assert_eq!(res.headers["Content-Type"], "text/plain");
+    }
+
+    #[test]
+    fn respond_with_default_charset() {
+        let fs = FileServer::new("/static", ".").default_charset("utf-8");
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/README.md"),
+            ..Request::default()
+        };
+
+        let res = fs.respond(&req).unwrap();
+        assert_eq!(res.headers["Content-Type"], "text/markdown; charset=utf-8");
+
+        let req = Request {
+            path: String::from("/static/src/error.rs"),
+            ..req
+        };
+        let res = fs.respond(&req).unwrap();
+        assert_eq!(res.headers["Content-Type"], "application/octet-stream");
+    }
+
+    #[test]
+    fn sniffing() {
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WEBPVP8"), "image/webp");
+        assert_eq!(sniff_mime(b"  <!DOCTYPE html><html>"), "text/html");
+        assert_eq!(sniff_mime(b"\xef\xbb\xbf<?xml version"), "application/xml");
+        assert_eq!(sniff_mime("caf\u{e9}".as_bytes()), "text/plain");
+        // A multi-byte character cut in half
+        assert_eq!(sniff_mime(&"caf\u{e9}".as_bytes()[..4]), "text/plain");
+        assert_eq!(sniff_mime(b"\x00\x01\x02"), "application/octet-stream");
+        assert_eq!(sniff_mime(b"\xff\xfe\xfd"), "application/octet-stream");
+    }
+
+    #[test]
+    fn respond_to_cached_file() {
+        let fs = FileServer::new("/static", ".");
+        let FileInfo {
+            etag,
+            last_modified,
+            ..
+        } = file_info("./README.md");
+
+        let req = Request {
+            method: String::from("GET"),
+            path: String::from("/static/README.md"),
+            headers: BTreeMap::from([("If-None-Match".to_string(), etag.clone())]),
+            ..Request::default()
+        };
+
+        assert_eq!(
+            fs.respond(&req).unwrap(),
+            Response::new()
+                .set_status(NOT_MODIFIED)
+                .set_header("Last-Modified", last_modified)
+                .set_header("ETag", etag)
+                .set_header("Cache-Control", "no-cache")
+        );
+    }
+
+    #[test]
+    fn file_name_decoding() {
+        let names = |path: &str| file_names(path).collect::<Vec<_>>();
+        assert_eq!(names("/caf%C3%A9.png"), ["/café.png", "/caf%C3%A9.png"]);
+        assert_eq!(names("/my%20file.txt"), ["/my file.txt", "/my%20file.txt"]);
+        assert_eq!(names("/café.png"), ["/café.png"]);
+        assert_eq!(names("/100%.txt"), ["/100%.txt"]);
+
+        // Encoded separators are only taken literally
+        assert_eq!(
+            names("/..%2F..%2Fetc%2Fpasswd"),
+            ["/..%2F..%2Fetc%2Fpasswd"]
+        );
+        assert_eq!(names("/a%5c..%5cb"), ["/a%5c..%5cb"]);
+        // Traversal that only shows after decoding
+        assert_eq!(names("/%2E%2E/secret"), ["/%2E%2E/secret"]);
+        assert!(names("/../secret").is_empty());
+        assert_eq!(names("/a%00.txt"), ["/a%00.txt"]);
+        assert_eq!(names("/%FF.txt"), ["/%FF.txt"]);
+    }
+
+    #[test]
+    fn windows_file_names() {
+        assert!(is_safe_file_name("/docs/readme.txt", true));
+        assert!(!is_safe_file_name("/C:/Windows/win.ini", true));
+        assert!(!is_safe_file_name("/index.html::$DATA", true));
+        assert!(!is_safe_file_name("/a\\..\\secret", true));
+        assert!(is_safe_file_name("/index.html::$DATA", false));
+
+        assert_eq!(without_verbatim_prefix(r"\\?\C:\static"), r"C:\static");
+        assert_eq!(
+            without_verbatim_prefix(r"\\?\UNC\host\share"),
+            r"\\host\share"
+        );
+        assert_eq!(without_verbatim_prefix("/srv/static"), "/srv/static");
+    }
+
+    #[test]
+    fn file_modified_before_epoch() {
+        let dir = std::env::temp_dir().join(format!("vintage-old-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("old.txt");
+        fs::write(&path, "old").unwrap();
+        filetime::set_file_mtime(&path, FileTime::from_unix_time(-86400, 0)).unwrap();
+
+        let served: &'static str = dir.to_str().unwrap().to_string().leak();
+        let file_server = FileServer::new("/", served);
+        let mut req = Request {
+            method: String::from("GET"),
+            path: String::from("/old.txt"),
+            ..Request::default()
+        };
+        let res = file_server.respond(&req).unwrap();
+        assert_eq!(res.headers["ETag"], "\"0\"");
+        assert_eq!(
+            res.headers["Last-Modified"],
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+
+        // Both validators identify the same version of the file
+        req.headers.insert(
+            String::from("If-Range"),
+            res.headers["Last-Modified"].clone(),
+        );
+        req.headers
+            .insert(String::from("Range"), String::from("bytes=0-0"));
+        assert_eq!(file_server.respond(&req).unwrap().status, PARTIAL_CONTENT);
+        req.headers
+            .insert(String::from("If-None-Match"), res.headers["ETag"].clone());
+        assert_eq!(file_server.respond(&req).unwrap().status, NOT_MODIFIED);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn respond_to_encoded_file_names() {
+        let dir = std::env::temp_dir().join(format!("vintage-files-{}", std::process::id()));
+        let served = dir.join("served");
+        fs::create_dir_all(&served).unwrap();
+        fs::write(served.join("café.png"), "unicode").unwrap();
+        fs::write(served.join("my file.txt"), "spaces").unwrap();
+        fs::write(served.join("50%25.txt"), "literal").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+
+        let served: &'static str = served.to_str().unwrap().to_string().leak();
+        let file_server = FileServer::new("/static", served);
+        let get = |path: &str| {
+            let req = Request {
+                method: String::from("GET"),
+                path: path.to_string(),
+                ..Request::default()
+            };
+            let res = file_server.respond(&req).unwrap();
+            (res.status, String::from_utf8(res.body).unwrap())
+        };
+
+        assert_eq!(get("/static/caf%C3%A9.png"), (OK, "unicode".into()));
+        assert_eq!(get("/static/café.png"), (OK, "unicode".into()));
+        assert_eq!(get("/static/my%20file.txt"), (OK, "spaces".into()));
+        assert_eq!(get("/static/my file.txt"), (OK, "spaces".into()));
+        // A name that was already decoded, and still contains a `%`
+        assert_eq!(get("/static/50%25.txt"), (OK, "literal".into()));
+
+        assert_eq!(get("/static/%2E%2E/secret.txt").0, NOT_FOUND);
+        assert_eq!(get("/static/..%2Fsecret.txt").0, NOT_FOUND);
+        assert_eq!(get("/static/x%2F..%2F..%2Fsecret.txt").0, NOT_FOUND);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,528 @@
+use crate::context::{Request, Response};
+use crate::headers;
+use crate::status;
+use percent_encoding::percent_decode_str;
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+mod de;
+
+// Parses `application/x-www-form-urlencoded` data (e.g. a query string) into name-value pairs.
+//
+// Invalid percent-encoded utf8 sequences are replaced with `U+FFFD`.
+pub fn parse(input: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+}
+
+fn decode(component: &str) -> String {
+    let component = component.replace('+', " ");
+    percent_decode_str(&component)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// A file uploaded with a `multipart/form-data` form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormFile {
+    name: String,
+    filename: String,
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+
+impl FormFile {
+    /// Returns the name of the form field
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the name of the file on the client's machine, as sent by the browser
+    ///
+    /// Don't use it as a path without sanitizing it first.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Returns the content type of the file, as sent by the browser
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Returns the content of the file
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// The fields of a submitted HTML form, and the validation errors found in them
+///
+/// A form is read from the query string of `GET` and `HEAD` requests, and from the body of other
+/// requests (`application/x-www-form-urlencoded` or `multipart/form-data`).
+///
+/// Classic server-rendered forms are validated on submission, and rendered again with the values
+/// the user entered and what is wrong with them. [`Form::value`] and [`Form::errors`] give
+/// templates both.
+///
+/// ```
+/// use vintage::{Form, Response, ServerConfig};
+///
+/// let config = ServerConfig::new().on_post(["/signup"], |req, _params| {
+///     let mut form = match Form::from_request(req) {
+///         Ok(form) => form,
+///         Err(rejection) => return rejection,
+///     };
+///
+///     if !form.value("email").unwrap_or_default().contains('@') {
+///         form.add_error("email", "Enter a valid email address");
+///     }
+///     if form.has_errors() {
+///         let error = form.errors("email").join(", ");
+///         return Response::html(format!("<p>{error}</p>")).set_status(422);
+///     }
+///
+///     Response::html("<p>Welcome!</p>")
+/// });
+/// ```
+///
+/// With the `serde` feature, fields can be deserialized into a struct with
+/// `Form::deserialize`, and the form can be serialized for template engines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<FormFile>,
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+impl Form {
+    /// Reads the fields of the form submitted with `req`
+    ///
+    /// Fails with a `415 Unsupported Media Type` response if the body is not a form, and a
+    /// `400 Bad Request` response if it is malformed.
+    pub fn from_request(req: &Request) -> Result<Self, Response> {
+        if matches!(req.method.as_str(), "GET" | "HEAD") {
+            return Ok(Self::from_fields(parse(&req.query_string)));
+        }
+
+        let content_type = req.header(headers::CONTENT_TYPE).unwrap_or_default();
+        let (media_type, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+        let media_type = media_type.trim();
+
+        if media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            let Ok(body) = std::str::from_utf8(&req.body) else {
+                return Err(reject(status::BAD_REQUEST, "The form is not valid utf8"));
+            };
+            return Ok(Self::from_fields(parse(body)));
+        }
+
+        if media_type.eq_ignore_ascii_case("multipart/form-data") {
+            let boundary = header_params(params)
+                .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+                .map(|(_, value)| value);
+            let Some(boundary) = boundary.filter(|b| !b.is_empty()) else {
+                return Err(reject(status::BAD_REQUEST, "The form has no boundary"));
+            };
+            return parse_multipart(&req.body, &boundary)
+                .ok_or_else(|| reject(status::BAD_REQUEST, "The form is malformed"));
+        }
+
+        Err(reject(
+            status::UNSUPPORTED_MEDIA_TYPE,
+            "Expected a form submission",
+        ))
+    }
+
+    /// Creates a form from name-value pairs (e.g. to render an empty or pre-filled form)
+    pub fn from_fields<N, V>(fields: impl IntoIterator<Item = (N, V)>) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        Self {
+            fields: fields
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the first value of the field `name`, if it was submitted
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every value of the field `name` (e.g. for checkboxes that share a name)
+    pub fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every submitted field, in order
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the file uploaded in the field `name`, if any
+    pub fn file(&self, name: &str) -> Option<&FormFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    /// Returns every uploaded file, in order
+    pub fn files(&self) -> &[FormFile] {
+        &self.files
+    }
+
+    /// Records a validation error for the field `name`
+    ///
+    /// Errors that are not about a single field can be recorded with an empty name.
+    pub fn add_error(&mut self, name: impl Into<String>, message: impl Into<String>) {
+        self.errors
+            .entry(name.into())
+            .or_default()
+            .push(message.into());
+    }
+
+    /// Returns the validation errors of the field `name`
+    pub fn errors(&self, name: &str) -> &[String] {
+        self.errors.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns true if any validation error was recorded
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    /// Returns the validation errors of every field, keyed by field name
+    pub fn all_errors(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.errors
+    }
+
+    /// Deserializes the fields into `T`
+    ///
+    /// Values are parsed according to the type of the field they are deserialized into:
+    /// - Numbers are parsed, and booleans are read from `true`/`on`/`yes`/`1` and
+    ///   `false`/`off`/`no`/`0`.
+    /// - Empty values are `None` when deserialized into an `Option`.
+    /// - Fields submitted several times can be deserialized into a `Vec`.
+    /// - Enums are read from the name of their variant.
+    ///
+    /// Browsers don't submit unchecked checkboxes, so `bool` fields need `#[serde(default)]`.
+    ///
+    /// If a value can't be deserialized, or a required field is missing, the error is recorded
+    /// for that field (see [`Form::errors`]) and `None` is returned. Only the first error is
+    /// recorded.
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    /// use vintage::Form;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Signup {
+    ///     email: String,
+    ///     age: Option<u8>,
+    ///     #[serde(default)]
+    ///     newsletter: bool,
+    /// }
+    ///
+    /// let mut form = Form::from_fields([("email", "ada@example.com"), ("age", "many")]);
+    /// assert!(form.deserialize::<Signup>().is_none());
+    /// assert_eq!(form.errors("age"), ["Enter a number"]);
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&mut self) -> Option<T> {
+        match de::from_fields(&self.fields) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.add_error(err.field.unwrap_or_default(), err.message);
+                None
+            }
+        }
+    }
+}
+
+/// Serializes to `{ "values": { name: value }, "errors": { name: [message] } }`, so templates
+/// can render the form again with what the user entered
+///
+/// Only the first value of fields submitted several times is included.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Form {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut values = BTreeMap::new();
+        for (name, value) in self.fields() {
+            values.entry(name).or_insert(value);
+        }
+
+        let mut form = serializer.serialize_struct("Form", 2)?;
+        form.serialize_field("values", &values)?;
+        form.serialize_field("errors", &self.errors)?;
+        form.end()
+    }
+}
+
+fn reject(status: u16, message: &str) -> Response {
+    Response::text(message).set_status(status)
+}
+
+// Parses the parameters of a header value (e.g. `; name="file"; filename="a.txt"`)
+fn header_params(params: &str) -> impl Iterator<Item = (&str, String)> {
+    params.split(';').filter_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        let value = value.trim();
+        let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+            None => value.to_string(),
+        };
+        Some((name.trim(), value))
+    })
+}
+
+// Parses a `multipart/form-data` body. Returns `None` if it is malformed.
+// https://www.rfc-editor.org/rfc/rfc7578
+fn parse_multipart(body: &[u8], boundary: &str) -> Option<Form> {
+    let delimiter = format!("\r\n--{boundary}");
+    let mut form = Form::default();
+
+    // The first delimiter is not preceded by a line break, unless there is a preamble
+    let mut rest = match body.strip_prefix(&delimiter.as_bytes()[2..]) {
+        Some(rest) => rest,
+        None => &body[find(body, delimiter.as_bytes())? + delimiter.len()..],
+    };
+
+    loop {
+        if rest.starts_with(b"--") {
+            return Some(form);
+        }
+        rest = rest.strip_prefix(b"\r\n")?;
+
+        let end = find(rest, delimiter.as_bytes())?;
+        let part = &rest[..end];
+        rest = &rest[end + delimiter.len()..];
+
+        let header_end = find(part, b"\r\n\r\n")?;
+        let part_headers = std::str::from_utf8(&part[..header_end]).ok()?;
+        let data = &part[header_end + 4..];
+
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in part_headers.lines() {
+            let (name, value) = line.split_once(':')?;
+            if name
+                .trim()
+                .eq_ignore_ascii_case(headers::CONTENT_DISPOSITION)
+            {
+                disposition = Some(value.trim());
+            } else if name.trim().eq_ignore_ascii_case(headers::CONTENT_TYPE) {
+                content_type = Some(value.trim().to_string());
+            }
+        }
+
+        let (kind, params) = disposition?.split_once(';').unwrap_or((disposition?, ""));
+        if !kind.trim().eq_ignore_ascii_case("form-data") {
+            return None;
+        }
+        let mut name = None;
+        let mut filename = None;
+        for (key, value) in header_params(params) {
+            if key.eq_ignore_ascii_case("name") {
+                name = Some(value);
+            } else if key.eq_ignore_ascii_case("filename") {
+                filename = Some(value);
+            }
+        }
+        let name = name?;
+
+        match filename {
+            Some(filename) => form.files.push(FormFile {
+                name,
+                filename,
+                content_type,
+                data: data.to_vec(),
+            }),
+            None => {
+                let value = String::from_utf8_lossy(data).into_owned();
+                form.fields.push((name, value));
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn pairs(input: &str) -> Vec<(String, String)> {
+        parse(input).collect()
+    }
+
+    fn request(method: &str, content_type: &str, body: &[u8]) -> Request {
+        Request {
+            method: method.into(),
+            headers: BTreeMap::from([("Content-Type".into(), content_type.into())]),
+            body: body.to_vec(),
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            pairs("a=1&b=two+words&c=%C3%A9%2B&flag&&d="),
+            [
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "two words".to_string()),
+                ("c".to_string(), "é+".to_string()),
+                ("flag".to_string(), String::new()),
+                ("d".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(pairs("x=%FF"), [("x".to_string(), "\u{FFFD}".to_string())]);
+        assert_eq!(pairs(""), []);
+    }
+
+    #[test]
+    fn urlencoded() {
+        let req = request(
+            "POST",
+            "application/x-www-form-urlencoded; charset=utf-8",
+            b"name=Ada+L&tags=a&tags=b",
+        );
+        let form = Form::from_request(&req).unwrap();
+        assert_eq!(form.value("name"), Some("Ada L"));
+        assert_eq!(form.values("tags").collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(form.value("missing"), None);
+
+        let req = Request {
+            method: "GET".into(),
+            query_string: "q=rust".into(),
+            ..Request::default()
+        };
+        assert_eq!(Form::from_request(&req).unwrap().value("q"), Some("rust"));
+
+        let rejection = Form::from_request(&request("POST", "application/json", b"{}"));
+        assert_eq!(
+            rejection.unwrap_err().status,
+            status::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+
+    #[test]
+    fn multipart() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            Hello\r\nWorld\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            file content\r\n--XyZ--\r\n";
+        let req = request("POST", "multipart/form-data; boundary=\"XyZ\"", body);
+        let form = Form::from_request(&req).unwrap();
+        assert_eq!(form.value("title"), Some("Hello\r\nWorld"));
+
+        let file = form.file("upload").unwrap();
+        assert_eq!(file.filename(), "a \"b\".txt");
+        assert_eq!(file.content_type(), Some("text/plain"));
+        assert_eq!(file.data(), b"file content");
+        assert_eq!(form.files().len(), 1);
+
+        let truncated = request("POST", "multipart/form-data; boundary=XyZ", &body[..60]);
+        let rejection = Form::from_request(&truncated).unwrap_err();
+        assert_eq!(rejection.status, status::BAD_REQUEST);
+
+        let no_boundary = request("POST", "multipart/form-data", body);
+        assert!(Form::from_request(&no_boundary).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let mut form = Form::from_fields([("email", "nope")]);
+        assert!(!form.has_errors());
+        assert_eq!(form.errors("email"), [] as [String; 0]);
+
+        form.add_error("email", "Enter a valid email address");
+        form.add_error("email", "Already taken");
+        assert!(form.has_errors());
+        assert_eq!(
+            form.errors("email"),
+            ["Enter a valid email address", "Already taken"]
+        );
+        assert_eq!(form.all_errors().len(), 1);
+        assert_eq!(form.value("email"), Some("nope"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        #[serde(rename_all = "lowercase")]
+        enum Plan {
+            Free,
+            Pro,
+        }
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Signup {
+            email: String,
+            age: Option<u8>,
+            plan: Plan,
+            #[serde(default)]
+            tags: Vec<String>,
+            #[serde(default)]
+            newsletter: bool,
+        }
+
+        let mut form = Form::from_fields([
+            ("email", "ada@example.com"),
+            ("age", ""),
+            ("plan", "pro"),
+            ("tags", "a"),
+            ("tags", "b"),
+            ("newsletter", "on"),
+        ]);
+        assert_eq!(
+            form.deserialize::<Signup>(),
+            Some(Signup {
+                email: "ada@example.com".into(),
+                age: None,
+                plan: Plan::Pro,
+                tags: vec!["a".into(), "b".into()],
+                newsletter: true,
+            })
+        );
+        assert!(!form.has_errors());
+
+        let mut form = Form::from_fields([("email", "ada@example.com"), ("plan", "free")]);
+        let signup = form.deserialize::<Signup>().unwrap();
+        assert_eq!((signup.plan, signup.newsletter), (Plan::Free, false));
+
+        let mut form = Form::from_fields([("plan", "free")]);
+        assert_eq!(form.deserialize::<Signup>(), None);
+        assert_eq!(form.errors("email"), ["This field is required"]);
+
+        let mut form = Form::from_fields([("email", "a@b.c"), ("age", "300"), ("plan", "pro")]);
+        assert_eq!(form.deserialize::<Signup>(), None);
+        assert_eq!(form.errors("age"), ["Enter a number"]);
+
+        let json = serde_json::to_value(&form).unwrap();
+        assert_eq!(json["values"]["age"], "300");
+        assert_eq!(json["errors"]["age"][0], "Enter a number");
+    }
+}
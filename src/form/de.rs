@@ -0,0 +1,235 @@
+// Deserializes form fields into types that implement `serde::Deserialize`.
+//
+// Every value of a form is a string, so the type being deserialized decides how it is read:
+// numbers and booleans are parsed, empty values are `None` for options, and fields submitted
+// several times (e.g. checkboxes that share a name) are sequences.
+use serde::de::value::{BorrowedStrDeserializer, StrDeserializer};
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use std::fmt;
+
+const REQUIRED: &str = "This field is required";
+
+// A deserialization error, and the field it is about (if it is about a single field)
+#[derive(Debug)]
+pub struct Error {
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "{field}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self {
+            field: None,
+            message: msg.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self {
+            field: Some(field.to_string()),
+            message: REQUIRED.to_string(),
+        }
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error {
+        field: None,
+        message: message.to_string(),
+    }
+}
+
+pub fn from_fields<T: de::DeserializeOwned>(fields: &[(String, String)]) -> Result<T, Error> {
+    // Group the values of fields submitted several times, keeping the order of the form
+    let mut grouped: Vec<(&str, Vec<&str>)> = Vec::new();
+    for (name, value) in fields {
+        match grouped.iter_mut().find(|(key, _)| key == name) {
+            Some((_, values)) => values.push(value),
+            None => grouped.push((name, vec![value])),
+        }
+    }
+    T::deserialize(FieldsDeserializer {
+        fields: grouped.into_iter(),
+        current: None,
+    })
+}
+
+struct FieldsDeserializer<'a> {
+    fields: std::vec::IntoIter<(&'a str, Vec<&'a str>)>,
+    current: Option<(&'a str, Vec<&'a str>)>,
+}
+
+impl<'de> de::Deserializer<'de> for FieldsDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for FieldsDeserializer<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some((name, values)) = self.fields.next() else {
+            return Ok(None);
+        };
+        self.current = Some((name, values));
+        seed.deserialize(BorrowedStrDeserializer::new(name))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (name, values) = self.current.take().expect("value requested before its key");
+        seed.deserialize(ValueDeserializer { values })
+            .map_err(|mut err| {
+                err.field.get_or_insert_with(|| name.to_string());
+                err
+            })
+    }
+}
+
+// The values submitted for one field
+struct ValueDeserializer<'a> {
+    values: Vec<&'a str>,
+}
+
+impl<'a> ValueDeserializer<'a> {
+    fn single(&self) -> Result<&'a str, Error> {
+        self.values
+            .first()
+            .copied()
+            .ok_or_else(|| invalid(REQUIRED))
+    }
+
+    fn parse<T: std::str::FromStr>(&self, message: &str) -> Result<T, Error> {
+        self.single()?.trim().parse().map_err(|_| invalid(message))
+    }
+}
+
+macro_rules! deserialize_numbers {
+    ($($method:ident $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse("Enter a number")?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.values.len() > 1 {
+            return self.deserialize_seq(visitor);
+        }
+        visitor.visit_borrowed_str(self.single()?)
+    }
+
+    deserialize_numbers! {
+        deserialize_i8 visit_i8, deserialize_i16 visit_i16, deserialize_i32 visit_i32,
+        deserialize_i64 visit_i64, deserialize_i128 visit_i128, deserialize_u8 visit_u8,
+        deserialize_u16 visit_u16, deserialize_u32 visit_u32, deserialize_u64 visit_u64,
+        deserialize_u128 visit_u128, deserialize_f32 visit_f32, deserialize_f64 visit_f64
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // Checkboxes are submitted with the value `on` when they don't have a `value` attribute
+        match self.single()?.trim().to_ascii_lowercase().as_str() {
+            "true" | "on" | "yes" | "1" => visitor.visit_bool(true),
+            "false" | "off" | "no" | "0" | "" => visitor.visit_bool(false),
+            _ => Err(invalid("Enter yes or no")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.values.as_slice() {
+            [] | [""] => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Values {
+            values: self.values.into_iter(),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let variant: StrDeserializer<'_, Error> = self.single()?.into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+struct Values<'a> {
+    values: std::vec::IntoIter<&'a str>,
+}
+
+impl<'de> SeqAccess<'de> for Values<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        let Some(value) = self.values.next() else {
+            return Ok(None);
+        };
+        seed.deserialize(ValueDeserializer {
+            values: vec![value],
+        })
+        .map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
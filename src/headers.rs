@@ -0,0 +1,324 @@
+//! HTTP header name constants, and builders for structured header values
+//!
+//! ```
+//! use vintage::headers::{self, CacheControl};
+//! use vintage::Response;
+//!
+//! let response = Response::text("hello")
+//!     .set_header(headers::CACHE_CONTROL, CacheControl::new().public().max_age(3600));
+//! ```
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::fmt;
+
+macro_rules! header_names {
+    ($($name:ident  $value:literal),* $(,)?) => {
+        $(
+            pub const $name: &str = $value;
+        )*
+    }
+}
+
+header_names! {
+    ACCEPT                      "Accept",
+    ACCEPT_ENCODING             "Accept-Encoding",
+    ACCEPT_LANGUAGE             "Accept-Language",
+    ACCEPT_RANGES               "Accept-Ranges",
+    ALLOW                       "Allow",
+    AUTHORIZATION               "Authorization",
+    CACHE_CONTROL               "Cache-Control",
+    CONTENT_DISPOSITION         "Content-Disposition",
+    CONTENT_ENCODING            "Content-Encoding",
+    CONTENT_LENGTH              "Content-Length",
+    CONTENT_RANGE               "Content-Range",
+    CONTENT_SECURITY_POLICY     "Content-Security-Policy",
+    CONTENT_TYPE                "Content-Type",
+    COOKIE                      "Cookie",
+    DATE                        "Date",
+    ETAG                        "ETag",
+    FORWARDED                   "Forwarded",
+    HOST                        "Host",
+    IDEMPOTENCY_KEY             "Idempotency-Key",
+    IDEMPOTENT_REPLAYED         "Idempotent-Replayed",
+    IF_MATCH                    "If-Match",
+    IF_MODIFIED_SINCE           "If-Modified-Since",
+    IF_NONE_MATCH               "If-None-Match",
+    IF_RANGE                    "If-Range",
+    IF_UNMODIFIED_SINCE         "If-Unmodified-Since",
+    LAST_MODIFIED               "Last-Modified",
+    LOCATION                    "Location",
+    PROXY_AUTHORIZATION         "Proxy-Authorization",
+    RANGE                       "Range",
+    REFERER                     "Referer",
+    RETRY_AFTER                 "Retry-After",
+    SERVER                      "Server",
+    SET_COOKIE                  "Set-Cookie",
+    STRICT_TRANSPORT_SECURITY   "Strict-Transport-Security",
+    USER_AGENT                  "User-Agent",
+    VARY                        "Vary",
+    WWW_AUTHENTICATE            "WWW-Authenticate",
+    X_FORWARDED_FOR             "X-Forwarded-For",
+    X_FORWARDED_HOST            "X-Forwarded-Host",
+    X_FORWARDED_PROTO           "X-Forwarded-Proto",
+    X_POWERED_BY                "X-Powered-By",
+}
+
+// Characters that must be percent-encoded in an RFC 8187 `ext-value`
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// The value of a `Content-Disposition` header
+///
+/// ```
+/// use vintage::headers::ContentDisposition;
+///
+/// let value = ContentDisposition::attachment().filename("report.pdf");
+/// assert_eq!(value.to_string(), r#"attachment; filename="report.pdf""#);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDisposition {
+    attachment: bool,
+    filename: Option<String>,
+}
+
+impl ContentDisposition {
+    /// The content should be displayed by the browser
+    pub fn inline() -> Self {
+        Self {
+            attachment: false,
+            filename: None,
+        }
+    }
+
+    /// The content should be downloaded
+    pub fn attachment() -> Self {
+        Self {
+            attachment: true,
+            filename: None,
+        }
+    }
+
+    /// Sets the name the file should be saved as
+    ///
+    /// Names that are not plain ASCII are also sent percent-encoded, as a `filename*` parameter.
+    pub fn filename(mut self, filename: impl Into<String>) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+}
+
+impl fmt::Display for ContentDisposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let disposition = if self.attachment {
+            "attachment"
+        } else {
+            "inline"
+        };
+        write!(f, "{disposition}")?;
+
+        if let Some(filename) = &self.filename {
+            // Old browsers only understand `filename`, so a lossy ASCII version is always sent
+            let ascii: String = filename
+                .chars()
+                .map(|c| match c {
+                    '"' | '\\' => '_',
+                    c if c.is_ascii() && !c.is_ascii_control() => c,
+                    _ => '_',
+                })
+                .collect();
+            write!(f, r#"; filename="{ascii}""#)?;
+
+            if ascii != *filename {
+                let encoded = utf8_percent_encode(filename, ATTR_CHAR);
+                write!(f, "; filename*=UTF-8''{encoded}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<ContentDisposition> for String {
+    fn from(value: ContentDisposition) -> Self {
+        value.to_string()
+    }
+}
+
+/// The value of a `Cache-Control` response header
+///
+/// ```
+/// use vintage::headers::CacheControl;
+///
+/// let value = CacheControl::new().public().max_age(31536000).immutable();
+/// assert_eq!(value.to_string(), "public, max-age=31536000, immutable");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    directives: Vec<String>,
+}
+
+impl CacheControl {
+    /// Creates an empty list of directives
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn directive(mut self, directive: impl Into<String>) -> Self {
+        self.directives.push(directive.into());
+        self
+    }
+
+    /// The response may be stored by shared caches
+    pub fn public(self) -> Self {
+        self.directive("public")
+    }
+
+    /// The response may only be stored by the browser
+    pub fn private(self) -> Self {
+        self.directive("private")
+    }
+
+    /// The response may be stored, but must be revalidated before it is reused
+    pub fn no_cache(self) -> Self {
+        self.directive("no-cache")
+    }
+
+    /// The response must not be stored
+    pub fn no_store(self) -> Self {
+        self.directive("no-store")
+    }
+
+    /// The response stays fresh for `seconds`
+    pub fn max_age(self, seconds: u64) -> Self {
+        self.directive(format!("max-age={seconds}"))
+    }
+
+    /// The response stays fresh for `seconds` in shared caches
+    pub fn s_maxage(self, seconds: u64) -> Self {
+        self.directive(format!("s-maxage={seconds}"))
+    }
+
+    /// The response must be revalidated once it is stale
+    pub fn must_revalidate(self) -> Self {
+        self.directive("must-revalidate")
+    }
+
+    /// The response will not change while it is fresh
+    pub fn immutable(self) -> Self {
+        self.directive("immutable")
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.directives.join(", "))
+    }
+}
+
+impl From<CacheControl> for String {
+    fn from(value: CacheControl) -> Self {
+        value.to_string()
+    }
+}
+
+/// The value of a `Strict-Transport-Security` header
+///
+/// ```
+/// use vintage::headers::StrictTransportSecurity;
+///
+/// let value = StrictTransportSecurity::new(63072000).include_subdomains();
+/// assert_eq!(value.to_string(), "max-age=63072000; includeSubDomains");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrictTransportSecurity {
+    max_age: u64,
+    include_subdomains: bool,
+    preload: bool,
+}
+
+impl StrictTransportSecurity {
+    /// Browsers should only use HTTPS for the next `max_age` seconds
+    pub fn new(max_age: u64) -> Self {
+        Self {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    /// The policy also applies to subdomains
+    pub fn include_subdomains(mut self) -> Self {
+        self.include_subdomains = true;
+        self
+    }
+
+    /// Asks for the domain to be included in browsers' preload lists
+    pub fn preload(mut self) -> Self {
+        self.preload = true;
+        self
+    }
+}
+
+impl fmt::Display for StrictTransportSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "max-age={}", self.max_age)?;
+        if self.include_subdomains {
+            write!(f, "; includeSubDomains")?;
+        }
+        if self.preload {
+            write!(f, "; preload")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<StrictTransportSecurity> for String {
+    fn from(value: StrictTransportSecurity) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_disposition() {
+        assert_eq!(ContentDisposition::inline().to_string(), "inline");
+        assert_eq!(
+            ContentDisposition::attachment()
+                .filename(r#"a "b".txt"#)
+                .to_string(),
+            r#"attachment; filename="a _b_.txt"; filename*=UTF-8''a%20%22b%22.txt"#
+        );
+        assert_eq!(
+            ContentDisposition::attachment()
+                .filename("café.txt")
+                .to_string(),
+            r#"attachment; filename="caf_.txt"; filename*=UTF-8''caf%C3%A9.txt"#
+        );
+    }
+
+    #[test]
+    fn structured_values() {
+        assert_eq!(
+            CacheControl::new().no_cache().must_revalidate().to_string(),
+            "no-cache, must-revalidate"
+        );
+        assert_eq!(
+            StrictTransportSecurity::new(1).preload().to_string(),
+            "max-age=1; preload"
+        );
+    }
+}
@@ -0,0 +1,381 @@
+// Formatting and parsing of the dates used in HTTP headers (e.g. `Date`, `Last-Modified`) and
+// in logs.
+//
+// See https://www.rfc-editor.org/rfc/rfc9110#name-date-time-formats
+
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const LONG_DAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// A date and time in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DateTime {
+    year: i64,
+    // 1 to 12
+    month: u32,
+    // 1 to 31
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanos: u32,
+}
+
+impl DateTime {
+    fn from_system_time(time: SystemTime) -> Self {
+        let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+            Err(err) => {
+                let before = err.duration();
+                match before.subsec_nanos() {
+                    0 => (-(before.as_secs() as i64), 0),
+                    n => (-(before.as_secs() as i64) - 1, 1_000_000_000 - n),
+                }
+            }
+        };
+
+        let days = seconds.div_euclid(86400);
+        let second_of_day = seconds.rem_euclid(86400) as u32;
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: second_of_day / 3600,
+            minute: second_of_day % 3600 / 60,
+            second: second_of_day % 60,
+            nanos,
+        }
+    }
+
+    fn to_system_time(self) -> Option<SystemTime> {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let seconds = days * 86400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+
+        match u64::try_from(seconds) {
+            Ok(seconds) => UNIX_EPOCH.checked_add(Duration::from_secs(seconds)),
+            Err(_) => UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs())),
+        }
+    }
+
+    // 0 is Monday
+    fn weekday(&self) -> usize {
+        // 1970-01-01 was a Thursday
+        (days_from_civil(self.year, self.month, self.day) + 3).rem_euclid(7) as usize
+    }
+}
+
+// Formats `time` as an HTTP date (e.g. `Wed, 21 Oct 2015 07:28:00 GMT`)
+//
+// Fractions of seconds are truncated.
+pub fn format(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAYS[dt.weekday()],
+        dt.day,
+        MONTHS[dt.month as usize - 1],
+        dt.year,
+        dt.hour,
+        dt.minute,
+        dt.second
+    )
+}
+
+// Formats `time` as an RFC 3339 timestamp in UTC (e.g. `2015-10-21T07:28:00.5Z`)
+//
+// Fractions of seconds are only written if there are any, without trailing zeros.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+    let mut formatted = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+    );
+    if dt.nanos != 0 {
+        let fraction = format!("{:09}", dt.nanos);
+        let _ = write!(formatted, ".{}", fraction.trim_end_matches('0'));
+    }
+    formatted.push('Z');
+    formatted
+}
+
+// Parses an HTTP date.
+//
+// Besides the preferred format (`Sun, 06 Nov 1994 08:49:37 GMT`), the two obsolete ones that
+// recipients must accept are supported: `Sunday, 06-Nov-94 08:49:37 GMT` and
+// `Sun Nov  6 08:49:37 1994`.
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let dt = parse_imf_fixdate(value)
+        .or_else(|| parse_rfc850(value))
+        .or_else(|| parse_asctime(value))?;
+
+    // Reject impossible dates (e.g. February 30th) and days that don't match the date
+    let valid = (1..=12).contains(&dt.month)
+        && dt.day >= 1
+        && dt.day <= days_in_month(dt.year, dt.month)
+        && dt.hour < 24
+        && dt.minute < 60
+        && dt.second < 61;
+    if !valid {
+        return None;
+    }
+
+    // A leap second is treated as the last second of the minute
+    let dt = DateTime {
+        second: dt.second.min(59),
+        ..dt
+    };
+    let weekday = dt.weekday();
+    let stated = value.split([',', ' ']).next()?;
+    if stated != DAYS[weekday] && stated != LONG_DAYS[weekday] {
+        return None;
+    }
+
+    dt.to_system_time()
+}
+
+// `Sun, 06 Nov 1994 08:49:37 GMT`
+fn parse_imf_fixdate(value: &str) -> Option<DateTime> {
+    let rest = value.get(3..)?.strip_prefix(", ")?.strip_suffix(" GMT")?;
+    let mut parts = rest.split(' ');
+    let day = number(parts.next()?, 2)?;
+    let month = month(parts.next()?)?;
+    let year = number(parts.next()?, 4)?;
+    let (hour, minute, second) = time_of_day(parts.next()?)?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(DateTime {
+        year: i64::from(year),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        nanos: 0,
+    })
+}
+
+// `Sunday, 06-Nov-94 08:49:37 GMT`
+fn parse_rfc850(value: &str) -> Option<DateTime> {
+    let (_, rest) = value.split_once(", ")?;
+    let rest = rest.strip_suffix(" GMT")?;
+    let (date, time) = rest.split_once(' ')?;
+    let mut date = date.split('-');
+    let day = number(date.next()?, 2)?;
+    let month = month(date.next()?)?;
+    let year = number(date.next()?, 2)?;
+    if date.next().is_some() {
+        return None;
+    }
+    let (hour, minute, second) = time_of_day(time)?;
+
+    // Two digit years that appear to be more than 50 years in the future are in the past
+    let current_year = DateTime::from_system_time(SystemTime::now()).year;
+    let mut year = current_year - current_year % 100 + i64::from(year);
+    if year > current_year + 50 {
+        year -= 100;
+    }
+
+    Some(DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        nanos: 0,
+    })
+}
+
+// `Sun Nov  6 08:49:37 1994`
+fn parse_asctime(value: &str) -> Option<DateTime> {
+    let rest = value.get(3..)?.strip_prefix(' ')?;
+    let month = month(rest.get(..3)?)?;
+    let rest = rest.get(3..)?.strip_prefix(' ')?;
+    let day = rest.get(..2)?.trim_start();
+    let day = number(day, day.len())?;
+    let rest = rest.get(2..)?.strip_prefix(' ')?;
+    let (time, year) = rest.split_once(' ')?;
+    let (hour, minute, second) = time_of_day(time)?;
+    let year = number(year, 4)?;
+
+    Some(DateTime {
+        year: i64::from(year),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        nanos: 0,
+    })
+}
+
+// Parses `HH:MM:SS`
+fn time_of_day(value: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = value.split(':');
+    let hour = number(parts.next()?, 2)?;
+    let minute = number(parts.next()?, 2)?;
+    let second = number(parts.next()?, 2)?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((hour, minute, second))
+}
+
+// Parses a number made of exactly `digits` ascii digits
+fn number(value: &str, digits: usize) -> Option<u32> {
+    if value.len() != digits || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn month(value: &str) -> Option<u32> {
+    let index = MONTHS.iter().position(|m| *m == value)?;
+    Some(index as u32 + 1)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Converts a date to a number of days since 1970-01-01.
+//
+// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Converts a number of days since 1970-01-01 to a date.
+//
+// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Removes the `Date` header lines from a response, whose value depends on when it was sent
+#[cfg(test)]
+pub fn without_date_header(response: &[u8]) -> Vec<u8> {
+    // e.g. `Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n`
+    let line_len = "Date: ".len() + "Sun, 06 Nov 1994 08:49:37 GMT".len();
+
+    let mut stripped = Vec::with_capacity(response.len());
+    let mut rest = response;
+    while !rest.is_empty() {
+        let at_line_start = stripped.is_empty() || stripped.ends_with(b"\n");
+        if at_line_start && rest.starts_with(b"Date: ") && rest.len() >= line_len {
+            let after = &rest[line_len..];
+            if let Some(after) = after.strip_prefix(b"\r\n").or(after.strip_prefix(b"\n")) {
+                rest = after;
+                continue;
+            }
+        }
+        stripped.push(rest[0]);
+        rest = &rest[1..];
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format(at(784111777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format(at(1445412480)), "Wed, 21 Oct 2015 07:28:00 GMT");
+        // A leap day
+        assert_eq!(format(at(951782400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(
+            format(UNIX_EPOCH - Duration::from_secs(1)),
+            "Wed, 31 Dec 1969 23:59:59 GMT"
+        );
+
+        assert_eq!(format_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_rfc3339(at(1445412480) + Duration::from_millis(500)),
+            "2015-10-21T07:28:00.5Z"
+        );
+    }
+
+    #[test]
+    fn parsing() {
+        let expected = Some(at(784111777));
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse(" Sun, 06 Nov 1994 08:49:37 GMT "), expected);
+
+        for time in [UNIX_EPOCH, at(951782400), at(4102444800)] {
+            assert_eq!(parse(&format(time)), Some(time));
+        }
+
+        for invalid in [
+            "",
+            "Sun, 06 Nov 1994",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Mon, 06 Nov 1994 08:49:37 GMT",
+            "Wed, 30 Feb 2000 00:00:00 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+        ] {
+            assert_eq!(parse(invalid), None, "{invalid}");
+        }
+    }
+}
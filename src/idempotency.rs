@@ -0,0 +1,472 @@
+use crate::context::{Request, Response};
+use crate::hash::fnv1a;
+use crate::headers;
+use crate::status;
+use crate::sync;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// How long responses are kept when no TTL is configured
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// How many responses are kept in memory when no capacity is configured
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Where [`Idempotency`] keeps the responses it replays
+///
+/// Implement it to share responses between several servers (e.g. in a database).
+/// Keys are scoped by caller and request path, so the same key sent by two users, or to two
+/// endpoints, does not collide.
+///
+/// Each response is stored with the fingerprint of the request body it answers, so that a retry
+/// with another body can be told apart.
+pub trait IdempotencyStore: Send + Sync {
+    /// Returns the fingerprint and response stored for `key`, unless they expired
+    fn get(&self, key: &str) -> Option<(u64, Response)>;
+
+    /// Stores `fingerprint` and `response` for `key`, for `ttl`
+    fn put(&self, key: &str, fingerprint: u64, response: &Response, ttl: Duration);
+}
+
+// Who a request comes from, to keep the keys of different callers apart
+type Scope = dyn Fn(&Request) -> Option<String> + Send + Sync;
+
+// Keeps up to `capacity` responses in memory. Expired ones are evicted as new ones are stored,
+// then the ones closest to expiring if the store is still full.
+//
+// Unlike other stores, it is given the current time, so that it follows the server's clock.
+struct MemoryStore {
+    entries: Mutex<BTreeMap<String, (Instant, u64, Response)>>,
+    capacity: usize,
+}
+
+impl MemoryStore {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::default(),
+            capacity,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, (Instant, u64, Response)>> {
+        sync::lock(&self.entries)
+    }
+
+    fn get(&self, key: &str, now: Instant) -> Option<(u64, Response)> {
+        let entries = self.lock();
+        let (expires_at, fingerprint, response) = entries.get(key)?;
+        (*expires_at > now).then(|| (*fingerprint, response.clone()))
+    }
+
+    fn put(&self, key: &str, fingerprint: u64, response: &Response, ttl: Duration, now: Instant) {
+        let mut entries = self.lock();
+        entries.retain(|_, (expires_at, _, _)| *expires_at > now);
+        while entries.len() >= self.capacity && !entries.contains_key(key) {
+            let closest = entries
+                .iter()
+                .min_by_key(|(_, (expires_at, _, _))| *expires_at);
+            match closest.map(|(key, _)| key.clone()) {
+                Some(closest) => entries.remove(&closest),
+                None => break,
+            };
+        }
+        entries.insert(key.to_string(), (now + ttl, fingerprint, response.clone()));
+    }
+}
+
+#[derive(Clone)]
+enum Store {
+    Memory(Arc<MemoryStore>),
+    Custom(Arc<dyn IdempotencyStore>),
+}
+
+/// Replays the response to a `POST` request when it is sent again with the same
+/// `Idempotency-Key` header
+///
+/// Clients (or proxies) that retry a request after a network failure can't tell whether the
+/// first attempt went through. When they send an `Idempotency-Key`, the first response is stored,
+/// and later requests from the same caller to the same path with the same key get that response
+/// again instead of being handled twice.
+/// Replayed responses have an `Idempotent-Replayed: true` header.
+///
+/// Callers are told apart by [`Request::remote_user`], or by the function set with
+/// [`Idempotency::scope`]. Requests without either share the keys of every other anonymous caller,
+/// so applications that don't authenticate requests should set a scope (e.g. a session cookie).
+///
+/// A request that comes in while another one with the same key is still being handled is
+/// answered with a `409 Conflict`, and a retry whose body differs from the first request's with a
+/// `422 Unprocessable Content`.
+/// Streamed responses and server errors (`5xx`) are not stored, so the request can be retried.
+/// Cookies are not stored either: they are only sent with the first response.
+/// Requests without the header, or with other methods, are handled as usual.
+///
+/// ```
+/// use std::time::Duration;
+/// use vintage::{Idempotency, Response, ServerConfig};
+///
+/// let config = ServerConfig::new()
+///     .idempotency(Idempotency::new().ttl(Duration::from_secs(3600)))
+///     .on_post(["/payments"], |_req, _params| Response::text("charged"));
+/// ```
+#[derive(Clone)]
+pub struct Idempotency {
+    store: Store,
+    ttl: Duration,
+    scope: Arc<Scope>,
+    in_flight: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl fmt::Debug for Idempotency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Idempotency")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Self {
+            store: Store::Memory(Arc::new(MemoryStore::new(DEFAULT_CAPACITY))),
+            ttl: DEFAULT_TTL,
+            scope: Arc::new(|req| req.remote_user().map(str::to_string)),
+            in_flight: Arc::default(),
+        }
+    }
+}
+
+impl Idempotency {
+    /// Creates an instance that keeps responses in memory for 24 hours
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long responses are replayed for. The default is 24 hours.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets how many responses are kept in memory. The default is 10 000.
+    ///
+    /// Once the limit is reached, the responses closest to expiring are dropped to make room.
+    /// This replaces a store set with [`Idempotency::store`].
+    pub fn capacity(mut self, responses: usize) -> Self {
+        self.store = Store::Memory(Arc::new(MemoryStore::new(responses)));
+        self
+    }
+
+    /// Keeps responses in `store` instead of in memory
+    pub fn store(mut self, store: impl IdempotencyStore + 'static) -> Self {
+        self.store = Store::Custom(Arc::new(store));
+        self
+    }
+
+    /// Tells callers apart with `scope` instead of [`Request::remote_user`]
+    ///
+    /// Keys are only replayed to requests for which `scope` returns the same value. Requests for
+    /// which it returns `None` share their keys.
+    ///
+    /// ```
+    /// use vintage::Idempotency;
+    ///
+    /// let idempotency = Idempotency::new().scope(|req| req.cookie("session").map(String::from));
+    /// ```
+    pub fn scope(
+        mut self,
+        scope: impl Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.scope = Arc::new(scope);
+        self
+    }
+
+    // Responds to `req` with `handler`, or with the stored response if it is a duplicate
+    pub(crate) fn respond(
+        &self,
+        req: &mut Request,
+        handler: impl FnOnce(&mut Request) -> Response,
+    ) -> Response {
+        let key = match req.header(headers::IDEMPOTENCY_KEY) {
+            Some(key) if req.method == "POST" => match (self.scope)(req) {
+                // Quoted, so that the scope can't run into the path
+                Some(scope) => format!("{scope:?} {} {key}", req.path),
+                None => format!("{} {key}", req.path),
+            },
+            _ => return handler(req),
+        };
+        let fingerprint = fnv1a(&req.body);
+
+        if let Some(response) = self.replay(&key, fingerprint, req) {
+            return response;
+        }
+
+        if !self.lock_in_flight().insert(key.clone()) {
+            return Response::text("A request with this idempotency key is in progress")
+                .set_status(status::CONFLICT);
+        }
+        // Releases the key even if the handler panics
+        let _in_flight = InFlight {
+            idempotency: self,
+            key: &key,
+        };
+        // The first request with this key may have finished since the store was checked
+        if let Some(response) = self.replay(&key, fingerprint, req) {
+            return response;
+        }
+
+        let response = handler(req);
+        if response.stream.is_none() && response.status < 500 {
+            // Cookies (e.g. a new session) are meant for whoever got the first response
+            let mut stored = response.clone();
+            stored.cookies.clear();
+            stored
+                .headers
+                .retain(|name, _| !name.eq_ignore_ascii_case(headers::SET_COOKIE));
+            let ttl = self.ttl;
+            match &self.store {
+                Store::Memory(store) => {
+                    store.put(&key, fingerprint, &stored, ttl, req.clock.instant())
+                }
+                Store::Custom(store) => store.put(&key, fingerprint, &stored, ttl),
+            }
+        }
+        response
+    }
+
+    // The stored response for `key`, if any, marked as replayed. A request whose body doesn't
+    // match the one the response was stored for is turned away instead.
+    fn replay(&self, key: &str, fingerprint: u64, req: &Request) -> Option<Response> {
+        let (stored_fingerprint, response) = match &self.store {
+            Store::Memory(store) => store.get(key, req.clock.instant()),
+            Store::Custom(store) => store.get(key),
+        }?;
+        if stored_fingerprint != fingerprint {
+            return Some(
+                Response::text("This idempotency key was used for another request")
+                    .set_status(status::UNPROCESSABLE_CONTENT),
+            );
+        }
+        Some(response.set_header(headers::IDEMPOTENT_REPLAYED, "true"))
+    }
+
+    fn lock_in_flight(&self) -> MutexGuard<'_, BTreeSet<String>> {
+        sync::lock(&self.in_flight)
+    }
+}
+
+struct InFlight<'a> {
+    idempotency: &'a Idempotency,
+    key: &'a str,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.idempotency.lock_in_flight().remove(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SharedClock;
+    use crate::TestClock;
+    use std::cell::Cell;
+    use std::time::SystemTime;
+
+    fn request(method: &str, key: Option<&str>) -> Request {
+        let mut req = Request {
+            method: method.into(),
+            path: "/payments".into(),
+            ..Request::default()
+        };
+        if let Some(key) = key {
+            req.headers.insert("Idempotency-Key".into(), key.into());
+        }
+        req
+    }
+
+    #[test]
+    fn replays_responses() {
+        let idempotency = Idempotency::new();
+        let calls = Cell::new(0);
+        let handler = |_req: &mut Request| {
+            calls.set(calls.get() + 1);
+            Response::text(format!("charge {}", calls.get())).set_status(201)
+        };
+
+        let first = idempotency.respond(&mut request("POST", Some("a")), handler);
+        let replayed = idempotency.respond(&mut request("POST", Some("a")), handler);
+        assert_eq!(first.body, b"charge 1");
+        assert_eq!(replayed.body, b"charge 1");
+        assert_eq!(replayed.status, 201);
+        assert_eq!(replayed.headers["Idempotent-Replayed"], "true");
+
+        let other_key = idempotency.respond(&mut request("POST", Some("b")), handler);
+        let no_key = idempotency.respond(&mut request("POST", None), handler);
+        let get = idempotency.respond(&mut request("GET", Some("a")), handler);
+        assert_eq!(other_key.body, b"charge 2");
+        assert_eq!(no_key.body, b"charge 3");
+        assert_eq!(get.body, b"charge 4");
+    }
+
+    #[test]
+    fn skips_failures_and_expired_responses() {
+        let idempotency = Idempotency::new().ttl(Duration::ZERO);
+        let ok = |_req: &mut Request| Response::text("ok");
+        idempotency.respond(&mut request("POST", Some("a")), ok);
+        let again = idempotency.respond(&mut request("POST", Some("a")), |_req| {
+            Response::text("again")
+        });
+        assert_eq!(again.body, b"again");
+
+        let idempotency = Idempotency::new();
+        let failed = idempotency.respond(&mut request("POST", Some("a")), |_req| {
+            Response::new().set_status(502)
+        });
+        assert_eq!(failed.status, 502);
+        let retried = idempotency.respond(&mut request("POST", Some("a")), ok);
+        assert_eq!(retried.body, b"ok");
+    }
+
+    #[test]
+    fn expires_with_the_clock() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let request = || {
+            let mut req = request("POST", Some("a"));
+            req.clock = SharedClock::new(clock.clone());
+            req
+        };
+        let calls = Cell::new(0);
+        let handler = |_req: &mut Request| {
+            calls.set(calls.get() + 1);
+            Response::text(format!("charge {}", calls.get()))
+        };
+
+        let idempotency = Idempotency::new().ttl(Duration::from_secs(60));
+        idempotency.respond(&mut request(), handler);
+        clock.advance(Duration::from_secs(59));
+        let replayed = idempotency.respond(&mut request(), handler);
+        assert_eq!(replayed.body, b"charge 1");
+
+        clock.advance(Duration::from_secs(1));
+        let expired = idempotency.respond(&mut request(), handler);
+        assert_eq!(expired.body, b"charge 2");
+    }
+
+    #[test]
+    fn scoped_by_caller() {
+        let request = |user: &str| {
+            let mut req = request("POST", Some("a"));
+            req.vars.insert("REMOTE_USER".into(), user.into());
+            req
+        };
+        let idempotency = Idempotency::new();
+        idempotency.respond(&mut request("alice"), |_req| Response::text("alice's"));
+        let bob = idempotency.respond(&mut request("bob"), |_req| Response::text("bob's"));
+        assert_eq!(bob.body, b"bob's");
+        let alice = idempotency.respond(&mut request("alice"), |_req| Response::text("again"));
+        assert_eq!(alice.body, b"alice's");
+
+        // With a custom scope, `REMOTE_USER` is ignored
+        let idempotency = Idempotency::new().scope(|req| req.header("X-Tenant").map(String::from));
+        let tenant = |tenant: &str, user: &str| {
+            let mut req = request(user);
+            req.headers.insert("X-Tenant".into(), tenant.into());
+            req
+        };
+        idempotency.respond(&mut tenant("acme", "alice"), |_req| Response::text("acme"));
+        let same_tenant =
+            idempotency.respond(&mut tenant("acme", "bob"), |_req| Response::text("again"));
+        assert_eq!(same_tenant.body, b"acme");
+        let other_tenant = idempotency.respond(&mut tenant("initech", "alice"), |_req| {
+            Response::text("initech")
+        });
+        assert_eq!(other_tenant.body, b"initech");
+    }
+
+    #[test]
+    fn cookies_are_not_replayed() {
+        let idempotency = Idempotency::new();
+        let handler = |_req: &mut Request| {
+            Response::text("logged in")
+                .set_header("set-cookie", "a=b")
+                .add_cookie("session=secret")
+        };
+        let first = idempotency.respond(&mut request("POST", Some("a")), handler);
+        assert_eq!(first.cookies, ["session=secret"]);
+        assert_eq!(first.headers["set-cookie"], "a=b");
+
+        let replayed = idempotency.respond(&mut request("POST", Some("a")), handler);
+        assert_eq!(replayed.body, b"logged in");
+        assert!(replayed.cookies.is_empty());
+        assert!(!replayed.headers.contains_key("set-cookie"));
+    }
+
+    #[test]
+    fn rejects_reuse_with_another_body() {
+        let request = |body: &str| {
+            let mut req = request("POST", Some("a"));
+            req.body = body.into();
+            req
+        };
+        let echo = |req: &mut Request| Response::text(String::from_utf8(req.take_body()).unwrap());
+
+        let idempotency = Idempotency::new();
+        idempotency.respond(&mut request("amount=10"), echo);
+        let reused = idempotency.respond(&mut request("amount=1000"), echo);
+        assert_eq!(reused.status, status::UNPROCESSABLE_CONTENT);
+        assert!(!reused.headers.contains_key("Idempotent-Replayed"));
+        let retried = idempotency.respond(&mut request("amount=10"), echo);
+        assert_eq!(retried.body, b"amount=10");
+    }
+
+    #[test]
+    fn conflicts() {
+        let idempotency = Idempotency::new();
+        let response = idempotency.respond(&mut request("POST", Some("a")), |_req| {
+            idempotency.respond(&mut request("POST", Some("a")), |_req| {
+                Response::text("nested")
+            })
+        });
+        assert_eq!(response.status, status::CONFLICT);
+    }
+
+    #[test]
+    fn first_request_finishes_before_retry_is_in_flight() {
+        // Answers `None` to the first lookup, as if the first request was still being handled,
+        // and has the response by the second
+        struct RacingStore(Mutex<Vec<Option<(u64, Response)>>>);
+        impl IdempotencyStore for RacingStore {
+            fn get(&self, _key: &str) -> Option<(u64, Response)> {
+                self.0.lock().unwrap().pop().flatten()
+            }
+            fn put(&self, _key: &str, _fingerprint: u64, _response: &Response, _ttl: Duration) {}
+        }
+
+        let first = (fnv1a(b""), Response::text("first"));
+        let store = RacingStore(Mutex::new(vec![Some(first), None]));
+        let idempotency = Idempotency::new().store(store);
+        let retry = idempotency.respond(&mut request("POST", Some("a")), |_req| {
+            Response::text("handled twice")
+        });
+        assert_eq!(retry.body, b"first");
+        assert_eq!(retry.headers["Idempotent-Replayed"], "true");
+    }
+
+    #[test]
+    fn capacity() {
+        let idempotency = Idempotency::new().capacity(2);
+        let respond = |key: &str, body: &'static str| {
+            let response = Response::text(body);
+            idempotency.respond(&mut request("POST", Some(key)), |_req| response)
+        };
+        respond("a", "a1");
+        respond("b", "b1");
+        respond("c", "c1");
+        assert_eq!(respond("c", "c2").body, b"c1");
+        assert_eq!(respond("b", "b2").body, b"b1");
+        // Dropped to make room for `c`
+        assert_eq!(respond("a", "a2").body, b"a2");
+    }
+}
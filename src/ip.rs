@@ -0,0 +1,222 @@
+use crate::sync;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A range of IP addresses in CIDR notation (e.g. `10.0.0.0/8`)
+///
+/// A single address (e.g. `127.0.0.1`) is a range containing only that address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Returns true if `ip` is part of this range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Compare IPv4-mapped IPv6 addresses (e.g. `::ffff:127.0.0.1`) as their IPv4 equivalent
+        let ip = ip.to_canonical();
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(ip: IpAddr) -> Self {
+        let ip = ip.to_canonical();
+        let prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+        Self {
+            network: ip,
+            prefix_len,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid IP address or CIDR range: '{s}'");
+
+        let Some((network, prefix_len)) = s.split_once('/') else {
+            let ip = s.parse::<IpAddr>().map_err(|_| invalid())?;
+            return Ok(Self::from(ip));
+        };
+
+        let network = network
+            .parse::<IpAddr>()
+            .map_err(|_| invalid())?
+            .to_canonical();
+        let prefix_len = prefix_len.parse::<u8>().map_err(|_| invalid())?;
+
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+/// A list of IP addresses and CIDR ranges that can be updated while the server is running
+///
+/// Clones share the same list, so a clone kept aside can update the list used by a server.
+/// See [`ServerConfig::block_ips`](crate::ServerConfig::block_ips) and
+/// [`ServerConfig::allow_ips`](crate::ServerConfig::allow_ips).
+///
+/// ```
+/// use vintage::{IpList, ServerConfig};
+///
+/// let blocked = IpList::new();
+/// blocked.add("203.0.113.0/24").unwrap();
+///
+/// let config = ServerConfig::new().block_ips(blocked.clone(), 403);
+///
+/// // Later, from another thread
+/// blocked.add("198.51.100.7").unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IpList {
+    ranges: Arc<RwLock<Vec<IpRange>>>,
+}
+
+impl IpList {
+    /// Creates an empty list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an IP address (e.g. `127.0.0.1`) or a CIDR range (e.g. `10.0.0.0/8`) to the list
+    ///
+    /// Fails if `range` is neither.
+    pub fn add(&self, range: &str) -> Result<(), String> {
+        let range = range.parse::<IpRange>()?;
+        let mut ranges = self.write();
+        if !ranges.contains(&range) {
+            ranges.push(range);
+        }
+        Ok(())
+    }
+
+    /// Removes an address or range that was added with [`IpList::add`]
+    ///
+    /// Returns `false` if it was not in the list. Only exact matches are removed: removing
+    /// `10.0.0.1` does not affect `10.0.0.0/8`.
+    pub fn remove(&self, range: &str) -> bool {
+        let Ok(range) = range.parse::<IpRange>() else {
+            return false;
+        };
+        let mut ranges = self.write();
+        let len = ranges.len();
+        ranges.retain(|r| *r != range);
+        ranges.len() != len
+    }
+
+    /// Replaces the content of the list with `ranges`
+    ///
+    /// Fails without changing the list if one of `ranges` is invalid.
+    pub fn replace<'a>(&self, ranges: impl IntoIterator<Item = &'a str>) -> Result<(), String> {
+        let ranges = ranges
+            .into_iter()
+            .map(str::parse)
+            .collect::<Result<Vec<IpRange>, _>>()?;
+        *self.write() = ranges;
+        Ok(())
+    }
+
+    /// Returns true if `ip` is part of any address or range of the list
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.read().iter().any(|range| range.contains(ip))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Vec<IpRange>> {
+        sync::read(&self.ranges)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<IpRange>> {
+        sync::write(&self.ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn range(s: &str) -> IpRange {
+        s.parse().unwrap()
+    }
+
+    #[track_caller]
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn single_address() {
+        assert!(range("127.0.0.1").contains(ip("127.0.0.1")));
+        assert!(!range("127.0.0.1").contains(ip("127.0.0.2")));
+        assert!(range("::1").contains(ip("::1")));
+    }
+
+    #[test]
+    fn cidr_ranges() {
+        assert!(range("10.0.0.0/8").contains(ip("10.20.30.40")));
+        assert!(!range("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(range("0.0.0.0/0").contains(ip("192.168.1.1")));
+        assert!(range("fd00::/8").contains(ip("fd12:3456::1")));
+        assert!(!range("fd00::/8").contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn mixed_address_families() {
+        assert!(!range("10.0.0.0/8").contains(ip("::1")));
+        assert!(range("127.0.0.1").contains(ip("::ffff:127.0.0.1")));
+    }
+
+    #[test]
+    fn lists() {
+        let list = IpList::new();
+        assert!(!list.contains(ip("10.1.2.3")));
+
+        list.add("10.0.0.0/8").unwrap();
+        list.add("::1").unwrap();
+        assert!(list.add("nope").is_err());
+        assert!(list.clone().contains(ip("10.1.2.3")));
+        assert!(list.contains(ip("::1")));
+
+        assert!(!list.remove("10.1.2.3"));
+        assert!(list.remove("10.0.0.0/8"));
+        assert!(!list.contains(ip("10.1.2.3")));
+
+        assert!(list.replace(["192.168.0.0/16", "bad"]).is_err());
+        assert!(list.contains(ip("::1")));
+        list.replace(["192.168.0.0/16"]).unwrap();
+        assert!(!list.contains(ip("::1")));
+        assert!(list.contains(ip("192.168.4.4")));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert!("localhost".parse::<IpRange>().is_err());
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("10.0.0.0/abc".parse::<IpRange>().is_err());
+    }
+}
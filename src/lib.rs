@@ -43,7 +43,8 @@
 //! Additionally, the passage of time has made some other parts of the specification obsolete.
 //!
 //! Notably:
-//! - I ignore the part about what file descriptors are open when the FastCGI server begins (Section 2.2)
+//! - I ignore the part about what file descriptors are open when the FastCGI server begins (Section 2.2),
+//!   unless asked to with [`ServerConfig::from_inherited_listener`].
 //! - I ignore the special processing of the magic `FCGI_WEB_SERVER_ADDRS` environment variable (Section 3.2)
 //! - `FCGI_UNKNOWN_TYPE` is sent for any unknown record type, instead of just unknown management
 //!   record types (Section 4.2).
@@ -57,28 +58,173 @@
 //!       OpenMarket's archived
 //!       [manual](https://fastcgi-archives.github.io/fcgi2/doc/fastcgi-prog-guide/ch1intro.htm)
 //!       has more info.
-//! - Writing a "stderr" record is not supported. As far as I can tell, it's pretty useless.
+//! - Handlers can't write "stderr" records. As far as I can tell, they are pretty useless.
 //!   At best, what you send in that record gets printed in the logs of the FastCGI _client_.
 //!   At worst, it gets ignored.
+//!   The server only sends one to say why it failed a request on its own (e.g. because its
+//!   params exceed a limit), which is worth a line in the logs of the web server.
+//!
+//! # Platform support
+//!
+//! The server runs on unix-like systems and on Windows. Some functionality depends on the
+//! platform:
+//! - Unix domain sockets ([`ListenAddress::Unix`]), inherited listening sockets
+//!   ([`ServerConfig::from_inherited_listener`]), [`WorkerModel::ThreadPerCore`] and the
+//!   [`privileges`] module are only available on unix-like systems.
+//! - Abstract unix sockets ([`ListenAddress::Abstract`]) are only available on Linux (and
+//!   Android).
+//! - Named pipes (`ListenAddress::NamedPipe`) are only available on Windows, as additional
+//!   listeners. The main socket is a TCP one, and IIS can't hand the server the pipe it created,
+//!   so IIS should connect to a socket bound to `localhost` (or to a pipe the server created).
+//! - The `FileServer` refuses file names with a `:` on Windows, since they can name another
+//!   drive or an alternate data stream.
+//!
+//! # Features
+//!
+//! Functionality that pulls in extra dependencies can be turned off, for a smaller build (e.g. for
+//! an API server that only has routes).
+//!
+//! - `fs` (enabled by default): Serving static files and fingerprinted assets (`FileServer` and
+//!   `Assets`).
+//! - `compression` (enabled by default): Compressing responses and decompressing request bodies
+//!   (`Compression` and `Decompression`).
+//! - `signed-cookies` (enabled by default): Signing cookies (`ServerConfig::keys`), which flash
+//!   messages use when it is enabled and logging in with a form (`FormLogin`) requires.
+//! - `csp-nonce` (enabled by default): Nonces for the inline scripts and styles allowed by the
+//!   `Content-Security-Policy` (`Request::csp_nonce`).
+//! - `serde`: Deserializing submitted forms into structs, and serializing them for templates
+//!   (see `Form`).
+//! - `tls`: Serving FastCGI over TLS, for web servers that reach the application over a network
+//!   that is not trusted (see `TlsConfig`).
 
+mod access_log;
+pub mod app_status;
+#[cfg(feature = "fs")]
+mod assets;
+mod audit;
+pub mod auth;
+mod body;
+mod bulkhead;
+mod capture;
+mod circuit_breaker;
+mod client;
+mod clock;
+#[cfg(feature = "compression")]
+mod compression;
+pub mod conformance;
 mod connection;
 mod context;
+#[cfg(unix)]
+mod control;
+#[cfg(feature = "compression")]
+mod decompression;
+mod diagnostics;
 mod error;
 mod event_loop;
+mod extensions;
 mod fastcgi_responder;
+#[cfg(feature = "fs")]
 mod file_server;
+mod form;
+mod hash;
+pub mod headers;
+mod httpdate;
+mod idempotency;
+mod ip;
+mod listener;
+mod locale;
+mod log_filter;
+mod memory_budget;
+pub mod method;
+mod middleware;
+mod mirror;
+#[cfg(windows)]
+mod named_pipe;
+mod panic_report;
+mod path_mapping;
+#[cfg(unix)]
+pub mod privileges;
+mod proxy;
+pub mod query;
+mod queue;
 mod record;
+mod response_builder;
 mod router;
+mod sampler;
+mod scheduler;
 mod server_config;
 mod server_handle;
+#[cfg(unix)]
+mod signals;
+#[cfg(feature = "signed-cookies")]
+mod signing;
+mod start_error;
+mod stats;
 pub mod status;
+mod supervisor;
+mod sync;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "tls")]
+mod tls;
+
+pub use access_log::{AccessLogEntry, AccessLogFilter};
+#[cfg(feature = "fs")]
+pub use assets::Assets;
+pub use audit::{AuditLog, AuditRecord, AuditSink};
+pub use body::BodyWriter;
+pub use capture::{Capture, Direction};
+pub use circuit_breaker::{CircuitBreaker, CircuitState};
+pub use client::{Backend, Client, ClientError};
+pub use clock::{Clock, SystemClock, TestClock};
+#[cfg(feature = "compression")]
+pub use compression::Compression;
+pub use context::{HeaderCase, InvalidHeader, LineEnding, Request, Response, Timings};
+#[cfg(feature = "compression")]
+pub use decompression::Decompression;
+pub use diagnostics::Diagnostics;
+#[cfg(feature = "fs")]
+pub use file_server::FileServer;
+pub use form::{Form, FormFile};
+pub use idempotency::{Idempotency, IdempotencyStore};
+pub use ip::IpList;
+pub use listener::{ListenAddress, ListenerInfo, SocketOptions};
+pub use locale::{Locale, LocaleNegotiation, LocaleSource};
+pub use log_filter::{FilteredLogger, InvalidLogFilter};
+pub use memory_budget::MemoryPolicy;
+pub use middleware::Next;
+pub use mirror::Mirror;
+pub use panic_report::PanicReport;
+pub use path_mapping::PathMapping;
+pub use proxy::{Balance, Proxy};
+pub use queue::QueuePolicy;
+pub use response_builder::{InvalidResponse, ResponseBuilder};
+pub use router::{ParamError, Route, RouteMatch, RouteParams};
+#[cfg(feature = "fs")]
+pub use server_config::DispatchOrder;
+pub use server_config::{ParamStrictness, ServerConfig, UnreadBody, WorkerModel};
+pub use server_handle::{ServerExitReason, ServerHandle, ServerOperation};
+pub use start_error::{StartError, StartFailure, StartStep};
+pub use stats::{Counters, RouteStats};
+pub use supervisor::Supervisor;
+#[cfg(feature = "tls")]
+pub use tls::{ClientAuth, ClientIdentity, TlsConfig};
 
-pub use context::{Request, Response};
-pub use server_config::ServerConfig;
-pub use server_handle::{ServerExitReason, ServerHandle};
+/// Checks path patterns before they are registered as routes, and parses route params
+pub mod routing {
+    pub use crate::router::{parse_param, validate_pattern, PatternError};
+}
+
+// Not part of the public API. Lets the fuzz targets reach the parsers of untrusted input.
+#[cfg(feature = "arbitrary")]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub use crate::record::pairs::from_record_bytes as parse_pairs;
+    pub use crate::record::Record;
+}
 
 use std::io;
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 
 /// Starts a FastCGI server with the given config at `address` and returns a handle to it.
 ///
@@ -87,10 +233,104 @@ use std::net::ToSocketAddrs;
 /// If `address` yields multiple addresses, only the first one is considered.
 ///
 /// This function does not block because the FastCGI server is created on a separate thread.
-pub fn start(config: ServerConfig, address: impl ToSocketAddrs) -> Result<ServerHandle, io::Error> {
-    let mut iter = address.to_socket_addrs()?;
-    let first_address = iter
-        .next()
-        .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
-    event_loop::create_handle(config, first_address)
+///
+/// If the server can't be started, the returned [`StartError`] lists every failure (e.g. each
+/// listening socket that couldn't be bound).
+pub fn start(
+    config: ServerConfig,
+    address: impl ToSocketAddrs,
+) -> Result<ServerHandle, StartError> {
+    event_loop::create_handle(config, first_address(address)?)
+}
+
+/// Runs a FastCGI server with the given config at `address` on the calling thread, until it exits
+///
+/// This is the blocking counterpart of [`start()`]: no thread is spawned to run the server loop,
+/// which suits environments with a tight thread budget. Since there is no [`ServerHandle`], the
+/// server is usually stopped with the `drain` command of the
+/// [control socket](ServerConfig::control_socket).
+///
+/// Start-up is the same as [`start()`], and so are the errors it returns. Once the server is
+/// running, this returns the reason it exited.
+///
+/// ```no_run
+/// use vintage::{Response, ServerConfig};
+///
+/// fn main() -> std::io::Result<()> {
+///     let config = ServerConfig::new()
+///         .control_socket("/run/app/control.sock")
+///         .on_get(["/"], |_req, _params| Response::text("hello"));
+///     let reason = vintage::run(config, "localhost:8000")?;
+///     println!("The server exited: {reason:?}");
+///     Ok(())
+/// }
+/// ```
+pub fn run(
+    config: ServerConfig,
+    address: impl ToSocketAddrs,
+) -> Result<ServerExitReason, StartError> {
+    event_loop::run(config, first_address(address)?)
+}
+
+// Resolves the address a server is started at
+fn first_address(address: impl ToSocketAddrs) -> Result<SocketAddr, StartError> {
+    let resolve_failed = |err| StartError::single(StartStep::ResolveAddress, err);
+    let mut iter = address.to_socket_addrs().map_err(resolve_failed)?;
+    iter.next()
+        .ok_or_else(|| resolve_failed(io::Error::from(io::ErrorKind::InvalidInput)))
+}
+
+/// Serves every request with `handler` at `address`, and blocks until the process receives
+/// SIGINT (e.g. Ctrl+C) or SIGTERM
+///
+/// This is meant for small programs that don't need the rest of [`ServerConfig`]. Once a signal
+/// is received, the server is stopped gracefully, like with [`ServerHandle::stop`]. A second
+/// signal kills the process right away.
+///
+/// If the server exits on its own first (e.g. because of an error), the reason is returned.
+///
+/// On platforms other than unix, signals are not handled: this blocks until the server exits.
+///
+/// ```no_run
+/// use vintage::Response;
+///
+/// fn main() -> std::io::Result<()> {
+///     vintage::serve("localhost:8000", |req| Response::text(format!("Hello from {}", req.path())))?;
+///     Ok(())
+/// }
+/// ```
+pub fn serve<C>(address: impl ToSocketAddrs, handler: C) -> Result<ServerExitReason, StartError>
+where
+    C: Fn(&mut Request) -> Response,
+    C: 'static + Send + Sync,
+{
+    #[cfg(unix)]
+    signals::install().map_err(|err| StartError::single(StartStep::Setup, err))?;
+
+    let handle = start(ServerConfig::new().unhandled(handler), address)?;
+
+    #[cfg(unix)]
+    let reason = stop_on_signal(handle);
+    #[cfg(not(unix))]
+    let reason = handle.join();
+    Ok(reason)
+}
+
+// Waits for the server to exit, and stops it once SIGINT or SIGTERM is received
+#[cfg(unix)]
+fn stop_on_signal(mut handle: ServerHandle) -> ServerExitReason {
+    // How often the signal flag is checked
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+    loop {
+        match handle.join_timeout(POLL_INTERVAL) {
+            Ok(reason) => return reason,
+            Err(running) => handle = running,
+        }
+        if signals::received() {
+            log::info!("Received a signal to stop. Shutting down");
+            handle.stop();
+            return ServerExitReason::Normal;
+        }
+    }
 }
@@ -0,0 +1,542 @@
+use crate::connection::Stream;
+#[cfg(windows)]
+use crate::named_pipe::PipeListener;
+use crate::ServerConfig;
+use mio::{Registry, Token, Waker};
+use std::fmt::{self, Display};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+#[cfg(unix)]
+use std::os::fd::RawFd;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// The backlog requested when none is configured
+pub const DEFAULT_BACKLOG: u32 = 1024;
+
+// What `ServerHandle::address` reports for a server whose main socket is not a TCP one
+pub const NO_TCP_ADDRESS: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+// The file descriptor a process manager passes the listening socket on (`FCGI_LISTENSOCK_FILENO`)
+#[cfg(unix)]
+pub const LISTENSOCK_FILENO: RawFd = 0;
+
+/// What a server is listening on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerInfo {
+    /// A TCP socket bound to `address`
+    Tcp {
+        /// The bound address. The port is the actual one, even if port 0 was requested.
+        address: SocketAddr,
+        /// The options of the socket
+        options: SocketOptions,
+    },
+    /// A unix domain socket bound to `path`
+    #[cfg(unix)]
+    Unix {
+        /// The path of the socket file
+        path: PathBuf,
+        /// The options of the socket
+        options: SocketOptions,
+    },
+    /// A unix domain socket bound to `name` in the abstract namespace
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Abstract {
+        /// The name of the socket, without the leading null byte
+        name: Vec<u8>,
+        /// The options of the socket
+        options: SocketOptions,
+    },
+    /// A listening socket inherited from the parent process (e.g. a process manager)
+    #[cfg(unix)]
+    InheritedFd {
+        /// The file descriptor of the socket
+        fd: RawFd,
+        /// The options of the socket
+        options: SocketOptions,
+    },
+    /// A named pipe of Windows
+    #[cfg(windows)]
+    NamedPipe {
+        /// The name of the pipe (e.g. `\\.\pipe\app`)
+        name: String,
+        /// The options of the pipe, which has neither a backlog nor `SO_REUSEADDR`
+        options: SocketOptions,
+    },
+}
+
+/// Options a listening socket was set up with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// The length of the queue of pending connections, after the operating system capped it.
+    /// `None` if it is unknown (e.g. for inherited sockets, or on Windows).
+    pub backlog: Option<u32>,
+    /// Whether `SO_REUSEADDR` is set
+    pub reuse_address: bool,
+}
+
+impl ListenerInfo {
+    /// Returns the options of the socket
+    pub fn options(&self) -> &SocketOptions {
+        match self {
+            Self::Tcp { options, .. } => options,
+            #[cfg(unix)]
+            Self::Unix { options, .. } => options,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Self::Abstract { options, .. } => options,
+            #[cfg(unix)]
+            Self::InheritedFd { options, .. } => options,
+            #[cfg(windows)]
+            Self::NamedPipe { options, .. } => options,
+        }
+    }
+
+    /// Returns the address of a TCP listener
+    pub fn tcp_address(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp { address, .. } => Some(*address),
+            #[cfg(any(unix, windows))]
+            _ => None,
+        }
+    }
+}
+
+impl Display for ListenerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { address, .. } => write!(f, "tcp://{address}"),
+            #[cfg(unix)]
+            Self::Unix { path, .. } => write!(f, "unix:{}", path.display()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Self::Abstract { name, .. } => write!(f, "unix:@{}", name.escape_ascii()),
+            #[cfg(unix)]
+            Self::InheritedFd { fd, .. } => write!(f, "fd:{fd}"),
+            #[cfg(windows)]
+            Self::NamedPipe { name, .. } => write!(f, "pipe:{name}"),
+        }
+    }
+}
+
+/// The address of an additional socket a server listens on
+///
+/// See [`ServerConfig::listen`](crate::ServerConfig::listen)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    /// A TCP socket bound to the address
+    Tcp(SocketAddr),
+    /// A unix domain socket created at the path
+    ///
+    /// Binding fails if a file already exists at the path, unless it is a stale socket and
+    /// [`ServerConfig::replace_stale_socket`] is set. The file is removed when the server stops.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// A unix domain socket bound to the name in the abstract namespace of Linux
+    ///
+    /// The name is given without the leading null byte that marks abstract addresses.
+    /// Abstract sockets have no file, so there is nothing to clean up when the server stops.
+    /// They are scoped to the network namespace, which makes them a good fit for a web server
+    /// running in the same container (or pod) as the application.
+    ///
+    /// ```no_run
+    /// use vintage::{ListenAddress, Response, ServerConfig};
+    ///
+    /// let admin = ServerConfig::new().on_get(["/health"], |_req, _params| Response::text("ok"));
+    /// let config = ServerConfig::new()
+    ///     .listen(ListenAddress::Abstract(b"app-admin".to_vec()), admin);
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Abstract(Vec<u8>),
+    /// A named pipe of Windows with the name (e.g. `\\.\pipe\app`)
+    ///
+    /// Binding fails with `AddrInUse` if a pipe with the same name exists. Only clients on the
+    /// same machine can connect.
+    ///
+    /// ```no_run
+    /// # #[cfg(windows)] {
+    /// use vintage::{ListenAddress, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .listen(ListenAddress::NamedPipe(r"\\.\pipe\app".into()), ServerConfig::new());
+    /// # }
+    /// ```
+    #[cfg(windows)]
+    NamedPipe(String),
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "tcp://{address}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Self::Abstract(name) => write!(f, "unix:@{}", name.escape_ascii()),
+            #[cfg(windows)]
+            Self::NamedPipe(name) => write!(f, "pipe:{name}"),
+        }
+    }
+}
+
+impl From<SocketAddr> for ListenAddress {
+    fn from(value: SocketAddr) -> Self {
+        Self::Tcp(value)
+    }
+}
+
+#[cfg(unix)]
+impl From<PathBuf> for ListenAddress {
+    fn from(value: PathBuf) -> Self {
+        Self::Unix(value)
+    }
+}
+
+#[cfg(unix)]
+impl From<&Path> for ListenAddress {
+    fn from(value: &Path) -> Self {
+        Self::Unix(value.to_path_buf())
+    }
+}
+
+// How the file of a unix socket is set up. See `ServerConfig::socket_permissions`
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketFile {
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+    pub replace_stale: bool,
+}
+
+// A listening socket of a server
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(mio::net::TcpListener),
+    // The path of the socket file, which is removed when the socket is dropped.
+    // Abstract sockets have no file.
+    #[cfg(unix)]
+    Unix(mio::net::UnixListener, Option<PathBuf>),
+    #[cfg(windows)]
+    NamedPipe(PipeListener),
+}
+
+#[cfg(unix)]
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Self::Unix(_, Some(path)) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Tcp(s) => s.as_raw_fd(),
+            Self::Unix(s, _) => s.as_raw_fd(),
+        }
+    }
+}
+
+impl Socket {
+    // Binds a socket at `address`, with the backlog (and socket file settings) of `config`
+    pub fn bind(
+        address: &ListenAddress,
+        config: &ServerConfig,
+    ) -> io::Result<(Self, ListenerInfo)> {
+        let backlog = config.backlog.unwrap_or(DEFAULT_BACKLOG);
+        match address {
+            ListenAddress::Tcp(address) => {
+                let socket = mio::net::TcpListener::bind(*address)?;
+                let backlog = listen(&socket, backlog)?;
+                let info = ListenerInfo::Tcp {
+                    address: socket.local_addr()?,
+                    options: SocketOptions {
+                        backlog,
+                        reuse_address: cfg!(not(windows)),
+                    },
+                };
+                Ok((Self::Tcp(socket), info))
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                let file = &config.socket_file;
+                if file.replace_stale {
+                    remove_stale_socket(path)?;
+                }
+                let listener = mio::net::UnixListener::bind(path)?;
+                // Dropping the socket removes the file if the rest of the setup fails
+                let socket = Self::Unix(listener, Some(path.clone()));
+                set_up_socket_file(path, file)?;
+                let backlog = listen(&socket, backlog)?;
+                let info = ListenerInfo::Unix {
+                    path: path.clone(),
+                    options: SocketOptions {
+                        backlog,
+                        reuse_address: false,
+                    },
+                };
+                Ok((socket, info))
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            ListenAddress::Abstract(name) => {
+                #[cfg(target_os = "android")]
+                use std::os::android::net::SocketAddrExt;
+                #[cfg(target_os = "linux")]
+                use std::os::linux::net::SocketAddrExt;
+
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                let socket = std::os::unix::net::UnixListener::bind_addr(&address)?;
+                socket.set_nonblocking(true)?;
+                let socket = mio::net::UnixListener::from_std(socket);
+                let backlog = listen(&socket, backlog)?;
+                let info = ListenerInfo::Abstract {
+                    name: name.clone(),
+                    options: SocketOptions {
+                        backlog,
+                        reuse_address: false,
+                    },
+                };
+                Ok((Self::Unix(socket, None), info))
+            }
+            #[cfg(windows)]
+            ListenAddress::NamedPipe(name) => {
+                let info = ListenerInfo::NamedPipe {
+                    name: name.clone(),
+                    options: SocketOptions {
+                        backlog: None,
+                        reuse_address: false,
+                    },
+                };
+                Ok((Self::NamedPipe(PipeListener::bind(name)?), info))
+            }
+        }
+    }
+
+    // The address of a TCP socket, or `NO_TCP_ADDRESS` for another one
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Tcp(s) => s.local_addr(),
+            #[cfg(unix)]
+            Self::Unix(..) => Ok(NO_TCP_ADDRESS),
+            #[cfg(windows)]
+            Self::NamedPipe(_) => Ok(NO_TCP_ADDRESS),
+        }
+    }
+
+    // Another handle to the same socket, for threads that share it.
+    //
+    // The handle doesn't remove the socket file when dropped.
+    #[cfg(unix)]
+    pub fn try_clone(&self) -> io::Result<Self> {
+        use std::os::fd::AsFd;
+
+        match self {
+            Self::Tcp(s) => {
+                let fd = s.as_fd().try_clone_to_owned()?;
+                Ok(Self::Tcp(mio::net::TcpListener::from_std(fd.into())))
+            }
+            Self::Unix(s, _) => {
+                let fd = s.as_fd().try_clone_to_owned()?;
+                Ok(Self::Unix(
+                    mio::net::UnixListener::from_std(fd.into()),
+                    None,
+                ))
+            }
+        }
+    }
+
+    // Has `registry` report the connections to accept with `token`.
+    //
+    // Named pipes can't be registered. `waker` is woken up when a connection to one is ready.
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    pub fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        waker: &Arc<Waker>,
+    ) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => registry.register(s, token, mio::Interest::READABLE),
+            #[cfg(unix)]
+            Self::Unix(s, _) => registry.register(s, token, mio::Interest::READABLE),
+            #[cfg(windows)]
+            Self::NamedPipe(pipe) => pipe.start(waker.clone()),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Self::Tcp(s) => s.accept().map(|(s, _)| Stream::Tcp(s.into())),
+            #[cfg(unix)]
+            Self::Unix(s, _) => s.accept().map(|(s, _)| Stream::Unix(s.into())),
+            #[cfg(windows)]
+            Self::NamedPipe(pipe) => pipe.accept().map(Stream::NamedPipe),
+        }
+    }
+}
+
+// Removes the socket file at `path` if nothing listens on it any more (e.g. because the server
+// that created it was killed)
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // Other files are left alone, and binding reports that the path is taken
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        _ => return Ok(()),
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+            log::info!(path:? = path; "Removing stale socket file");
+            std::fs::remove_file(path)
+        }
+        // Another process is listening, so binding fails with `AddrInUse`
+        _ => Ok(()),
+    }
+}
+
+// Applies the permissions and ownership of `file` to the socket file at `path`
+#[cfg(unix)]
+fn set_up_socket_file(path: &Path, file: &SocketFile) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if file.owner.is_some() || file.group.is_some() {
+        std::os::unix::fs::chown(path, file.owner, file.group)?;
+    }
+    if let Some(mode) = file.mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+// Returns the listening socket on file descriptor `fd`, or `None` if `fd` is not one.
+//
+// The socket is duplicated, so `fd` itself stays open.
+// Errors if `fd` is a listening socket that is neither a TCP nor a unix domain one.
+#[cfg(unix)]
+pub fn inherited(fd: RawFd) -> io::Result<Option<(Socket, ListenerInfo)>> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let mut accepting: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `accepting` and `len` are valid for writes, and `len` is the size of `accepting`.
+    // If `fd` is closed, or not a socket, this fails without side effects.
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut accepting as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 || accepting == 0 {
+        return Ok(None);
+    }
+
+    // SAFETY: `fd` was just checked to be an open socket
+    let duplicate = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+    if duplicate < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `duplicate` is a new file descriptor that nothing else owns
+    let socket = socket2::Socket::from(unsafe { OwnedFd::from_raw_fd(duplicate) });
+
+    let address = socket.local_addr()?;
+    let info = ListenerInfo::InheritedFd {
+        fd,
+        options: SocketOptions {
+            // The backlog can't be read back from the socket
+            backlog: None,
+            reuse_address: socket.reuse_address()?,
+        },
+    };
+    socket.set_nonblocking(true)?;
+
+    // `spawn-fcgi -s`, for one, passes a unix domain socket
+    let socket = if address.is_unix() {
+        // The socket file belongs to the parent process
+        Socket::Unix(mio::net::UnixListener::from_std(socket.into()), None)
+    } else if address.as_socket().is_some() {
+        Socket::Tcp(mio::net::TcpListener::from_std(socket.into()))
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The inherited listening socket is neither a TCP nor a unix domain socket",
+        ));
+    };
+
+    Ok(Some((socket, info)))
+}
+
+// Starts listening on `listener` with a queue of `backlog` pending connections, and returns the
+// queue length the operating system actually uses
+#[cfg(unix)]
+pub fn listen(listener: &impl std::os::fd::AsRawFd, backlog: u32) -> io::Result<Option<u32>> {
+    let requested = backlog.min(i32::MAX as u32);
+    // The socket is already listening. Calling `listen` again updates the queue length.
+    // SAFETY: the file descriptor is owned by `listener`, which outlives this call
+    let result = unsafe { libc::listen(listener.as_raw_fd(), requested as i32) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Linux silently caps the queue length
+    #[cfg(target_os = "linux")]
+    if let Some(max) = std::fs::read_to_string("/proc/sys/net/core/somaxconn")
+        .ok()
+        .and_then(|max| max.trim().parse::<u32>().ok())
+    {
+        return Ok(Some(requested.min(max)));
+    }
+
+    Ok(Some(requested))
+}
+
+// The backlog can't be changed once the socket listens, and mio doesn't let it be set before.
+// It is whatever mio sets up the socket with.
+#[cfg(not(unix))]
+pub fn listen(_listener: &mio::net::TcpListener, _backlog: u32) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn inherited_socket() {
+        let tcp = std::net::TcpListener::bind("localhost:0").unwrap();
+        let (socket, info) = inherited(tcp.as_raw_fd()).unwrap().unwrap();
+        assert_matches!(socket, Socket::Tcp(_));
+        assert_eq!(socket.local_addr().unwrap(), tcp.local_addr().unwrap());
+        assert_ne!(socket.as_raw_fd(), tcp.as_raw_fd());
+        assert_eq!(info.to_string(), format!("fd:{}", tcp.as_raw_fd()));
+
+        let connected = std::net::TcpStream::connect(tcp.local_addr().unwrap()).unwrap();
+        assert!(inherited(connected.as_raw_fd()).unwrap().is_none());
+
+        let file = std::fs::File::open("Cargo.toml").unwrap();
+        assert!(inherited(file.as_raw_fd()).unwrap().is_none());
+
+        let dir = std::env::temp_dir().join(format!("vintage-inherited-{}", std::process::id()));
+        let _ = std::fs::remove_file(&dir);
+        let unix = std::os::unix::net::UnixListener::bind(&dir).unwrap();
+        let (socket, info) = inherited(unix.as_raw_fd()).unwrap().unwrap();
+        assert_eq!(info.to_string(), format!("fd:{}", unix.as_raw_fd()));
+        let _client = std::os::unix::net::UnixStream::connect(&dir).unwrap();
+        assert_matches!(socket.accept(), Ok(Stream::Unix(_)));
+        // The socket file is left to the parent process
+        drop(socket);
+        assert!(dir.exists());
+        std::fs::remove_file(&dir).unwrap();
+
+        let udp = std::net::UdpSocket::bind("localhost:0").unwrap();
+        assert!(inherited(udp.as_raw_fd()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::context::{Request, Response};
+use crate::headers;
+use crate::query;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+// Characters that are percent-encoded when a path is put back in a `Location` header
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// The locale negotiated for a request by [`LocaleNegotiation`]
+///
+/// It is stored in the request extensions:
+///
+/// ```
+/// use vintage::{Locale, LocaleNegotiation, Response, ServerConfig};
+///
+/// let config = ServerConfig::new()
+///     .locales(LocaleNegotiation::new(["en", "fr"]))
+///     .on_get(["/"], |req, _params| {
+///         match req.ext::<Locale>().map(|l| l.0.as_str()) {
+///             Some("fr") => Response::text("Bonjour"),
+///             _ => Response::text("Hello"),
+///         }
+///     });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale(pub String);
+
+/// A place the locale of a request can be found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocaleSource {
+    /// The first segment of the path (e.g. `/fr/about`).
+    /// The prefix is removed from the request path, so routes don't need to include it.
+    PathPrefix,
+    /// The query parameter with the given name
+    Query(String),
+    /// The cookie with the given name
+    Cookie(String),
+    /// The `Accept-Language` header
+    AcceptLanguage,
+}
+
+/// Determines the locale of requests
+///
+/// Each [`LocaleSource`] is consulted in order, and the first one that names a supported locale
+/// wins.
+/// If none of them do, the first supported locale is used.
+/// The result is stored in the request extensions as a [`Locale`].
+///
+/// See [`ServerConfig::locales`](crate::ServerConfig::locales)
+#[derive(Debug, Clone)]
+pub struct LocaleNegotiation {
+    supported: Vec<String>,
+    sources: Vec<LocaleSource>,
+    redirect: bool,
+}
+
+impl LocaleNegotiation {
+    /// Creates a negotiation between the `supported` locales (e.g. `en`, `fr-CA`).
+    /// The first one is the default.
+    ///
+    /// By default, sources are consulted in this order: [`LocaleSource::PathPrefix`], the `lang`
+    /// query parameter, the `lang` cookie, then [`LocaleSource::AcceptLanguage`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `supported` is empty
+    pub fn new<const N: usize>(supported: [&str; N]) -> Self {
+        assert!(N > 0, "At least one locale must be supported");
+        Self {
+            supported: supported.iter().map(|s| s.to_string()).collect(),
+            sources: vec![
+                LocaleSource::PathPrefix,
+                LocaleSource::Query("lang".into()),
+                LocaleSource::Cookie("lang".into()),
+                LocaleSource::AcceptLanguage,
+            ],
+            redirect: false,
+        }
+    }
+
+    /// Sets the sources to consult, in order of priority
+    pub fn sources(mut self, sources: impl IntoIterator<Item = LocaleSource>) -> Self {
+        self.sources = sources.into_iter().collect();
+        self
+    }
+
+    /// Redirects `GET` and `HEAD` requests without a locale prefix to the same path, prefixed
+    /// with the negotiated locale (e.g. `/about` to `/fr/about`).
+    ///
+    /// This has no effect unless [`LocaleSource::PathPrefix`] is one of the sources.
+    pub fn redirect_to_prefix(mut self, redirect: bool) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    /// Determines the locale of `req` and stores it in its extensions
+    ///
+    /// Returns a redirect if the request should be made again with a locale prefix.
+    pub fn negotiate(&self, req: &mut Request) -> Option<Response> {
+        let uses_prefix = self.sources.contains(&LocaleSource::PathPrefix);
+        let prefix = if uses_prefix {
+            self.strip_prefix(req)
+        } else {
+            None
+        };
+
+        let locale = self
+            .sources
+            .iter()
+            .find_map(|source| match source {
+                LocaleSource::PathPrefix => prefix.clone(),
+                LocaleSource::Query(name) => req.query(name).and_then(|l| self.find(l)),
+                LocaleSource::Cookie(name) => req.cookie(name).and_then(|l| self.find(l)),
+                LocaleSource::AcceptLanguage => req
+                    .header(headers::ACCEPT_LANGUAGE)
+                    .and_then(|value| self.find_accepted(value)),
+            })
+            .unwrap_or_else(|| self.supported[0].clone());
+
+        let redirect = self.redirect
+            && uses_prefix
+            && prefix.is_none()
+            && matches!(req.method(), "GET" | "HEAD");
+
+        if redirect {
+            let path = utf8_percent_encode(req.path(), PATH);
+            let location = query::merge(&format!("/{locale}{path}"), &req.query_string);
+            return Some(Response::temporary_redirect(location));
+        }
+
+        req.insert_ext(Locale(locale));
+        None
+    }
+
+    // Removes the locale prefix from the request path, and returns the locale
+    fn strip_prefix(&self, req: &mut Request) -> Option<String> {
+        let path = req.path().strip_prefix('/')?;
+        let (segment, rest) = match path.split_once('/') {
+            Some((segment, rest)) => (segment, rest),
+            None => (path, ""),
+        };
+
+        let locale = self
+            .supported
+            .iter()
+            .find(|l| l.eq_ignore_ascii_case(segment))?
+            .clone();
+        req.path = format!("/{rest}");
+        Some(locale)
+    }
+
+    // Returns the supported locale that matches `tag`, if any
+    //
+    // Tags only need to share a primary language to match (e.g. `fr-CH` matches `fr`).
+    fn find(&self, tag: &str) -> Option<String> {
+        let primary = |tag: &str| tag.split('-').next().unwrap_or_default().to_string();
+
+        self.supported
+            .iter()
+            .find(|l| l.eq_ignore_ascii_case(tag))
+            .or_else(|| {
+                self.supported
+                    .iter()
+                    .find(|l| primary(l).eq_ignore_ascii_case(&primary(tag)))
+            })
+            .cloned()
+    }
+
+    // Returns the supported locale the client prefers, according to an `Accept-Language` header
+    fn find_accepted(&self, header: &str) -> Option<String> {
+        let mut tags: Vec<(&str, f32)> = header
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.split(';').map(str::trim);
+                let tag = parts.next().filter(|tag| !tag.is_empty() && *tag != "*")?;
+                let quality = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                (quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // Sorting is stable, so tags of equal quality stay in the client's order
+        tags.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        tags.into_iter().find_map(|(tag, _)| self.find(tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status;
+
+    fn request(path: &str, query: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".into(),
+            path: path.into(),
+            query_string: query.into(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Request::default()
+        }
+    }
+
+    fn negotiate(negotiation: &LocaleNegotiation, req: &mut Request) -> Option<String> {
+        assert_eq!(negotiation.negotiate(req), None);
+        req.ext::<Locale>().map(|l| l.0.clone())
+    }
+
+    #[test]
+    fn sources() {
+        let negotiation = LocaleNegotiation::new(["en", "fr", "pt-BR"]);
+
+        let mut req = request("/fr/about", "lang=en", &[]);
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("fr"));
+        assert_eq!(req.path(), "/about");
+
+        let mut req = request("/fr", "", &[]);
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("fr"));
+        assert_eq!(req.path(), "/");
+
+        let mut req = request("/about", "lang=fr", &[("Cookie", "lang=en")]);
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("fr"));
+        assert_eq!(req.path(), "/about");
+
+        let mut req = request("/", "", &[("Cookie", "theme=dark; lang=pt-BR")]);
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("pt-BR"));
+
+        let accept = [("Accept-Language", "de;q=0.9, pt-PT, fr;q=0.8, *;q=0.5")];
+        let mut req = request("/", "", &accept);
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("pt-BR"));
+
+        let mut req = request("/", "lang=xx", &[("Accept-Language", "de")]);
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("en"));
+
+        // Without the path prefix source, the path is left alone
+        let negotiation = negotiation.sources([LocaleSource::AcceptLanguage]);
+        let mut req = request("/fr/about", "", &[("Accept-Language", "fr-CA")]);
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("fr"));
+        assert_eq!(req.path(), "/fr/about");
+    }
+
+    #[test]
+    fn redirect_to_prefix() {
+        let negotiation = LocaleNegotiation::new(["en", "fr"]).redirect_to_prefix(true);
+
+        let mut req = request("/a b", "x=1", &[("Accept-Language", "fr")]);
+        let response = negotiation.negotiate(&mut req).unwrap();
+        assert_eq!(response.status, status::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers["Location"], "/fr/a%20b?x=1");
+
+        // Line breaks in the query string can't inject headers
+        let mut req = request("/a", "x=%41\r\nSet-Cookie: a=b", &[]);
+        let response = negotiation.negotiate(&mut req).unwrap();
+        assert_eq!(
+            response.headers["Location"],
+            "/en/a?x=%41%0D%0ASet-Cookie:%20a=b"
+        );
+
+        let mut req = request("/en/a", "", &[("Accept-Language", "fr")]);
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("en"));
+
+        let mut req = request("/a", "", &[]);
+        req.method = "POST".into();
+        assert_eq!(negotiate(&negotiation, &mut req).as_deref(), Some("en"));
+    }
+}
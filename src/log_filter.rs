@@ -0,0 +1,246 @@
+use crate::sync;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fmt;
+use std::sync::RwLock;
+
+// The filter set by `ServerHandle::set_log_filter`, or `None` if it was never called.
+//
+// Log records go to a single logger for the whole process, so the filter is global too.
+static FILTER: RwLock<Option<Filter>> = RwLock::new(None);
+
+// Which log records are let through: the most specific directive that matches the target of a
+// record sets its maximum level, and records of other targets get the default level
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Filter {
+    default: LevelFilter,
+    // `(target, level)`, longest target first
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    // Parses `filter` (e.g. `warn,vintage=debug`). Without a default level, `default` is used.
+    fn parse(filter: &str, default: LevelFilter) -> Result<Self, InvalidLogFilter> {
+        let invalid = |directive: &str| InvalidLogFilter(directive.to_string());
+        let mut parsed = Self {
+            default,
+            directives: vec![],
+        };
+
+        for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let level = level.trim().parse().map_err(|_| invalid(directive))?;
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(invalid(directive));
+                    }
+                    parsed.directives.push((target.to_string(), level));
+                }
+                None => match directive.parse() {
+                    Ok(level) => parsed.default = level,
+                    // A target on its own gets every record
+                    Err(_) => parsed
+                        .directives
+                        .push((directive.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+
+        parsed
+            .directives
+            .sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(parsed)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(prefix, _)| {
+                // `vintage` matches `vintage::connection`, but not `vintage_extra`
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    // The most verbose level of the filter, which is what `log` is told to let through
+    fn max_level(&self) -> LevelFilter {
+        let levels = self.directives.iter().map(|(_, level)| *level);
+        levels.fold(self.default, Ord::max)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (target, level) in &self.directives {
+            write!(f, ",{target}={}", level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+// Replaces the global filter. See `ServerHandle::set_log_filter`
+pub(crate) fn set(filter: &str) -> Result<(), InvalidLogFilter> {
+    let filter = {
+        let mut current = sync::write(&FILTER);
+        let default = current
+            .as_ref()
+            .map_or_else(log::max_level, |filter| filter.default);
+        let filter = Filter::parse(filter, default)?;
+        log::set_max_level(filter.max_level());
+        current.insert(filter).to_string()
+    };
+
+    // Not while holding the lock: `FilteredLogger` takes it to filter this record
+    log::info!(filter:% = filter; "Log filter changed");
+    Ok(())
+}
+
+// Describes the global filter, in the syntax `set` accepts
+pub(crate) fn current() -> String {
+    match &*sync::read(&FILTER) {
+        Some(filter) => filter.to_string(),
+        None => log::max_level().as_str().to_lowercase(),
+    }
+}
+
+/// A logger that applies the filter set by
+/// [`ServerHandle::set_log_filter`](crate::ServerHandle::set_log_filter) before passing records
+/// on to another logger
+///
+/// `log` only has a global maximum level, so without this logger, a filter like `vintage=debug`
+/// also lets through the debug records of every other crate.
+///
+/// ```
+/// use vintage::FilteredLogger;
+///
+/// # struct StderrLogger;
+/// # impl log::Log for StderrLogger {
+/// #     fn enabled(&self, _: &log::Metadata) -> bool { true }
+/// #     fn log(&self, record: &log::Record) { eprintln!("{}", record.args()) }
+/// #     fn flush(&self) {}
+/// # }
+/// // Any logger works, e.g. the one built by `env_logger::Builder::build()`
+/// FilteredLogger::new(StderrLogger, "info").unwrap().install().unwrap();
+/// ```
+pub struct FilteredLogger {
+    inner: Box<dyn Log>,
+}
+
+impl FilteredLogger {
+    /// Wraps `logger`, and sets the filter to `filter` (e.g. `info` or `warn,vintage=debug`)
+    ///
+    /// See [`ServerHandle::set_log_filter`](crate::ServerHandle::set_log_filter) for the syntax.
+    pub fn new(logger: impl Log + 'static, filter: &str) -> Result<Self, InvalidLogFilter> {
+        set(filter)?;
+        Ok(Self {
+            inner: Box::new(logger),
+        })
+    }
+
+    /// Makes this the logger of the process
+    ///
+    /// This fails if a logger was already installed.
+    pub fn install(self) -> Result<(), log::SetLoggerError> {
+        log::set_boxed_logger(Box::new(self))
+    }
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let filter = sync::read(&FILTER);
+        let allowed = filter
+            .as_ref()
+            .is_none_or(|filter| metadata.level() <= filter.level(metadata.target()));
+        allowed && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+impl fmt::Debug for FilteredLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilteredLogger").finish_non_exhaustive()
+    }
+}
+
+/// The error returned when a log filter can't be parsed. It holds the invalid directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLogFilter(pub String);
+
+impl fmt::Display for InvalidLogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid log filter directive: '{}'",
+            self.0.escape_debug()
+        )
+    }
+}
+
+impl std::error::Error for InvalidLogFilter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives() {
+        let filter = Filter::parse(
+            "vintage=debug, vintage::connection=trace",
+            LevelFilter::Warn,
+        );
+        let filter = filter.unwrap();
+        assert_eq!(filter.level("app"), LevelFilter::Warn);
+        assert_eq!(filter.level("vintage"), LevelFilter::Debug);
+        assert_eq!(filter.level("vintage::event_loop"), LevelFilter::Debug);
+        assert_eq!(filter.level("vintage::connection"), LevelFilter::Trace);
+        assert_eq!(filter.level("vintage_extra"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert_eq!(
+            filter.to_string(),
+            "warn,vintage::connection=trace,vintage=debug"
+        );
+
+        let filter = Filter::parse("OFF,app", LevelFilter::Warn).unwrap();
+        assert_eq!(filter.level("vintage"), LevelFilter::Off);
+        assert_eq!(filter.level("app::db"), LevelFilter::Trace);
+
+        for invalid in ["vintage=loud", "=debug"] {
+            let err = Filter::parse(invalid, LevelFilter::Info).unwrap_err();
+            assert_eq!(err, InvalidLogFilter(invalid.to_string()));
+        }
+    }
+
+    #[test]
+    fn change_filter_of_installed_logger() {
+        struct NullLogger;
+        impl Log for NullLogger {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+            fn log(&self, _: &Record) {}
+            fn flush(&self) {}
+        }
+
+        let logger = FilteredLogger::new(NullLogger, "info").unwrap();
+        logger.install().unwrap();
+
+        // Changing the filter logs the change, through the installed logger
+        let (done, changed) = std::sync::mpsc::channel();
+        std::thread::spawn(move || done.send(set("info,vintage=debug")));
+        let result = changed.recv_timeout(std::time::Duration::from_secs(5));
+        assert_eq!(result, Ok(Ok(())));
+        assert_eq!(current(), "info,vintage=debug");
+    }
+}
@@ -0,0 +1,188 @@
+use crate::sync;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// What happens to a request that would take the memory used by requests over the budget
+///
+/// See [`ServerConfig::memory_budget`](crate::ServerConfig::memory_budget)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryPolicy {
+    /// Stops reading the request until other requests release enough memory, for at most the
+    /// given duration. If there still isn't enough memory by then, the request is shed.
+    ///
+    /// The web server buffers or holds back the rest of the upload in the meantime.
+    Wait(Duration),
+    /// Responds to the request with a `503 Service Unavailable` right away
+    Shed,
+}
+
+// How many bytes of params and request bodies the requests being handled can buffer together
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    policy: MemoryPolicy,
+    used: Mutex<usize>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize, policy: MemoryPolicy) -> Self {
+        Self {
+            limit,
+            policy,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    // A budget with the same settings and nothing used, for a new server.
+    // See `ServerConfig::stats`
+    pub fn renewed(&self) -> Self {
+        Self::new(self.limit, self.policy)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // Starts an empty reservation against the budget
+    pub fn reservation(self: &Arc<Self>) -> Reservation {
+        Reservation {
+            budget: self.clone(),
+            bytes: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn used(&self) -> usize {
+        *self.lock()
+    }
+
+    // Takes `bytes` from the budget, waiting for them according to the policy.
+    // `reserved` is how many bytes the caller already holds.
+    //
+    // Returns false if they can't be had.
+    fn take(&self, bytes: usize, reserved: usize) -> bool {
+        // A request that needs more than the whole budget would wait forever
+        if reserved.saturating_add(bytes) > self.limit {
+            return false;
+        }
+
+        let fits = |used: &usize| *used + bytes <= self.limit;
+        let mut used = self.lock();
+        if !fits(&used) {
+            let MemoryPolicy::Wait(timeout) = self.policy else {
+                return false;
+            };
+            let deadline = Instant::now() + timeout;
+            while !fits(&used) {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return false;
+                }
+                used = self
+                    .released
+                    .wait_timeout(used, left)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+        }
+        *used += bytes;
+        true
+    }
+
+    fn release(&self, bytes: usize) {
+        *self.lock() -= bytes;
+        self.released.notify_all();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, usize> {
+        sync::lock(&self.used)
+    }
+}
+
+// The part of the budget held by a request. It is given back when the reservation is dropped.
+#[derive(Debug)]
+pub struct Reservation {
+    budget: Arc<MemoryBudget>,
+    bytes: usize,
+}
+
+impl Reservation {
+    // Adds `bytes` to the reservation. Returns false if the budget can't spare them.
+    pub fn grow(&mut self, bytes: usize) -> bool {
+        if bytes == 0 {
+            return true;
+        }
+        let taken = self.budget.take(bytes, self.bytes);
+        if taken {
+            self.bytes += bytes;
+        }
+        taken
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.bytes > 0 {
+            self.budget.release(self.bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn reservations() {
+        let budget = Arc::new(MemoryBudget::new(100, MemoryPolicy::Shed));
+        let mut first = budget.reservation();
+        let mut second = budget.reservation();
+
+        assert!(first.grow(60));
+        assert!(!second.grow(50));
+        assert!(second.grow(40));
+        assert_eq!(budget.used(), 100);
+
+        drop(first);
+        assert_eq!(budget.used(), 40);
+        // More than the whole budget is never granted
+        assert!(!second.grow(61));
+        assert!(second.grow(60));
+        drop(second);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn waiting() {
+        let timeout = Duration::from_secs(5);
+        let budget = Arc::new(MemoryBudget::new(100, MemoryPolicy::Wait(timeout)));
+        let mut first = budget.reservation();
+        assert!(first.grow(80));
+
+        let waiter = thread::spawn({
+            let budget = budget.clone();
+            move || {
+                let mut second = budget.reservation();
+                let started = Instant::now();
+                (second.grow(50), started.elapsed())
+            }
+        });
+        thread::sleep(Duration::from_millis(50));
+        drop(first);
+        let (granted, waited) = waiter.join().unwrap();
+        assert!(granted);
+        assert!(waited < timeout);
+
+        let budget = Arc::new(MemoryBudget::new(
+            100,
+            MemoryPolicy::Wait(Duration::from_millis(20)),
+        ));
+        let mut first = budget.reservation();
+        assert!(first.grow(80));
+        assert!(!budget.reservation().grow(50));
+        assert_eq!(budget.used(), 80);
+    }
+}
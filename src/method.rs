@@ -0,0 +1,30 @@
+//! HTTP request method constants, including the extension methods used by WebDAV
+//!
+//! Any of them can be routed with [`ServerConfig::on`](crate::ServerConfig::on).
+//! Request bodies are available for every method (e.g. the XML body of a `PROPFIND`).
+
+macro_rules! methods {
+    ($($name:ident),* $(,)?) => {
+        $(
+            pub const $name: &str = stringify!($name);
+        )*
+    }
+}
+
+methods! {
+    GET,
+    HEAD,
+    POST,
+    PUT,
+    DELETE,
+    OPTIONS,
+    PATCH,
+    // WebDAV (RFC 4918)
+    PROPFIND,
+    PROPPATCH,
+    MKCOL,
+    COPY,
+    MOVE,
+    LOCK,
+    UNLOCK,
+}
@@ -0,0 +1,62 @@
+use crate::context::{Request, Response};
+use std::sync::Arc;
+
+pub type MiddlewareCallback = Arc<dyn Fn(&mut Request, Next) -> Response + Send + Sync>;
+
+/// The rest of the middleware chain, followed by the request handler
+///
+/// See [`ServerConfig::middleware`](crate::ServerConfig::middleware)
+pub struct Next<'a> {
+    chain: &'a [MiddlewareCallback],
+    handler: &'a dyn Fn(&mut Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        chain: &'a [MiddlewareCallback],
+        handler: &'a dyn Fn(&mut Request) -> Response,
+    ) -> Self {
+        Self { chain, handler }
+    }
+
+    /// Passes `req` on to the next middleware, or to the request handler, and returns its response
+    pub fn run(self, req: &mut Request) -> Response {
+        match self.chain.split_first() {
+            Some((middleware, chain)) => middleware(req, Next::new(chain, self.handler)),
+            None => (self.handler)(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn chain_order() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let middleware = |name: &'static str| -> MiddlewareCallback {
+            let calls = calls.clone();
+            Arc::new(move |req, next| {
+                calls.lock().unwrap().push(format!("{name} before"));
+                let response = next.run(req);
+                calls.lock().unwrap().push(format!("{name} after"));
+                response
+            })
+        };
+        let chain = [middleware("a"), middleware("b")];
+
+        let handler = |_req: &mut Request| {
+            calls.lock().unwrap().push("handler".to_string());
+            Response::text("hi")
+        };
+        let response = Next::new(&chain, &handler).run(&mut Request::default());
+
+        assert_eq!(response, Response::text("hi"));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["a before", "b before", "handler", "b after", "a after"]
+        );
+    }
+}
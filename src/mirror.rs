@@ -0,0 +1,188 @@
+use crate::client::Client;
+use crate::context::Request;
+use crate::router::PathSet;
+use crate::sampler::Sampler;
+use std::fmt;
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+// How many copies can wait to be sent. Past that, requests are not mirrored until the backend
+// catches up.
+const MAX_PENDING_COPIES: usize = 64;
+
+struct MirrorInner {
+    client: Client,
+    sampler: Sampler,
+    routes: Option<PathSet>,
+    // Started with the first copy, so that configs that are never served don't spawn a thread.
+    // The thread exits once every clone of the mirror is dropped.
+    sender: OnceLock<SyncSender<Request>>,
+}
+
+/// Sends a copy of requests to another FastCGI server, without affecting their responses
+///
+/// This is useful to try a new version of a service against production traffic.
+/// Copies are sent in the background, one at a time. The responses of the mirror backend are
+/// discarded, and its failures are only logged.
+/// If the backend can't keep up, requests are not mirrored until it catches up.
+///
+/// Requests are copied before they reach route handlers (and the middleware registered after
+/// the mirror), so the copy is the request as it was received.
+///
+/// ```no_run
+/// use vintage::{Client, Mirror, ServerConfig};
+///
+/// let mirror = Mirror::new(Client::tcp("10.0.0.3:9000").unwrap())
+///     .sample(0.1)
+///     .paths(["/api/{*rest}"]);
+///
+/// let config = ServerConfig::new().mirror(mirror);
+/// ```
+#[derive(Clone)]
+pub struct Mirror {
+    inner: Arc<MirrorInner>,
+}
+
+impl fmt::Debug for Mirror {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mirror")
+            .field("backend", self.inner.client.backend())
+            .field("rate", &self.inner.sampler.rate())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mirror {
+    /// Creates a mirror that sends a copy of every request to the backend of `client`
+    pub fn new(client: Client) -> Self {
+        Self {
+            inner: Arc::new(MirrorInner {
+                client,
+                sampler: Sampler::new(1.0),
+                routes: None,
+                sender: OnceLock::new(),
+            }),
+        }
+    }
+
+    /// Sets the fraction of requests that are mirrored, between 0 and 1. The default is 1.
+    ///
+    /// Mirrored requests are spread evenly: with `0.25`, every fourth request is mirrored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not between 0 and 1, or if the mirror was cloned
+    #[track_caller]
+    pub fn sample(mut self, rate: f64) -> Self {
+        self.inner_mut().sampler = Sampler::new(rate);
+        self
+    }
+
+    /// Only mirrors requests to `paths`. By default, requests to any path are mirrored.
+    ///
+    /// Paths use the same syntax as routes (see [`ServerConfig::on`](crate::ServerConfig::on)).
+    /// The sampling rate applies to the requests that match.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a path is not a valid route or is repeated, or if the mirror was cloned
+    #[track_caller]
+    pub fn paths<const N: usize>(mut self, paths: [&str; N]) -> Self {
+        self.inner_mut().routes = Some(PathSet::new(paths));
+        self
+    }
+
+    fn inner_mut(&mut self) -> &mut MirrorInner {
+        Arc::get_mut(&mut self.inner).expect("mirror should not be configured after being cloned")
+    }
+
+    // Queues a copy of `req` to be sent to the mirror backend, if it is selected
+    pub(crate) fn copy(&self, req: &Request) {
+        let inner = &self.inner;
+        if let Some(routes) = &inner.routes {
+            if !routes.matches(&req.path) {
+                return;
+            }
+        }
+
+        if !inner.sampler.sample() {
+            return;
+        }
+
+        let copy = Request {
+            method: req.method.clone(),
+            path: req.path.clone(),
+            query_string: req.query_string.clone(),
+            headers: req.headers.clone(),
+            vars: req.vars.clone(),
+            body: req.body.clone(),
+            ..Request::default()
+        };
+
+        let sender = inner.sender.get_or_init(|| {
+            let (sender, copies) = sync_channel::<Request>(MAX_PENDING_COPIES);
+            let client = inner.client.clone();
+            thread::spawn(move || {
+                for copy in copies {
+                    if let Err(err) = client.send(&copy) {
+                        log::warn!(error:err = err, backend:% = client.backend(); "Failed to mirror request");
+                    }
+                }
+            });
+            sender
+        });
+
+        match sender.try_send(copy) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                log::debug!(backend:% = inner.client.backend(); "Mirror backend is behind. Request not mirrored");
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Response;
+    use crate::testing::{collector, receive_exactly};
+    use crate::ServerConfig;
+
+    fn request(path: &str) -> Request {
+        Request {
+            method: "POST".into(),
+            path: path.into(),
+            body: b"payload".to_vec(),
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn mirroring() {
+        let (collect, received) = collector();
+        let backend = ServerConfig::new().unhandled(move |req| {
+            collect((req.path().to_string(), req.body().to_vec()));
+            Response::text("ignored")
+        });
+        let backend = crate::start(backend, "localhost:0").unwrap();
+
+        let mirror = Mirror::new(Client::new(backend.address()))
+            .sample(0.5)
+            .paths(["/api/{*rest}"]);
+        for path in ["/api/1", "/other", "/api/2", "/api/3", "/api/4"] {
+            mirror.copy(&request(path));
+        }
+
+        assert_eq!(
+            receive_exactly(&received, 2),
+            [
+                ("/api/2".to_string(), b"payload".to_vec()),
+                ("/api/4".to_string(), b"payload".to_vec()),
+            ]
+        );
+
+        backend.stop();
+    }
+}
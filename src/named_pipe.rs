@@ -0,0 +1,405 @@
+use crate::sync;
+use mio::Waker;
+use std::io::{self, Read, Write};
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{mem, ptr, thread};
+use windows_sys::Win32::Foundation::{
+    BOOL, ERROR_ACCESS_DENIED, ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_NO_DATA,
+    ERROR_OPERATION_ABORTED, ERROR_PIPE_CONNECTED, FALSE, HANDLE, INVALID_HANDLE_VALUE, TRUE,
+    WAIT_OBJECT_0,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX,
+};
+use windows_sys::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, PeekNamedPipe, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows_sys::Win32::System::Threading::{
+    CreateEventW, SetEvent, WaitForMultipleObjects, WaitForSingleObject, INFINITE,
+};
+use windows_sys::Win32::System::IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED};
+
+// How many bytes the pipe buffers in each direction
+const BUFFER_SIZE: u32 = 64 * 1024;
+
+// A named pipe that connections are accepted on.
+//
+// Every connection gets an instance of the pipe of its own. Named pipes can't be polled like
+// sockets, so a thread waits for a client to connect to the next instance, and wakes the event
+// loop up to accept it.
+#[derive(Debug)]
+pub struct PipeListener {
+    // The first instance, until the thread starts waiting on it
+    first: Option<OwnedHandle>,
+    name: Vec<u16>,
+    accepted: Receiver<io::Result<PipeStream>>,
+    sender: Option<Sender<io::Result<PipeStream>>>,
+    // Set when the listener is dropped, to stop the thread
+    stop: Arc<Event>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl PipeListener {
+    // Creates the pipe `name`. Fails with `AddrInUse` if it already exists, like sockets do.
+    pub fn bind(name: &str) -> io::Result<Self> {
+        let name: Vec<u16> = std::ffi::OsStr::new(name)
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+        let first = create_instance(&name, true).map_err(|err| match err.raw_os_error() {
+            Some(code) if code == ERROR_ACCESS_DENIED as i32 => io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "A pipe with this name already exists",
+            ),
+            _ => err,
+        })?;
+        let (sender, accepted) = channel();
+        Ok(Self {
+            first: Some(first),
+            name,
+            accepted,
+            sender: Some(sender),
+            stop: Arc::new(Event::new()?),
+            thread: None,
+        })
+    }
+
+    // Starts accepting connections, waking `waker` up whenever one is ready
+    pub fn start(&mut self, waker: Arc<Waker>) -> io::Result<()> {
+        let (Some(first), Some(sender)) = (self.first.take(), self.sender.take()) else {
+            return Ok(());
+        };
+        let name = self.name.clone();
+        let stop = self.stop.clone();
+        let thread = thread::Builder::new()
+            .name("vintage-pipe".into())
+            .spawn(move || accept_loop(first, &name, &stop, &sender, &waker))?;
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    // Returns a connection that was accepted, or `WouldBlock` if there is none
+    pub fn accept(&self) -> io::Result<PipeStream> {
+        match self.accepted.try_recv() {
+            Ok(accepted) => accepted,
+            Err(_) => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl Drop for PipeListener {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop.set() {
+            log::warn!(error:err = err; "Failed to stop accepting connections on a named pipe");
+            return;
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Hands the clients that connect to the pipe `name` to the event loop, until `stop` is set
+fn accept_loop(
+    first: OwnedHandle,
+    name: &[u16],
+    stop: &Event,
+    sender: &Sender<io::Result<PipeStream>>,
+    waker: &Waker,
+) {
+    let mut instance = first;
+    loop {
+        let accepted = match connect(&instance, stop) {
+            Ok(true) => Ok(PipeStream::new(instance)),
+            Ok(false) => return,
+            // The client went away before it was accepted
+            Err(err) if err.raw_os_error() == Some(ERROR_NO_DATA as i32) => {
+                Ok(PipeStream::new(instance))
+            }
+            Err(err) => Err(err),
+        };
+        let failed = accepted.is_err();
+        if sender.send(accepted).is_err() || waker.wake().is_err() || failed {
+            return;
+        }
+
+        // The next client connects to a new instance
+        instance = match create_instance(name, false) {
+            Ok(instance) => instance,
+            Err(err) => {
+                let _ = sender.send(Err(err));
+                let _ = waker.wake();
+                return;
+            }
+        };
+    }
+}
+
+// Waits for a client to connect to `instance`.
+// Returns `false` if `stop` was set first.
+fn connect(instance: &OwnedHandle, stop: &Event) -> io::Result<bool> {
+    let handle = raw(instance);
+    let connected = Event::new()?;
+    // SAFETY: all-zero is a valid `OVERLAPPED`
+    let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+    overlapped.hEvent = connected.raw();
+
+    // SAFETY: `overlapped` outlives the operation, which is over when this function returns
+    if unsafe { ConnectNamedPipe(handle, &mut overlapped) } == 0 {
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            // A client connected between the creation of the instance and now
+            Some(code) if code == ERROR_PIPE_CONNECTED as i32 => return Ok(true),
+            Some(code) if code == ERROR_IO_PENDING as i32 => {}
+            _ => return Err(err),
+        }
+    }
+
+    let events = [connected.raw(), stop.raw()];
+    // SAFETY: both events are open
+    let woken = unsafe { WaitForMultipleObjects(2, events.as_ptr(), FALSE, INFINITE) };
+    if woken != WAIT_OBJECT_0 {
+        let err = io::Error::last_os_error();
+        cancel(handle, &overlapped);
+        if woken == WAIT_OBJECT_0 + 1 {
+            return Ok(false);
+        }
+        return Err(err);
+    }
+    let mut transferred = 0;
+    // SAFETY: the operation is over, since its event is set
+    if unsafe { GetOverlappedResult(handle, &overlapped, &mut transferred, FALSE) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(true)
+}
+
+// Creates an instance of the pipe `name`, which must be the first one if `first` is set
+fn create_instance(name: &[u16], first: bool) -> io::Result<OwnedHandle> {
+    let mut open_mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED;
+    if first {
+        // Fails if another process created the pipe, which could then see the connections
+        open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+    }
+    // Pipes are a local transport. Clients on other machines use TCP.
+    let pipe_mode = PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS;
+    // SAFETY: `name` is null-terminated, and no security attributes are passed
+    let handle = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            open_mode,
+            pipe_mode,
+            PIPE_UNLIMITED_INSTANCES,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            ptr::null(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `handle` was just created, and nothing else owns it
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) })
+}
+
+// A connection accepted on a named pipe, in blocking mode.
+//
+// The pipe is opened for overlapped I/O, so that reads can time out like socket reads do. Clones
+// share the pipe and its settings, like duplicated sockets do.
+#[derive(Debug, Clone)]
+pub struct PipeStream(Arc<PipeState>);
+
+#[derive(Debug)]
+struct PipeState {
+    handle: OwnedHandle,
+    nonblocking: AtomicBool,
+    read_timeout: Mutex<Option<Duration>>,
+    write_timeout: Mutex<Option<Duration>>,
+}
+
+impl PipeStream {
+    // Wraps `handle`, which must have been opened with `FILE_FLAG_OVERLAPPED`
+    pub fn new(handle: OwnedHandle) -> Self {
+        Self(Arc::new(PipeState {
+            handle,
+            nonblocking: AtomicBool::new(false),
+            read_timeout: Mutex::new(None),
+            write_timeout: Mutex::new(None),
+        }))
+    }
+
+    // Makes reads fail with `WouldBlock` when no input arrived yet. Writes still block.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.0.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *sync::lock(&self.0.read_timeout) = timeout;
+        Ok(())
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *sync::lock(&self.0.write_timeout) = timeout;
+        Ok(())
+    }
+
+    // Copies the input that already arrived into `buf`, without consuming it.
+    // Returns 0 if the client closed the pipe, and fails with `WouldBlock` if nothing arrived.
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let mut peeked = 0;
+        // SAFETY: `buf` is valid for writes of `len` bytes
+        let result = unsafe {
+            PeekNamedPipe(
+                raw(&self.0.handle),
+                buf.as_mut_ptr().cast(),
+                len,
+                &mut peeked,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if result == 0 {
+            return end_of_input(io::Error::last_os_error());
+        }
+        match peeked {
+            0 if !buf.is_empty() => Err(io::ErrorKind::WouldBlock.into()),
+            peeked => Ok(peeked as usize),
+        }
+    }
+
+    // Runs the overlapped operation that `start` starts until it is over, cancelling it once
+    // `timeout` elapses. Returns how many bytes were transferred.
+    fn overlapped(
+        &self,
+        timeout: Option<Duration>,
+        start: impl FnOnce(HANDLE, *mut OVERLAPPED) -> BOOL,
+    ) -> io::Result<usize> {
+        let handle = raw(&self.0.handle);
+        let done = Event::new()?;
+        // SAFETY: all-zero is a valid `OVERLAPPED`
+        let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+        overlapped.hEvent = done.raw();
+
+        if start(handle, &mut overlapped) == 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(ERROR_IO_PENDING as i32) {
+                return Err(err);
+            }
+            let millis = timeout.map_or(INFINITE, |timeout| {
+                timeout.as_millis().clamp(1, u128::from(INFINITE - 1)) as u32
+            });
+            // SAFETY: the event is open
+            if unsafe { WaitForSingleObject(done.raw(), millis) } != WAIT_OBJECT_0 {
+                cancel(handle, &overlapped);
+            }
+        }
+
+        let mut transferred = 0;
+        // SAFETY: this waits for the operation to be over, so `overlapped` and the buffer of the
+        // operation outlive it
+        if unsafe { GetOverlappedResult(handle, &overlapped, &mut transferred, TRUE) } == 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(ERROR_OPERATION_ABORTED as i32) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            return Err(err);
+        }
+        Ok(transferred as usize)
+    }
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.nonblocking.load(Ordering::Relaxed) {
+            // Fails with `WouldBlock` if there is nothing to read
+            let mut byte = [0];
+            if self.peek(&mut byte)? == 0 {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let timeout = *sync::lock(&self.0.read_timeout);
+        self.overlapped(timeout, |handle, overlapped| {
+            // SAFETY: `buf` is valid for writes of `len` bytes until the operation is over
+            unsafe { ReadFile(handle, buf.as_mut_ptr(), len, ptr::null_mut(), overlapped) }
+        })
+        .or_else(end_of_input)
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let timeout = *sync::lock(&self.0.write_timeout);
+        self.overlapped(timeout, |handle, overlapped| {
+            // SAFETY: `buf` is valid for reads of `len` bytes until the operation is over
+            unsafe { WriteFile(handle, buf.as_ptr(), len, ptr::null_mut(), overlapped) }
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Reading from a pipe the client closed fails, where sockets report the end of the input
+fn end_of_input(err: io::Error) -> io::Result<usize> {
+    match err.raw_os_error() {
+        Some(code) if code == ERROR_BROKEN_PIPE as i32 => Ok(0),
+        _ => Err(err),
+    }
+}
+
+// Cancels the operation of `overlapped` on `handle`, and waits for it to be over
+fn cancel(handle: HANDLE, overlapped: &OVERLAPPED) {
+    let mut transferred = 0;
+    // SAFETY: `overlapped` is the one the operation was started with, and outlives it since
+    // this waits for the operation to be over
+    unsafe {
+        CancelIoEx(handle, overlapped);
+        GetOverlappedResult(handle, overlapped, &mut transferred, TRUE);
+    }
+}
+
+fn raw(handle: &OwnedHandle) -> HANDLE {
+    handle.as_raw_handle() as HANDLE
+}
+
+// A manual-reset event, closed when dropped
+#[derive(Debug)]
+struct Event(OwnedHandle);
+
+impl Event {
+    fn new() -> io::Result<Self> {
+        // SAFETY: no security attributes or name are passed
+        let handle = unsafe { CreateEventW(ptr::null(), TRUE, FALSE, ptr::null()) };
+        if handle == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `handle` was just created, and nothing else owns it
+        Ok(Self(unsafe {
+            OwnedHandle::from_raw_handle(handle as RawHandle)
+        }))
+    }
+
+    fn raw(&self) -> HANDLE {
+        raw(&self.0)
+    }
+
+    fn set(&self) -> io::Result<()> {
+        // SAFETY: the event is open
+        if unsafe { SetEvent(self.raw()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
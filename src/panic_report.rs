@@ -0,0 +1,124 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    // Whether a panic on this thread is caught by `catch()`
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    // Where the last caught panic of this thread happened
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// What is known about a panic of the server thread or of a handler
+///
+/// The backtrace is captured where the panic happened, regardless of `RUST_BACKTRACE`. It is
+/// [disabled](std::backtrace::BacktraceStatus::Disabled) when it could not be captured, e.g.
+/// because the application replaced the panic hook with [`std::panic::set_hook`] after the server
+/// started.
+#[derive(Debug)]
+pub struct PanicReport {
+    message: String,
+    backtrace: Backtrace,
+}
+
+impl PanicReport {
+    pub(crate) fn new(message: impl Into<String>, backtrace: Backtrace) -> Self {
+        Self {
+            message: message.into(),
+            backtrace,
+        }
+    }
+
+    // The report of a panic that was caught without a backtrace (e.g. by joining its thread)
+    pub(crate) fn from_payload(payload: &(dyn Any + Send)) -> Self {
+        Self::new(message_of(payload), Backtrace::disabled())
+    }
+
+    /// The panic message, or an empty string if the panic payload is not a string
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Where the panic happened
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+impl fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// Runs `f`, turning a panic into a report with the backtrace of the panic.
+//
+// The panic is still printed by the panic hook that was installed before, as usual.
+pub(crate) fn catch<T>(f: impl FnOnce() -> T) -> Result<T, PanicReport> {
+    install_hook();
+
+    // `catch()` calls can be nested (e.g. a handler running on the server thread)
+    let was_catching = CATCHING.replace(true);
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.set(was_catching);
+
+    let backtrace = BACKTRACE.take();
+    result.map_err(|payload| {
+        let mut report = PanicReport::from_payload(payload.as_ref());
+        if let Some(backtrace) = backtrace {
+            report.backtrace = backtrace;
+        }
+        report
+    })
+}
+
+// The hook captures backtraces for the threads that are in `catch()`, and defers to the previous
+// hook for everything else
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.get() {
+                BACKTRACE.set(Some(Backtrace::force_capture()));
+            }
+            previous(info);
+        }));
+    });
+}
+
+fn message_of(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::backtrace::BacktraceStatus;
+
+    #[test]
+    fn catch_panics() {
+        assert_eq!(catch(|| 42).unwrap(), 42);
+
+        let report = catch(|| panic!("oops {}", 1)).unwrap_err();
+        assert_eq!(report.message(), "oops 1");
+        assert_eq!(report.backtrace().status(), BacktraceStatus::Captured);
+
+        let report = catch(|| std::panic::panic_any(7)).unwrap_err();
+        assert_eq!(report.to_string(), "");
+
+        // The inner panic doesn't leak into the outer call
+        let nested = catch(|| catch(|| panic!("inner")).unwrap_err());
+        assert_eq!(nested.unwrap().message(), "inner");
+    }
+}
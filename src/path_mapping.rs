@@ -0,0 +1,158 @@
+use percent_encoding::percent_decode_str;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+type PathCallback = Arc<dyn Fn(&BTreeMap<String, String>) -> Option<String> + Send + Sync>;
+
+/// Determines the request path from the params sent by the web server
+///
+/// Web servers don't agree on how to communicate the request path.
+/// Nginx and Caddy are usually configured to send `PATH_INFO`, while lighttpd's default
+/// configuration sends `SCRIPT_NAME` and `REQUEST_URI` instead.
+///
+/// See [`ServerConfig::path_mapping`](crate::ServerConfig::path_mapping)
+#[derive(Clone, Default)]
+pub enum PathMapping {
+    /// Uses `PATH_INFO` if it is not empty.
+    /// Otherwise, falls back to the path of `REQUEST_URI`, then to `SCRIPT_NAME`.
+    #[default]
+    Auto,
+    /// Only uses `PATH_INFO`
+    PathInfo,
+    /// Uses the path of `REQUEST_URI`, without the query string and percent-decoded
+    RequestUri,
+    /// Uses `SCRIPT_NAME` followed by `PATH_INFO`.
+    /// Either of them can be missing, but not both.
+    ScriptName,
+    /// Uses a custom callback that receives all the params
+    Custom(PathCallback),
+}
+
+impl fmt::Debug for PathMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "Auto"),
+            Self::PathInfo => write!(f, "PathInfo"),
+            Self::RequestUri => write!(f, "RequestUri"),
+            Self::ScriptName => write!(f, "ScriptName"),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl PathMapping {
+    /// Creates a mapping that uses `callback` to determine the request path.
+    ///
+    /// Returning `None` rejects the request.
+    pub fn custom<C>(callback: C) -> Self
+    where
+        C: Fn(&BTreeMap<String, String>) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(callback))
+    }
+
+    // Returns the request path, or `None` if `params` do not contain enough information
+    pub(crate) fn resolve(&self, params: &BTreeMap<String, String>) -> Option<String> {
+        let path_info = || params.get("PATH_INFO").cloned();
+        let request_uri = || params.get("REQUEST_URI").map(|uri| request_uri_path(uri));
+        let script_name = || {
+            let script_name = params.get("SCRIPT_NAME");
+            let path_info = params.get("PATH_INFO");
+            if script_name.is_none() && path_info.is_none() {
+                return None;
+            }
+            let script_name = script_name.map(String::as_str).unwrap_or_default();
+            let path_info = path_info.map(String::as_str).unwrap_or_default();
+            Some(format!("{script_name}{path_info}"))
+        };
+
+        match self {
+            Self::Auto => path_info()
+                .filter(|p| !p.is_empty())
+                .or_else(request_uri)
+                .or_else(script_name),
+            Self::PathInfo => path_info(),
+            Self::RequestUri => request_uri(),
+            Self::ScriptName => script_name(),
+            Self::Custom(callback) => callback(params),
+        }
+    }
+}
+
+// Returns the decoded path of a `REQUEST_URI` value
+fn request_uri_path(uri: &str) -> String {
+    let path = uri.split_once('?').map(|(path, _)| path).unwrap_or(uri);
+    percent_decode_str(path).decode_utf8_lossy().into_owned()
+}
+
+// Returns the query string of a `REQUEST_URI` value
+pub(crate) fn request_uri_query(uri: &str) -> Option<&str> {
+    uri.split_once('?').map(|(_, query)| query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn auto() {
+        let mapping = PathMapping::Auto;
+
+        let nginx = params(&[("PATH_INFO", "/a"), ("REQUEST_URI", "/ignored")]);
+        assert_eq!(mapping.resolve(&nginx).as_deref(), Some("/a"));
+
+        let lighttpd = params(&[
+            ("SCRIPT_NAME", "/caf%C3%A9"),
+            ("REQUEST_URI", "/caf%C3%A9?q=1"),
+        ]);
+        assert_eq!(mapping.resolve(&lighttpd).as_deref(), Some("/café"));
+
+        let empty_path_info = params(&[("PATH_INFO", ""), ("REQUEST_URI", "/b")]);
+        assert_eq!(mapping.resolve(&empty_path_info).as_deref(), Some("/b"));
+
+        let script_name = params(&[("SCRIPT_NAME", "/app")]);
+        assert_eq!(mapping.resolve(&script_name).as_deref(), Some("/app"));
+
+        assert_eq!(mapping.resolve(&params(&[])), None);
+    }
+
+    #[test]
+    fn strategies() {
+        let all = params(&[
+            ("PATH_INFO", "/info"),
+            ("REQUEST_URI", "/app/info?x=y"),
+            ("SCRIPT_NAME", "/app"),
+        ]);
+
+        assert_eq!(
+            PathMapping::PathInfo.resolve(&all).as_deref(),
+            Some("/info")
+        );
+        assert_eq!(
+            PathMapping::RequestUri.resolve(&all).as_deref(),
+            Some("/app/info")
+        );
+        assert_eq!(
+            PathMapping::ScriptName.resolve(&all).as_deref(),
+            Some("/app/info")
+        );
+
+        let custom = PathMapping::custom(|params| params.get("X_PATH").cloned());
+        assert_eq!(custom.resolve(&all), None);
+        assert_eq!(
+            custom.resolve(&params(&[("X_PATH", "/x")])).as_deref(),
+            Some("/x")
+        );
+
+        let request_uri_only = params(&[("REQUEST_URI", "/b")]);
+        assert_eq!(PathMapping::PathInfo.resolve(&request_uri_only), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Helpers to give up the privileges of a server started as root
+//!
+//! These are meant to be called from a [`ServerConfig::before_serve`] callback, once the
+//! listening socket is bound.
+//!
+//! [`ServerConfig::before_serve`]: crate::ServerConfig::before_serve
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::path::Path;
+
+/// Changes the root directory of the process to `path`, then moves into it
+///
+/// Paths given to the file server and to asset directories are resolved relative to the new
+/// root afterwards.
+pub fn chroot(path: impl AsRef<Path>) -> io::Result<()> {
+    std::os::unix::fs::chroot(path)?;
+    std::env::set_current_dir("/")
+}
+
+/// A user account, as found in the user database (e.g. `/etc/passwd`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    name: CString,
+    /// The user ID
+    pub uid: u32,
+    /// The ID of the user's primary group
+    pub gid: u32,
+}
+
+impl User {
+    /// Looks up the user called `name`
+    ///
+    /// This should be done before calling [`chroot`], since the user database is usually not
+    /// reachable from the new root.
+    pub fn lookup(name: &str) -> io::Result<Self> {
+        let c_name = CString::new(name)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid user name"))?;
+
+        let mut buffer = vec![0u8; 1024];
+        loop {
+            // SAFETY: `passwd` is plain old data, for which all zeroes is a valid value
+            let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            // SAFETY: every pointer is valid for the duration of the call, and `buffer.len()` is
+            // the size of `buffer`
+            let code = unsafe {
+                libc::getpwnam_r(
+                    c_name.as_ptr(),
+                    &mut passwd,
+                    buffer.as_mut_ptr() as *mut libc::c_char,
+                    buffer.len(),
+                    &mut result,
+                )
+            };
+
+            if code == libc::ERANGE && buffer.len() < 1024 * 1024 {
+                buffer.resize(buffer.len() * 2, 0);
+                continue;
+            }
+            if code != 0 {
+                return Err(io::Error::from_raw_os_error(code));
+            }
+            if result.is_null() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No user named '{name}'"),
+                ));
+            }
+
+            // SAFETY: on success, `pw_name` points to a nul-terminated string in `buffer`
+            let name = unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned();
+            return Ok(Self {
+                name,
+                uid: passwd.pw_uid,
+                gid: passwd.pw_gid,
+            });
+        }
+    }
+
+    /// Switches the process to this user, its primary group, and its supplementary groups
+    ///
+    /// This is irreversible: the process can't get its privileges back afterwards.
+    pub fn switch(&self) -> io::Result<()> {
+        // The groups must be changed first, since changing the user drops the privilege to do so.
+        // SAFETY: `name` is a nul-terminated string
+        let result = unsafe { libc::initgroups(self.name.as_ptr(), self.gid as _) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        set_ids(self.uid, self.gid)
+    }
+}
+
+/// Switches the process to user `uid` and group `gid`, dropping supplementary groups
+///
+/// This is irreversible: the process can't get its privileges back afterwards.
+pub fn switch_ids(uid: u32, gid: u32) -> io::Result<()> {
+    // Only root can change supplementary groups
+    // SAFETY: an empty list is never read from
+    if unsafe { libc::getuid() == 0 && libc::setgroups(0, std::ptr::null()) != 0 } {
+        return Err(io::Error::last_os_error());
+    }
+    set_ids(uid, gid)
+}
+
+fn set_ids(uid: u32, gid: u32) -> io::Result<()> {
+    // SAFETY: these calls only take plain integers
+    unsafe {
+        if libc::setgid(gid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::setuid(uid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let root = User::lookup("root").unwrap();
+        assert_eq!(root.uid, 0);
+
+        let err = User::lookup("vintage-no-such-user").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
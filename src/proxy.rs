@@ -0,0 +1,304 @@
+use crate::client::{Client, ClientError};
+use crate::context::{Request, Response};
+use crate::status;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// How a [`Proxy`] picks the upstream that receives a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Balance {
+    /// Upstreams take turns
+    #[default]
+    RoundRobin,
+    /// The upstream with the fewest in-flight requests is picked.
+    /// Ties are broken in round-robin order.
+    LeastConnections,
+}
+
+#[derive(Debug)]
+struct Upstream {
+    client: Client,
+    in_flight: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct ProxyInner {
+    upstreams: Vec<Upstream>,
+    balance: Balance,
+    retries: Option<usize>,
+    next: AtomicUsize,
+}
+
+/// Forwards requests to one of several FastCGI servers
+///
+/// This lets vintage act as a small FastCGI load balancer.
+/// When an upstream cannot be connected to, or reports that it is overloaded, the request is
+/// retried on another one.
+/// Other failures are not retried, since the upstream might have already acted on the request.
+///
+/// ```no_run
+/// use vintage::{Balance, Client, Proxy, ServerConfig};
+///
+/// let proxy = Proxy::new()
+///     .upstream(Client::tcp("10.0.0.1:9000").unwrap())
+///     .upstream(Client::tcp("10.0.0.2:9000").unwrap())
+///     .balance(Balance::LeastConnections);
+///
+/// let config = ServerConfig::new().proxy(proxy);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Proxy {
+    inner: Arc<ProxyInner>,
+}
+
+impl Proxy {
+    /// Creates a proxy without any upstreams
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an upstream that requests can be forwarded to
+    ///
+    /// # Panics
+    ///
+    /// Panics if the proxy was cloned
+    pub fn upstream(mut self, client: Client) -> Self {
+        self.inner_mut().upstreams.push(Upstream {
+            client,
+            in_flight: AtomicUsize::new(0),
+        });
+        self
+    }
+
+    /// Sets how upstreams are picked. The default is [`Balance::RoundRobin`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the proxy was cloned
+    pub fn balance(mut self, balance: Balance) -> Self {
+        self.inner_mut().balance = balance;
+        self
+    }
+
+    /// Sets how many times a request is retried on another upstream.
+    /// By default, every upstream is tried once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the proxy was cloned
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.inner_mut().retries = Some(retries);
+        self
+    }
+
+    fn inner_mut(&mut self) -> &mut ProxyInner {
+        Arc::get_mut(&mut self.inner).expect("proxy should not be configured after being cloned")
+    }
+
+    /// Forwards `req` to one of the upstreams, and returns its response
+    ///
+    /// Returns the error of the last attempt if every attempt failed.
+    pub fn send(&self, req: &Request) -> Result<Response, ClientError> {
+        let upstreams = &self.inner.upstreams;
+        let attempts = match self.inner.retries {
+            Some(retries) => retries.saturating_add(1),
+            None => upstreams.len(),
+        };
+
+        let mut tried = vec![false; upstreams.len()];
+        let mut last_error = ClientError::Rejected;
+
+        for _ in 0..attempts {
+            // Once every upstream has been tried, start over
+            if tried.iter().all(|t| *t) {
+                tried.fill(false);
+            }
+
+            let Some(index) = self.pick(&tried) else {
+                break;
+            };
+            tried[index] = true;
+
+            let upstream = &upstreams[index];
+            upstream.in_flight.fetch_add(1, Ordering::SeqCst);
+            let result = upstream.client.send(req);
+            upstream.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            match result {
+                Err(err @ (ClientError::Connect(_) | ClientError::Overloaded)) => {
+                    log::warn!(error:err = err, backend:% = upstream.client.backend(); "Upstream unavailable. Trying another one");
+                    last_error = err;
+                }
+                result => return result,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Forwards `req` to one of the upstreams
+    ///
+    /// Failures are answered with a `503 Service Unavailable` if the upstreams are overloaded, and
+    /// a `502 Bad Gateway` otherwise.
+    pub fn respond(&self, req: &Request) -> Response {
+        match self.send(req) {
+            Ok(response) => response,
+            Err(err) => {
+                log::error!(error:err = err; "Failed to proxy request");
+                let status = match err {
+                    ClientError::Overloaded => status::SERVICE_UNAVAILABLE,
+                    _ => status::BAD_GATEWAY,
+                };
+                Response::default().set_status(status)
+            }
+        }
+    }
+
+    // Picks one of the upstreams that have not been tried yet
+    fn pick(&self, tried: &[bool]) -> Option<usize> {
+        let upstreams = &self.inner.upstreams;
+        if upstreams.is_empty() {
+            return None;
+        }
+
+        let start = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        let mut candidates = (0..upstreams.len())
+            .map(|offset| (start + offset) % upstreams.len())
+            .filter(|i| !tried[*i]);
+
+        match self.inner.balance {
+            Balance::RoundRobin => candidates.next(),
+            Balance::LeastConnections => {
+                candidates.min_by_key(|i| upstreams[*i].in_flight.load(Ordering::SeqCst))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use crate::record::{EndRequest, ProtocolStatus, Record};
+    use crate::ServerConfig;
+    use std::net::{SocketAddr, TcpListener};
+
+    fn request() -> Request {
+        Request {
+            method: "GET".into(),
+            path: "/".into(),
+            ..Request::default()
+        }
+    }
+
+    fn upstream(name: &'static str) -> crate::ServerHandle {
+        let config = ServerConfig::new().unhandled(move |_req| Response::text(name));
+        crate::start(config, "localhost:0").unwrap()
+    }
+
+    fn unused_address() -> SocketAddr {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    // An upstream that answers every request with `FCGI_OVERLOADED`
+    fn overloaded_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = mio::net::TcpStream::from_std(stream.unwrap());
+                let mut conn = Connection::try_from(stream).unwrap();
+                let _ = conn.read_record();
+                let end = EndRequest::new(0, ProtocolStatus::Overloaded);
+                let _ = conn.write_record(&Record::from(end));
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn round_robin() {
+        let a = upstream("a");
+        let b = upstream("b");
+        let proxy = Proxy::new()
+            .upstream(Client::new(a.address()))
+            .upstream(Client::new(b.address()));
+
+        let bodies: Vec<_> = (0..4)
+            .map(|_| proxy.send(&request()).unwrap().body)
+            .collect();
+        assert_eq!(bodies, [b"a", b"b", b"a", b"b"]);
+
+        a.stop();
+        b.stop();
+    }
+
+    #[test]
+    fn least_connections() {
+        let a = upstream("a");
+        let b = upstream("b");
+        let proxy = Proxy::new()
+            .upstream(Client::new(a.address()))
+            .upstream(Client::new(b.address()))
+            .balance(Balance::LeastConnections);
+
+        // Pretend the first upstream is busy
+        proxy.inner.upstreams[0]
+            .in_flight
+            .store(1, Ordering::SeqCst);
+
+        for _ in 0..3 {
+            assert_eq!(proxy.send(&request()).unwrap().body, b"b");
+        }
+
+        a.stop();
+        b.stop();
+    }
+
+    #[test]
+    fn failover() {
+        let b = upstream("b");
+        let proxy = Proxy::new()
+            .upstream(Client::new(unused_address()))
+            .upstream(Client::new(b.address()));
+
+        for _ in 0..3 {
+            assert_eq!(proxy.send(&request()).unwrap().body, b"b");
+        }
+
+        b.stop();
+    }
+
+    #[test]
+    fn overloaded() {
+        let b = upstream("b");
+        let proxy = Proxy::new()
+            .upstream(Client::new(overloaded_upstream()))
+            .upstream(Client::new(b.address()));
+
+        for _ in 0..3 {
+            assert_eq!(proxy.send(&request()).unwrap().body, b"b");
+        }
+
+        let proxy = Proxy::new().upstream(Client::new(overloaded_upstream()));
+        let response = proxy.respond(&request());
+        assert_eq!(response.status, status::SERVICE_UNAVAILABLE);
+
+        b.stop();
+    }
+
+    #[test]
+    fn all_upstreams_down() {
+        let proxy = Proxy::new()
+            .upstream(Client::new(unused_address()))
+            .upstream(Client::new(unused_address()))
+            .retries(5);
+
+        let response = proxy.respond(&request());
+        assert_eq!(response.status, status::BAD_GATEWAY);
+
+        let response = Proxy::new().respond(&request());
+        assert_eq!(response.status, status::BAD_GATEWAY);
+    }
+}
@@ -0,0 +1,114 @@
+//! Helpers for adding query parameters to URLs (e.g. the `Location` of a redirect)
+//!
+//! Concatenating query strings by hand tends to go wrong: a second `?` gets added to a URL that
+//! already has a query, or the query ends up after the `#fragment`. These helpers take care of it.
+//!
+//! ```
+//! use vintage::query;
+//!
+//! let url = query::append("/search#results", "q", "fish & chips");
+//! assert_eq!(url, "/search?q=fish%20%26%20chips#results");
+//!
+//! let url = query::merge("/search?page=2", "q=fish&page=1");
+//! assert_eq!(url, "/search?page=2&q=fish");
+//! ```
+
+use crate::form;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
+
+// Characters that are percent-encoded in the names and values of query parameters.
+// These are the RFC 3986 unreserved characters.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// Characters that are percent-encoded in query strings that are already encoded, so that raw
+// query strings (e.g. with line breaks) can't break out of the URL. `%` is left alone.
+const ENCODED_QUERY: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'<').add(b'>');
+
+/// Adds the parameter `key=value` to the query of `url`, percent-encoding both
+///
+/// Existing parameters are kept, even those with the same name.
+pub fn append(url: &str, key: &str, value: &str) -> String {
+    let key = utf8_percent_encode(key, COMPONENT);
+    let value = utf8_percent_encode(value, COMPONENT);
+    add_pairs(url, &[format!("{key}={value}")])
+}
+
+/// Adds the parameters of `query` (an already encoded query string, like
+/// [`Request::query_string`](crate::Request::query_string)) to the query of `url`
+///
+/// Parameters of `query` that `url` already has are left out, so the ones in `url` win.
+pub fn merge(url: &str, query: &str) -> String {
+    let (without_fragment, _) = split_fragment(url);
+    let existing: Vec<String> = without_fragment
+        .split_once('?')
+        .map(|(_, existing)| form::parse(existing).map(|(name, _)| name).collect())
+        .unwrap_or_default();
+
+    let pairs: Vec<String> = query
+        .split('&')
+        .filter(|pair| {
+            let decoded = form::parse(pair).next();
+            decoded.is_some_and(|(name, _)| !existing.contains(&name))
+        })
+        .map(|pair| utf8_percent_encode(pair, ENCODED_QUERY).to_string())
+        .collect();
+    add_pairs(url, &pairs)
+}
+
+// Adds encoded `name=value` pairs to the query of `url`, before its fragment
+fn add_pairs(url: &str, pairs: &[String]) -> String {
+    if pairs.is_empty() {
+        return url.to_string();
+    }
+
+    let (url, fragment) = split_fragment(url);
+    let separator = match url.split_once('?') {
+        None => "?",
+        Some((_, "")) => "",
+        Some((_, query)) if query.ends_with('&') => "",
+        Some(_) => "&",
+    };
+    let fragment = fragment.map(|f| format!("#{f}")).unwrap_or_default();
+    format!("{url}{separator}{}{fragment}", pairs.join("&"))
+}
+
+fn split_fragment(url: &str) -> (&str, Option<&str>) {
+    match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appending() {
+        assert_eq!(append("/a", "k", "v"), "/a?k=v");
+        assert_eq!(append("/a?", "k", "v"), "/a?k=v");
+        assert_eq!(append("/a?x=1", "k", "v"), "/a?x=1&k=v");
+        assert_eq!(append("/a?x=1&", "k", "v"), "/a?x=1&k=v");
+        assert_eq!(append("/a?k=1#top", "k", "v"), "/a?k=1&k=v#top");
+        assert_eq!(append("/a", "a b", "é/?&="), "/a?a%20b=%C3%A9%2F%3F%26%3D");
+    }
+
+    #[test]
+    fn merging() {
+        assert_eq!(merge("/a", ""), "/a");
+        assert_eq!(merge("/a", "x=1&y=%20"), "/a?x=1&y=%20");
+        assert_eq!(merge("/a?x=2", "x=1&y=2"), "/a?x=2&y=2");
+        // Names are compared decoded
+        assert_eq!(merge("/a?a+b=2", "a%20b=1&&c"), "/a?a+b=2&c");
+        assert_eq!(merge("/a#top", "x=1"), "/a?x=1#top");
+        // Raw query strings stay in the URL
+        assert_eq!(
+            merge("/a", "x=1\r\nSet-Cookie: a=b&y=#"),
+            "/a?x=1%0D%0ASet-Cookie:%20a=b&y=%23"
+        );
+    }
+}
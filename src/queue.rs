@@ -0,0 +1,180 @@
+use crate::connection::Connection;
+use crate::server_config::ServerConfig;
+use crate::sync;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// What happens to a connection accepted while the request queue is full
+///
+/// See [`ServerConfig::request_queue`](crate::ServerConfig::request_queue)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Stops accepting connections until there is room in the queue.
+    /// New connections wait in the listen backlog instead.
+    #[default]
+    Block,
+    /// Responds to the new connection with a `503 Service Unavailable`
+    ShedNewest,
+    /// Responds to the connection that has been waiting the longest with a
+    /// `503 Service Unavailable`, and queues the new one in its place
+    ShedOldest,
+}
+
+// A connection waiting for a worker thread
+pub struct Queued {
+    pub connection: Connection,
+    // The configuration of the listener that accepted the connection
+    pub config: ServerConfig,
+    pub accepted_at: Instant,
+}
+
+// The connections accepted by an event loop, waiting for a worker thread
+pub struct RequestQueue {
+    entries: Mutex<VecDeque<Queued>>,
+    capacity: Option<usize>,
+    policy: QueuePolicy,
+}
+
+impl RequestQueue {
+    pub fn new(capacity: Option<usize>, policy: QueuePolicy) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::new()),
+            capacity,
+            policy,
+        }
+    }
+
+    // Adds a connection to the back of the queue, applying the policy if the queue is full.
+    //
+    // Returns the connection that was shed, if any. Depending on the policy, it is either
+    // `queued` itself or the oldest connection in the queue.
+    //
+    // With `QueuePolicy::Block`, connections are only accepted when the queue `accepts()` them.
+    pub fn push(&self, queued: Queued) -> Option<Queued> {
+        let mut entries = self.lock();
+
+        let Some(capacity) = self.capacity else {
+            entries.push_back(queued);
+            return None;
+        };
+
+        if entries.len() < capacity {
+            entries.push_back(queued);
+            return None;
+        }
+
+        match self.policy {
+            QueuePolicy::Block => {
+                entries.push_back(queued);
+                None
+            }
+            QueuePolicy::ShedNewest => Some(queued),
+            QueuePolicy::ShedOldest => {
+                let oldest = entries.pop_front();
+                entries.push_back(queued);
+                oldest
+            }
+        }
+    }
+
+    // Takes the connection at the front of the queue.
+    //
+    // Calls `made_room` if the queue stopped new connections from being accepted, and no longer
+    // does.
+    pub fn pop(&self, made_room: impl FnOnce()) -> Option<Queued> {
+        let mut entries = self.lock();
+        let was_blocking = self.is_blocking(&entries);
+        let queued = entries.pop_front();
+        let blocking = self.is_blocking(&entries);
+        drop(entries);
+
+        if was_blocking && !blocking {
+            made_room();
+        }
+        queued
+    }
+
+    // Whether new connections can be accepted. They can't while the queue is full, if the
+    // policy is to wait for room.
+    pub fn accepts(&self) -> bool {
+        !self.is_blocking(&self.lock())
+    }
+
+    fn is_blocking(&self, entries: &VecDeque<Queued>) -> bool {
+        self.policy == QueuePolicy::Block
+            && self
+                .capacity
+                .is_some_and(|capacity| entries.len() >= capacity)
+    }
+
+    pub fn policy(&self) -> QueuePolicy {
+        self.policy
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Queued>> {
+        sync::lock(&self.entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn queued(id: u8) -> Queued {
+        Queued {
+            connection: Connection::Test(VecDeque::from([id])),
+            config: ServerConfig::default(),
+            accepted_at: Instant::now(),
+        }
+    }
+
+    fn id(queued: Queued) -> u8 {
+        let Connection::Test(bytes) = queued.connection else {
+            unreachable!()
+        };
+        bytes[0]
+    }
+
+    #[test]
+    fn shedding() {
+        let queue = RequestQueue::new(Some(2), QueuePolicy::ShedNewest);
+        assert!(queue.push(queued(1)).is_none());
+        assert!(queue.push(queued(2)).is_none());
+        assert_eq!(queue.push(queued(3)).map(id), Some(3));
+        assert_eq!(queue.pop(|| {}).map(id), Some(1));
+
+        let queue = RequestQueue::new(Some(2), QueuePolicy::ShedOldest);
+        assert!(queue.push(queued(1)).is_none());
+        assert!(queue.push(queued(2)).is_none());
+        assert_eq!(queue.push(queued(3)).map(id), Some(1));
+        assert_eq!(queue.pop(|| {}).map(id), Some(2));
+        assert_eq!(queue.pop(|| {}).map(id), Some(3));
+        assert!(queue.pop(|| {}).is_none());
+    }
+
+    #[test]
+    fn blocking() {
+        let queue = RequestQueue::new(Some(1), QueuePolicy::Block);
+        assert!(queue.accepts());
+        assert!(queue.push(queued(1)).is_none());
+        assert!(!queue.accepts());
+
+        let made_room = Cell::new(false);
+        assert_eq!(queue.pop(|| made_room.set(true)).map(id), Some(1));
+        assert!(made_room.get());
+        assert!(queue.accepts());
+
+        assert!(queue.push(queued(2)).is_none());
+        assert!(queue.pop(|| {}).is_some());
+        // Popping from a queue that accepts connections doesn't make room for anything
+        assert!(queue.pop(|| panic!("no room was made")).is_none());
+
+        // The other policies always accept connections
+        let queue = RequestQueue::new(Some(1), QueuePolicy::ShedNewest);
+        assert!(queue.push(queued(1)).is_none());
+        assert!(queue.accepts());
+        assert!(queue.pop(|| panic!("no room was made")).is_some());
+    }
+}
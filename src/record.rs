@@ -4,7 +4,7 @@ mod data;
 mod end_request;
 mod get_values;
 mod get_values_result;
-mod pairs;
+pub mod pairs;
 mod params;
 mod protocol_status;
 mod role;
@@ -22,8 +22,9 @@ pub use get_values::GetValues;
 pub use get_values_result::GetValuesResult;
 pub use params::Params;
 pub use protocol_status::ProtocolStatus;
-#[cfg(test)]
 pub use role::Role;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
 use std::io::{self, Write};
 pub use stderr::Stderr;
 pub use stdin::Stdin;
@@ -45,6 +46,19 @@ pub const FCGI_UNKNOWN_TYPE: u8 = 11;
 pub const MANAGEMENT_RECORD_TYPES: [u8; 3] =
     [FCGI_GET_VALUES, FCGI_GET_VALUES_RESULT, FCGI_UNKNOWN_TYPE];
 
+// Params worth showing in a record summary
+const KEY_PARAMS: [&str; 6] = [
+    "REQUEST_METHOD",
+    "REQUEST_URI",
+    "SCRIPT_NAME",
+    "PATH_INFO",
+    "QUERY_STRING",
+    "CONTENT_LENGTH",
+];
+
+// How much of a payload is shown in a record summary
+const HEXDUMP_LIMIT: usize = 64;
+
 pub const DISCRETE_RECORD_TYPES: [u8; 6] = [
     FCGI_GET_VALUES,
     FCGI_GET_VALUES_RESULT,
@@ -58,6 +72,7 @@ pub const DISCRETE_RECORD_TYPES: [u8; 6] = [
 ///
 /// All data that flows between FastCGI client and server is carried in records. The variant used
 /// communicates the intent of the message
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Record {
     GetValues(GetValues),
@@ -111,6 +126,50 @@ impl Record {
         Ok(record)
     }
 
+    /// Returns a human-readable description of the record.
+    ///
+    /// Stream payloads are shown as a hexdump of their first few bytes.
+    pub fn summary(&self) -> String {
+        let pairs = |pairs: &BTreeMap<String, String>, only: &[&str]| {
+            pairs
+                .iter()
+                .filter(|(key, _)| only.is_empty() || only.contains(&key.as_str()))
+                .map(|(key, value)| format!(" {key}={value:?}"))
+                .collect::<String>()
+        };
+        let stream =
+            |name: &str, bytes: &[u8]| format!("{name} {} bytes{}", bytes.len(), hexdump(bytes));
+
+        match self {
+            Self::GetValues(r) => {
+                let names: Vec<_> = r.get_variables().collect();
+                format!("GET_VALUES {}", names.join(" "))
+            }
+            Self::GetValuesResult(r) => format!("GET_VALUES_RESULT{}", pairs(r.values(), &[])),
+            Self::BeginRequest(r) => format!(
+                "BEGIN_REQUEST role={:?} keep_conn={}",
+                r.role(),
+                r.keep_alive()
+            ),
+            Self::Params(r) => format!(
+                "PARAMS {} pairs{}",
+                r.pairs().len(),
+                pairs(r.pairs(), &KEY_PARAMS)
+            ),
+            Self::Stdin(r) => stream("STDIN", &r.0),
+            Self::Data(r) => stream("DATA", &r.0),
+            Self::Stdout(r) => stream("STDOUT", &r.0),
+            Self::Stderr(r) => stream("STDERR", &r.0),
+            Self::AbortRequest(_) => "ABORT_REQUEST".to_string(),
+            Self::EndRequest(r) => format!(
+                "END_REQUEST app_status={} protocol_status={:?}",
+                r.exit_code(),
+                r.protocol_status()
+            ),
+            Self::UnknownType(r) => format!("UNKNOWN_TYPE type={}", r.0),
+        }
+    }
+
     pub fn write_bytes<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
         match self {
             Self::GetValues(r) => r.write_record_bytes(writer),
@@ -128,6 +187,33 @@ impl Record {
     }
 }
 
+/// Formats the first few bytes of `bytes` like `hexdump -C` does, one indented line per 16 bytes.
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (i, line) in bytes.chunks(16).take(HEXDUMP_LIMIT / 16).enumerate() {
+        let _ = write!(dump, "\n  {:04x} ", i * 16);
+        for byte in line {
+            let _ = write!(dump, " {byte:02x}");
+        }
+        let padding = (16 - line.len()) * 3;
+        let ascii: String = line
+            .iter()
+            .map(|b| match b {
+                0x20..=0x7e => *b as char,
+                _ => '.',
+            })
+            .collect();
+        let _ = write!(dump, "{:padding$}  |{ascii}|", "");
+    }
+
+    if bytes.len() > HEXDUMP_LIMIT {
+        let _ = write!(dump, "\n  ... {} more bytes", bytes.len() - HEXDUMP_LIMIT);
+    }
+
+    dump
+}
+
 // This just make it easier to work with the inner record types
 macro_rules!  from_impls {
     ($($t:ident),*) => {
@@ -154,3 +240,121 @@ from_impls! {
     EndRequest,
     UnknownType
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::{btree_map, vec};
+    use proptest::prelude::*;
+
+    fn any_pairs() -> impl Strategy<Value = BTreeMap<String, String>> {
+        btree_map(".{0,200}", ".{0,200}", 0..8)
+    }
+
+    fn any_protocol_status() -> impl Strategy<Value = ProtocolStatus> {
+        prop_oneof![
+            Just(ProtocolStatus::RequestComplete),
+            Just(ProtocolStatus::MultiplexingUnsupported),
+            Just(ProtocolStatus::Overloaded),
+            Just(ProtocolStatus::UnknownRole),
+        ]
+    }
+
+    fn any_record() -> impl Strategy<Value = Record> {
+        let bytes = || vec(any::<u8>(), 0..512);
+        prop_oneof![
+            any_pairs().prop_map(|pairs| {
+                let mut record = GetValues::default();
+                for name in pairs.into_keys() {
+                    record = record.add(name);
+                }
+                record.into()
+            }),
+            any_pairs().prop_map(|pairs| {
+                let mut record = GetValuesResult::default();
+                for (key, value) in pairs {
+                    record = record.add(key, value);
+                }
+                record.into()
+            }),
+            // Only the responder role is supported, so other roles are not expected to round trip
+            any::<bool>().prop_map(|keep| BeginRequest::new(Role::Responder, keep).into()),
+            any_pairs().prop_map(|pairs| {
+                let mut record = Params::default();
+                for (key, value) in pairs {
+                    record = record.add(key, value);
+                }
+                record.into()
+            }),
+            bytes().prop_map(|b| Stdin(b).into()),
+            bytes().prop_map(|b| Data(b).into()),
+            bytes().prop_map(|b| Stdout(b).into()),
+            bytes().prop_map(|b| Stderr(b).into()),
+            Just(AbortRequest.into()),
+            (any::<u32>(), any_protocol_status())
+                .prop_map(|(code, status)| EndRequest::new(code, status).into()),
+            any::<u8>().prop_map(|t| UnknownType(t).into()),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn records_round_trip(record in any_record()) {
+            let mut payload = vec![];
+            record.write_bytes(&mut payload).unwrap();
+            let parsed = Record::from_bytes(record.type_id(), payload).unwrap();
+            prop_assert_eq!(parsed, record);
+        }
+
+        #[test]
+        fn parsing_never_panics(type_id in any::<u8>(), payload in vec(any::<u8>(), 0..1024)) {
+            let _ = Record::from_bytes(type_id, payload);
+        }
+
+        #[test]
+        fn pair_parsing_never_panics(payload in vec(any::<u8>(), 0..1024)) {
+            let _ = pairs::from_record_bytes(payload);
+        }
+    }
+
+    #[test]
+    fn pair_lengths_are_bounded_by_the_payload() {
+        // Claims a name of 2GiB, followed by nothing
+        let payload = vec![0xFF, 0xFF, 0xFF, 0xFF, 0];
+        assert!(pairs::from_record_bytes(payload).is_err());
+    }
+
+    #[test]
+    fn summaries() {
+        let params = Params::default()
+            .add("REQUEST_METHOD", "GET")
+            .add("PATH_INFO", "/")
+            .add("HTTP_COOKIE", "secret");
+        assert_eq!(
+            Record::from(params).summary(),
+            r#"PARAMS 3 pairs PATH_INFO="/" REQUEST_METHOD="GET""#
+        );
+
+        assert_eq!(
+            Record::from(BeginRequest::new(Role::Responder, false)).summary(),
+            "BEGIN_REQUEST role=Responder keep_conn=false"
+        );
+
+        assert_eq!(
+            Record::from(EndRequest::new(1, ProtocolStatus::Overloaded)).summary(),
+            "END_REQUEST app_status=1 protocol_status=Overloaded"
+        );
+
+        assert_eq!(
+            Record::from(Stdin(b"hello\n".to_vec())).summary(),
+            "STDIN 6 bytes\n  0000  68 65 6c 6c 6f 0a                                |hello.|"
+        );
+    }
+
+    #[test]
+    fn hexdump_is_truncated() {
+        let dump = hexdump(&[b'a'; 100]);
+        assert_eq!(dump.lines().count(), 6);
+        assert!(dump.ends_with("... 36 more bytes"));
+    }
+}
@@ -10,6 +10,7 @@ use std::io::{self, Write};
 /// Note: This record is widely un-implemented in most FastCGI clients, so it is very very
 /// unlikely to be ever received. Nevertheless, we are nothing if not thorough, so it is still
 /// defined here.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AbortRequest;
 
@@ -7,6 +7,7 @@ const MASK_FCGI_KEEP_CONN: u8 = 0x01;
 /// A FastCGI `FCGI_BEGIN_REQUEST` record
 ///
 /// The FastCGI client sends a FCGI_BEGIN_REQUEST record to start a request.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BeginRequest {
     role: Role,
@@ -33,11 +34,14 @@ impl BeginRequest {
         writer.write_all(&[self.flags, 0, 0, 0, 0, 0])
     }
 
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
     pub fn keep_alive(&self) -> bool {
         self.flags & MASK_FCGI_KEEP_CONN == 1
     }
 
-    #[cfg(test)]
     pub fn new(role: Role, keep_alive: bool) -> Self {
         let flags = if keep_alive { 1 } else { 0 };
         Self { role, flags }
@@ -4,6 +4,7 @@ use std::io::{self, Write};
 // A FastCGI `FCGI_DATA` record
 //
 // Similar to `FCGI_STDIN`, but does not get used as part of the `Responder` flow.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Data(pub Vec<u8>);
 
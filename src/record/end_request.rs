@@ -6,6 +6,7 @@ use std::io::{self, Write};
 ///
 /// This record is used by a FastCGI server to indicate a request is complete, either because it
 /// has been processed successfully or because it has been rejected.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct EndRequest {
     exit_code: u32,
@@ -32,6 +33,14 @@ impl EndRequest {
         writer.write_all(&[0, 0, 0])
     }
 
+    pub fn exit_code(&self) -> u32 {
+        self.exit_code
+    }
+
+    pub fn protocol_status(&self) -> ProtocolStatus {
+        self.protocol_status
+    }
+
     pub fn new(exit_code: u32, status: ProtocolStatus) -> Self {
         Self {
             exit_code,
@@ -7,6 +7,7 @@ use std::io::{self, Write};
 ///
 // A FastCGI client can query specific variables within a FastCGI server using this record type.
 // It is designed to allow querying an open-ended set of variables.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct GetValues {
     names: BTreeMap<String, String>,
@@ -6,6 +6,7 @@ use std::io::{self, Write};
 /// A FastCGI `FCGI_GET_VALUES_RESULT` record
 ///
 /// This is sent by a FastCGI server in response to a request with a `GetValues` record.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct GetValuesResult {
     values: BTreeMap<String, String>,
@@ -22,6 +23,10 @@ impl GetValuesResult {
         pairs::to_record_bytes(&self.values, writer)
     }
 
+    pub fn values(&self) -> &BTreeMap<String, String> {
+        &self.values
+    }
+
     pub fn add<K, V>(mut self, key: K, value: V) -> Self
     where
         K: std::fmt::Display,
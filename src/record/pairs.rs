@@ -46,6 +46,53 @@ fn write_pair_len<W: Write>((key, value): (&str, &str), writer: &mut W) -> Resul
     Ok(())
 }
 
+// Bounds the name-value pairs of a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairLimits {
+    pub max_pairs: usize,
+    pub max_name_len: usize,
+    pub max_value_len: usize,
+}
+
+impl Default for PairLimits {
+    fn default() -> Self {
+        Self {
+            max_pairs: usize::MAX,
+            max_name_len: usize::MAX,
+            max_value_len: usize::MAX,
+        }
+    }
+}
+
+// Checks the pairs encoded in `bytes` against `limits`, without decoding them.
+//
+// Malformed payloads are left for `from_record_bytes` to report.
+pub fn check_limits(bytes: &[u8], limits: &PairLimits) -> Result<(), Error> {
+    let mut cursor = Cursor::new(bytes);
+    let mut pairs = 0;
+
+    while (cursor.position() as usize) < bytes.len() {
+        let (Ok(name_len), Ok(value_len)) =
+            (read_pair_len(&mut cursor), read_pair_len(&mut cursor))
+        else {
+            return Ok(());
+        };
+
+        pairs += 1;
+        if pairs > limits.max_pairs {
+            return Err(Error::ParamLimitExceeded("number of params"));
+        }
+        if name_len as usize > limits.max_name_len {
+            return Err(Error::ParamLimitExceeded("param name size"));
+        }
+        if value_len as usize > limits.max_value_len {
+            return Err(Error::ParamLimitExceeded("param value size"));
+        }
+        cursor.set_position(cursor.position() + name_len as u64 + value_len as u64);
+    }
+    Ok(())
+}
+
 // FastCGI transmits a name-value pair as the length of the name, followed by the length of the
 // value, followed by the name, followed by the value. Lengths of 127 bytes and less can be
 // encoded in one byte, while longer lengths are always encoded in four bytes:
@@ -64,6 +111,12 @@ pub fn from_record_bytes(bytes: Vec<u8>) -> Result<Pairs, Error> {
         let name_len = read_pair_len(&mut cursor)?;
         let value_len = read_pair_len(&mut cursor)?;
 
+        // Lengths come from the peer. Don't allocate more than what was actually sent.
+        let remaining = len - cursor.position() as usize;
+        if name_len as usize + value_len as usize > remaining {
+            return Err(Error::MalformedRecordPayload("Params"));
+        }
+
         let mut name = vec![0u8; name_len as usize];
         let mut value = vec![0u8; value_len as usize];
 
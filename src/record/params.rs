@@ -6,6 +6,7 @@ use std::io::{self, Write};
 /// A FastCGI `FCGI_PARAMS` record
 ///
 /// Used for sending name-value pairs between FastCGI server and client
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Params(BTreeMap<String, String>);
 
@@ -18,7 +19,6 @@ impl Params {
         pairs::to_record_bytes(&self.0, writer)
     }
 
-    #[cfg(test)]
     pub fn add<K, V>(mut self, key: K, value: V) -> Self
     where
         K: std::fmt::Display,
@@ -28,6 +28,15 @@ impl Params {
         self
     }
 
+    // Adds the pairs of `other`, which replace those of the same name
+    pub fn extend(&mut self, other: Params) {
+        self.0.extend(other.0);
+    }
+
+    pub fn pairs(&self) -> &BTreeMap<String, String> {
+        &self.0
+    }
+
     pub fn take(&mut self) -> BTreeMap<String, String> {
         std::mem::take(&mut self.0)
     }
@@ -2,6 +2,7 @@ use crate::error::Error;
 use std::io::{self, Write};
 
 /// An indication of the completion status of a FastCGI request  
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum ProtocolStatus {
     RequestComplete,
@@ -5,6 +5,7 @@ use std::io::{self, Write};
 ///
 /// A FastCGI Server plays one of several well-defined roles.
 /// The most familiar is the Responder role, which is the only role implemented by this crate because no one uses the other two.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Role {
     Responder,
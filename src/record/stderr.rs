@@ -4,6 +4,7 @@ use std::io::{self, Write};
 /// A FastCGI `FCGI_STDERR` record
 ///
 /// Used to send error data from the FastCGI server to the client.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Stderr(pub Vec<u8>);
 
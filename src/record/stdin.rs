@@ -5,6 +5,7 @@ use std::io::{self, Write};
 ///
 /// A record used to send arbitrary data from the FastCGI client to the server.
 /// For example, this is used to send the POST request payload
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Stdin(pub Vec<u8>);
 
@@ -9,6 +9,7 @@ use std::io::{self, Write};
 /// This begs the question: "What about non-recognized application records"? The says nothing about
 /// this.
 /// Therefore, this library also emits this record type in response to unrecognized application records.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct UnknownType(pub u8);
 
@@ -0,0 +1,301 @@
+use crate::body::BodyWriter;
+use crate::context::{check_header, same_header_name, InvalidHeader, Response};
+use crate::headers;
+use crate::status;
+use std::fmt;
+use std::io;
+
+/// Builds a [`Response`], checking it for common mistakes once it is complete
+///
+/// The setters of [`Response`] accept any combination of status, headers and body, some of which
+/// confuse clients (e.g. a redirect without a `Location`). [`ResponseBuilder::try_build`] catches
+/// those instead.
+///
+/// ```
+/// use vintage::{status, InvalidResponse, Response};
+///
+/// let response = Response::builder()
+///     .status(status::SEE_OTHER)
+///     .header("Location", "/done")
+///     .try_build();
+/// assert!(response.is_ok());
+///
+/// let response = Response::builder()
+///     .status(status::NO_CONTENT)
+///     .body("Deleted")
+///     .try_build();
+/// assert_eq!(response, Err(InvalidResponse::BodyNotAllowed(status::NO_CONTENT)));
+/// ```
+#[derive(Debug, Default)]
+pub struct ResponseBuilder {
+    response: Response,
+    // The first invalid header, reported by `try_build`
+    invalid_header: Option<InvalidHeader>,
+}
+
+impl ResponseBuilder {
+    /// Starts building an empty `200 OK` response. Same as [`Response::builder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the status code of the response to `code`
+    pub fn status(mut self, code: u16) -> Self {
+        self.response = self.response.set_status(code);
+        self
+    }
+
+    /// Sets the response header `key` to `value`
+    ///
+    /// An invalid name or value is reported by [`try_build`](ResponseBuilder::try_build)
+    pub fn header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        let value = value.into();
+        match check_header(&key, &value) {
+            Ok(()) => {
+                self.response.headers.insert(key, value);
+            }
+            Err(err) => {
+                self.invalid_header.get_or_insert(err);
+            }
+        }
+        self
+    }
+
+    /// Sets the response body
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.response = self.response.set_raw_body(body.into());
+        self
+    }
+
+    /// Sets the response body to be produced by `producer` while it is being sent
+    ///
+    /// See [`Response::set_body_stream`]
+    pub fn body_stream<F>(mut self, producer: F) -> Self
+    where
+        F: FnOnce(&mut BodyWriter) -> io::Result<()> + Send + 'static,
+    {
+        self.response = self.response.set_body_stream(producer);
+        self
+    }
+
+    /// Returns the response, unless it is invalid
+    ///
+    /// Besides invalid headers, this rejects:
+    /// - Status codes that are not three digits long
+    /// - A body on informational (`1xx`), `204 No Content` and `304 Not Modified` responses
+    /// - Redirects (`301`, `302`, `303`, `307` and `308`) without a `Location`
+    /// - A `Content-Length` that doesn't match the body, or several that don't match each other
+    pub fn try_build(self) -> Result<Response, InvalidResponse> {
+        if let Some(err) = self.invalid_header {
+            return Err(InvalidResponse::Header(err));
+        }
+
+        let response = self.response;
+        let code = response.status;
+        if !(100..=999).contains(&code) {
+            return Err(InvalidResponse::Status(code));
+        }
+
+        let has_body = !response.body.is_empty() || response.stream.is_some();
+        let bodiless = (100..200).contains(&code)
+            || code == status::NO_CONTENT
+            || code == status::NOT_MODIFIED;
+        if has_body && bodiless {
+            return Err(InvalidResponse::BodyNotAllowed(code));
+        }
+
+        let is_redirect = matches!(
+            code,
+            status::MOVED_PERMANENTLY
+                | status::FOUND
+                | status::SEE_OTHER
+                | status::TEMPORARY_REDIRECT
+                | status::PERMANENT_REDIRECT
+        );
+        if is_redirect && !response.has_header(headers::LOCATION) {
+            return Err(InvalidResponse::MissingLocation(code));
+        }
+
+        // Spellings that differ in case are distinct headers, so there can be several
+        let lengths: Vec<&String> = response
+            .headers
+            .iter()
+            .filter(|(name, _)| same_header_name(name, headers::CONTENT_LENGTH))
+            .map(|(_, value)| value)
+            .collect();
+        if lengths.iter().any(|length| *length != lengths[0]) {
+            let lengths = lengths.into_iter().cloned().collect();
+            return Err(InvalidResponse::ConflictingContentLength(lengths));
+        }
+        // The length of a streamed body is only known once it is sent
+        if let (Some(declared), None) = (lengths.first(), &response.stream) {
+            if declared.trim().parse() != Ok(response.body.len()) {
+                return Err(InvalidResponse::ContentLengthMismatch {
+                    declared: declared.to_string(),
+                    actual: response.body.len(),
+                });
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Returns the response
+    ///
+    /// # Panics
+    ///
+    /// Panics if the response is invalid. See [`try_build`](ResponseBuilder::try_build).
+    #[track_caller]
+    pub fn build(self) -> Response {
+        match self.try_build() {
+            Ok(response) => response,
+            Err(err) => panic!("{err}"),
+        }
+    }
+}
+
+/// The error returned by [`ResponseBuilder::try_build`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InvalidResponse {
+    /// A header has an invalid name or value
+    Header(InvalidHeader),
+    /// The status code is not three digits long
+    Status(u16),
+    /// Responses with this status code can't have a body
+    BodyNotAllowed(u16),
+    /// Redirects with this status code need a `Location` header
+    MissingLocation(u16),
+    /// `Content-Length` is set more than once, to these different values
+    ConflictingContentLength(Vec<String>),
+    /// `Content-Length` doesn't match the length of the body
+    ContentLengthMismatch {
+        /// The value of the `Content-Length` header
+        declared: String,
+        /// The length of the body
+        actual: usize,
+    },
+}
+
+impl fmt::Display for InvalidResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header(err) => write!(f, "{err}"),
+            Self::Status(code) => write!(f, "Invalid status code: {code}"),
+            Self::BodyNotAllowed(code) => write!(f, "A {code} response can't have a body"),
+            Self::MissingLocation(code) => {
+                write!(f, "A {code} redirect needs a Location header")
+            }
+            Self::ConflictingContentLength(lengths) => {
+                write!(
+                    f,
+                    "Conflicting Content-Length headers: {}",
+                    lengths.join(", ")
+                )
+            }
+            Self::ContentLengthMismatch { declared, actual } => write!(
+                f,
+                "Content-Length is '{}', but the body is {actual} bytes long",
+                declared.escape_debug()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidResponse {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_responses() {
+        let response = Response::builder()
+            .status(status::CREATED)
+            .header("Location", "/items/1")
+            .header("Content-Length", "2")
+            .body("{}")
+            .build();
+        assert_eq!(
+            response,
+            Response::created("/items/1")
+                .set_header("Content-Length", "2")
+                .set_body("{}")
+        );
+
+        assert!(Response::builder()
+            .status(status::NOT_MODIFIED)
+            .try_build()
+            .is_ok());
+        assert!(Response::builder()
+            .header("Content-Length", "100")
+            .body_stream(|_writer| Ok(()))
+            .try_build()
+            .is_ok());
+        assert!(Response::builder()
+            .status(status::MULTIPLE_CHOICES)
+            .body("a or b")
+            .try_build()
+            .is_ok());
+    }
+
+    #[test]
+    fn invalid_responses() {
+        let invalid = |builder: ResponseBuilder| builder.try_build().unwrap_err();
+
+        assert_eq!(
+            invalid(
+                Response::builder()
+                    .header("Bad Name", "x")
+                    .header("X-Ok", "\n")
+            ),
+            InvalidResponse::Header(InvalidHeader::Name("Bad Name".to_string()))
+        );
+        assert_eq!(
+            invalid(Response::builder().status(42)),
+            InvalidResponse::Status(42)
+        );
+        assert_eq!(
+            invalid(Response::builder().status(status::NOT_MODIFIED).body("x")),
+            InvalidResponse::BodyNotAllowed(status::NOT_MODIFIED)
+        );
+        assert_eq!(
+            invalid(
+                Response::builder()
+                    .status(status::NO_CONTENT)
+                    .body_stream(|_writer| Ok(()))
+            ),
+            InvalidResponse::BodyNotAllowed(status::NO_CONTENT)
+        );
+        assert_eq!(
+            invalid(Response::builder().status(status::FOUND)),
+            InvalidResponse::MissingLocation(status::FOUND)
+        );
+        assert_eq!(
+            invalid(
+                Response::builder()
+                    .header("Content-Length", "3")
+                    .header("content-length", "4")
+            ),
+            InvalidResponse::ConflictingContentLength(vec!["3".to_string(), "4".to_string()])
+        );
+        let mismatch = invalid(
+            Response::builder()
+                .header("Content-Length", "3")
+                .body("hello"),
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "Content-Length is '3', but the body is 5 bytes long"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "A 308 redirect needs a Location header")]
+    fn build_panics() {
+        Response::builder()
+            .status(status::PERMANENT_REDIRECT)
+            .build();
+    }
+}
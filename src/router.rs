@@ -1,13 +1,101 @@
-use crate::context::{Request, Response};
+use crate::context::{self, Request, Response};
+use crate::headers;
+use crate::method;
+use crate::status;
+use percent_encoding::percent_decode_str;
 use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 
 pub type RouteParams = BTreeMap<String, String>;
 pub type RouterCallback = Arc<dyn Fn(&mut Request, RouteParams) -> Response + Send + Sync>;
 
+/// A routing backend, which finds the handler of a request from its method and path
+///
+/// Routes registered with [`ServerConfig::on`](crate::ServerConfig::on) and its shorthands are
+/// matched by the built-in backend, which uses the syntax of the `matchit` crate. Backends with
+/// other semantics (e.g. case-insensitive paths, or locale prefixes) can be added with
+/// [`ServerConfig::router`](crate::ServerConfig::router).
+///
+/// ```
+/// use vintage::{Request, Response, Route, RouteMatch, RouteParams};
+///
+/// // Matches `/hello`, whatever its case
+/// struct CaseInsensitive {
+///     hello: fn(&mut Request, RouteParams) -> Response,
+/// }
+///
+/// impl Route for CaseInsensitive {
+///     fn find(&self, method: &str, path: &str) -> Option<RouteMatch<'_>> {
+///         if method == "GET" && path.eq_ignore_ascii_case("/hello") {
+///             return Some(RouteMatch::new("/hello", RouteParams::new(), &self.hello));
+///         }
+///         None
+///     }
+/// }
+/// ```
+pub trait Route: Send + Sync {
+    /// Returns the route matching a request for `path` with `method`, if any
+    fn find(&self, method: &str, path: &str) -> Option<RouteMatch<'_>>;
+
+    /// Returns the methods that have a route matching `path`
+    ///
+    /// These are used to answer `OPTIONS` requests, and requests with other methods (with a
+    /// `405 Method Not Allowed`). By default, none are reported.
+    fn allowed_methods(&self, path: &str) -> Vec<&str> {
+        let _ = path;
+        Vec::new()
+    }
+
+    /// Returns the method and path pattern of every route, for
+    /// [`ServerConfig::routes`](crate::ServerConfig::routes). By default, none are listed.
+    fn routes(&self) -> Vec<(&str, &str)> {
+        Vec::new()
+    }
+}
+
+/// A route that matches a request, as found by a [`Route`] backend
+pub struct RouteMatch<'a> {
+    pub(crate) pattern: &'a str,
+    pub(crate) params: RouteParams,
+    pub(crate) handler: &'a (dyn Fn(&mut Request, RouteParams) -> Response + Send + Sync),
+    // The length of the literal part of the path pattern, before its first parameter
+    #[cfg_attr(not(feature = "fs"), allow(dead_code))]
+    pub(crate) literal_len: usize,
+}
+
+impl<'a> RouteMatch<'a> {
+    /// Creates a match that calls `handler` with `params`
+    ///
+    /// `pattern` identifies the route in the access log and in the route stats (e.g.
+    /// `/users/{id}`). The part before its first `{` is how specific the route is, when the
+    /// file server and the router are dispatched by specificity (`DispatchOrder::MostSpecific`).
+    pub fn new(
+        pattern: &'a str,
+        params: RouteParams,
+        handler: &'a (dyn Fn(&mut Request, RouteParams) -> Response + Send + Sync),
+    ) -> Self {
+        Self {
+            pattern,
+            params,
+            handler,
+            literal_len: pattern.find('{').unwrap_or(pattern.len()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RegisteredRoute {
+    callback: RouterCallback,
+    pattern: Arc<str>,
+}
+
 #[derive(Default, Clone)]
 pub struct Router {
-    map: BTreeMap<&'static str, matchit::Router<RouterCallback>>,
+    map: BTreeMap<&'static str, matchit::Router<RegisteredRoute>>,
+    // Every registered route by (pattern, method), since `matchit` routers can't be listed
+    routes: BTreeMap<(Arc<str>, &'static str), RouterCallback>,
 }
 
 impl Router {
@@ -20,21 +108,46 @@ impl Router {
         C: Fn(&mut Request, RouteParams) -> Response,
         C: 'static + Send + Sync,
     {
+        assert!(context::is_token(method), "Invalid method: '{method}'");
         let callback = Arc::new(callback);
 
         for path in paths {
-            self.map
-                .entry(method)
-                .or_default()
-                .insert(path, callback.clone())
-                .unwrap()
+            if let Err(err) = validate_pattern(path) {
+                panic!("Invalid route pattern '{path}': {err}");
+            }
+            self.insert(method, path.into(), callback.clone());
+        }
+    }
+
+    // Adds the routes of `other`
+    pub fn merge(&mut self, other: Router) {
+        for ((pattern, method), callback) in other.routes {
+            self.insert(method, pattern, callback);
+        }
+    }
+
+    fn insert(&mut self, method: &'static str, pattern: Arc<str>, callback: RouterCallback) {
+        let route = RegisteredRoute {
+            callback: callback.clone(),
+            pattern: pattern.clone(),
+        };
+        if let Err(err) = self.map.entry(method).or_default().insert(&*pattern, route) {
+            panic!("Can't register {method} '{pattern}': {err}");
         }
+        self.routes.insert((pattern, method), callback);
     }
 
+    #[cfg(test)]
     pub fn respond(&self, req: &mut Request) -> Option<Response> {
-        let router = self.map.get(req.method())?;
+        let found = self.find(req.method(), req.path())?;
+        Some((found.handler)(req, found.params))
+    }
+}
 
-        let entry = router.at(req.path()).ok()?;
+impl Route for Router {
+    fn find(&self, method: &str, path: &str) -> Option<RouteMatch<'_>> {
+        let router = self.map.get(method)?;
+        let entry = router.at(path).ok()?;
 
         let mut params = BTreeMap::new();
 
@@ -42,13 +155,284 @@ impl Router {
             params.insert(key.to_string(), value.to_string());
         }
 
-        Some((entry.value)(req, params))
+        Some(RouteMatch::new(
+            &entry.value.pattern,
+            params,
+            &*entry.value.callback,
+        ))
+    }
+
+    // Returns the methods registered for `path`, in alphabetical order
+    fn allowed_methods(&self, path: &str) -> Vec<&str> {
+        self.map
+            .iter()
+            .filter(|(_, router)| router.at(path).is_ok())
+            .map(|(method, _)| *method)
+            .collect()
+    }
+
+    // Returns the method and pattern of every route, ordered by pattern then method
+    fn routes(&self) -> Vec<(&str, &str)> {
+        self.routes
+            .keys()
+            .map(|(pattern, method)| (*method, pattern.as_ref()))
+            .collect()
+    }
+}
+
+// Path patterns, with the syntax of routes, that requests are matched against to select which
+// of them a feature applies to (e.g. the paths excluded from authentication)
+#[derive(Clone)]
+pub struct PathSet {
+    // Maps to the pattern that matched
+    routes: matchit::Router<String>,
+    patterns: Vec<String>,
+}
+
+impl Default for PathSet {
+    fn default() -> Self {
+        Self {
+            routes: matchit::Router::new(),
+            patterns: vec![],
+        }
+    }
+}
+
+impl fmt::Debug for PathSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.patterns).finish()
+    }
+}
+
+impl PathSet {
+    pub fn new<const N: usize>(paths: [&str; N]) -> Self {
+        let mut set = Self::default();
+        set.extend(paths);
+        set
+    }
+
+    // Panics if a path is not a valid route, or is already in the set
+    #[track_caller]
+    pub fn extend<const N: usize>(&mut self, paths: [&str; N]) {
+        for path in paths {
+            if let Err(err) = self.routes.insert(path, path.to_string()) {
+                panic!("Invalid path '{path}': {err}");
+            }
+            self.patterns.push(path.to_string());
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    // Returns the pattern that matches `path`, if any
+    pub fn find(&self, path: &str) -> Option<&str> {
+        self.routes
+            .at(path)
+            .ok()
+            .map(|matched| matched.value.as_str())
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+}
+
+/// Why a path pattern can't be registered as a route
+///
+/// See [`validate_pattern`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PatternError {
+    /// The pattern does not start with `/`, so no request path can match it
+    MissingLeadingSlash,
+    /// A segment has more than one parameter, or text after a parameter (e.g. `/{a}-{b}`)
+    InvalidParamSegment,
+    /// A parameter has no name, or its braces don't match (e.g. `/{}` or `/{id`)
+    InvalidParam,
+    /// A catch-all parameter is not at the end of the pattern (e.g. `/{*rest}/more`)
+    InvalidCatchAll,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingLeadingSlash => write!(f, "Patterns must start with '/'"),
+            Self::InvalidParamSegment => write!(
+                f,
+                "Only one parameter is allowed per segment, and nothing can follow it"
+            ),
+            Self::InvalidParam => write!(f, "Parameters must have a name and matching braces"),
+            Self::InvalidCatchAll => {
+                write!(f, "Catch-all parameters are only allowed at the end")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// Checks that `pattern` is a valid path pattern for [`ServerConfig::on`]
+///
+/// Registering an invalid pattern panics. This lets code that builds patterns at runtime (e.g.
+/// from a configuration file) report them instead. Patterns that are valid on their own can still
+/// conflict with each other (e.g. `/{id}` and `/{name}`), which is only detected when both are
+/// registered.
+///
+/// ```
+/// use vintage::routing::{validate_pattern, PatternError};
+///
+/// assert_eq!(validate_pattern("/users/{id}"), Ok(()));
+/// assert_eq!(validate_pattern("/files/{*path}"), Ok(()));
+/// assert_eq!(validate_pattern("users"), Err(PatternError::MissingLeadingSlash));
+/// assert_eq!(validate_pattern("/users/{id"), Err(PatternError::InvalidParam));
+/// ```
+///
+/// [`ServerConfig::on`]: crate::ServerConfig::on
+pub fn validate_pattern(pattern: &str) -> Result<(), PatternError> {
+    if !pattern.starts_with('/') {
+        return Err(PatternError::MissingLeadingSlash);
+    }
+
+    // The built-in router is backed by `matchit`, so a pattern is valid if it accepts it
+    match matchit::Router::new().insert(pattern, ()) {
+        Ok(()) => Ok(()),
+        Err(matchit::InsertError::InvalidParamSegment) => Err(PatternError::InvalidParamSegment),
+        Err(matchit::InsertError::InvalidCatchAll) => Err(PatternError::InvalidCatchAll),
+        Err(_) => Err(PatternError::InvalidParam),
     }
 }
 
+/// Why a route param can't be used by a handler
+///
+/// When [`ServerConfig::decode_route_params`](crate::ServerConfig::decode_route_params) is set,
+/// the server answers requests whose params are not valid UTF-8 once percent-decoded on its own.
+/// Handlers get the other errors from [`parse_param`], and can return them as a response.
+/// Either way, the response can be customized with
+/// [`ServerConfig::on_param_error`](crate::ServerConfig::on_param_error).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParamError {
+    /// The route has no param with this name
+    Missing(String),
+    /// The param with this name is not valid UTF-8 once percent-decoded
+    Encoding(String),
+    /// The param can't be parsed into the type the handler asked for
+    Invalid {
+        /// The name of the param
+        name: String,
+        /// The value of the param
+        value: String,
+        /// Why it can't be parsed
+        reason: String,
+    },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "The route has no param named '{name}'"),
+            Self::Encoding(name) => write!(f, "The '{name}' param is not valid UTF-8"),
+            Self::Invalid {
+                name,
+                value,
+                reason,
+            } => write!(
+                f,
+                "The '{name}' param is invalid ('{}'): {reason}",
+                value.escape_debug()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// Answers with a `400 Bad Request` that describes the error, or a `500 Internal Server Error`
+/// for a [`ParamError::Missing`], since that is a mistake of the handler
+impl From<ParamError> for Response {
+    fn from(err: ParamError) -> Self {
+        let status = match err {
+            ParamError::Missing(_) => status::INTERNAL_SERVER_ERROR,
+            _ => status::BAD_REQUEST,
+        };
+        let mut response = Response::text(err.to_string()).set_status(status);
+        response.param_error = Some(Box::new(err));
+        response
+    }
+}
+
+/// Parses the route param `name` into a `T`
+///
+/// ```
+/// use vintage::routing::parse_param;
+/// use vintage::{Response, ServerConfig};
+///
+/// let config = ServerConfig::new().on_get(["/users/{id}"], |_req, params| {
+///     // `/users/abc` is answered with a `400 Bad Request`
+///     let id: u64 = match parse_param(&params, "id") {
+///         Ok(id) => id,
+///         Err(err) => return err.into(),
+///     };
+///     Response::text(format!("user {id}"))
+/// });
+/// ```
+pub fn parse_param<T>(params: &RouteParams, name: &str) -> Result<T, ParamError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let value = params
+        .get(name)
+        .ok_or_else(|| ParamError::Missing(name.to_string()))?;
+    value.parse().map_err(|err: T::Err| ParamError::Invalid {
+        name: name.to_string(),
+        value: value.clone(),
+        reason: err.to_string(),
+    })
+}
+
+// Percent-decodes the values of `params`
+pub fn decode_params(params: RouteParams) -> Result<RouteParams, ParamError> {
+    params
+        .into_iter()
+        .map(|(name, value)| {
+            let decoded = percent_decode_str(&value).decode_utf8();
+            match decoded {
+                Ok(decoded) => Ok((name, decoded.into_owned())),
+                Err(_) => Err(ParamError::Encoding(name)),
+            }
+        })
+        .collect()
+}
+
+// Responds to a request whose path is routed, but not for its method.
+//
+// `OPTIONS` requests are answered with the `allowed` methods, and other methods are not allowed.
+pub fn respond_to_other_method(req: &Request, mut allowed: Vec<&str>) -> Option<Response> {
+    allowed.sort();
+    allowed.dedup();
+    if allowed.is_empty() {
+        return None;
+    }
+
+    let response = if req.method() == method::OPTIONS {
+        if let Err(i) = allowed.binary_search(&method::OPTIONS) {
+            allowed.insert(i, method::OPTIONS);
+        }
+        Response::no_content()
+    } else {
+        Response::new().set_status(status::METHOD_NOT_ALLOWED)
+    };
+    Some(response.set_header(headers::ALLOW, allowed.join(", ")))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
 
     fn make_request(method: &str, path: &str) -> Request {
         Request {
@@ -123,4 +507,189 @@ mod test {
 
         assert_eq!(response, Response::default().set_body(String::from("2")));
     }
+
+    #[test]
+    fn other_methods() {
+        let mut router = Router::default();
+        let callback = |_req: &mut Request, _params| Response::default();
+        router.register("GET", ["/dav/{*path}"], callback);
+        router.register("PROPFIND", ["/dav/{*path}"], callback);
+        router.register("MKCOL", ["/dav/{*path}"], callback);
+
+        let other_method = |method: &str, path: &str| {
+            respond_to_other_method(&make_request(method, path), router.allowed_methods(path))
+        };
+
+        let response = other_method("DELETE", "/dav/a").unwrap();
+        assert_eq!(response.status, status::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers["Allow"], "GET, MKCOL, PROPFIND");
+
+        let response = other_method("OPTIONS", "/dav/a").unwrap();
+        assert_eq!(response.status, status::NO_CONTENT);
+        assert_eq!(response.headers["Allow"], "GET, MKCOL, OPTIONS, PROPFIND");
+
+        assert_eq!(other_method("DELETE", "/other"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid method: 'NOT A METHOD'")]
+    fn invalid_method() {
+        let mut router = Router::default();
+        router.register("NOT A METHOD", ["/"], |_req, _params| Response::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid route pattern 'path': Patterns must start with '/'")]
+    fn invalid_pattern() {
+        let mut router = Router::default();
+        router.register("GET", ["path"], |_req, _params| Response::default());
+    }
+
+    #[test]
+    fn pattern_errors() {
+        assert_eq!(
+            validate_pattern("/{a}-{b}"),
+            Err(PatternError::InvalidParamSegment)
+        );
+        assert_eq!(validate_pattern("/{}"), Err(PatternError::InvalidParam));
+        assert_eq!(
+            validate_pattern("/{*rest}/more"),
+            Err(PatternError::InvalidCatchAll)
+        );
+        assert_eq!(validate_pattern("/{{literal}}"), Ok(()));
+    }
+
+    #[test]
+    fn param_errors() {
+        let mut params = RouteParams::new();
+        params.insert("id".into(), "my%20file.txt".into());
+        let decoded = decode_params(params).unwrap();
+        assert_eq!(decoded["id"], "my file.txt");
+
+        let mut params = RouteParams::new();
+        params.insert("id".into(), "%FF".into());
+        assert_eq!(
+            decode_params(params),
+            Err(ParamError::Encoding("id".into()))
+        );
+
+        let mut params = RouteParams::new();
+        params.insert("id".into(), "12".into());
+        assert_eq!(parse_param::<u8>(&params, "id"), Ok(12));
+        assert_eq!(
+            parse_param::<u8>(&params, "name"),
+            Err(ParamError::Missing("name".into()))
+        );
+        let err = parse_param::<bool>(&params, "id").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The 'id' param is invalid ('12'): provided string was not `true` or `false`"
+        );
+        assert_eq!(Response::from(err).status, status::BAD_REQUEST);
+    }
+
+    // A segment of a generated pattern
+    #[derive(Debug, Clone)]
+    enum Segment {
+        Literal(String),
+        Param,
+    }
+
+    fn any_pattern() -> impl Strategy<Value = (Vec<Segment>, bool)> {
+        let segment = prop_oneof![
+            "[a-z0-9_.-]{1,8}".prop_map(Segment::Literal),
+            Just(Segment::Param),
+        ];
+        (vec(segment, 0..6), any::<bool>())
+    }
+
+    // Returns the pattern made of `segments`, with parameters named after their position, and an
+    // optional catch-all at the end
+    fn pattern(segments: &[Segment], catch_all: bool) -> String {
+        let mut pattern: String = segments
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| match segment {
+                Segment::Literal(literal) => format!("/{literal}"),
+                Segment::Param => format!("/{{p{i}}}"),
+            })
+            .collect();
+        if catch_all {
+            pattern.push_str("/{*rest}");
+        } else if pattern.is_empty() {
+            pattern.push('/');
+        }
+        pattern
+    }
+
+    proptest! {
+        #[test]
+        fn validation_never_panics(pattern in ".{0,64}") {
+            let _ = validate_pattern(&pattern);
+        }
+
+        #[test]
+        fn valid_patterns_can_be_registered(pattern in "/[a-z{}*/-]{0,24}") {
+            if validate_pattern(&pattern).is_ok() {
+                let mut router = Router::default();
+                router.register("GET", [pattern.as_str()], |_req, _params| Response::default());
+                prop_assert_eq!(router.routes(), [("GET", pattern.as_str())]);
+            }
+        }
+
+        #[test]
+        fn patterns_match_consistently(
+            (segments, catch_all) in any_pattern(),
+            values in vec("[a-zA-Z0-9._~-]{1,10}", 6),
+            rest in "[a-z0-9]{1,6}(/[a-z0-9]{1,6}){0,2}",
+        ) {
+            let pattern = pattern(&segments, catch_all);
+            prop_assert_eq!(validate_pattern(&pattern), Ok(()));
+            let mut router = Router::default();
+            router.register("GET", [pattern.as_str()], |_req, _params| Response::default());
+
+            // A path that fills in every parameter matches, and yields the values
+            let mut path = String::new();
+            let mut expected = RouteParams::new();
+            for (i, segment) in segments.iter().enumerate() {
+                match segment {
+                    Segment::Literal(literal) => path.push_str(&format!("/{literal}")),
+                    Segment::Param => {
+                        path.push_str(&format!("/{}", values[i]));
+                        expected.insert(format!("p{i}"), values[i].clone());
+                    }
+                }
+            }
+            if catch_all {
+                path.push_str(&format!("/{rest}"));
+                expected.insert("rest".into(), rest.clone());
+            } else if path.is_empty() {
+                path.push('/');
+            }
+            let found = router.find("GET", &path);
+            prop_assert!(found.is_some(), "{} does not match {}", path, pattern);
+            let found = found.unwrap();
+            prop_assert_eq!(found.pattern, pattern.as_str());
+            prop_assert_eq!(&found.params, &expected);
+
+            // Other methods, and paths with a different literal or an extra segment, don't
+            prop_assert!(router.find("POST", &path).is_none());
+            if let Some(i) = segments.iter().position(|s| matches!(s, Segment::Literal(_))) {
+                let mut segments = segments.clone();
+                segments[i] = Segment::Literal(format!("{}~", values[i]));
+                let mut changed = String::new();
+                for (i, segment) in segments.iter().enumerate() {
+                    match segment {
+                        Segment::Literal(literal) => changed.push_str(&format!("/{literal}")),
+                        Segment::Param => changed.push_str(&format!("/{}", values[i])),
+                    }
+                }
+                prop_assert!(router.find("GET", &changed).is_none());
+            }
+            if !catch_all {
+                let longer = format!("{}/extra", path.trim_end_matches('/'));
+                prop_assert!(router.find("GET", &longer).is_none());
+            }
+        }
+    }
 }
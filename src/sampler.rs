@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Selects a fraction of the events it sees, spread evenly: with a rate of `0.25`, every fourth
+// event is selected
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    rate: f64,
+    seen: AtomicU64,
+}
+
+impl Sampler {
+    // Panics if `rate` is not between 0 and 1
+    #[track_caller]
+    pub(crate) fn new(rate: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "The sampling rate must be between 0 and 1"
+        );
+        Self {
+            rate,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    // Whether the next event is selected.
+    //
+    // An event is selected when it brings the expected number of selected events to the next
+    // whole number.
+    pub(crate) fn sample(&self) -> bool {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * self.rate).floor() != (seen * self.rate).floor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreading() {
+        let selected = |rate| {
+            let sampler = Sampler::new(rate);
+            (0..8).map(|_| sampler.sample()).collect::<Vec<_>>()
+        };
+        assert_eq!(selected(0.0), [false; 8]);
+        assert_eq!(selected(1.0), [true; 8]);
+        assert_eq!(
+            selected(0.25),
+            [false, false, false, true, false, false, false, true]
+        );
+        assert_eq!(selected(0.5).iter().filter(|s| **s).count(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "The sampling rate must be between 0 and 1")]
+    fn invalid_rate() {
+        Sampler::new(1.5);
+    }
+}
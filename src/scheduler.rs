@@ -0,0 +1,166 @@
+use crate::panic_report;
+use crate::sync;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub type JobCallback = Arc<dyn Fn() + Send + Sync>;
+
+// A job that runs every `interval`
+#[derive(Clone)]
+pub struct Schedule {
+    pub interval: Duration,
+    pub job: JobCallback,
+}
+
+// Runs scheduled jobs on a dedicated thread, so that they don't hold up the event loop or the
+// workers
+pub struct Scheduler {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Scheduler {
+    // Starts running `schedules`. Returns `None` if there are none.
+    pub fn start(schedules: Vec<Schedule>) -> Option<Self> {
+        if schedules.is_empty() {
+            return None;
+        }
+
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = thread::Builder::new()
+            .name("vintage-scheduler".to_string())
+            .spawn({
+                let stopped = stopped.clone();
+                move || run(schedules, &stopped)
+            })
+            .ok()?;
+
+        Some(Self { stopped, thread })
+    }
+
+    // Stops running jobs.
+    //
+    // If `wait` is true, blocks until the job that is currently running, if any, completes.
+    pub fn stop(self, wait: bool) {
+        let (lock, condvar) = &*self.stopped;
+        *sync::lock(lock) = true;
+        condvar.notify_one();
+
+        if wait {
+            let _ = self.thread.join();
+        }
+    }
+}
+
+fn run(schedules: Vec<Schedule>, stopped: &(Mutex<bool>, Condvar)) {
+    // `None` when the interval is too long for the next run to be represented, i.e. never
+    let start = Instant::now();
+    let mut next_runs: Vec<Option<Instant>> = schedules
+        .iter()
+        .map(|s| start.checked_add(s.interval))
+        .collect();
+
+    let (lock, condvar) = stopped;
+    loop {
+        let next = next_runs.iter().flatten().min();
+
+        let guard = sync::lock(lock);
+        let timeout = match next {
+            Some(next) => next.saturating_duration_since(Instant::now()),
+            None => Duration::MAX,
+        };
+        let (guard, _) = condvar
+            .wait_timeout_while(guard, timeout, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        if *guard {
+            return;
+        }
+        drop(guard);
+
+        let now = Instant::now();
+        for (schedule, next_run) in schedules.iter().zip(next_runs.iter_mut()) {
+            if !next_run.is_some_and(|next_run| next_run <= now) {
+                continue;
+            }
+
+            // A failing job should not stop the others
+            if let Err(report) = panic_report::catch(|| (schedule.job)()) {
+                log::error!(panic:% = report, backtrace:% = report.backtrace(); "Scheduled job panicked");
+            }
+
+            // Runs that were missed because a job took too long are skipped, not caught up on
+            *next_run = next_run
+                .and_then(|next_run| next_run.checked_add(schedule.interval))
+                .filter(|next_run| *next_run > now)
+                .or_else(|| now.checked_add(schedule.interval));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn run_and_stop() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let scheduler = Scheduler::start(vec![
+            Schedule {
+                interval: Duration::from_millis(10),
+                job: Arc::new({
+                    let runs = runs.clone();
+                    move || {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                    }
+                }),
+            },
+            Schedule {
+                interval: Duration::from_millis(5),
+                job: Arc::new(|| panic!("job failed")),
+            },
+        ])
+        .unwrap();
+
+        while runs.load(Ordering::SeqCst) < 3 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        scheduler.stop(true);
+
+        let stopped_at = runs.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(runs.load(Ordering::SeqCst), stopped_at);
+
+        assert!(Scheduler::start(vec![]).is_none());
+    }
+
+    #[test]
+    fn very_long_intervals() {
+        let (collect, runs) = crate::testing::collector();
+        let scheduler = Scheduler::start(vec![
+            Schedule {
+                interval: Duration::MAX,
+                job: Arc::new(|| panic!("never due")),
+            },
+            Schedule {
+                interval: Duration::from_millis(5),
+                job: Arc::new(move || collect(())),
+            },
+        ])
+        .unwrap();
+
+        for _ in 0..2 {
+            runs.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        scheduler.stop(true);
+
+        // Only jobs that can never run
+        let scheduler = Scheduler::start(vec![Schedule {
+            interval: Duration::MAX,
+            job: Arc::new(|| panic!("never due")),
+        }])
+        .unwrap();
+        scheduler.stop(true);
+    }
+}
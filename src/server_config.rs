@@ -1,16 +1,216 @@
-use crate::context::{Request, Response};
+use crate::access_log::{self, AccessLogCallback, AccessLogEntry, AccessLogFilter};
+#[cfg(feature = "fs")]
+use crate::assets::Assets;
+use crate::audit::AuditLog;
+use crate::auth::BasicAuth;
+#[cfg(feature = "signed-cookies")]
+use crate::auth::FormLogin;
+use crate::bulkhead::Bulkhead;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::clock::{Clock, SharedClock};
+#[cfg(feature = "compression")]
+use crate::compression::Compression;
+use crate::connection::DEFAULT_BUFFER_SIZE;
+use crate::context::{same_header_name, HeaderCase, LineEnding, Request, Response};
+#[cfg(feature = "compression")]
+use crate::decompression::Decompression;
+#[cfg(feature = "fs")]
 use crate::file_server::FileServer;
-use crate::router::{RouteParams, Router};
+use crate::headers;
+use crate::idempotency::Idempotency;
+use crate::ip::{IpList, IpRange};
+use crate::listener::ListenAddress;
+#[cfg(unix)]
+use crate::listener::SocketFile;
+use crate::locale::LocaleNegotiation;
+use crate::memory_budget::{MemoryBudget, MemoryPolicy};
+use crate::middleware::{MiddlewareCallback, Next};
+use crate::mirror::Mirror;
+use crate::panic_report::PanicReport;
+use crate::path_mapping::PathMapping;
+use crate::proxy::Proxy;
+use crate::queue::QueuePolicy;
+use crate::router::{self, ParamError, PathSet, Route, RouteMatch, RouteParams, Router};
+use crate::scheduler::Schedule;
+#[cfg(feature = "signed-cookies")]
+use crate::signing::Keys;
+use crate::stats::Stats;
+use crate::status;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use std::error::Error;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
 
 type FallbackCallback = Arc<dyn Fn(&mut Request) -> Response + Send + Sync>;
+type AbortCallback = Arc<dyn Fn(&Request) + Send + Sync>;
+type WriteErrorCallback = Arc<dyn Fn(&Request, &io::Error) + Send + Sync>;
+type PanicCallback = Arc<dyn Fn(&Request, &PanicReport) + Send + Sync>;
+type ManagementValueCallback = Arc<dyn Fn() -> String + Send + Sync>;
+type IpFilterCallback = Arc<dyn Fn(Option<IpAddr>, Option<IpAddr>) -> Option<u16> + Send + Sync>;
+type BeforeServeCallback = Arc<dyn Fn() -> io::Result<()> + Send + Sync>;
+type StartCallback = Arc<dyn Fn() -> Result<(), Box<dyn Error + Send + Sync>> + Send + Sync>;
+type ParamErrorCallback = Arc<dyn Fn(&Request, &ParamError) -> Response + Send + Sync>;
+type MediaTypePredicate = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+type BodyTransformCallback = Arc<dyn Fn(&Request, Vec<u8>) -> Vec<u8> + Send + Sync>;
+
+// The default `Retry-After` of the responses to connections turned away during shutdown
+const DEFAULT_DRAIN_RETRY_AFTER: Duration = Duration::from_secs(5);
 
 /// Configuration for a `vintage` FastCGI Server
 #[derive(Clone, Default)]
 pub struct ServerConfig {
+    #[cfg(feature = "fs")]
+    pub(crate) assets: Option<Assets>,
+    #[cfg(feature = "fs")]
     pub(crate) file_server: Option<FileServer>,
     pub(crate) router: Option<Router>,
+    pub(crate) custom_routers: Vec<Arc<dyn Route>>,
+    pub(crate) route_listing: Option<String>,
+    pub(crate) index_page: bool,
+    pub(crate) startup_banner: bool,
     pub(crate) fallback: Option<FallbackCallback>,
+    pub(crate) decode_route_params: bool,
+    pub(crate) on_param_error: Option<ParamErrorCallback>,
+    pub(crate) trusted_proxies: Arc<Vec<IpRange>>,
+    #[cfg(feature = "signed-cookies")]
+    pub(crate) keys: Option<Arc<Keys>>,
+    pub(crate) clock: SharedClock,
+    pub(crate) ip_filters: Vec<IpFilterCallback>,
+    pub(crate) access_log: Option<AccessLogCallback>,
+    pub(crate) access_log_filter: Option<Arc<AccessLogFilter>>,
+    pub(crate) on_abort: Option<AbortCallback>,
+    pub(crate) on_write_error: Option<WriteErrorCallback>,
+    pub(crate) on_panic: Option<PanicCallback>,
+    pub(crate) capture: Option<Arc<PathBuf>>,
+    pub(crate) management_values: Vec<(String, ManagementValueCallback)>,
+    pub(crate) path_mapping: PathMapping,
+    pub(crate) param_strictness: ParamStrictness,
+    pub(crate) unread_body: UnreadBody,
+    pub(crate) header_case: HeaderCase,
+    pub(crate) line_ending: LineEnding,
+    pub(crate) identity_headers: Vec<(&'static str, String)>,
+    pub(crate) middleware: Vec<MiddlewareCallback>,
+    pub(crate) body_transforms: Vec<(MediaTypePredicate, BodyTransformCallback)>,
+    pub(crate) max_record_packets: Option<usize>,
+    pub(crate) max_interleaved_packets: Option<usize>,
+    pub(crate) max_connection_memory: Option<usize>,
+    pub(crate) max_params: Option<usize>,
+    pub(crate) max_param_sizes: Option<(usize, usize)>,
+    pub(crate) handshake_timeout: Option<Duration>,
+    pub(crate) memory_budget: Option<Arc<MemoryBudget>>,
+    pub(crate) buffer_sizes: Option<(usize, usize)>,
+    pub(crate) backlog: Option<u32>,
+    #[cfg(unix)]
+    pub(crate) socket_file: SocketFile,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsConfig>,
+    pub(crate) worker_model: WorkerModel,
+    pub(crate) queue_capacity: Option<usize>,
+    pub(crate) queue_policy: QueuePolicy,
+    pub(crate) drain_retry_after: Option<Duration>,
+    pub(crate) before_serve: Vec<BeforeServeCallback>,
+    pub(crate) on_start: Vec<StartCallback>,
+    #[cfg(feature = "fs")]
+    pub(crate) dispatch_order: DispatchOrder,
+    pub(crate) bulkheads: Vec<Bulkhead>,
+    pub(crate) circuit_breakers: Vec<CircuitBreaker>,
+    pub(crate) schedules: Vec<Schedule>,
+    pub(crate) listeners: Vec<(ListenAddress, ServerConfig)>,
+    // Replaced when the server starts, so that servers started from the same config don't share
+    // their counters
+    pub(crate) stats: Arc<Stats>,
+    // Replaced when the server starts, like `stats`. See `ServerHandle::set_maintenance`
+    pub(crate) maintenance: Arc<AtomicBool>,
+    #[cfg(unix)]
+    pub(crate) inherited_listener: bool,
+    #[cfg(unix)]
+    pub(crate) control_socket: Option<PathBuf>,
+}
+
+/// Whether the file server or the router gets the first chance to handle a request
+///
+/// Fingerprinted assets are always served first, since their URLs can't collide with routes.
+///
+/// See [`ServerConfig::dispatch_order`]
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DispatchOrder {
+    /// Requests under the prefix of the file server are served from disk if the file exists.
+    /// Routes only see the requests the file server did not handle.
+    #[default]
+    FilesFirst,
+    /// Requests that match a route are handled by the router, even if a file exists.
+    /// The file server only sees the requests that don't match a route.
+    RouterFirst,
+    /// Whichever of the file server prefix and the literal part of the matching route (e.g.
+    /// `/api/` for `/api/{id}`) is longer goes first.
+    /// The file server goes first on ties.
+    ///
+    /// For example, with files served at `/`, a request to `/api/users` is handled by a
+    /// `/api/{name}` route, but a request to `/index.html` is not handled by a `/{page}` route.
+    MostSpecific,
+}
+
+/// How connections are spread between threads
+///
+/// See [`ServerConfig::worker_model`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WorkerModel {
+    /// A single thread accepts connections, and hands them off to a pool of worker threads
+    #[default]
+    ThreadPool,
+    /// Each thread owns its own listening socket (bound with `SO_REUSEPORT`) and its own poller,
+    /// and handles the connections it accepts itself.
+    /// One thread is started per available CPU core.
+    ///
+    /// This avoids handing connections off between threads, but a slow handler holds up every
+    /// connection waiting on its thread, even if other threads are idle.
+    #[cfg(unix)]
+    ThreadPerCore,
+}
+
+/// What happens when the web server leaves out params every request should have
+///
+/// A missing `QUERY_STRING` is always treated as an empty query string, since some minimal
+/// FastCGI clients only send it when there is a query.
+///
+/// See [`ServerConfig::param_strictness`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParamStrictness {
+    /// Requests without a `REQUEST_METHOD`, or without the params the
+    /// [`PathMapping`] needs to determine the request path, fail with a 500 response
+    #[default]
+    Strict,
+    /// A missing `REQUEST_METHOD` defaults to `GET`, and a missing request path defaults to `/`
+    Lenient,
+}
+
+/// What happens to the rest of the request body when a request is rejected before it is fully
+/// read (e.g. because its params are too large)
+///
+/// Handlers always receive the whole body, so this only concerns the requests the server
+/// answers itself.
+///
+/// See [`ServerConfig::unread_body`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnreadBody {
+    /// Reads and discards up to this many bytes of the body before responding.
+    /// Web servers that only read the response once they are done sending the request then
+    /// still get it. Connections with more left to read are closed after the response.
+    Drain(usize),
+    /// Responds right away, then closes the connection
+    Close,
+}
+
+impl Default for UnreadBody {
+    fn default() -> Self {
+        Self::Drain(1024 * 1024)
+    }
 }
 
 impl ServerConfig {
@@ -19,6 +219,25 @@ impl ServerConfig {
         Self::default()
     }
 
+    /// Creates a new specification for a FastCGI server that uses the listening socket it
+    /// inherited from its parent process, if any
+    ///
+    /// Process managers like `spawn-fcgi`, and some web servers, start FastCGI applications with
+    /// the listening socket already open as file descriptor 0.
+    /// If that is the case, [`start()`](crate::start) accepts connections on it and ignores its
+    /// `address` argument.
+    /// Otherwise, it binds to `address` as usual, so the same program also runs standalone.
+    ///
+    /// The inherited socket can be a TCP or a unix domain socket (e.g. with `spawn-fcgi -s`).
+    /// The file of a unix domain socket belongs to the parent process, and is left alone.
+    #[cfg(unix)]
+    pub fn from_inherited_listener() -> Self {
+        Self {
+            inherited_listener: true,
+            ..Self::default()
+        }
+    }
+
     /// Adds support for serving static files
     ///
     /// Matches requests that start with `prefix` and uses the rest of the path to lookup a file on
@@ -30,8 +249,44 @@ impl ServerConfig {
     /// # Panics
     ///
     /// Panics if `path` contains invalid utf8 values
-    pub fn serve_files(mut self, prefix: &'static str, path: &'static str) -> Self {
-        self.file_server = Some(FileServer::new(prefix, path));
+    #[cfg(feature = "fs")]
+    pub fn serve_files(self, prefix: &'static str, path: &'static str) -> Self {
+        self.file_server(FileServer::new(prefix, path))
+    }
+
+    /// Adds support for serving static files using a customized [`FileServer`]
+    ///
+    /// See [`ServerConfig::serve_files`]
+    #[cfg(feature = "fs")]
+    pub fn file_server(mut self, file_server: FileServer) -> Self {
+        self.file_server = Some(file_server);
+        self
+    }
+
+    /// Sets whether the file server or the router gets the first chance to handle a request.
+    /// The default is [`DispatchOrder::FilesFirst`].
+    ///
+    /// ```
+    /// use vintage::{DispatchOrder, Response, ServerConfig};
+    ///
+    /// // `/search` is handled by the route, even if there is a file called `search`
+    /// let config = ServerConfig::new()
+    ///     .serve_files("/", "public")
+    ///     .on_get(["/search"], |_req, _params| Response::text("results"))
+    ///     .dispatch_order(DispatchOrder::RouterFirst);
+    /// ```
+    #[cfg(feature = "fs")]
+    pub fn dispatch_order(mut self, order: DispatchOrder) -> Self {
+        self.dispatch_order = order;
+        self
+    }
+
+    /// Adds support for serving fingerprinted static assets
+    ///
+    /// See [`Assets`]
+    #[cfg(feature = "fs")]
+    pub fn serve_assets(mut self, assets: Assets) -> Self {
+        self.assets = Some(assets);
         self
     }
 
@@ -42,6 +297,17 @@ impl ServerConfig {
     /// Paths support basic segment matching.
     /// Matched path segments are passed to the callback as a second argument.
     ///
+    /// Any method can be registered, including extension methods like WebDAV's `PROPFIND`.
+    /// See the [`method`](crate::method) module.
+    ///
+    /// When a path is registered, but not for the method of a request, and the request is not
+    /// handled by the [`ServerConfig::unhandled`] callback, the response is a
+    /// `405 Method Not Allowed` listing the registered methods in its `Allow` header.
+    /// `OPTIONS` requests get that list in a `204 No Content` response.
+    ///
+    /// Panics if `method` is not a valid HTTP method name, or if one of `paths` is not a valid
+    /// pattern (see [`routing::validate_pattern`](crate::routing::validate_pattern)).
+    ///
     /// # Path Matching Syntax
     ///
     /// _Segment_ matchers look like `/{id}/whatever`.
@@ -69,6 +335,11 @@ impl ServerConfig {
     /// In the following example, if the request path was `/folder/a/b/c`, `&params["subfolders"]` would
     /// be `a/b/c`.
     ///
+    /// Params are matched against the request path, which web servers and the default
+    /// [`PathMapping`] send already percent-decoded. For paths that reach the server still encoded,
+    /// see [`ServerConfig::decode_route_params`]. See [`ParamError`] for parsing params into other
+    /// types.
+    ///
     /// ```
     /// use vintage::{Response, ServerConfig};
     ///
@@ -97,6 +368,241 @@ impl ServerConfig {
         self
     }
 
+    /// Returns the method and path pattern of every registered route, ordered by pattern then
+    /// method
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .on_get(["/users", "/users/{id}"], |_req, _params| Response::text("user"))
+    ///     .on_post(["/users"], |_req, _params| Response::text("created"));
+    ///
+    /// assert_eq!(
+    ///     config.routes(),
+    ///     [("GET", "/users"), ("POST", "/users"), ("GET", "/users/{id}")]
+    /// );
+    /// ```
+    pub fn routes(&self) -> Vec<(&str, &str)> {
+        let mut routes: Vec<_> = self
+            .route_backends()
+            .flat_map(|router| router.routes())
+            .collect();
+        routes.sort_by_key(|(method, pattern)| (*pattern, *method));
+        routes
+    }
+
+    /// Adds a routing backend, which is consulted before the routes registered with
+    /// [`ServerConfig::on`]
+    ///
+    /// Use this for routes that the built-in syntax can't express (e.g. case-insensitive paths).
+    /// Backends added by several calls are consulted in the order they were added. See [`Route`].
+    pub fn router(mut self, router: impl Route + 'static) -> Self {
+        self.custom_routers.push(Arc::new(router));
+        self
+    }
+
+    // The routing backends, in the order they are consulted
+    fn route_backends(&self) -> impl Iterator<Item = &dyn Route> {
+        let custom = self.custom_routers.iter().map(|router| &**router);
+        let builtin = self.router.as_ref().map(|router| router as &dyn Route);
+        custom.chain(builtin)
+    }
+
+    /// Percent-decodes route params before they are passed to handlers (e.g. `my%20file.txt`
+    /// becomes `my file.txt`)
+    ///
+    /// Only use this when the request path reaches the server still encoded, e.g. with a
+    /// [`PathMapping::custom`] that returns the raw path of `REQUEST_URI`. The default mappings
+    /// already decode the path: decoding it a second time would turn a double-encoded `%252e%252e`
+    /// into `..`. Decoding a param within its segment keeps an encoded `%2F` from matching a
+    /// separate segment.
+    ///
+    /// Requests whose params are not valid UTF-8 once decoded are answered with a
+    /// `400 Bad Request`.
+    pub fn decode_route_params(mut self) -> Self {
+        self.decode_route_params = true;
+        self
+    }
+
+    /// Registers a callback that answers requests whose route params can't be used
+    ///
+    /// It replaces the default `400 Bad Request` response to params that are not valid UTF-8
+    /// once percent-decoded (see [`ServerConfig::decode_route_params`]), and the responses that
+    /// handlers make out of a [`ParamError`].
+    ///
+    /// ```
+    /// use vintage::{status, Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new().on_param_error(|_req, err| {
+    ///     Response::json(format!(r#"{{"error": "{err}"}}"#)).set_status(status::BAD_REQUEST)
+    /// });
+    /// ```
+    pub fn on_param_error<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&Request, &ParamError) -> Response + Send + Sync + 'static,
+    {
+        self.on_param_error = Some(Arc::new(callback));
+        self
+    }
+
+    // Calls the handler of `found` with its params, decoded if asked to, and lets the
+    // `on_param_error` callback answer param errors
+    pub(crate) fn call_route(&self, req: &mut Request, found: RouteMatch<'_>) -> Response {
+        let params = if self.decode_route_params {
+            router::decode_params(found.params)
+        } else {
+            Ok(found.params)
+        };
+        let mut response = match params {
+            Ok(params) => (found.handler)(req, params),
+            Err(err) => Response::from(err),
+        };
+        if let (Some(err), Some(callback)) = (response.param_error.take(), &self.on_param_error) {
+            response = callback(req, &err);
+        }
+        response
+    }
+
+    // Returns the route matching `req`, if any
+    pub(crate) fn find_route(&self, req: &Request) -> Option<RouteMatch<'_>> {
+        self.route_backends()
+            .find_map(|router| router.find(req.method(), req.path()))
+    }
+
+    // Responds to a request whose path is routed, but not for its method
+    pub(crate) fn respond_to_other_method(&self, req: &Request) -> Option<Response> {
+        let allowed = self
+            .route_backends()
+            .flat_map(|router| router.allowed_methods(req.path()))
+            .collect();
+        router::respond_to_other_method(req, allowed)
+    }
+
+    /// Answers `GET` requests to `path` with the list of registered routes, as plain text
+    ///
+    /// Each line has the method and path pattern of a route (see [`ServerConfig::routes`]).
+    /// Routes registered after this call are listed too.
+    /// This is meant for development: it tells what is actually mounted, but also tells anyone
+    /// who can reach it.
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let config = ServerConfig::new().route_listing("/_debug/routes");
+    /// ```
+    pub fn route_listing(mut self, path: impl Into<String>) -> Self {
+        self.route_listing = Some(path.into());
+        self
+    }
+
+    // Responds to requests for the route listing, if it is enabled
+    pub(crate) fn list_routes(&self, req: &Request) -> Option<Response> {
+        let path = self.route_listing.as_ref()?;
+        if req.path != *path || !matches!(req.method.as_str(), "GET" | "HEAD") {
+            return None;
+        }
+        let listing: String = self
+            .routes()
+            .into_iter()
+            .map(|(method, pattern)| format!("{method} {pattern}\n"))
+            .collect();
+        Some(Response::text(listing))
+    }
+
+    /// Answers `GET /` with a page that lists the registered routes and static file mounts, when
+    /// no route handles it and there is no [`ServerConfig::unhandled`] callback
+    ///
+    /// This is meant to check that the web server is wired up correctly, before the application
+    /// has a home page. It only works in debug builds: in release builds (i.e. without
+    /// `debug_assertions`), `/` is answered as if it was not enabled.
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let config = ServerConfig::new().index_page();
+    /// ```
+    pub fn index_page(mut self) -> Self {
+        self.index_page = true;
+        self
+    }
+
+    /// Logs a one-line summary of the server's setup when it starts: its version, sockets,
+    /// worker threads and limits
+    ///
+    /// This is handy to include in bug reports. The same information is available as data from
+    /// [`ServerHandle::diagnostics`](crate::ServerHandle::diagnostics).
+    pub fn startup_banner(mut self) -> Self {
+        self.startup_banner = true;
+        self
+    }
+
+    /// Reads the current time from `clock` instead of the system clock
+    ///
+    /// This is meant for tests: with a [`TestClock`](crate::TestClock), the `Date` of responses,
+    /// the timestamps and durations in the access log, and the expiry of idempotent responses
+    /// only change when the test advances the clock. See [`Clock`].
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    // Responds with the default index page, if it is enabled
+    pub(crate) fn default_index_page(&self, req: &Request) -> Option<Response> {
+        if !cfg!(debug_assertions) || !self.index_page || self.fallback.is_some() {
+            return None;
+        }
+        if req.path != "/" || !matches!(req.method.as_str(), "GET" | "HEAD") {
+            return None;
+        }
+
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head><title>vintage</title></head>\n<body>\n\
+             <h1>vintage is running</h1>\n\
+             <p>No route handles <code>/</code>. This page is only shown in debug builds.</p>\n",
+        );
+
+        html.push_str("<h2>Routes</h2>\n");
+        let routes = self.routes();
+        if routes.is_empty() {
+            html.push_str("<p>No routes are registered.</p>\n");
+        } else {
+            html.push_str("<ul>\n");
+            for (method, pattern) in routes {
+                let (method, pattern) = (escape_html(method), escape_html(pattern));
+                html.push_str(&format!("<li><code>{method} {pattern}</code></li>\n"));
+            }
+            html.push_str("</ul>\n");
+        }
+
+        #[cfg(feature = "fs")]
+        {
+            let mounts: Vec<&FileServer> = self
+                .assets
+                .iter()
+                .map(Assets::files)
+                .chain(self.file_server.iter())
+                .collect();
+            html.push_str("<h2>Static files</h2>\n");
+            if mounts.is_empty() {
+                html.push_str("<p>No directories are served.</p>\n");
+            } else {
+                html.push_str("<ul>\n");
+                for mount in mounts {
+                    let prefix = escape_html(mount.request_prefix());
+                    let path = escape_html(mount.fs_path().as_str());
+                    html.push_str(&format!(
+                        "<li><code>{prefix}</code> from <code>{path}</code></li>\n"
+                    ));
+                }
+                html.push_str("</ul>\n");
+            }
+        }
+
+        html.push_str("</body>\n</html>\n");
+        Some(Response::html(html))
+    }
+
     /// Registers a path for the "GET" method
     ///
     /// See [`ServerConfig::on`]
@@ -141,120 +647,3642 @@ impl ServerConfig {
         self.on("DELETE", paths, callback)
     }
 
-    /// Registers a callback that will be invoked for any unhandled requests
-    pub fn unhandled<C>(mut self, callback: C) -> Self
+    /// Trusts the `Forwarded` and `X-Forwarded-*` headers of requests coming from `proxies`
+    ///
+    /// Each proxy is either an IP address (e.g. `127.0.0.1`) or a CIDR range (e.g. `10.0.0.0/8`).
+    /// This affects [`Request::client_ip`] and [`Request::scheme`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of `proxies` is not a valid IP address or CIDR range
+    pub fn trust_proxies<const N: usize>(mut self, proxies: [&str; N]) -> Self {
+        let mut trusted = self.trusted_proxies.to_vec();
+        for proxy in proxies {
+            trusted.push(proxy.parse().unwrap_or_else(|e| panic!("{e}")));
+        }
+        self.trusted_proxies = Arc::new(trusted);
+        self
+    }
+
+    /// Sets the keys that sign cookies, so that clients can't forge or change them
+    ///
+    /// Cookies are signed with `primary`, and accepted if `primary` or any of `fallbacks` signed
+    /// them. To rotate keys without invalidating the cookies of current visitors, make the new key
+    /// the primary one and keep the old one as a fallback until those cookies expire.
+    ///
+    /// This applies to [`Request::sign_cookie`] and [`Request::signed_cookie`], and to flash
+    /// messages (see [`Response::with_flash`]), which are sent unsigned otherwise.
+    ///
+    /// Keys should be long random secrets (e.g. 32 bytes), kept out of the source code.
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// // Cookies signed with the old key are still accepted
+    /// let config = ServerConfig::new().keys("new secret", ["old secret"]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the keys is empty
+    #[cfg(feature = "signed-cookies")]
+    pub fn keys<K: Into<Vec<u8>>>(
+        mut self,
+        primary: K,
+        fallbacks: impl IntoIterator<Item = K>,
+    ) -> Self {
+        let primary = primary.into();
+        let fallbacks: Vec<Vec<u8>> = fallbacks.into_iter().map(Into::into).collect();
+        assert!(
+            !primary.is_empty() && fallbacks.iter().all(|key| !key.is_empty()),
+            "Cookie signing keys can't be empty"
+        );
+        self.keys = Some(Arc::new(Keys::new(primary, fallbacks)));
+        self
+    }
+
+    /// Registers a hook that can reject requests based on where they come from, before they are
+    /// routed
+    ///
+    /// The hook receives the address of the peer that sent the request to the web server (the
+    /// `REMOTE_ADDR` variable), and the address of the client (see [`Request::client_ip`]).
+    /// They only differ when the request was forwarded by a trusted proxy, and either is `None`
+    /// if the web server did not send it.
+    /// Returning a status rejects the request with that status.
+    ///
+    /// Hooks run in the order they were registered, before static files, middleware and routes.
+    /// See [`ServerConfig::block_ips`] and [`ServerConfig::allow_ips`] for ready-made hooks.
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let config = ServerConfig::new().ip_filter(|_peer, client| match client {
+    ///     Some(ip) if ip.is_loopback() => None,
+    ///     _ => Some(403),
+    /// });
+    /// ```
+    pub fn ip_filter<C>(mut self, filter: C) -> Self
     where
-        C: Fn(&mut Request) -> Response,
-        C: 'static + Send + Sync,
+        C: Fn(Option<IpAddr>, Option<IpAddr>) -> Option<u16> + Send + Sync + 'static,
     {
-        self.fallback = Some(Arc::new(callback));
+        self.ip_filters.push(Arc::new(filter));
         self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::connection::Connection;
-    use crate::error::Error;
-    use crate::record::*;
-    use assert_matches::assert_matches;
-    use mio::net::TcpStream;
-    use std::net::SocketAddr;
+    /// Rejects requests from clients in `list` with `status` (e.g. `403 Forbidden`)
+    ///
+    /// The list can be updated while the server is running. See [`IpList`] and
+    /// [`ServerConfig::ip_filter`]
+    pub fn block_ips(self, list: IpList, status: u16) -> Self {
+        self.ip_filter(move |_peer, client| {
+            let blocked = client.is_some_and(|ip| list.contains(ip));
+            blocked.then_some(status)
+        })
+    }
 
-    macro_rules! records {
-        ($($record:expr),* $(,)?) => {{
-            #[allow(unused_mut)]
-            let mut records: Vec<Record> = vec![];
-            $(
-                records.push($record.into());
-            )*
-            records
-        }}
+    /// Rejects requests from clients that are not in `list` with `status` (e.g.
+    /// `403 Forbidden`)
+    ///
+    /// Requests whose client address is unknown are rejected too.
+    /// The list can be updated while the server is running. See [`IpList`] and
+    /// [`ServerConfig::ip_filter`]
+    pub fn allow_ips(self, list: IpList, status: u16) -> Self {
+        self.ip_filter(move |_peer, client| {
+            let allowed = client.is_some_and(|ip| list.contains(ip));
+            (!allowed).then_some(status)
+        })
     }
 
-    fn basic_params() -> Params {
-        Params::default()
-            .add("REQUEST_METHOD", "GET")
-            .add("PATH_INFO", "/")
-            .add("QUERY_STRING", "")
+    /// Registers a callback that receives an entry for every handled request
+    ///
+    /// This replaces the default behavior of logging the entries through the [`log`] crate.
+    /// The callback runs on the worker thread that handled the request, so it should be quick.
+    pub fn access_log<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&AccessLogEntry) + Send + Sync + 'static,
+    {
+        self.access_log = Some(Arc::new(callback));
+        self
     }
 
-    // Test that when we send `to_send` records to the server at `address`, we get back the
-    // `expected` records
-    #[track_caller]
-    fn assert_request(address: SocketAddr, to_send: Vec<Record>, mut expected: Vec<Record>) {
-        let socket = TcpStream::connect(address).unwrap();
-        let mut connection = Connection::try_from(socket).unwrap();
+    /// Writes an entry for every handled request to `writer`, one line per entry
+    ///
+    /// This replaces the default behavior of logging the entries through the [`log`] crate.
+    /// See [`AccessLogEntry`] for the format of each line.
+    pub fn access_log_writer<W>(mut self, writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        self.access_log = Some(access_log::to_writer(writer));
+        self
+    }
 
-        for record in to_send.iter() {
-            connection.write_record(record).unwrap();
-        }
+    /// Keeps the entries that match the rules of `filter` out of the access log
+    ///
+    /// This applies to the default logging through the [`log`] crate, as well as to the
+    /// callbacks registered with [`ServerConfig::access_log`] and
+    /// [`ServerConfig::access_log_writer`].
+    pub fn access_log_filter(mut self, filter: AccessLogFilter) -> Self {
+        self.access_log_filter = Some(Arc::new(filter));
+        self
+    }
 
-        loop {
-            if expected.is_empty() {
-                let result = connection.read_record();
-                assert_matches!(result, Err(Error::UnexpectedSocketClose(_)));
-                break;
-            }
+    /// Records requests and their responses, bodies included, with `audit`
+    ///
+    /// This registers a middleware. Requests it sees are recorded as the middleware registered
+    /// after it and the route handlers see them. See [`AuditLog`]
+    pub fn audit_log(self, audit: AuditLog) -> Self {
+        self.middleware(move |req, next| audit.record(req, next))
+    }
 
-            match connection.read_record() {
-                Ok(record) => {
-                    assert_eq!(record, expected.remove(0));
-                }
-                Err(err) => panic!("{err}"),
-            }
-        }
+    /// Registers a callback that is invoked when the FastCGI client aborts a request before its
+    /// response is completely sent.
+    ///
+    /// This happens when the client sends an `FCGI_ABORT_REQUEST` record, or closes the
+    /// connection (e.g. because the browser went away).
+    /// Use this to release resources tied to the request.
+    ///
+    /// See also [`Response::set_body_stream`]
+    pub fn on_abort<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&Request) + Send + Sync + 'static,
+    {
+        self.on_abort = Some(Arc::new(callback));
+        self
     }
 
-    #[test]
-    fn get_values() {
-        let server = crate::start(ServerConfig::new(), "localhost:0").unwrap();
+    /// Registers a callback that is invoked when a response can't be sent because writing to the
+    /// connection failed (e.g. the web server closed it)
+    ///
+    /// The failure is logged and counted (see
+    /// [`ServerHandle::write_errors`](crate::ServerHandle::write_errors)) either way.
+    /// Responses too large to be sent at once are finished by the server thread once the handler
+    /// returns: failures to send those are logged and counted, but don't invoke this callback.
+    pub fn on_write_error<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&Request, &io::Error) + Send + Sync + 'static,
+    {
+        self.on_write_error = Some(Arc::new(callback));
+        self
+    }
 
-        assert_request(
-            server.address(),
-            records! {
-                GetValues::default(),
-            },
-            records! {
-                GetValuesResult::default(),
-            },
-        );
+    /// Registers a callback that is invoked when a handler (or middleware) panics
+    ///
+    /// The request is answered with a `500 Internal Server Error` and the
+    /// [`HANDLER_PANICKED`](crate::app_status::HANDLER_PANICKED) app status, and the panic is
+    /// logged with its backtrace, either way. Use this to send the report to an error tracker.
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let config = ServerConfig::new().on_panic(|req, report| {
+    ///     eprintln!("{} panicked: {report}\n{}", req.path(), report.backtrace());
+    /// });
+    /// ```
+    pub fn on_panic<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&Request, &PanicReport) + Send + Sync + 'static,
+    {
+        self.on_panic = Some(Arc::new(callback));
+        self
+    }
 
-        assert_request(
-            server.address(),
-            records! {
-                GetValues::default().add("FCGI_MPXS_CONNS").add("VALUE_WE_DONT_KNOW"),
-            },
-            records! {
-                GetValuesResult::default().add("FCGI_MPXS_CONNS", "0"),
-            },
-        );
+    /// Answers FastCGI management queries (`FCGI_GET_VALUES`) for the variable `name` with the
+    /// value returned by `provider`
+    ///
+    /// Web servers and proxies send these queries on their own connections, outside of any
+    /// request. The server already answers `FCGI_MPXS_CONNS`, which can't be replaced. Queries
+    /// for other variables that have no provider are left unanswered, as the FastCGI
+    /// specification requires.
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let config = ServerConfig::new()
+    ///     .management_value("FCGI_MAX_CONNS", || "64".to_string())
+    ///     .management_value("APP_VERSION", || env!("CARGO_PKG_VERSION").to_string());
+    /// ```
+    pub fn management_value<C>(mut self, name: impl Into<String>, provider: C) -> Self
+    where
+        C: Fn() -> String + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.management_values
+            .retain(|(existing, _)| *existing != name);
+        self.management_values.push((name, Arc::new(provider)));
+        self
     }
 
-    #[test]
+    /// Records the raw bytes of every connection to a new file in `dir`
+    ///
+    /// This is meant for debugging: captures can be loaded with
+    /// [`Capture::load`](crate::Capture::load), inspected, and replayed with
+    /// [`Capture::replay`](crate::Capture::replay) to reproduce a bug.
+    ///
+    /// Captures include request bodies and headers (e.g. cookies), so they should be handled with
+    /// care. If a capture file cannot be created, the connection is served without being recorded.
+    pub fn capture(mut self, dir: impl AsRef<Path>) -> Self {
+        self.capture = Some(Arc::new(dir.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Sets how the request path is determined from the params sent by the web server
+    ///
+    /// The default, [`PathMapping::Auto`], works with the default configurations of most web
+    /// servers.
+    pub fn path_mapping(mut self, mapping: PathMapping) -> Self {
+        self.path_mapping = mapping;
+        self
+    }
+
+    /// Sets what happens when the web server leaves out the request method or path.
+    /// The default is [`ParamStrictness::Strict`].
+    pub fn param_strictness(mut self, strictness: ParamStrictness) -> Self {
+        self.param_strictness = strictness;
+        self
+    }
+
+    /// Sets what happens to the rest of the body of a request that is rejected before it is fully
+    /// read. The default is [`UnreadBody::Drain`] of up to 1MiB.
+    pub fn unread_body(mut self, policy: UnreadBody) -> Self {
+        self.unread_body = policy;
+        self
+    }
+
+    /// Sends a `Server` header with every response, unless the handler set one
+    ///
+    /// Every response already gets a `Date` header.
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let config = ServerConfig::new().server_header("my-app/1.2");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` contains a line break
+    #[track_caller]
+    pub fn server_header(self, value: impl Into<String>) -> Self {
+        self.identity_header(headers::SERVER, value.into())
+    }
+
+    /// Sends an `X-Powered-By` header with every response, unless the handler set one
+    ///
+    /// Some web servers replace the `Server` header with their own. This one is usually passed
+    /// through.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` contains a line break
+    #[track_caller]
+    pub fn powered_by(self, value: impl Into<String>) -> Self {
+        self.identity_header(headers::X_POWERED_BY, value.into())
+    }
+
+    #[track_caller]
+    fn identity_header(mut self, name: &'static str, value: String) -> Self {
+        assert!(
+            !value.contains(['\r', '\n', '\0']),
+            "Invalid {name} header: '{}'",
+            value.escape_debug()
+        );
+        self.identity_headers.retain(|(n, _)| *n != name);
+        self.identity_headers.push((name, value));
+        self
+    }
+
+    /// Sends a `Content-Security-Policy` header with every response of a route or middleware,
+    /// unless the handler set one
+    ///
+    /// If the handler used `Request::csp_nonce` (with the `csp-nonce` feature), the nonce is added
+    /// to the `script-src` and `style-src` directives of `policy` (or to `default-src`, if it has
+    /// neither). This lets templates mark the inline scripts they trust, while any other inline
+    /// script is blocked.
+    ///
+    /// This registers a middleware.
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .content_security_policy("default-src 'self'; script-src 'self'")
+    ///     .on_get(["/"], |req, _params| {
+    ///         let nonce = req.csp_nonce();
+    ///         Response::html(format!("<script nonce=\"{nonce}\">init()</script>"))
+    ///     });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `policy` contains a line break
+    #[track_caller]
+    pub fn content_security_policy(self, policy: impl Into<String>) -> Self {
+        let policy = policy.into();
+        assert!(
+            !policy.contains(['\r', '\n', '\0']),
+            "Invalid Content-Security-Policy header: '{}'",
+            policy.escape_debug()
+        );
+
+        self.middleware(move |req, next| {
+            let response = next.run(req);
+            if response.has_header(headers::CONTENT_SECURITY_POLICY) {
+                return response;
+            }
+            #[cfg(feature = "csp-nonce")]
+            if let Some(nonce) = req.csp_nonce.get() {
+                let policy = add_csp_nonce(&policy, nonce);
+                return response.set_header(headers::CONTENT_SECURITY_POLICY, policy);
+            }
+            response.set_header(headers::CONTENT_SECURITY_POLICY, policy.clone())
+        })
+    }
+
+    /// Sets how the names of request headers are spelled. The default is [`HeaderCase::Train`].
+    pub fn header_case(mut self, case: HeaderCase) -> Self {
+        self.header_case = case;
+        self
+    }
+
+    /// Sets the line ending used between response headers. The default is [`LineEnding::CrLf`].
+    ///
+    /// [`LineEnding::Lf`] restores the behavior of older versions.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Sets how many packets a single FastCGI record can be split into.
+    /// The default is 65536.
+    ///
+    /// Web servers split request bodies into packets of at most 64KiB, and often much smaller.
+    /// Connections that exceed this limit are closed.
+    pub fn max_record_packets(mut self, packets: usize) -> Self {
+        self.max_record_packets = Some(packets);
+        self
+    }
+
+    /// Sets how many packets of other records can arrive while a FastCGI record is being put
+    /// together from its packets. The default is 64.
+    ///
+    /// Some web servers start sending the request body before they are done with the params, or
+    /// interleave records in other unexpected ways. Such packets are buffered until their record
+    /// is read. Past this limit, the connection is closed. `0` rejects any interleaving.
+    ///
+    /// Buffered packets are logged at the debug level, with the type of the record they
+    /// interrupted, under the `vintage::connection` target.
+    pub fn max_interleaved_packets(mut self, packets: usize) -> Self {
+        self.max_interleaved_packets = Some(packets);
+        self
+    }
+
+    /// Sets how many bytes of params and request body can be buffered for a single connection.
+    /// The default is 64MiB.
+    ///
+    /// Connections that exceed this limit are closed.
+    pub fn max_connection_memory(mut self, bytes: usize) -> Self {
+        self.max_connection_memory = Some(bytes);
+        self
+    }
+
+    /// Sets how many params (i.e. CGI variables and request headers) a single request can have.
+    /// The default is 1024.
+    ///
+    /// Requests with more params are answered with `431 Request Header Fields Too Large`.
+    pub fn max_params(mut self, count: usize) -> Self {
+        self.max_params = Some(count);
+        self
+    }
+
+    /// Sets how many bytes the name and the value of a single param can take.
+    /// The defaults are 1KiB for names and 64KiB for values.
+    ///
+    /// Unlike [`ServerConfig::max_connection_memory`], this bounds each param on its own. Requests
+    /// with larger params are answered with `431 Request Header Fields Too Large`.
+    pub fn max_param_sizes(mut self, name: usize, value: usize) -> Self {
+        self.max_param_sizes = Some((name, value));
+        self
+    }
+
+    /// Sets how long a new connection has to send the record that begins its request.
+    /// The default is 5 seconds.
+    ///
+    /// Until then, the connection holds on to a worker thread. Each read on a connection already
+    /// gives up after a few seconds without input, but a client that keeps sending a byte at a
+    /// time (i.e. slow loris) could hold on to the thread indefinitely. Connections that take
+    /// longer are closed.
+    ///
+    /// Connections turned away with a `503` (see [`ServerConfig::request_queue`]) have this long
+    /// to send their whole request.
+    ///
+    /// `Duration::ZERO` disables this timeout.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the capacity, in bytes, of the read and write buffers of each connection.
+    /// The default is 8KiB for both.
+    ///
+    /// Smaller buffers save memory on constrained devices, while larger ones take fewer system
+    /// calls to move large requests and responses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either size is 0
+    pub fn buffer_sizes(mut self, read: usize, write: usize) -> Self {
+        assert!(
+            read > 0 && write > 0,
+            "Connection buffers must be able to hold a byte"
+        );
+        self.buffer_sizes = Some((read, write));
+        self
+    }
+
+    // The capacity of the read and write buffers of connections
+    pub(crate) fn connection_buffer_sizes(&self) -> (usize, usize) {
+        self.buffer_sizes
+            .unwrap_or((DEFAULT_BUFFER_SIZE, DEFAULT_BUFFER_SIZE))
+    }
+
+    /// Bounds how many bytes of params and request bodies the requests being handled can buffer
+    /// together
+    ///
+    /// By default, only the memory of each connection is bounded (see
+    /// [`ServerConfig::max_connection_memory`]). With a budget, bursts of uploads can't exhaust the
+    /// memory of the machine either. Requests that would go over the budget are handled according
+    /// to `policy`. The memory used by a request is released once its response is produced.
+    ///
+    /// The budget is shared by every listener of the server.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use vintage::{MemoryPolicy, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .memory_budget(256 * 1024 * 1024, MemoryPolicy::Wait(Duration::from_secs(5)));
+    /// ```
+    pub fn memory_budget(mut self, bytes: usize, policy: MemoryPolicy) -> Self {
+        self.memory_budget = Some(Arc::new(MemoryBudget::new(bytes, policy)));
+        self
+    }
+
+    /// Sets how many pending connections the listening socket can queue. The default is 1024.
+    ///
+    /// The operating system might cap it. The actual value is reported by
+    /// [`ServerHandle::listener`](crate::ServerHandle::listener).
+    ///
+    /// This is ignored on Windows, where the socket can't be set up with another backlog than
+    /// the default one. The backlog reported by the listener is `None` there.
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.backlog = Some(backlog);
+        self
+    }
+
+    /// Sets the permissions of the socket file (e.g. `0o660`)
+    ///
+    /// This applies to configurations given to [`ServerConfig::listen`] with a
+    /// [`ListenAddress::Unix`] address. By default, the file gets the permissions allowed by the
+    /// umask of the process. They are changed right after the socket is bound, before the server
+    /// accepts connections.
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// // nginx workers are in the `www-data` group (gid 33 on Debian)
+    /// let app = ServerConfig::new()
+    ///     .on_get(["/"], |_req, _params| Response::text("welcome"))
+    ///     .socket_permissions(0o660)
+    ///     .socket_owner(None, Some(33))
+    ///     .replace_stale_socket(true);
+    ///
+    /// let config = ServerConfig::new().listen(Path::new("/run/app/app.sock"), app);
+    /// ```
+    #[cfg(unix)]
+    pub fn socket_permissions(mut self, mode: u32) -> Self {
+        self.socket_file.mode = Some(mode);
+        self
+    }
+
+    /// Sets the user and group that own the socket file. `None` keeps the current one.
+    ///
+    /// This applies to the same sockets as [`ServerConfig::socket_permissions`]. Changing the
+    /// group only works if the process is a member of it (or is privileged), and changing the
+    /// user needs privileges. Starting the server fails otherwise.
+    #[cfg(unix)]
+    pub fn socket_owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.socket_file.owner = uid;
+        self.socket_file.group = gid;
+        self
+    }
+
+    /// Sets whether a socket file left at the path (e.g. by a server that was killed) is removed
+    /// before binding. The default is `false`.
+    ///
+    /// The file is only removed if it is a socket that refuses connections, so a server that is
+    /// still running keeps its socket, and starting fails with
+    /// [`ErrorKind::AddrInUse`](std::io::ErrorKind::AddrInUse).
+    ///
+    /// This applies to the same sockets as [`ServerConfig::socket_permissions`].
+    #[cfg(unix)]
+    pub fn replace_stale_socket(mut self, replace: bool) -> Self {
+        self.socket_file.replace_stale = replace;
+        self
+    }
+
+    /// Serves connections to the socket over TLS, with the certificate of `tls`
+    ///
+    /// Like the [`backlog`](ServerConfig::backlog), this applies to the socket of this
+    /// configuration: the one bound by [`start()`](crate::start), or the one given to
+    /// [`ServerConfig::listen`] along with it. So a server can take TLS connections from remote
+    /// web servers on one listener, and plain ones from a local web server on another.
+    ///
+    /// The handshake happens on a worker thread, when the first request of a connection is read.
+    /// Clients that fail it are disconnected, and no request is handled.
+    ///
+    /// Only available with the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets how connections are spread between threads. The default is [`WorkerModel::ThreadPool`].
+    pub fn worker_model(mut self, model: WorkerModel) -> Self {
+        self.worker_model = model;
+        self
+    }
+
+    /// Handles requests to `paths` on a dedicated pool of `threads` worker threads
+    ///
+    /// This isolates slow routes from the others: a flood of requests to these paths can only
+    /// occupy the threads of their pool, so requests to other routes are still handled promptly.
+    /// Requests are matched against the first bulkhead whose paths match, using the same syntax
+    /// as routes (see [`ServerConfig::on`]).
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .bulkhead(["/reports/{*path}"], 2)
+    ///     .on_get(["/reports/{*path}"], |_req, _params| Response::text("slow report"))
+    ///     .on_get(["/api/{*path}"], |_req, _params| Response::text("fast"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0, or if a path is not a valid route or is repeated
+    pub fn bulkhead<const N: usize>(mut self, paths: [&str; N], threads: usize) -> Self {
+        self.bulkheads.push(Bulkhead::new(paths, threads));
+        self
+    }
+
+    /// Guards the routes at `paths` with a [`CircuitBreaker`]
+    ///
+    /// Each path gets its own circuit, keyed by the path (using the same syntax as routes, see
+    /// [`ServerConfig::on`]). Responses with a `5xx` status count as failures.
+    /// While a circuit is open, its requests are answered with a `503 Service Unavailable`, and a
+    /// `Retry-After` header set to the rest of the cooldown.
+    ///
+    /// This registers a middleware. The state of the circuits is reported by
+    /// [`ServerHandle::circuits`](crate::ServerHandle::circuits).
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use vintage::{CircuitBreaker, Response, ServerConfig};
+    ///
+    /// let breaker = CircuitBreaker::new(5, Duration::from_secs(30));
+    /// let config = ServerConfig::new()
+    ///     .circuit_breaker(["/reports/{id}"], breaker)
+    ///     .on_get(["/reports/{id}"], |_req, _params| Response::text("report"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if a path is not a valid route or is repeated
+    #[track_caller]
+    pub fn circuit_breaker<const N: usize>(
+        mut self,
+        paths: [&str; N],
+        breaker: CircuitBreaker,
+    ) -> Self {
+        let routes = PathSet::new(paths);
+        self.circuit_breakers.push(breaker.clone());
+        self.middleware(move |req, next| {
+            let Some(key) = routes.find(&req.path) else {
+                return next.run(req);
+            };
+
+            let Some(attempt) = breaker.attempt(key) else {
+                let retry_after = breaker.retry_after(key).as_secs_f64().ceil();
+                return Response::new()
+                    .set_status(status::SERVICE_UNAVAILABLE)
+                    .set_header(headers::RETRY_AFTER, retry_after.to_string());
+            };
+
+            let response = next.run(req);
+            attempt.finish(response.status < 500);
+            response
+        })
+    }
+
+    /// Bounds the queue of accepted connections waiting for a worker thread
+    ///
+    /// By default, the queue is unbounded.
+    /// When it is full, new connections are handled according to `policy`.
+    /// How long each request waited in the queue is reported in the access log.
+    ///
+    /// This has no effect with [`WorkerModel::ThreadPerCore`], since connections are handled as
+    /// soon as they are accepted.
+    ///
+    /// ```
+    /// use vintage::{QueuePolicy, ServerConfig};
+    ///
+    /// let config = ServerConfig::new().request_queue(256, QueuePolicy::ShedOldest);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0
+    pub fn request_queue(mut self, capacity: usize, policy: QueuePolicy) -> Self {
+        assert!(
+            capacity > 0,
+            "The request queue must have room for a connection"
+        );
+        self.queue_capacity = Some(capacity);
+        self.queue_policy = policy;
+        self
+    }
+
+    /// Sets the `Retry-After` of the `503 Service Unavailable` responses sent while the server
+    /// shuts down or is in maintenance mode. The default is 5 seconds.
+    ///
+    /// When the server is stopped with [`ServerHandle::stop`](crate::ServerHandle::stop), requests
+    /// that are being handled are completed, but connections still waiting in the request queue
+    /// are turned away with a `503`. Clients (or the web server) can retry them once the server
+    /// is back (e.g. after a restart).
+    ///
+    /// This has no effect with [`WorkerModel::ThreadPerCore`], since connections don't wait in a
+    /// queue.
+    pub fn drain_retry_after(mut self, retry_after: Duration) -> Self {
+        self.drain_retry_after = Some(retry_after);
+        self
+    }
+
+    /// Accepts admin commands on a unix socket created at `path`
+    ///
+    /// The socket is only accessible to the user the server runs as, so the admin commands don't
+    /// need to be exposed as routes on the public listener. It is removed when the server stops.
+    ///
+    /// Commands are sent one per line (e.g. with `echo stats | socat - UNIX-CONNECT:<path>`).
+    /// Each reply is the output of the command followed by `ok`, or an `error: ` line.
+    ///
+    /// - `stats`: the traffic of every route (see [`ServerHandle::route_stats`])
+    /// - `routes`: the method and pattern of every route (see [`ServerConfig::routes`])
+    /// - `drain`: stops the server, like [`ServerHandle::stop`]
+    /// - `maintenance on|off`: turns maintenance mode on or off (see
+    ///   [`ServerHandle::set_maintenance`])
+    /// - `log-level <filter>`: changes the log filter (see [`ServerHandle::set_log_filter`])
+    /// - `help`: lists the commands
+    ///
+    /// Starting the server fails if the socket can't be created (e.g. if `path` already exists).
+    ///
+    /// [`ServerHandle::route_stats`]: crate::ServerHandle::route_stats
+    /// [`ServerHandle::stop`]: crate::ServerHandle::stop
+    /// [`ServerHandle::set_maintenance`]: crate::ServerHandle::set_maintenance
+    /// [`ServerHandle::set_log_filter`]: crate::ServerHandle::set_log_filter
+    #[cfg(unix)]
+    pub fn control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    // The `Retry-After` of the responses sent during shutdown or maintenance
+    pub(crate) fn drain_retry_after_or_default(&self) -> Duration {
+        self.drain_retry_after.unwrap_or(DEFAULT_DRAIN_RETRY_AFTER)
+    }
+
+    /// Also accepts connections on `address`, and handles their requests with `config` instead
+    /// of this configuration
+    ///
+    /// This lets one process serve several applications, e.g. an admin API on a local unix socket
+    /// next to the public application on TCP.
+    /// Connections to every listener share the worker threads and request queue of the server.
+    ///
+    /// Only the request handling settings of `config` are used (routes, middleware, static
+    /// files, logging, limits, ...). The [`backlog`](ServerConfig::backlog) applies to its own
+    /// socket. Everything that concerns the server as a whole (worker model, request queue,
+    /// start-up hooks, scheduled jobs and further listeners) is taken from this configuration.
+    /// With [`WorkerModel::ThreadPerCore`], connections to additional listeners are all handled
+    /// by the first thread.
+    ///
+    /// The sockets are bound by [`start()`](crate::start), which fails if one can't be.
+    /// What they are listening on is reported by
+    /// [`ServerHandle::listeners`](crate::ServerHandle::listeners).
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let admin = ServerConfig::new().on_get(["/health"], |_req, _params| Response::text("ok"));
+    ///
+    /// let config = ServerConfig::new()
+    ///     .on_get(["/"], |_req, _params| Response::text("welcome"))
+    ///     .listen(Path::new("/run/app/admin.sock"), admin);
+    ///
+    /// let handle = vintage::start(config, "127.0.0.1:9000").unwrap();
+    /// ```
+    pub fn listen(mut self, address: impl Into<ListenAddress>, config: ServerConfig) -> Self {
+        self.listeners.push((address.into(), config));
+        self
+    }
+
+    /// Adds what `other` sets up to this config, so that libraries can ship reusable fragments
+    /// (e.g. health checks and metrics routes) for applications to bolt onto their own config
+    ///
+    /// The two are combined as follows:
+    /// - Routes are combined. A route of `other` with the same method and pattern as one of this
+    ///   config panics, like registering it twice would.
+    /// - The middleware of `other` runs after (i.e. inside) the middleware of this config.
+    /// - Body transforms run after this config's.
+    /// - [Custom routers](ServerConfig::router), IP filters, management values,
+    ///   `Server`/`X-Powered-By` headers, bulkheads, circuit breakers, scheduled jobs, start-up
+    ///   tasks and `before_serve` callbacks are combined. Where both set the same one, this
+    ///   config's wins.
+    /// - Handlers and callbacks that there can only be one of (e.g. the
+    ///   [fallback](ServerConfig::unhandled), the file server, or the
+    ///   [access log](ServerConfig::access_log)) are taken from `other` only if this config has
+    ///   none.
+    /// - The [index page](ServerConfig::index_page) and
+    ///   [startup banner](ServerConfig::startup_banner) are enabled if either enables them.
+    /// - Everything else (e.g. limits, the worker model, additional listeners, TLS, trusted
+    ///   proxies and keys) is this config's. Fragments can't change how the server is deployed, or what it trusts.
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// // Shipped by a library
+    /// fn health() -> ServerConfig {
+    ///     ServerConfig::new()
+    ///         .on_get(["/healthz"], |_req, _params| Response::text("ok"))
+    ///         .management_value("HEALTHY", || "1".to_string())
+    /// }
+    ///
+    /// let config = ServerConfig::new()
+    ///     .on_get(["/"], |_req, _params| Response::text("home"))
+    ///     .merge(health());
+    ///
+    /// assert_eq!(config.routes(), [("GET", "/"), ("GET", "/healthz")]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if both configs have a route with the same method and pattern
+    pub fn merge(mut self, other: ServerConfig) -> Self {
+        self.router = match (self.router, other.router) {
+            (Some(mut router), Some(other)) => {
+                router.merge(other);
+                Some(router)
+            }
+            (router, other) => router.or(other),
+        };
+        self.middleware.extend(other.middleware);
+        self.body_transforms.extend(other.body_transforms);
+        self.ip_filters.extend(other.ip_filters);
+        self.management_values.extend(other.management_values);
+        for (name, value) in other.identity_headers {
+            if !self.identity_headers.iter().any(|(n, _)| *n == name) {
+                self.identity_headers.push((name, value));
+            }
+        }
+        self.bulkheads.extend(other.bulkheads);
+        self.circuit_breakers.extend(other.circuit_breakers);
+        self.schedules.extend(other.schedules);
+        self.on_start.extend(other.on_start);
+        self.before_serve.extend(other.before_serve);
+
+        self.custom_routers.extend(other.custom_routers);
+        self.route_listing = self.route_listing.or(other.route_listing);
+        self.fallback = self.fallback.or(other.fallback);
+        self.on_param_error = self.on_param_error.or(other.on_param_error);
+        #[cfg(feature = "fs")]
+        {
+            self.file_server = self.file_server.or(other.file_server);
+            self.assets = self.assets.or(other.assets);
+        }
+        self.access_log = self.access_log.or(other.access_log);
+        self.access_log_filter = self.access_log_filter.or(other.access_log_filter);
+        self.on_abort = self.on_abort.or(other.on_abort);
+        self.on_write_error = self.on_write_error.or(other.on_write_error);
+        self.on_panic = self.on_panic.or(other.on_panic);
+
+        self.index_page |= other.index_page;
+        self.startup_banner |= other.startup_banner;
+        self
+    }
+
+    /// Registers a task that must succeed before the server starts (e.g. opening a database pool,
+    /// or loading templates)
+    ///
+    /// Tasks run in the order they were registered, on the thread that calls
+    /// [`start()`](crate::start), before the listening socket is bound.
+    /// If one of them fails, the server does not start, and `start()` returns a
+    /// [`StartError`](crate::StartError) for the task's error. The original error can be recovered
+    /// by converting it into an [`io::Error`] and calling [`io::Error::into_inner`].
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let config = ServerConfig::new().on_start(|| {
+    ///     std::fs::read_to_string("templates/missing.html")?;
+    ///     Ok::<_, std::io::Error>(())
+    /// });
+    ///
+    /// let err = vintage::start(config, "localhost:0").unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    /// ```
+    pub fn on_start<C, E>(mut self, task: C) -> Self
+    where
+        C: Fn() -> Result<(), E> + Send + Sync + 'static,
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        self.on_start
+            .push(Arc::new(move || task().map_err(Into::into)));
+        self
+    }
+
+    /// Registers a callback that runs on the server thread once the listening socket is bound, but
+    /// before any connection is accepted
+    ///
+    /// This is where a server started as root can give up its privileges, once it has bound a
+    /// privileged port. The [`privileges`](crate::privileges) module has helpers for that.
+    ///
+    /// Callbacks run in the order they were registered.
+    /// If one of them fails, [`start()`](crate::start) returns its error, and the server does
+    /// not start.
+    ///
+    /// ```no_run
+    /// use vintage::privileges::{self, User};
+    /// use vintage::ServerConfig;
+    ///
+    /// // The user must be looked up before `chroot`, which hides `/etc/passwd`
+    /// let user = User::lookup("www-data").unwrap();
+    ///
+    /// let config = ServerConfig::new().before_serve(move || {
+    ///     privileges::chroot("/srv/app")?;
+    ///     user.switch()
+    /// });
+    ///
+    /// let handle = vintage::start(config, "0.0.0.0:80").unwrap();
+    /// ```
+    pub fn before_serve<C>(mut self, callback: C) -> Self
+    where
+        C: Fn() -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.before_serve.push(Arc::new(callback));
+        self
+    }
+
+    /// Registers a job that runs every `interval` while the server is running (e.g. evicting
+    /// expired cache entries, or flushing metrics)
+    ///
+    /// Jobs run one after the other on a dedicated thread, so they never hold up requests, but a
+    /// slow job delays the others. Runs missed while a job was busy are skipped.
+    /// The first run happens one `interval` after the server starts.
+    /// A job that panics is logged, and still runs at its next interval.
+    ///
+    /// Jobs are stopped when the server shuts down. [`ServerHandle::stop`] waits for the job
+    /// that is currently running, if any, to complete.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use vintage::ServerConfig;
+    ///
+    /// let config = ServerConfig::new().every(Duration::from_secs(60), || {
+    ///     log::info!("Still alive");
+    /// });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero
+    ///
+    /// [`ServerHandle::stop`]: crate::ServerHandle::stop
+    pub fn every<C>(mut self, interval: Duration, job: C) -> Self
+    where
+        C: Fn() + Send + Sync + 'static,
+    {
+        assert!(!interval.is_zero(), "A job can't run every 0 seconds");
+        self.schedules.push(Schedule {
+            interval,
+            job: Arc::new(job),
+        });
+        self
+    }
+
+    /// Registers a middleware that wraps the handling of requests
+    ///
+    /// The middleware receives the request, and the rest of the chain as [`Next`].
+    /// It can modify the request before calling [`Next::run`], modify the response returned by
+    /// it, or respond on its own without calling it at all.
+    ///
+    /// Middleware runs in the order it was registered, around route handlers and the
+    /// [`ServerConfig::unhandled`] callback.
+    /// Static files and assets are served without going through middleware.
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .middleware(|req, next| {
+    ///         if req.header("Authorization").is_none() {
+    ///             return Response::new().set_status(401);
+    ///         }
+    ///         next.run(req).set_header("X-Frame-Options", "DENY")
+    ///     })
+    ///     .on_get(["/"], |_req, _params| Response::text("hello"));
+    /// ```
+    pub fn middleware<C>(mut self, middleware: C) -> Self
+    where
+        C: Fn(&mut Request, Next) -> Response,
+        C: 'static + Send + Sync,
+    {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Compresses responses that are not static files or assets, when clients accept it
+    ///
+    /// This registers a middleware. It should be registered before other middleware, so that
+    /// their responses are compressed too. See [`Compression`]
+    #[cfg(feature = "compression")]
+    pub fn compression(self, compression: Compression) -> Self {
+        self.middleware(move |req, next| {
+            compression.strip_etag_suffixes(req);
+            let response = next.run(req);
+            compression.compress(req, response)
+        })
+    }
+
+    /// Decompresses the bodies of requests sent with a `Content-Encoding`
+    ///
+    /// This registers a middleware, so only middleware registered after it sees the decompressed
+    /// body. See [`Decompression`]
+    #[cfg(feature = "compression")]
+    pub fn decompression(self, decompression: Decompression) -> Self {
+        self.middleware(move |req, next| match decompression.decompress(req) {
+            Some(rejection) => rejection,
+            None => next.run(req),
+        })
+    }
+
+    /// Requires HTTP Basic authentication for requests
+    ///
+    /// This registers a middleware, so middleware registered before it sees requests before they
+    /// are authenticated. See [`BasicAuth`]
+    pub fn basic_auth(self, auth: BasicAuth) -> Self {
+        self.middleware(move |req, next| auth.respond(req, next))
+    }
+
+    /// Requires users to log in with a form
+    ///
+    /// This registers a middleware, so middleware registered before it sees requests before they
+    /// are authenticated. Cookie signing keys must be set with [`ServerConfig::keys`].
+    /// See [`FormLogin`]
+    #[cfg(feature = "signed-cookies")]
+    pub fn form_login(self, login: FormLogin) -> Self {
+        self.middleware(move |req, next| login.respond(req, next))
+    }
+
+    /// Replays the response to `POST` requests sent again with the same `Idempotency-Key`
+    /// header
+    ///
+    /// This registers a middleware. See [`Idempotency`]
+    pub fn idempotency(self, idempotency: Idempotency) -> Self {
+        self.middleware(move |req, next| idempotency.respond(req, |req| next.run(req)))
+    }
+
+    /// Sends a copy of requests to another FastCGI server, without affecting their responses
+    ///
+    /// This registers a middleware. Requests are copied as they reach it, so middleware
+    /// registered before it can still change them. See [`Mirror`]
+    pub fn mirror(self, mirror: Mirror) -> Self {
+        self.middleware(move |req, next| {
+            mirror.copy(req);
+            next.run(req)
+        })
+    }
+
+    /// Determines the locale of requests handled by route handlers and the
+    /// [`ServerConfig::unhandled`] callback
+    ///
+    /// This registers a middleware. See [`LocaleNegotiation`]
+    pub fn locales(self, negotiation: LocaleNegotiation) -> Self {
+        self.middleware(move |req, next| match negotiation.negotiate(req) {
+            Some(redirect) => redirect,
+            None => next.run(req),
+        })
+    }
+
+    /// Rewrites the bodies of responses whose media type matches `predicate` (e.g. to minify HTML,
+    /// inject a snippet, or point asset URLs to a CDN)
+    ///
+    /// `predicate` is given the lowercased media type of the response, without its parameters
+    /// (e.g. `text/html` for `text/html; charset=utf-8`). `transform` is given the request and the
+    /// body, and returns the new body. A stale `Content-Length` is removed.
+    ///
+    /// Transforms run in the order they were registered, on the responses of route handlers and
+    /// the [`ServerConfig::unhandled`] callback, before any middleware sees them. This means
+    /// compression (see `ServerConfig::compression`) always applies to the transformed body.
+    /// Streamed and raw bodies, empty bodies, bodies that already have a `Content-Encoding`, and
+    /// `204`, `206` and `304` responses are left alone.
+    /// Static files and assets are served without being transformed.
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new()
+    ///     .transform_body(
+    ///         |media_type| media_type == "text/html",
+    ///         |_req, body| {
+    ///             let html = String::from_utf8_lossy(&body);
+    ///             html.replace("</body>", "<script src=\"/stats.js\"></script></body>")
+    ///                 .into_bytes()
+    ///         },
+    ///     )
+    ///     .on_get(["/"], |_req, _params| Response::html("<body>hello</body>"));
+    /// ```
+    pub fn transform_body<P, C>(mut self, predicate: P, transform: C) -> Self
+    where
+        P: Fn(&str) -> bool + Send + Sync + 'static,
+        C: Fn(&Request, Vec<u8>) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.body_transforms
+            .push((Arc::new(predicate), Arc::new(transform)));
+        self
+    }
+
+    // Applies the body transforms that match `response`
+    pub(crate) fn transform_body_of(&self, req: &Request, mut response: Response) -> Response {
+        if self.body_transforms.is_empty()
+            || response.stream.is_some()
+            || response.raw
+            || response.body.is_empty()
+            || response.has_header(headers::CONTENT_ENCODING)
+            || matches!(
+                response.status,
+                status::NO_CONTENT | status::PARTIAL_CONTENT | status::NOT_MODIFIED
+            )
+        {
+            return response;
+        }
+        let Some(media_type) = response.media_type() else {
+            return response;
+        };
+
+        let mut transformed = false;
+        for (predicate, transform) in &self.body_transforms {
+            if predicate(&media_type) {
+                response.body = transform(req, std::mem::take(&mut response.body));
+                transformed = true;
+            }
+        }
+        if transformed {
+            response
+                .headers
+                .retain(|name, _| !same_header_name(name, headers::CONTENT_LENGTH));
+        }
+        response
+    }
+
+    /// Registers a callback that will be invoked for any unhandled requests
+    pub fn unhandled<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&mut Request) -> Response,
+        C: 'static + Send + Sync,
+    {
+        self.fallback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Forwards any unhandled requests to the upstreams of `proxy`
+    ///
+    /// This replaces the callback registered with [`ServerConfig::unhandled`].
+    /// See [`Proxy`]
+    pub fn proxy(self, proxy: Proxy) -> Self {
+        self.unhandled(move |req| proxy.respond(req))
+    }
+}
+
+// Adds `nonce` to the directives of `policy` that govern inline scripts and styles.
+// `default-src` is their fallback when one of them is missing.
+#[cfg(feature = "csp-nonce")]
+fn add_csp_nonce(policy: &str, nonce: &str) -> String {
+    let name = |directive: &str| {
+        let name = directive.split_whitespace().next().unwrap_or_default();
+        name.to_ascii_lowercase()
+    };
+    let has = |wanted: &str| policy.split(';').any(|directive| name(directive) == wanted);
+    let fallback = !has("script-src") || !has("style-src");
+
+    policy
+        .split(';')
+        .map(|directive| match name(directive).as_str() {
+            "script-src" | "style-src" => format!("{} 'nonce-{nonce}'", directive.trim_end()),
+            "default-src" if fallback => format!("{} 'nonce-{nonce}'", directive.trim_end()),
+            _ => directive.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+// Escapes text to be included in HTML
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_status;
+    use crate::connection::{Connection, Packet};
+    use crate::error::Error;
+    use crate::httpdate;
+    use crate::record::*;
+    use crate::{ServerExitReason, ServerHandle};
+    use assert_matches::assert_matches;
+    use mio::net::TcpStream;
+    use std::backtrace::BacktraceStatus;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    macro_rules! records {
+        ($($record:expr),* $(,)?) => {{
+            let records: Vec<Record> = vec![$($record.into()),*];
+            records
+        }}
+    }
+
+    fn basic_params() -> Params {
+        Params::default()
+            .add("REQUEST_METHOD", "GET")
+            .add("PATH_INFO", "/")
+            .add("QUERY_STRING", "")
+    }
+
+    // Test that when we send `to_send` records to the server at `address`, we get back the
+    // `expected` records.
+    //
+    // The `Date` header of responses is left out of the comparison.
+    #[track_caller]
+    // Encodes `record` as a single packet of the request `request_id`
+    fn packet_of(request_id: u16, record: impl Into<Record>) -> Vec<u8> {
+        let record = record.into();
+        let mut content = vec![];
+        record.write_bytes(&mut content).unwrap();
+        let packet = Packet {
+            type_id: record.type_id(),
+            content,
+        };
+        let mut bytes = vec![];
+        packet.encode_for(request_id, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn assert_request(address: SocketAddr, to_send: Vec<Record>, mut expected: Vec<Record>) {
+        let socket = TcpStream::connect(address).unwrap();
+        let mut connection = Connection::try_from(socket).unwrap();
+
+        for record in to_send.iter() {
+            connection.write_record(record).unwrap();
+        }
+
+        loop {
+            if expected.is_empty() {
+                let result = connection.read_record();
+                assert_matches!(result, Err(Error::UnexpectedSocketClose(_)));
+                break;
+            }
+
+            match connection.read_record() {
+                Ok(Record::Stdout(stdout)) => {
+                    let stdout = Stdout(httpdate::without_date_header(&stdout.0));
+                    assert_eq!(Record::Stdout(stdout), expected.remove(0));
+                }
+                Ok(record) => {
+                    assert_eq!(record, expected.remove(0));
+                }
+                Err(err) => panic!("{err}"),
+            }
+        }
+    }
+
+    #[test]
+    fn get_values() {
+        let server = crate::start(ServerConfig::new(), "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                GetValues::default(),
+            },
+            records! {
+                GetValuesResult::default(),
+            },
+        );
+
+        assert_request(
+            server.address(),
+            records! {
+                GetValues::default().add("FCGI_MPXS_CONNS").add("VALUE_WE_DONT_KNOW"),
+            },
+            records! {
+                GetValuesResult::default().add("FCGI_MPXS_CONNS", "0"),
+            },
+        );
+    }
+
+    #[test]
+    fn management_values() {
+        let config = ServerConfig::new()
+            .management_value("APP_VERSION", || "1.0".to_string())
+            .management_value("APP_VERSION", || "2.0".to_string())
+            .management_value("FCGI_MPXS_CONNS", || "1".to_string());
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                GetValues::default()
+                    .add("APP_VERSION")
+                    .add("FCGI_MPXS_CONNS")
+                    .add("VALUE_WE_DONT_KNOW"),
+            },
+            records! {
+                GetValuesResult::default()
+                    .add("APP_VERSION", "2.0")
+                    .add("FCGI_MPXS_CONNS", "0"),
+            },
+        );
+    }
+
+    #[test]
+    fn other_requests_on_the_connection() {
+        use std::io::Read;
+
+        let config = ServerConfig::new().on_get(["/"], |_req, _params| Response::text("first"));
+        let server = crate::start(config, "localhost:0").unwrap();
+        let mut socket = std::net::TcpStream::connect(server.address()).unwrap();
+        let mut connection =
+            Connection::try_from(TcpStream::from_std(socket.try_clone().unwrap())).unwrap();
+
+        connection
+            .write_record(&BeginRequest::new(Role::Responder, false).into())
+            .unwrap();
+        // The same request begins again, then another request begins
+        connection
+            .write_record(&BeginRequest::new(Role::Responder, false).into())
+            .unwrap();
+        let begin_other = BeginRequest::new(Role::Responder, false);
+        socket.write_all(&packet_of(2, begin_other)).unwrap();
+        socket.write_all(&packet_of(2, Params::default())).unwrap();
+        connection.write_record(&basic_params().into()).unwrap();
+        connection.write_record(&Stdin(vec![]).into()).unwrap();
+
+        // The other request is ended with `FCGI_CANT_MPX_CONN`
+        let rejection = EndRequest::new(0, ProtocolStatus::MultiplexingUnsupported);
+        let mut received = vec![0; 16];
+        socket.read_exact(&mut received).unwrap();
+        assert_eq!(received, packet_of(2, rejection));
+
+        // The first request is served
+        let Record::Stdout(stdout) = connection.read_record().unwrap() else {
+            panic!("expected stdout");
+        };
+        assert_eq!(
+            httpdate::without_date_header(&stdout.0),
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nfirst"
+        );
+        assert_eq!(
+            connection.read_record().unwrap(),
+            EndRequest::new(0, ProtocolStatus::RequestComplete).into()
+        );
+    }
+
+    #[test]
+    fn only_another_request() {
+        use std::io::Read;
+
+        // Long enough that the connection is not closed for taking too long
+        let config = ServerConfig::new().handshake_timeout(Duration::from_secs(60));
+        let server = crate::start(config, "localhost:0").unwrap();
+        let mut socket = std::net::TcpStream::connect(server.address()).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // Only request 1 can be served, so the connection is closed
+        let begin = BeginRequest::new(Role::Responder, false);
+        socket.write_all(&packet_of(2, begin)).unwrap();
+        socket.write_all(&packet_of(2, basic_params())).unwrap();
+        let result = socket.read(&mut [0; 8]);
+        assert!(
+            matches!(&result, Ok(0))
+                || matches!(&result, Err(e) if e.kind() == io::ErrorKind::ConnectionReset),
+            "{result:?}"
+        );
+    }
+
+    #[test]
     fn unsupported_keepalive() {
         let server = crate::start(ServerConfig::new(), "localhost:0").unwrap();
 
         assert_request(
             server.address(),
             records! {
-                BeginRequest::new(Role::Responder, true),
+                BeginRequest::new(Role::Responder, true),
+                basic_params(),
+                Stdin(vec![])
+            },
+            records! {
+                EndRequest::new(0, ProtocolStatus::MultiplexingUnsupported)
+            },
+        );
+    }
+
+    #[test]
+    fn successful_responder_flow() {
+        // A server that echoes the body
+        let config = ServerConfig::new().unhandled(|req| {
+            let body = std::mem::take(&mut req.body);
+            Response::default().set_raw_body(body)
+        });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(b"BAR".to_vec())
+            },
+            records! {
+                Stdout(b"Status: 200\r\n\r\nBAR".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn request_uri_without_path_info() {
+        // Echoes the path and query string
+        let config = ServerConfig::new()
+            .unhandled(|req| Response::text(format!("{}?{}", req.path(), req.query_string)));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        // The default lighttpd configuration
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                Params::default()
+                    .add("REQUEST_METHOD", "GET")
+                    .add("SCRIPT_NAME", "/hello")
+                    .add("REQUEST_URI", "/hello?name=world"),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\n/hello?name=world".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn header_case() {
+        // Echoes the names of the request headers
+        let config = ServerConfig::new()
+            .header_case(HeaderCase::Original)
+            .unhandled(|req| {
+                let names: Vec<_> = req.headers.keys().cloned().collect();
+                assert_eq!(req.header("dnt"), Some("1"));
+                Response::text(names.join(","))
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("HTTP_DNT", "1").add("HTTP_X_API_KEY", "k"),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nDNT,X-API-KEY".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn middleware() {
+        let config = ServerConfig::new()
+            .locales(LocaleNegotiation::new(["en", "fr"]))
+            .middleware(|req, next| {
+                let locale = req.ext::<crate::Locale>().unwrap().0.clone();
+                next.run(req).set_header("Content-Language", locale)
+            })
+            .on_get(["/about"], |req, _params| {
+                Response::text(req.path().to_string())
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                Params::default()
+                    .add("REQUEST_METHOD", "GET")
+                    .add("PATH_INFO", "/fr/about")
+                    .add("QUERY_STRING", ""),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Content-Language: fr\r\nContent-Type: text/plain\r\nStatus: 200\r\n\r\n/about".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn transform_body() {
+        let config = ServerConfig::new()
+            .compression(Compression::new().min_size(0))
+            .transform_body(
+                |media_type| media_type == "text/html",
+                |req, body| [body, req.path().as_bytes().to_vec()].concat(),
+            )
+            .transform_body(|media_type| media_type == "text/css", |_req, _body| vec![])
+            .on_get(["/"], |_req, _params| {
+                Response::html("<p>hello</p>")
+                    .set_header("Content-Type", "Text/HTML; charset=utf-8")
+                    .set_header("Content-Length", "12")
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        // Compression applies to the transformed body
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::new(6));
+        encoder.write_all(b"<p>hello</p>/").unwrap();
+        let mut expected = b"Content-Encoding: gzip\r\nContent-Type: Text/HTML; charset=utf-8\r\nVary: Accept-Encoding\r\nStatus: 200\r\n\r\n".to_vec();
+        expected.extend(encoder.finish().unwrap());
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("HTTP_ACCEPT_ENCODING", "gzip"),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(expected),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compression() {
+        let config = ServerConfig::new()
+            .compression(Compression::new().min_size(0))
+            .on_get(["/"], |_req, _params| Response::text("hello"));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::new(6));
+        encoder.write_all(b"hello").unwrap();
+        let mut expected = b"Content-Encoding: gzip\r\nContent-Type: text/plain\r\nVary: Accept-Encoding\r\nStatus: 200\r\n\r\n".to_vec();
+        expected.extend(encoder.finish().unwrap());
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("HTTP_ACCEPT_ENCODING", "gzip"),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(expected),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn decompression() {
+        let config = ServerConfig::new()
+            .decompression(Decompression::new())
+            .on_post(["/"], |req, _params| {
+                Response::default().set_raw_body(req.take_body())
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::new(6));
+        encoder.write_all(b"hello").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                Params::default()
+                    .add("REQUEST_METHOD", "POST")
+                    .add("PATH_INFO", "/")
+                    .add("QUERY_STRING", "")
+                    .add("HTTP_CONTENT_ENCODING", "gzip"),
+                Stdin(encoder.finish().unwrap())
+            },
+            records! {
+                Stdout(b"Status: 200\r\n\r\nhello".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    fn authenticator(username: &str, password: &str) -> Option<String> {
+        crate::auth::constant_time_eq(password.as_bytes(), b"pw").then(|| username.to_string())
+    }
+
+    // Answers with how the request was authenticated, and as whom
+    fn whoami(req: &mut Request, _params: RouteParams) -> Response {
+        Response::text(format!(
+            "{} {}",
+            req.var("AUTH_TYPE").unwrap_or("-"),
+            req.var("REMOTE_USER").unwrap_or("-")
+        ))
+    }
+
+    fn send(
+        server: &ServerHandle,
+        method: &str,
+        path: &str,
+        header: Option<(&str, &str)>,
+    ) -> Response {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let req = Request {
+            method: method.into(),
+            path: path.into(),
+            query_string: query.into(),
+            headers: header
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ..Request::default()
+        };
+        crate::Client::new(server.address()).send(&req).unwrap()
+    }
+
+    #[test]
+    fn basic_authentication() {
+        let config = ServerConfig::new()
+            .basic_auth(BasicAuth::new("test", authenticator).except(["/health"]))
+            .on_get(["/", "/health"], whoami);
+        let server = crate::start(config, "localhost:0").unwrap();
+        let response = send(&server, "GET", "/", None);
+        assert_eq!(response.status, status::UNAUTHORIZED);
+        assert_eq!(
+            response.headers["WWW-Authenticate"],
+            "Basic realm=\"test\", charset=\"UTF-8\""
+        );
+        // `ferris:pw`, then `ferris:nope`
+        let response = send(
+            &server,
+            "GET",
+            "/",
+            Some(("Authorization", "Basic ZmVycmlzOnB3")),
+        );
+        assert_eq!(response.body, b"Basic ferris");
+        let wrong = Some(("Authorization", "Basic ZmVycmlzOm5vcGU="));
+        assert_eq!(
+            send(&server, "GET", "/", wrong).status,
+            status::UNAUTHORIZED
+        );
+        assert_eq!(send(&server, "GET", "/health", None).body, b"- -");
+        server.stop();
+    }
+
+    #[test]
+    #[cfg(feature = "signed-cookies")]
+    fn form_login() {
+        let log_in = |server: &ServerHandle, form: &str| {
+            let req = Request {
+                method: "POST".into(),
+                path: "/login".into(),
+                headers: [(
+                    "Content-Type".to_string(),
+                    "application/x-www-form-urlencoded".to_string(),
+                )]
+                .into(),
+                body: form.as_bytes().to_vec(),
+                ..Request::default()
+            };
+            crate::Client::new(server.address()).send(&req).unwrap()
+        };
+
+        let config = ServerConfig::new()
+            .keys("key", [])
+            .form_login(FormLogin::new("/login", authenticator))
+            .on_get(["/", "/login"], whoami);
+        let server = crate::start(config, "localhost:0").unwrap();
+        let response = send(&server, "GET", "/?tab=1", None);
+        assert_eq!(response.status, status::SEE_OTHER);
+        assert_eq!(response.headers["Location"], "/login?next=%2F%3Ftab%3D1");
+        assert_eq!(
+            send(&server, "POST", "/", None).status,
+            status::UNAUTHORIZED
+        );
+        assert_eq!(send(&server, "GET", "/login", None).body, b"- -");
+
+        let response = log_in(&server, "username=ferris&password=no&next=/a");
+        assert_eq!(response.headers["Location"], "/login?error=1&next=%2Fa");
+        // Redirects off the site are ignored
+        let response = log_in(&server, "username=ferris&password=pw&next=//evil");
+        assert_eq!(response.headers["Location"], "/");
+        let cookie = response.headers["Set-Cookie"].split(';').next().unwrap();
+        assert!(cookie.starts_with("login=ferris."));
+
+        let response = send(&server, "GET", "/", Some(("Cookie", cookie)));
+        assert_eq!(response.body, b"Form ferris");
+        let forged = cookie.replacen("ferris", "admin", 1);
+        let response = send(&server, "GET", "/", Some(("Cookie", &forged)));
+        assert_eq!(response.status, status::SEE_OTHER);
+        for next in ["/%09/evil", "/%0D%0ALocation:%20//evil"] {
+            let response = log_in(&server, &format!("username=ferris&password=pw&next={next}"));
+            assert_eq!(response.headers["Location"], "/");
+        }
+        server.stop();
+
+        let clock =
+            crate::TestClock::new(httpdate::parse("Sun, 06 Nov 1994 08:49:37 GMT").unwrap());
+        let login = FormLogin::new("/login", authenticator).max_age(Duration::from_secs(60));
+        let config = ServerConfig::new()
+            .keys("key", [])
+            .clock(clock.clone())
+            .form_login(login)
+            .on_get(["/", "/login"], whoami);
+        let server = crate::start(config, "localhost:0").unwrap();
+        let response = log_in(&server, "username=ferris&password=pw");
+        let set_cookie = &response.headers["Set-Cookie"];
+        assert!(set_cookie.contains("; Max-Age=60;"));
+        let cookie = set_cookie.split(';').next().unwrap();
+        clock.advance(Duration::from_secs(59));
+        let response = send(&server, "GET", "/", Some(("Cookie", cookie)));
+        assert_eq!(response.body, b"Form ferris");
+        clock.advance(Duration::from_secs(1));
+        let response = send(&server, "GET", "/", Some(("Cookie", cookie)));
+        assert_eq!(response.status, status::SEE_OTHER);
+        server.stop();
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn file_responses() {
+        assert!(Response::file("src/missing.rs").is_err());
+        assert!(Response::file("src").is_err());
+
+        let config = ServerConfig::new()
+            .on_get(["/license"], |_req, _params| {
+                Response::file("LICENSE")
+                    .unwrap()
+                    .set_header("Content-Disposition", "attachment")
+            })
+            .on_get(["/manifest"], |_req, _params| {
+                Response::file("Cargo.toml")
+                    .unwrap()
+                    .set_header("content-type", "text/x-toml")
+            })
+            // Middleware sees the contents of the file
+            .middleware(|req, next| {
+                let response = next.run(req);
+                let len = response.body.len();
+                response.set_header("X-Length", len.to_string())
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+        let client = crate::Client::new(server.address());
+        let get = |path: &str, headers: &[(&str, &str)]| {
+            let req = Request {
+                method: "GET".into(),
+                path: path.into(),
+                headers: headers
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                ..Request::default()
+            };
+            client.send(&req).unwrap()
+        };
+
+        let license = std::fs::read("LICENSE").unwrap();
+        let response = get("/license", &[]);
+        assert_eq!(response.status, status::OK);
+        assert_eq!(response.body, license);
+        assert_eq!(response.headers["Content-Type"], "application/octet-stream");
+        assert_eq!(response.headers["Content-Disposition"], "attachment");
+        assert_eq!(response.headers["X-Length"], license.len().to_string());
+
+        let etag = response.headers["ETag"].clone();
+        let response = get("/license", &[("If-None-Match", &etag)]);
+        assert_eq!(response.status, status::NOT_MODIFIED);
+        assert!(response.body.is_empty());
+
+        let response = get("/license", &[("Range", "bytes=0-9")]);
+        assert_eq!(response.status, status::PARTIAL_CONTENT);
+        assert_eq!(response.body, license[..10]);
+        assert_eq!(
+            response.headers["Content-Range"],
+            format!("bytes 0-9/{}", license.len())
+        );
+
+        // The content type set by the handler replaces the guessed one
+        let response = get("/manifest", &[]);
+        let content_types: Vec<_> = response
+            .headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value.as_str())
+            .collect();
+        assert_eq!(content_types, ["text/x-toml"]);
+        server.stop();
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn dispatch_order() {
+        // Returns whether `path` was handled by a route rather than the file server
+        let routed = |order: DispatchOrder, path: &str| {
+            let config = ServerConfig::new()
+                .serve_files("/", "src")
+                .on_get(["/lib.rs", "/{page}"], |_req, _params| {
+                    Response::text("route")
+                })
+                .dispatch_order(order);
+            let server = crate::start(config, "localhost:0").unwrap();
+
+            let socket = TcpStream::connect(server.address()).unwrap();
+            let mut connection = Connection::try_from(socket).unwrap();
+            let params = basic_params().add("PATH_INFO", path);
+            for record in records![
+                BeginRequest::new(Role::Responder, false),
+                params,
+                Stdin(vec![])
+            ] {
+                connection.write_record(&record).unwrap();
+            }
+            let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+                panic!("Expected a response");
+            };
+            server.stop();
+            stdout.0.ends_with(b"\r\n\r\nroute")
+        };
+
+        assert!(!routed(DispatchOrder::FilesFirst, "/lib.rs"));
+        assert!(routed(DispatchOrder::RouterFirst, "/lib.rs"));
+        assert!(routed(DispatchOrder::RouterFirst, "/missing.rs"));
+        assert!(routed(DispatchOrder::MostSpecific, "/lib.rs"));
+        assert!(!routed(DispatchOrder::MostSpecific, "/router.rs"));
+    }
+
+    #[test]
+    fn webdav_methods() {
+        // Echoes the body of PROPFIND requests
+        let config = ServerConfig::new()
+            .on(crate::method::PROPFIND, ["/dav/{*path}"], |req, _params| {
+                Response::new()
+                    .set_status(207)
+                    .set_raw_body(req.take_body())
+            })
+            .on_get(["/dav/{*path}"], |_req, _params| Response::new());
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let params = |method| {
+            Params::default()
+                .add("REQUEST_METHOD", method)
+                .add("PATH_INFO", "/dav/a")
+                .add("QUERY_STRING", "")
+        };
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                params("PROPFIND"),
+                Stdin(b"<propfind/>".to_vec()),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Status: 207\r\n\r\n<propfind/>".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                params("MKCOL"),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Allow: GET, PROPFIND\r\nStatus: 405\r\n\r\n".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn connection_memory_limit() {
+        let config = ServerConfig::new().max_connection_memory(64);
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        // The connection is closed without a response
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(vec![b'A'; 64])
+            },
+            records! {},
+        );
+    }
+
+    #[test]
+    fn param_limits() {
+        let config = ServerConfig::new().max_params(4).max_param_sizes(32, 8);
+        let server = crate::start(config, "localhost:0").unwrap();
+        let too_large = |limit: &str| {
+            records! {
+                Stderr(format!("vintage: The request params exceed the limit on {limit}\n").into_bytes()),
+                Stderr(vec![]),
+                Stdout(b"Content-Type: text/plain\r\nStatus: 431\r\n\r\nRequest Header Fields Too Large".to_vec()),
+                EndRequest::new(app_status::REQUEST_FAILED, ProtocolStatus::RequestComplete)
+            }
+        };
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("HTTP_COOKIE", "a=123456789"),
+                Stdin(vec![])
+            },
+            too_large("param value size"),
+        );
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("HTTP_A", "1").add("HTTP_B", "2"),
+                Stdin(vec![])
+            },
+            too_large("number of params"),
+        );
+    }
+
+    #[test]
+    fn drain_unread_body() {
+        let config = ServerConfig::new()
+            .max_params(4)
+            .unread_body(UnreadBody::Drain(4 * 1024 * 1024));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        // More than what is discarded when the connection is closed
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("HTTP_A", "1").add("HTTP_B", "2"),
+                Stdin(vec![b'A'; 2 * 1024 * 1024])
+            },
+            records! {
+                Stderr(b"vintage: The request params exceed the limit on number of params\n".to_vec()),
+                Stderr(vec![]),
+                Stdout(b"Content-Type: text/plain\r\nStatus: 431\r\n\r\nRequest Header Fields Too Large".to_vec()),
+                EndRequest::new(app_status::REQUEST_FAILED, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        use std::io::Read;
+
+        let config = ServerConfig::new().handshake_timeout(Duration::from_millis(200));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        // Each byte comes well within the read timeout, but the record never completes
+        let mut socket = std::net::TcpStream::connect(server.address()).unwrap();
+        for byte in [1, FCGI_BEGIN_REQUEST, 0, 1, 0, 8] {
+            if socket.write_all(&[byte]).is_err() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        // The server gave up on the connection instead of waiting for more
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let result = socket.read(&mut [0; 8]);
+        assert!(
+            matches!(&result, Ok(0))
+                || matches!(&result, Err(e) if e.kind() == io::ErrorKind::ConnectionReset),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn buffer_sizes() {
+        let config = ServerConfig::new()
+            .buffer_sizes(16, 16)
+            .on_post(["/"], |req, _params| {
+                Response::default().set_raw_body(req.take_body())
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let body = (0..5000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut expected = b"Status: 200\r\n\r\n".to_vec();
+        expected.extend_from_slice(&body);
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("REQUEST_METHOD", "POST"),
+                Stdin(body)
+            },
+            records! {
+                Stdout(expected),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn memory_budget() {
+        let config = ServerConfig::new()
+            .memory_budget(256, MemoryPolicy::Shed)
+            .on_post(["/"], |req, _params| {
+                Response::text(format!("{} bytes", req.take_body().len()))
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+        let params = || basic_params().add("REQUEST_METHOD", "POST");
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                params(),
+                Stdin(vec![b'A'; 64])
+            },
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\n64 bytes".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+
+        // The memory of the first request was released, but this one doesn't fit on its own
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                params(),
+                Stdin(vec![b'A'; 256])
+            },
+            records! {
+                Stderr(b"vintage: The server's memory budget can't fit the request\n".to_vec()),
+                Stderr(vec![]),
+                Stdout(b"Content-Type: text/plain\r\nStatus: 503\r\n\r\nService Unavailable".to_vec()),
+                EndRequest::new(app_status::REJECTED, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn stalled_request() {
+        use std::io::Read;
+
+        let server = crate::start(ServerConfig::new(), "localhost:0").unwrap();
+
+        // The params never end
+        let mut bytes = vec![];
+        for record in records! {
+            BeginRequest::new(Role::Responder, false),
+            basic_params()
+        } {
+            crate::connection::encode_record(&record, &mut bytes).unwrap();
+        }
+        // Drops the empty packet that ends the params
+        bytes.truncate(bytes.len() - 8);
+
+        let mut socket = std::net::TcpStream::connect(server.address()).unwrap();
+        socket.write_all(&bytes).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut response = vec![];
+        socket.read_to_end(&mut response).unwrap();
+
+        let mut connection = Connection::Test(std::collections::VecDeque::from(response));
+        let mut records = vec![];
+        while let Ok(record) = connection.read_record() {
+            records.push(record);
+        }
+        let Record::Stdout(stdout) = &records[2] else {
+            panic!("expected a Stdout record");
+        };
+        assert_eq!(
+            httpdate::without_date_header(&stdout.0),
+            b"Content-Type: text/plain\r\nStatus: 408\r\n\r\nRequest Timeout"
+        );
+        assert_eq!(
+            records[3],
+            EndRequest::new(app_status::TIMED_OUT, ProtocolStatus::RequestComplete).into()
+        );
+
+        // The request is counted by its response only
+        let counters = server.counters();
+        assert_eq!(counters.responses_4xx, 1);
+        assert_eq!(counters.protocol_errors, 0);
+    }
+
+    #[test]
+    fn circuit_breaker() {
+        use crate::circuit_breaker::CircuitState;
+
+        let config = ServerConfig::new()
+            .circuit_breaker(["/flaky"], CircuitBreaker::new(1, Duration::from_secs(60)))
+            .on_get(["/flaky"], |_req, _params| Response::new().set_status(500));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let request = || {
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("PATH_INFO", "/flaky"),
+                Stdin(vec![])
+            }
+        };
+        assert_request(
+            server.address(),
+            request(),
+            records! {
+                Stdout(b"Status: 500\r\n\r\n".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+        assert_request(
+            server.address(),
+            request(),
+            records! {
+                Stdout(b"Retry-After: 60\r\nStatus: 503\r\n\r\n".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+
+        let circuits = server.circuits();
+        assert_eq!(circuits.len(), 1);
+        assert_eq!(circuits["/flaky"], CircuitState::Open);
+    }
+
+    #[test]
+    fn route_params() {
+        let route = |config: ServerConfig| {
+            config
+                .on_get(["/files/{name}"], |_req, params| {
+                    Response::text(&params["name"])
+                })
+                .on_get(
+                    ["/users/{id}"],
+                    |_req, params| match crate::routing::parse_param::<u64>(&params, "id") {
+                        Ok(id) => Response::text(format!("user {id}")),
+                        Err(err) => err.into(),
+                    },
+                )
+                .on_param_error(|req, err| {
+                    Response::text(format!("{}: {err}", req.path())).set_status(status::BAD_REQUEST)
+                })
+        };
+        let get = |server: &ServerHandle, params: Params, expected: &[u8]| {
+            assert_request(
+                server.address(),
+                records! {
+                    BeginRequest::new(Role::Responder, false),
+                    params,
+                    Stdin(vec![])
+                },
+                records! {
+                    Stdout(expected.to_vec()),
+                    EndRequest::new(0, ProtocolStatus::RequestComplete)
+                },
+            );
+        };
+
+        // The path is decoded once, by the web server or the path mapping
+        let server = crate::start(route(ServerConfig::new()), "localhost:0").unwrap();
+        get(
+            &server,
+            basic_params().add("PATH_INFO", "/files/%2e%2e%2fsecret"),
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\n%2e%2e%2fsecret",
+        );
+        get(
+            &server,
+            basic_params()
+                .add("PATH_INFO", "")
+                .add("REQUEST_URI", "/files/%252e%252e%252fsecret"),
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\n%2e%2e%2fsecret",
+        );
+        get(
+            &server,
+            basic_params().add("PATH_INFO", "/users/7"),
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nuser 7",
+        );
+        get(
+            &server,
+            basic_params().add("PATH_INFO", "/users/me"),
+            b"Content-Type: text/plain\r\nStatus: 400\r\n\r\n/users/me: The 'id' param is invalid ('me'): invalid digit found in string",
+        );
+
+        // The raw path is routed, and params are decoded within their segment
+        let raw_path = PathMapping::custom(|params| {
+            let uri = params.get("REQUEST_URI")?;
+            Some(uri.split('?').next().unwrap_or_default().to_string())
+        });
+        let config = ServerConfig::new()
+            .path_mapping(raw_path)
+            .decode_route_params();
+        let server = crate::start(route(config), "localhost:0").unwrap();
+        let get_uri = |uri: &str, expected: &[u8]| {
+            get(&server, basic_params().add("REQUEST_URI", uri), expected);
+        };
+        get_uri(
+            "/files/my%20file.txt",
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nmy file.txt",
+        );
+        get_uri(
+            "/files/a%2Fb",
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\na/b",
+        );
+        get_uri(
+            "/files/%252e%252e",
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\n%2e%2e",
+        );
+        get_uri(
+            "/files/%FF",
+            b"Content-Type: text/plain\r\nStatus: 400\r\n\r\n/files/%FF: The 'name' param is not valid UTF-8",
+        );
+    }
+
+    #[test]
+    fn params_after_early_end() {
+        // The params stream may be ended early, and then continued before the body
+        let config = ServerConfig::new().on_get(["/"], |req, _params| {
+            Response::text(req.header("X-Late").unwrap_or("missing"))
+        });
+        let server = crate::start(config, "localhost:0").unwrap();
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Params::default().add("HTTP_X_LATE", "here"),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nhere".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+
+        let config = ServerConfig::new()
+            .max_params(3)
+            .on_get(["/"], |_req, _params| Response::default());
+        let server = crate::start(config, "localhost:0").unwrap();
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Params::default().add("HTTP_X_LATE", "here"),
+                Stdin(vec![])
+            },
+            records! {
+                Stderr(b"vintage: The request params exceed the limit on number of params\n".to_vec()),
+                Stderr(vec![]),
+                Stdout(b"Content-Type: text/plain\r\nStatus: 431\r\n\r\nRequest Header Fields Too Large".to_vec()),
+                EndRequest::new(app_status::REQUEST_FAILED, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn handler_panics() {
+        let reports = Arc::new(std::sync::Mutex::new(vec![]));
+        let config = ServerConfig::new()
+            .on_get(["/panic"], |_req, _params| panic!("handler bug"))
+            .on_get(["/stream"], |_req, _params| {
+                Response::default().set_body_stream(|writer| {
+                    writer.write_all(b"partial")?;
+                    panic!("stream bug")
+                })
+            })
+            .on_get(["/"], |_req, _params| Response::text("still up"))
+            .on_panic({
+                let reports = reports.clone();
+                move |req, report| {
+                    let captured = report.backtrace().status() == BacktraceStatus::Captured;
+                    let report = format!("{} {report} {captured}", req.path());
+                    reports.lock().unwrap().push(report);
+                }
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let get = |path: &str, expected: &[u8], app_status| {
+            assert_request(
+                server.address(),
+                records! {
+                    BeginRequest::new(Role::Responder, false),
+                    basic_params().add("PATH_INFO", path),
+                    Stdin(vec![])
+                },
+                records! {
+                    Stdout(expected.to_vec()),
+                    EndRequest::new(app_status, ProtocolStatus::RequestComplete)
+                },
+            );
+        };
+        get(
+            "/panic",
+            b"Content-Type: text/plain\r\nStatus: 500\r\n\r\nInternal Server Error",
+            app_status::HANDLER_PANICKED,
+        );
+        get(
+            "/",
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nstill up",
+            app_status::OK,
+        );
+        // The headers were sent already, so the response is only cut short
+        get(
+            "/stream",
+            b"Status: 200\r\n\r\npartial",
+            app_status::HANDLER_PANICKED,
+        );
+        assert_eq!(
+            *reports.lock().unwrap(),
+            ["/panic handler bug true", "/stream stream bug true"]
+        );
+        assert_eq!(server.counters().responses_5xx, 1);
+    }
+
+    #[test]
+    fn route_stats() {
+        let config = ServerConfig::new()
+            .on_post(["/echo/{name}"], |req, _params| {
+                Response::default().set_raw_body(req.take_body())
+            })
+            .unhandled(|_req| Response::text("unhandled"));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let params = Params::default()
+            .add("REQUEST_METHOD", "POST")
+            .add("PATH_INFO", "/echo/a")
+            .add("QUERY_STRING", "");
+        let mut params_len = vec![];
+        params.write_record_bytes(&mut params_len).unwrap();
+
+        for _ in 0..2 {
+            assert_request(
+                server.address(),
+                records! {
+                    BeginRequest::new(Role::Responder, false),
+                    params.clone(),
+                    Stdin(b"hello".to_vec())
+                },
+                records! {
+                    Stdout(b"Status: 200\r\n\r\nhello".to_vec()),
+                    EndRequest::new(0, ProtocolStatus::RequestComplete)
+                },
+            );
+        }
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nunhandled".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+
+        let stats = server.route_stats();
+        assert_eq!(stats.len(), 1);
+        let echo = stats["/echo/{name}"];
+        assert_eq!(echo.requests, 2);
+        assert_eq!(echo.bytes_in, 2 * (params_len.len() as u64 + 5));
+        let date = b"Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n".len();
+        let response = b"Status: 200\r\n\r\nhello".len();
+        assert_eq!(echo.bytes_out, 2 * (date + response) as u64);
+        assert_eq!(server.write_errors(), 0);
+    }
+
+    #[test]
+    fn app_status() {
+        let config = ServerConfig::new()
+            .on_get(["/upstream"], |_req, _params| {
+                Response::new().set_status(502).set_app_status(256)
+            })
+            .on_get(["/stream"], |_req, _params| {
+                Response::new().set_body_stream(|writer| {
+                    writer.write_all(b"partial")?;
+                    Err(io::Error::other("the database went away"))
+                })
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let request = |path| {
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("PATH_INFO", path),
+                Stdin(vec![])
+            }
+        };
+        assert_request(
+            server.address(),
+            request("/upstream"),
+            records! {
+                Stdout(b"Status: 502\r\n\r\n".to_vec()),
+                EndRequest::new(256, ProtocolStatus::RequestComplete)
+            },
+        );
+        assert_request(
+            server.address(),
+            request("/stream"),
+            records! {
+                Stdout(b"Status: 200\r\n\r\npartial".to_vec()),
+                EndRequest::new(app_status::BODY_FAILED, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn counters() {
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let config = ServerConfig::new()
+            .on_get(["/slow"], {
+                let barrier = barrier.clone();
+                move |_req, _params| {
+                    barrier.wait();
+                    barrier.wait();
+                    Response::text("slow")
+                }
+            })
+            .on_get(["/moved"], |_req, _params| {
+                Response::temporary_redirect("/")
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+        let address = server.address();
+
+        let slow = std::thread::spawn(move || {
+            assert_request(
+                address,
+                records! {
+                    BeginRequest::new(Role::Responder, false),
+                    Params::default()
+                        .add("REQUEST_METHOD", "GET")
+                        .add("PATH_INFO", "/slow"),
+                    Stdin(vec![])
+                },
+                records! {
+                    Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nslow".to_vec()),
+                    EndRequest::new(0, ProtocolStatus::RequestComplete)
+                },
+            );
+        });
+        // The handler is running
+        barrier.wait();
+        assert_eq!(server.counters().active_connections, 1);
+        barrier.wait();
+        slow.join().unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                Params::default()
+                    .add("REQUEST_METHOD", "GET")
+                    .add("PATH_INFO", "/moved"),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Location: /\r\nStatus: 307\r\n\r\n".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+        for _ in 0..2 {
+            assert_request(
+                server.address(),
+                records! {
+                    BeginRequest::new(Role::Responder, false),
+                    basic_params(),
+                    Stdin(vec![])
+                },
+                records! {
+                    Stdout(b"Status: 404\r\n\r\n".to_vec()),
+                    EndRequest::new(0, ProtocolStatus::RequestComplete)
+                },
+            );
+        }
+        // A connection that doesn't begin with `FCGI_BEGIN_REQUEST` is closed
+        assert_request(server.address(), records! { Stdin(vec![]) }, records! {});
+
+        let counters = server.counters();
+        assert_eq!(counters.responses_2xx, 1);
+        assert_eq!(counters.responses_3xx, 1);
+        assert_eq!(counters.responses_4xx, 2);
+        assert_eq!(counters.responses_5xx, 0);
+        assert_eq!(counters.protocol_errors, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn additional_listeners() {
+        use crate::connection::Stream;
+        use crate::listener::ListenerInfo;
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("vintage-listen-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let admin = ServerConfig::new().on_get(["/"], |_req, _params| Response::text("admin"));
+        let config = ServerConfig::new()
+            .on_get(["/"], |_req, _params| Response::text("public"))
+            .listen(path.as_path(), admin);
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let listeners: Vec<_> = server.listeners().cloned().collect();
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0].tcp_address(), Some(server.address()));
+        assert_matches!(&listeners[1], ListenerInfo::Unix { path: p, .. } if *p == path);
+
+        let body = |mut connection: Connection| {
+            for record in records![
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(vec![])
+            ] {
+                connection.write_record(&record).unwrap();
+            }
+            let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+                panic!("Expected a response");
+            };
+            let stdout = String::from_utf8(stdout.0).unwrap();
+            stdout.split("\r\n\r\n").nth(1).unwrap().to_string()
+        };
+
+        let tcp = TcpStream::connect(server.address()).unwrap();
+        assert_eq!(body(Connection::try_from(tcp).unwrap()), "public");
+        let unix = UnixStream::connect(&path).unwrap();
+        assert_eq!(
+            body(Connection::try_from(Stream::Unix(unix)).unwrap()),
+            "admin"
+        );
+
+        // The socket file goes away with the server
+        server.stop();
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_file() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("vintage-file-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = |replace_stale| {
+            let config = ServerConfig::new()
+                .socket_permissions(0o660)
+                // SAFETY: `getgid` has no preconditions
+                .socket_owner(None, Some(unsafe { libc::getgid() }))
+                .replace_stale_socket(replace_stale);
+            ServerConfig::new().listen(path.as_path(), config)
+        };
+
+        // A socket file left behind by a server that is gone
+        drop(UnixListener::bind(&path).unwrap());
+        let err = crate::start(listener(false), "localhost:0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let server = crate::start(listener(true), "localhost:0").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+        // SAFETY: `getgid` has no preconditions
+        assert_eq!(metadata.gid(), unsafe { libc::getgid() });
+
+        // The socket of a running server is kept
+        let err = crate::start(listener(true), "localhost:0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+
+        server.stop();
+        assert!(!path.exists());
+
+        // A server that fails to start removes the socket files it created
+        let taken = std::net::TcpListener::bind("localhost:0").unwrap();
+        let err = crate::start(listener(false), taken.local_addr().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(!path.exists());
+    }
+
+    // A self-signed server certificate, and a client certificate signed by a CA
+    #[cfg(feature = "tls")]
+    struct TestCertificates {
+        server: rcgen::CertifiedKey,
+        ca: rcgen::CertifiedKey,
+        client: rcgen::CertifiedKey,
+    }
+
+    #[cfg(feature = "tls")]
+    impl TestCertificates {
+        fn new() -> Self {
+            use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, DnType, IsCa, KeyPair};
+
+            let key_pair = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec!["localhost".to_string()])
+                .unwrap()
+                .self_signed(&key_pair)
+                .unwrap();
+            let server = CertifiedKey { cert, key_pair };
+
+            let key_pair = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let cert = params.self_signed(&key_pair).unwrap();
+            let ca = CertifiedKey { cert, key_pair };
+
+            let key_pair = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["web".to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, "web-1");
+            let cert = params.signed_by(&key_pair, &ca.cert, &ca.key_pair).unwrap();
+            let client = CertifiedKey { cert, key_pair };
+
+            Self { server, ca, client }
+        }
+
+        fn config(&self, client_auth: Option<crate::ClientAuth>) -> crate::TlsConfig {
+            let tls = crate::TlsConfig::from_pem(
+                self.server.cert.pem().as_bytes(),
+                self.server.key_pair.serialize_pem().as_bytes(),
+            )
+            .unwrap();
+            match client_auth {
+                Some(policy) => tls
+                    .client_auth(policy, self.ca.cert.pem().as_bytes())
+                    .unwrap(),
+                None => tls,
+            }
+        }
+
+        // Sends a request over TLS, and returns the stdout of the response
+        fn request(&self, address: SocketAddr, with_certificate: bool) -> io::Result<String> {
+            use crate::connection::{encode_record, read_packet};
+            use rustls::pki_types::PrivateKeyDer;
+            use std::io::Write;
+
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(self.server.cert.der().clone()).unwrap();
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let builder = rustls::ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+            let client = if with_certificate {
+                let key = PrivateKeyDer::try_from(self.client.key_pair.serialize_der()).unwrap();
+                builder
+                    .with_client_auth_cert(vec![self.client.cert.der().clone()], key)
+                    .unwrap()
+            } else {
+                builder.with_no_client_auth()
+            };
+            let session =
+                rustls::ClientConnection::new(Arc::new(client), "localhost".try_into().unwrap())
+                    .unwrap();
+            let socket = std::net::TcpStream::connect(address).unwrap();
+            let mut tls = rustls::StreamOwned::new(session, socket);
+            for record in records![
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(vec![])
+            ] {
+                encode_record(&record, &mut tls)?;
+            }
+            tls.flush()?;
+
+            let mut stdout = vec![];
+            loop {
+                let packet =
+                    read_packet(&mut tls).map_err(|err| io::Error::other(err.to_string()))?;
+                if packet.type_id != FCGI_STDOUT {
+                    return Ok(String::from_utf8(stdout).unwrap());
+                }
+                stdout.extend(packet.content);
+            }
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_listener() {
+        let certificates = TestCertificates::new();
+        // Larger than the write buffer, and than what `rustls` buffers by default
+        let body = "tls".repeat(50_000);
+        let config = ServerConfig::new()
+            .on_get(["/"], move |_req, _params| Response::text(body.clone()))
+            .tls(certificates.config(Some(crate::ClientAuth::Required)));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let stdout = certificates.request(server.address(), true).unwrap();
+        assert!(stdout.ends_with(&format!("\r\n\r\n{}", "tls".repeat(50_000))));
+
+        // Clients without a certificate are turned away during the handshake
+        assert!(certificates.request(server.address(), false).is_err());
+
+        // So are clients that don't speak TLS
+        let socket = TcpStream::connect(server.address()).unwrap();
+        let mut connection = Connection::try_from(socket).unwrap();
+        connection
+            .write_record(&BeginRequest::new(Role::Responder, false).into())
+            .unwrap();
+        assert_matches!(
+            connection.read_record(),
+            Err(Error::UnexpectedSocketClose(_) | Error::UnsuportedVersion(_))
+        );
+
+        server.stop();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn client_identity() {
+        use crate::ClientAuth;
+
+        let certificates = TestCertificates::new();
+        let whoami = |req: &mut Request, _params: RouteParams| {
+            let name = req
+                .client_identity()
+                .map_or("anonymous", |client| client.common_name().unwrap());
+            Response::text(name)
+        };
+        let optional = ServerConfig::new()
+            .on_get(["/"], whoami)
+            .tls(certificates.config(Some(ClientAuth::Optional)));
+        let anonymous = ServerConfig::new()
+            .on_get(["/"], whoami)
+            .tls(certificates.config(None));
+        let any_port: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let config = ServerConfig::new()
+            .on_get(["/"], whoami)
+            .tls(certificates.config(Some(ClientAuth::Required)))
+            .listen(any_port, optional)
+            .listen(any_port, anonymous);
+        let server = crate::start(config, "localhost:0").unwrap();
+        let address = |i: usize| server.listeners().nth(i).unwrap().tcp_address().unwrap();
+        let (optional_address, anonymous_address) = (address(1), address(2));
+
+        let body = |address, with_certificate| {
+            let stdout = certificates.request(address, with_certificate);
+            stdout.map(|stdout| stdout.split("\r\n\r\n").nth(1).unwrap().to_string())
+        };
+        assert_eq!(body(server.address(), true).unwrap(), "web-1");
+        assert!(body(server.address(), false).is_err());
+        assert_eq!(body(optional_address, true).unwrap(), "web-1");
+        assert_eq!(body(optional_address, false).unwrap(), "anonymous");
+        // Listeners that don't ask for a certificate don't get one
+        assert_eq!(body(anonymous_address, true).unwrap(), "anonymous");
+
+        server.stop();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn abstract_listener() {
+        use crate::connection::Stream;
+        use crate::listener::ListenerInfo;
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixStream};
+
+        let name = format!("vintage-abstract-{}", std::process::id()).into_bytes();
+        let admin = ServerConfig::new().on_get(["/"], |_req, _params| Response::text("admin"));
+        let config = ServerConfig::new().listen(ListenAddress::Abstract(name.clone()), admin);
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let listener = server.listeners().nth(1).unwrap().clone();
+        assert_matches!(&listener, ListenerInfo::Abstract { name: n, .. } if *n == name);
+        assert_eq!(
+            listener.to_string(),
+            format!("unix:@vintage-abstract-{}", std::process::id())
+        );
+
+        let address = SocketAddr::from_abstract_name(&name).unwrap();
+        let unix = UnixStream::connect_addr(&address).unwrap();
+        let mut connection = Connection::try_from(Stream::Unix(unix)).unwrap();
+        for record in records![
+            BeginRequest::new(Role::Responder, false),
+            basic_params(),
+            Stdin(vec![])
+        ] {
+            connection.write_record(&record).unwrap();
+        }
+        let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+            panic!("Expected a response");
+        };
+        assert!(stdout.0.ends_with(b"\r\n\r\nadmin"));
+
+        // The name is taken until the server stops
+        let config =
+            ServerConfig::new().listen(ListenAddress::Abstract(name.clone()), ServerConfig::new());
+        let err = crate::start(config, "localhost:0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        server.stop();
+        let config = ServerConfig::new().listen(ListenAddress::Abstract(name), ServerConfig::new());
+        crate::start(config, "localhost:0").unwrap().stop();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn named_pipe_listener() {
+        use crate::connection::Stream;
+        use crate::listener::ListenerInfo;
+        use crate::named_pipe::PipeStream;
+        use std::os::windows::fs::OpenOptionsExt;
+        use windows_sys::Win32::Storage::FileSystem::FILE_FLAG_OVERLAPPED;
+
+        let name = format!(r"\\.\pipe\vintage-test-{}", std::process::id());
+        let admin = ServerConfig::new().on_get(["/"], |_req, _params| Response::text("admin"));
+        let config = ServerConfig::new().listen(ListenAddress::NamedPipe(name.clone()), admin);
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let listener = server.listeners().nth(1).unwrap().clone();
+        assert_matches!(&listener, ListenerInfo::NamedPipe { name: n, .. } if *n == name);
+        assert_eq!(listener.to_string(), format!("pipe:{name}"));
+
+        // Every connection gets an instance of the pipe of its own
+        for _ in 0..2 {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(FILE_FLAG_OVERLAPPED)
+                .open(&name)
+                .unwrap();
+            let pipe = Stream::NamedPipe(PipeStream::new(file.into()));
+            let mut connection = Connection::try_from(pipe).unwrap();
+            for record in records![
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(vec![])
+            ] {
+                connection.write_record(&record).unwrap();
+            }
+            let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+                panic!("Expected a response");
+            };
+            assert!(stdout.0.ends_with(b"\r\n\r\nadmin"));
+        }
+
+        // The name is taken until the server stops
+        let config =
+            ServerConfig::new().listen(ListenAddress::NamedPipe(name.clone()), ServerConfig::new());
+        let err = crate::start(config, "localhost:0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        server.stop();
+        let config =
+            ServerConfig::new().listen(ListenAddress::NamedPipe(name), ServerConfig::new());
+        crate::start(config, "localhost:0").unwrap().stop();
+    }
+
+    #[test]
+    fn server_headers() {
+        let config = ServerConfig::new()
+            .server_header("vintage-test")
+            .powered_by("rust")
+            .on_get(["/custom"], |_req, _params| {
+                Response::new().set_header(headers::SERVER, "custom")
+            })
+            .unhandled(|_req| Response::new());
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let response = |path: &str| {
+            let socket = TcpStream::connect(server.address()).unwrap();
+            let mut connection = Connection::try_from(socket).unwrap();
+            for record in records![
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("PATH_INFO", path),
+                Stdin(vec![])
+            ] {
+                connection.write_record(&record).unwrap();
+            }
+            let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+                panic!("Expected a response");
+            };
+            String::from_utf8(stdout.0).unwrap()
+        };
+
+        let default = response("/");
+        let date = default
+            .lines()
+            .find_map(|l| l.strip_prefix("Date: "))
+            .unwrap();
+        assert!(httpdate::parse(date).is_some());
+        assert!(default.contains("Server: vintage-test\r\n"));
+        assert!(default.contains("X-Powered-By: rust\r\n"));
+
+        let custom = response("/custom");
+        assert!(custom.contains("Server: custom\r\n"));
+        assert!(!custom.contains("vintage-test"));
+    }
+
+    #[test]
+    fn test_clock() {
+        use crate::Clock;
+
+        let clock =
+            crate::TestClock::new(httpdate::parse("Sun, 06 Nov 1994 08:49:37 GMT").unwrap());
+        let entries = Arc::new(std::sync::Mutex::new(vec![]));
+        let config = ServerConfig::new()
+            .clock(clock.clone())
+            .access_log({
+                let entries = entries.clone();
+                move |entry| {
+                    entries
+                        .lock()
+                        .unwrap()
+                        .push((entry.timestamp, entry.elapsed, entry.handler))
+                }
+            })
+            .on_get(["/"], {
+                let clock = clock.clone();
+                move |req, _params| {
+                    clock.advance(Duration::from_millis(250));
+                    assert_eq!(req.timings().handler, Duration::from_millis(250));
+                    assert_eq!(req.timings().read, Duration::ZERO);
+                    Response::text("slow")
+                }
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let socket = TcpStream::connect(server.address()).unwrap();
+        let mut connection = Connection::try_from(socket).unwrap();
+        for record in records![
+            BeginRequest::new(Role::Responder, false),
+            basic_params(),
+            Stdin(vec![])
+        ] {
+            connection.write_record(&record).unwrap();
+        }
+        let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+            panic!("Expected a response");
+        };
+        let response = String::from_utf8(stdout.0).unwrap();
+        assert!(response.contains("Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+
+        // The entry is logged once the handler returned
+        let entries = entries.lock().unwrap();
+        let slow = Duration::from_millis(250);
+        assert_eq!(*entries, [(clock.now(), slow, slow)]);
+    }
+
+    #[test]
+    fn missing_params() {
+        let server = crate::start(ServerConfig::new(), "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                Params::default().add("PATH_INFO", "/").add("QUERY_STRING", ""),
+                Stdin(vec![])
+            },
+            records! {
+                Stderr(b"vintage: The REQUEST_METHOD param is missing\n".to_vec()),
+                Stderr(vec![]),
+                Stdout(b"Content-Type: text/plain\r\nStatus: 500\r\n\r\nInternal Server Error".to_vec()),
+                EndRequest::new(app_status::REQUEST_FAILED, ProtocolStatus::RequestComplete)
+            },
+        );
+
+        // Without a query string, the query is empty
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                Params::default().add("REQUEST_METHOD", "GET").add("PATH_INFO", "/missing"),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Status: 404\r\n\r\n".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+
+        // The web server sent the body before the params
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                Stdin(vec![]),
+                basic_params()
+            },
+            records! {
+                Stderr(b"vintage: The web server did not send FCGI_PARAMS\n".to_vec()),
+                Stderr(vec![]),
+                Stdout(b"Content-Type: text/plain\r\nStatus: 500\r\n\r\nInternal Server Error".to_vec()),
+                EndRequest::new(app_status::REQUEST_FAILED, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn lenient_params() {
+        let config = ServerConfig::new()
+            .param_strictness(ParamStrictness::Lenient)
+            .on_get(["/"], |req, _params| {
+                Response::text(format!(
+                    "{} {}?{}",
+                    req.method(),
+                    req.path(),
+                    req.query_string
+                ))
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                Params::default(),
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nGET /?".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+        server.stop();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn thread_per_core() {
+        let config = ServerConfig::new()
+            .worker_model(WorkerModel::ThreadPerCore)
+            .on_get(["/"], |_req, _params| Response::text("hi"));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        for _ in 0..8 {
+            assert_request(
+                server.address(),
+                records! {
+                    BeginRequest::new(Role::Responder, false),
+                    basic_params(),
+                    Stdin(vec![])
+                },
+                records! {
+                    Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nhi".to_vec()),
+                    EndRequest::new(0, ProtocolStatus::RequestComplete)
+                },
+            );
+        }
+
+        server.stop();
+    }
+
+    // Starts a server whose first `workers` requests wait for `release`, once they all reached
+    // `started`
+    fn busy_server(
+        queue_policy: QueuePolicy,
+        started: Arc<std::sync::Barrier>,
+        release: Arc<std::sync::Barrier>,
+    ) -> crate::ServerHandle {
+        let workers = std::thread::available_parallelism().unwrap().get();
+        let requests = AtomicUsize::new(0);
+        let config = ServerConfig::new().request_queue(1, queue_policy).on_get(
+            ["/"],
+            move |_req, _params| {
+                if requests.fetch_add(1, Ordering::SeqCst) < workers {
+                    started.wait();
+                    release.wait();
+                }
+                Response::text("done")
+            },
+        );
+        crate::start(config, "localhost:0").unwrap()
+    }
+
+    fn queued_request() -> Vec<Record> {
+        records! {
+            BeginRequest::new(Role::Responder, false),
+            basic_params(),
+            Stdin(vec![])
+        }
+    }
+
+    fn queued_response() -> Vec<Record> {
+        records! {
+            Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\ndone".to_vec()),
+            EndRequest::new(0, ProtocolStatus::RequestComplete)
+        }
+    }
+
+    #[test]
+    fn request_queue_shedding() {
+        let workers = std::thread::available_parallelism().unwrap().get();
+        let started = Arc::new(std::sync::Barrier::new(workers + 1));
+        let release = Arc::new(std::sync::Barrier::new(workers + 1));
+        let server = busy_server(QueuePolicy::ShedNewest, started.clone(), release.clone());
+        let address = server.address();
+
+        // Occupy every worker thread
+        let mut clients = vec![];
+        for _ in 0..workers {
+            clients.push(std::thread::spawn(move || {
+                assert_request(address, queued_request(), queued_response())
+            }));
+        }
+        started.wait();
+
+        // Of the next two connections, one is queued and the other one is shed
+        let (sender, responses) = std::sync::mpsc::channel();
+        for _ in 0..2 {
+            let sender = sender.clone();
+            clients.push(std::thread::spawn(move || {
+                let socket = TcpStream::connect(address).unwrap();
+                let mut connection = Connection::try_from(socket).unwrap();
+                for record in queued_request() {
+                    connection.write_record(&record).unwrap();
+                }
+                let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+                    panic!("expected a Stdout record");
+                };
+                sender
+                    .send(httpdate::without_date_header(&stdout.0))
+                    .unwrap();
+            }));
+        }
+
+        // The shed connection is answered while the workers are still busy
+        assert_eq!(responses.recv().unwrap(), b"Status: 503\r\n\r\n");
+        release.wait();
+        assert_eq!(
+            responses.recv().unwrap(),
+            b"Content-Type: text/plain\r\nStatus: 200\r\n\r\ndone"
+        );
+        for client in clients {
+            client.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn request_queue_shedding_slow_clients() {
+        let workers = std::thread::available_parallelism().unwrap().get();
+        let started = Arc::new(std::sync::Barrier::new(workers + 1));
+        let release = Arc::new(std::sync::Barrier::new(workers + 1));
+        let requests = AtomicUsize::new(0);
+        let config = ServerConfig::new()
+            .request_queue(1, QueuePolicy::ShedNewest)
+            .handshake_timeout(Duration::from_millis(300))
+            .on_get(["/"], {
+                let (started, release) = (started.clone(), release.clone());
+                move |_req, _params| {
+                    if requests.fetch_add(1, Ordering::SeqCst) < workers {
+                        started.wait();
+                        release.wait();
+                    }
+                    Response::text("done")
+                }
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+        let address = server.address();
+
+        // Occupy every worker thread, and fill the queue
+        let mut clients = vec![];
+        for _ in 0..workers {
+            clients.push(std::thread::spawn(move || {
+                assert_request(address, queued_request(), queued_response())
+            }));
+        }
+        started.wait();
+        clients.push(std::thread::spawn(move || {
+            assert_request(address, queued_request(), queued_response())
+        }));
+        std::thread::sleep(Duration::from_millis(100));
+
+        // A shed connection that trickles its request in, a byte at a time
+        let mut slow = std::net::TcpStream::connect(address).unwrap();
+        let mut request = vec![];
+        for record in queued_request() {
+            crate::connection::encode_record(&record, &mut request).unwrap();
+        }
+        let trickle = std::thread::spawn(move || {
+            for byte in request {
+                if slow.write_all(&[byte]).is_err() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+        std::thread::sleep(Duration::from_millis(100));
+
+        // The next shed connection is answered once the slow one ran out of time, and management
+        // records are answered right away
+        let before = std::time::Instant::now();
+        assert_request(
+            address,
+            records! { GetValues::default().add("FCGI_MPXS_CONNS") },
+            records! { GetValuesResult::default().add("FCGI_MPXS_CONNS", "0") },
+        );
+        assert!(before.elapsed() < Duration::from_secs(2));
+
+        release.wait();
+        trickle.join().unwrap();
+        for client in clients {
+            client.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn request_queue_blocking() {
+        let workers = std::thread::available_parallelism().unwrap().get();
+        let started = Arc::new(std::sync::Barrier::new(workers + 1));
+        let release = Arc::new(std::sync::Barrier::new(workers + 1));
+        let server = busy_server(QueuePolicy::Block, started.clone(), release.clone());
+        let address = server.address();
+
+        let mut clients = vec![];
+        for _ in 0..workers {
+            clients.push(std::thread::spawn(move || {
+                assert_request(address, queued_request(), queued_response())
+            }));
+        }
+        started.wait();
+
+        // One connection fills the queue, the others wait in the listen backlog until there is
+        // room for them
+        for _ in 0..3 {
+            clients.push(std::thread::spawn(move || {
+                assert_request(address, queued_request(), queued_response())
+            }));
+        }
+        release.wait();
+        for client in clients {
+            client.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn drain() {
+        let workers = std::thread::available_parallelism().unwrap().get();
+        let started = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(AtomicBool::new(false));
+
+        let config = ServerConfig::new()
+            .drain_retry_after(Duration::from_millis(1500))
+            .on_get(["/"], {
+                let started = started.clone();
+                let release = release.clone();
+                move |_req, _params| {
+                    started.fetch_add(1, Ordering::SeqCst);
+                    while !release.load(Ordering::SeqCst) {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                    Response::text("done")
+                }
+            });
+        let server = crate::start(config, "localhost:0").unwrap();
+        let address = server.address();
+
+        let request = || {
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(vec![])
+            }
+        };
+
+        // Occupy every worker thread, then queue one more connection
+        let mut clients = vec![];
+        for _ in 0..workers {
+            clients.push(std::thread::spawn(move || {
+                let done = records! {
+                    Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\ndone".to_vec()),
+                    EndRequest::new(0, ProtocolStatus::RequestComplete)
+                };
+                assert_request(address, request(), done)
+            }));
+        }
+        while started.load(Ordering::SeqCst) < workers {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let queued = std::thread::spawn(move || {
+            let rejected = records! {
+                Stdout(b"Retry-After: 2\r\nStatus: 503\r\n\r\n".to_vec()),
+                EndRequest::new(app_status::REJECTED, ProtocolStatus::RequestComplete)
+            };
+            assert_request(address, request(), rejected)
+        });
+        std::thread::sleep(Duration::from_millis(100));
+
+        // Requests being handled are completed, but the queued one is turned away
+        let stopping = std::thread::spawn(move || server.stop());
+        std::thread::sleep(Duration::from_millis(100));
+        release.store(true, Ordering::SeqCst);
+        for client in clients {
+            client.join().unwrap();
+        }
+        queued.join().unwrap();
+        stopping.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn control_socket() {
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixStream;
+
+        let path =
+            std::env::temp_dir().join(format!("vintage-control-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = ServerConfig::new()
+            .control_socket(&path)
+            .on_get(["/"], |_req, _params| Response::text("hello"));
+        let server = crate::start(config, "localhost:0").unwrap();
+        let address = server.address();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut control = UnixStream::connect(&path).unwrap();
+        let mut replies = BufReader::new(control.try_clone().unwrap()).lines();
+        let mut send = |command: &str, lines: usize| {
+            writeln!(control, "{command}").unwrap();
+            (0..lines)
+                .map(|_| replies.next().unwrap().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let request = || {
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(vec![])
+            }
+        };
+
+        assert_eq!(send("routes", 2), ["GET /", "ok"]);
+        assert_eq!(send("maintenance on", 1), ["ok"]);
+        let unavailable = records! {
+            Stdout(b"Retry-After: 5\r\nStatus: 503\r\n\r\n".to_vec()),
+            EndRequest::new(app_status::REJECTED, ProtocolStatus::RequestComplete)
+        };
+        assert_request(address, request(), unavailable);
+
+        assert_eq!(send("maintenance off", 1), ["ok"]);
+        let hello = records! {
+            Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nhello".to_vec()),
+            EndRequest::new(0, ProtocolStatus::RequestComplete)
+        };
+        assert_request(address, request(), hello);
+        assert_eq!(
+            send("stats", 3),
+            [
+                "route / requests=1 bytes_in=45 bytes_out=83",
+                "write_errors 0",
+                "ok"
+            ]
+        );
+
+        // The server stops on its own, and removes the socket
+        assert_eq!(send("drain", 1), ["ok"]);
+        assert_matches!(
+            server.join_timeout(Duration::from_secs(5)),
+            Ok(crate::ServerExitReason::Normal)
+        );
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_on_calling_thread() {
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("vintage-run-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = ServerConfig::new().control_socket(&path);
+        let server = std::thread::spawn(move || crate::run(config, "localhost:0"));
+
+        let mut control = loop {
+            match UnixStream::connect(&path) {
+                Ok(control) => break control,
+                Err(_) => std::thread::sleep(Duration::from_millis(5)),
+            }
+        };
+        writeln!(control, "drain").unwrap();
+        assert_matches!(server.join().unwrap(), Ok(crate::ServerExitReason::Normal));
+
+        let config = ServerConfig::new()
+            .before_serve(|| Err(io::Error::from(io::ErrorKind::PermissionDenied)));
+        let err = crate::run(config, "localhost:0").unwrap_err();
+        assert_eq!(err.failures()[0].step, crate::StartStep::BeforeServe);
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn merge() {
+        let fragment = ServerConfig::new()
+            .on_get(["/healthz"], |_req, _params| Response::text("ok"))
+            .middleware(|req, next| next.run(req).set_header("X-Fragment", "1"))
+            .server_header("fragment")
+            .unhandled(|_req| Response::text("fragment fallback"))
+            .listen(
+                "127.0.0.1:0".parse::<SocketAddr>().unwrap(),
+                ServerConfig::new(),
+            );
+        let config = ServerConfig::new()
+            .on_get(["/"], |_req, _params| Response::text("home"))
+            .middleware(|req, next| next.run(req).set_header("X-App", "1"))
+            .server_header("app")
+            .merge(fragment);
+        assert_eq!(config.routes(), [("GET", "/"), ("GET", "/healthz")]);
+        // The fragment doesn't add listeners
+        assert!(config.listeners.is_empty());
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let request = |path: &str| {
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("PATH_INFO", path),
+                Stdin(vec![])
+            }
+        };
+        assert_request(
+            server.address(),
+            request("/healthz"),
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nServer: app\r\nX-App: 1\r\nX-Fragment: 1\r\nStatus: 200\r\n\r\nok".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+        assert_request(
+            server.address(),
+            request("/missing"),
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nServer: app\r\nX-App: 1\r\nX-Fragment: 1\r\nStatus: 200\r\n\r\nfragment fallback".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't register GET '/'")]
+    fn merge_conflicting_routes() {
+        let route = || ServerConfig::new().on_get(["/"], |_req, _params| Response::text("home"));
+        let _ = route().merge(route());
+    }
+
+    #[test]
+    fn every() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let config = ServerConfig::new().every(Duration::from_millis(5), {
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let server = crate::start(config, "localhost:0").unwrap();
+        while runs.load(Ordering::SeqCst) < 2 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        server.stop();
+
+        let stopped_at = runs.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(runs.load(Ordering::SeqCst), stopped_at);
+    }
+
+    #[test]
+    fn on_start() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let config = ServerConfig::new()
+            .on_start({
+                let ran = ran.clone();
+                move || {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                    Ok::<_, io::Error>(())
+                }
+            })
+            .on_start(|| Err("templates are missing"));
+        let err = crate::start(config, "localhost:0").unwrap_err();
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+        assert_eq!(err.failures()[0].step, crate::StartStep::StartTask);
+        assert_eq!(
+            io::Error::from(err).into_inner().unwrap().to_string(),
+            "templates are missing"
+        );
+    }
+
+    #[test]
+    fn bulkhead() {
+        let workers = std::thread::available_parallelism().unwrap().get();
+        let started = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(AtomicBool::new(false));
+
+        let config = ServerConfig::new()
+            .bulkhead(["/slow"], 1)
+            .on_get(["/slow"], {
+                let started = started.clone();
+                let release = release.clone();
+                move |_req, _params| {
+                    started.fetch_add(1, Ordering::SeqCst);
+                    while !release.load(Ordering::SeqCst) {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                    Response::text("slow")
+                }
+            })
+            .on_get(["/fast"], |_req, _params| Response::text("fast"));
+        let server = crate::start(config, "localhost:0").unwrap();
+        let address = server.address();
+
+        let request = |path: &str| {
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("PATH_INFO", path),
+                Stdin(vec![])
+            }
+        };
+        let response = |body: &str| {
+            let stdout = format!("Content-Type: text/plain\r\nStatus: 200\r\n\r\n{body}");
+            records! {
+                Stdout(stdout.into_bytes()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            }
+        };
+
+        // More slow requests than there are general workers
+        let mut clients = vec![];
+        for _ in 0..workers + 1 {
+            clients.push(std::thread::spawn(move || {
+                assert_request(address, request("/slow"), response("slow"))
+            }));
+        }
+        while started.load(Ordering::SeqCst) < 1 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // They only occupy the thread of the bulkhead
+        assert_request(address, request("/fast"), response("fast"));
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+
+        release.store(true, Ordering::SeqCst);
+        for client in clients {
+            client.join().unwrap();
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn before_serve() {
+        let caller = std::thread::current().id();
+        let ran = Arc::new(AtomicBool::new(false));
+        let config = ServerConfig::new().before_serve({
+            let ran = ran.clone();
+            move || {
+                assert_ne!(std::thread::current().id(), caller);
+                ran.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+        let server = crate::start(config, "localhost:0").unwrap();
+        assert!(ran.load(Ordering::SeqCst));
+        server.stop();
+
+        let config = ServerConfig::new()
+            .before_serve(|| Err(io::Error::from(io::ErrorKind::PermissionDenied)));
+        let err = crate::start(config, "localhost:0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let config = ServerConfig::new().before_serve(|| panic!("no config file"));
+        let err = crate::start(config.clone(), "localhost:0").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("The server thread panicked before serving requests: no config file"));
+        let reason = crate::run(config, "localhost:0").unwrap();
+        let ServerExitReason::Panic(report) = reason else {
+            panic!("Expected a panic, got {reason:?}");
+        };
+        assert_eq!(report.message(), "no config file");
+        assert_eq!(report.backtrace().status(), BacktraceStatus::Captured);
+    }
+
+    #[test]
+    fn block_ips() {
+        let blocked = IpList::new();
+        blocked.add("10.0.0.0/8").unwrap();
+        let config = ServerConfig::new()
+            .block_ips(blocked.clone(), 403)
+            .on_get(["/"], |_req, _params| Response::text("hello"));
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let request = |remote_addr| {
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params().add("REMOTE_ADDR", remote_addr),
+                Stdin(vec![])
+            }
+        };
+        let forbidden = || {
+            records! {
+                Stdout(b"Status: 403\r\n\r\n".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            }
+        };
+
+        assert_request(server.address(), request("10.1.2.3"), forbidden());
+        assert_request(
+            server.address(),
+            request("192.168.1.1"),
+            records! {
+                Stdout(b"Content-Type: text/plain\r\nStatus: 200\r\n\r\nhello".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+
+        // The list is shared with the running server
+        blocked.add("192.168.0.0/16").unwrap();
+        assert_request(server.address(), request("192.168.1.1"), forbidden());
+    }
+
+    #[test]
+    fn custom_access_log() {
+        let entries = Arc::new(std::sync::Mutex::new(vec![]));
+        let config = ServerConfig::new().access_log({
+            let entries = entries.clone();
+            move |entry| {
+                let line = format!("{} {} {}", entry.method, entry.path, entry.status);
+                entries.lock().unwrap().push(line);
+            }
+        });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
                 basic_params(),
                 Stdin(vec![])
             },
             records! {
-                EndRequest::new(0, ProtocolStatus::MultiplexingUnsupported)
+                Stdout(b"Status: 404\r\n\r\n".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
             },
         );
+
+        assert_eq!(*entries.lock().unwrap(), ["GET / 404"]);
     }
 
     #[test]
-    fn successful_responder_flow() {
-        // A server that echoes the body
-        let config = ServerConfig::new().unhandled(|req| {
-            let body = std::mem::take(&mut req.body);
-            Response::default().set_raw_body(body)
+    fn streamed_response() {
+        use std::io::Write;
+
+        let config = ServerConfig::new().unhandled(|_req| {
+            Response::default().set_body_stream(|writer| {
+                writer.write_all(b"HELLO")?;
+                writer.flush()?;
+                writer.write_all(b"WORLD")
+            })
         });
         let server = crate::start(config, "localhost:0").unwrap();
 
@@ -263,12 +4291,432 @@ mod tests {
             records! {
                 BeginRequest::new(Role::Responder, false),
                 basic_params(),
-                Stdin(b"BAR".to_vec())
+                Stdin(vec![])
+            },
+            records! {
+                Stdout(b"Status: 200\r\n\r\nHELLOWORLD".to_vec()),
+                EndRequest::new(0, ProtocolStatus::RequestComplete)
+            },
+        );
+    }
+
+    #[test]
+    fn raw_response() {
+        use std::io::Write;
+
+        let config = ServerConfig::new().unhandled(|_req| {
+            Response::raw(|writer| writer.write_all(b"Status: 200\nX-Custom: 1\n\nhi"))
+                .set_header("X-Ignored", "1")
+        });
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        assert_request(
+            server.address(),
+            records! {
+                BeginRequest::new(Role::Responder, false),
+                basic_params(),
+                Stdin(vec![])
             },
             records! {
-                Stdout(b"Status: 200\n\nBAR".to_vec()),
+                Stdout(b"Status: 200\nX-Custom: 1\n\nhi".to_vec()),
                 EndRequest::new(0, ProtocolStatus::RequestComplete)
             },
         );
     }
+
+    // Starts a server whose responses stream forever, until the request is aborted.
+    // Returns the server, and a channel that receives a message when the abort is detected.
+    fn endless_stream_server() -> (crate::ServerHandle, std::sync::mpsc::Receiver<()>) {
+        use std::io::Write;
+
+        let (aborted, observe_abort) = std::sync::mpsc::channel();
+        let aborted = std::sync::Mutex::new(aborted);
+
+        let config = ServerConfig::new()
+            .unhandled(|_req| {
+                Response::default().set_body_stream(|writer| loop {
+                    writer.write_all(b"MORE")?;
+                    writer.flush()?;
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                })
+            })
+            .on_abort(move |_req| {
+                aborted.lock().unwrap().send(()).unwrap();
+            });
+
+        (crate::start(config, "localhost:0").unwrap(), observe_abort)
+    }
+
+    fn begin_streaming(address: SocketAddr) -> Connection {
+        let socket = TcpStream::connect(address).unwrap();
+        let mut connection = Connection::try_from(socket).unwrap();
+
+        for record in records! {
+            BeginRequest::new(Role::Responder, false),
+            basic_params(),
+            Stdin(vec![])
+        } {
+            connection.write_record(&record).unwrap();
+        }
+
+        // Wait until the response starts
+        let packet = connection.read_packet().unwrap();
+        assert_eq!(packet.type_id, FCGI_STDOUT);
+        connection
+    }
+
+    #[test]
+    fn abort_request_while_streaming() {
+        let (server, observe_abort) = endless_stream_server();
+        let mut connection = begin_streaming(server.address());
+
+        connection.write_record(&AbortRequest.into()).unwrap();
+
+        let timeout = std::time::Duration::from_secs(5);
+        assert_eq!(observe_abort.recv_timeout(timeout), Ok(()));
+
+        // The request still completes.
+        // Some output may have been sent before the abort was noticed. That stream is left
+        // unterminated.
+        loop {
+            let packet = connection.read_packet().unwrap();
+            if packet.type_id == FCGI_END_REQUEST {
+                let end = Record::from_bytes(packet.type_id, packet.content).unwrap();
+                assert_eq!(
+                    end,
+                    EndRequest::new(0, ProtocolStatus::RequestComplete).into()
+                );
+                break;
+            }
+            assert_eq!(packet.type_id, FCGI_STDOUT);
+        }
+    }
+
+    #[test]
+    fn client_disconnect_while_streaming() {
+        let (server, observe_abort) = endless_stream_server();
+        let connection = begin_streaming(server.address());
+
+        drop(connection);
+
+        let timeout = std::time::Duration::from_secs(5);
+        assert_eq!(observe_abort.recv_timeout(timeout), Ok(()));
+    }
+
+    #[test]
+    fn partial_abort_request_while_streaming() {
+        use std::io::Write;
+
+        let (server, observe_abort) = endless_stream_server();
+        let mut connection = begin_streaming(server.address());
+
+        // The header of an `FCGI_ABORT_REQUEST` packet, sent in two parts
+        let header = [1, FCGI_ABORT_REQUEST, 0, 1, 0, 0, 0, 0];
+        connection.write_all(&header[..3]).unwrap();
+        connection.flush().unwrap();
+
+        // The response keeps streaming while the rest of the packet is missing
+        let start = std::time::Instant::now();
+        for _ in 0..100 {
+            let packet = connection.read_packet().unwrap();
+            assert_eq!(packet.type_id, FCGI_STDOUT);
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert!(observe_abort.try_recv().is_err());
+
+        connection.write_all(&header[3..]).unwrap();
+        connection.flush().unwrap();
+        let timeout = std::time::Duration::from_secs(5);
+        assert_eq!(observe_abort.recv_timeout(timeout), Ok(()));
+    }
+
+    // Large enough to fill up the socket buffers, so that the response can't be written in one go
+    const LARGE_BODY_LEN: usize = 32 * 1024 * 1024;
+
+    fn large_response_server() -> crate::ServerHandle {
+        let config = ServerConfig::new()
+            .unhandled(|_req| Response::default().set_raw_body(vec![b'A'; LARGE_BODY_LEN]));
+        crate::start(config, "localhost:0").unwrap()
+    }
+
+    #[track_caller]
+    fn assert_large_response(connection: &mut Connection) {
+        let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+            panic!("expected a Stdout record");
+        };
+        let stdout = httpdate::without_date_header(&stdout.0);
+        assert!(stdout.starts_with(b"Status: 200\r\n\r\nAAAA"));
+        assert_eq!(stdout.len(), LARGE_BODY_LEN + b"Status: 200\r\n\r\n".len());
+
+        assert_eq!(
+            connection.read_record().unwrap(),
+            EndRequest::new(0, ProtocolStatus::RequestComplete).into()
+        );
+    }
+
+    fn send_request(address: SocketAddr) -> Connection {
+        let socket = TcpStream::connect(address).unwrap();
+        let mut connection = Connection::try_from(socket).unwrap();
+        for record in records! {
+            BeginRequest::new(Role::Responder, false),
+            basic_params(),
+            Stdin(vec![])
+        } {
+            connection.write_record(&record).unwrap();
+        }
+        connection
+    }
+
+    #[test]
+    fn slow_client() {
+        let server = large_response_server();
+        let mut connection = send_request(server.address());
+
+        // By now, the worker thread has handed off the rest of the response to the event loop
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        assert_large_response(&mut connection);
+    }
+
+    #[test]
+    fn shutdown_with_slow_client() {
+        let server = large_response_server();
+        let mut connection = send_request(server.address());
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        // Graceful shutdown waits for the rest of the response to be written
+        let stopping = std::thread::spawn(move || server.stop());
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        assert_large_response(&mut connection);
+        stopping.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "csp-nonce")]
+    fn content_security_policy() {
+        assert_eq!(
+            add_csp_nonce("default-src 'self'; script-src 'self' ; img-src *", "abc"),
+            "default-src 'self' 'nonce-abc'; script-src 'self' 'nonce-abc'; img-src *"
+        );
+        assert_eq!(
+            add_csp_nonce(
+                "Script-Src 'self'; style-src 'self'; default-src 'none'",
+                "abc"
+            ),
+            "Script-Src 'self' 'nonce-abc'; style-src 'self' 'nonce-abc'; default-src 'none'"
+        );
+
+        let config = ServerConfig::new()
+            .content_security_policy("script-src 'self'; style-src 'self'")
+            .on_get(["/nonce"], |req, _params| {
+                assert_eq!(req.csp_nonce(), req.csp_nonce());
+                Response::text(req.csp_nonce().to_string())
+            })
+            .on_get(["/static"], |_req, _params| Response::text("plain"));
+        let server = crate::start(config, "localhost:0").unwrap();
+        let client = crate::Client::new(server.address());
+
+        let get = |path: &str| {
+            let req = Request {
+                method: "GET".into(),
+                path: path.into(),
+                ..Request::default()
+            };
+            client.send(&req).unwrap()
+        };
+
+        let response = get("/nonce");
+        let nonce = String::from_utf8(response.body).unwrap();
+        assert_eq!(nonce.len(), 32);
+        assert_eq!(
+            response.headers["Content-Security-Policy"],
+            format!("script-src 'self' 'nonce-{nonce}'; style-src 'self' 'nonce-{nonce}'")
+        );
+        assert_ne!(String::from_utf8(get("/nonce").body).unwrap(), nonce);
+        assert_eq!(
+            get("/static").headers["Content-Security-Policy"],
+            "script-src 'self'; style-src 'self'"
+        );
+
+        server.stop();
+    }
+
+    #[test]
+    fn route_listing() {
+        let ok = |_req: &mut Request, _params| Response::new();
+        let config = ServerConfig::new()
+            .on_get(["/users/{id}"], ok)
+            .route_listing("/_routes")
+            .on("PROPFIND", ["/dav/{*path}"], ok)
+            .on_get(["/"], ok)
+            .on_delete(["/users/{id}"], ok);
+        assert_eq!(
+            config.routes(),
+            [
+                ("GET", "/"),
+                ("PROPFIND", "/dav/{*path}"),
+                ("DELETE", "/users/{id}"),
+                ("GET", "/users/{id}"),
+            ]
+        );
+
+        let server = crate::start(config, "localhost:0").unwrap();
+        let req = Request {
+            method: "GET".into(),
+            path: "/_routes".into(),
+            ..Request::default()
+        };
+        let response = crate::Client::new(server.address()).send(&req).unwrap();
+        assert_eq!(
+            response.body,
+            b"GET /\nPROPFIND /dav/{*path}\nDELETE /users/{id}\nGET /users/{id}\n"
+        );
+        server.stop();
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, feature = "fs"))]
+    fn index_page() {
+        let ok = |_req: &mut Request, _params| Response::new();
+        let config = ServerConfig::new()
+            .index_page()
+            .serve_files("/static", "./src")
+            .on_get(["/users/{id}"], ok)
+            .on_post(["/<script>"], ok);
+
+        let get = |config: &ServerConfig, method: &str, path: &str| {
+            let req = Request {
+                method: method.into(),
+                path: path.into(),
+                ..Request::default()
+            };
+            config.default_index_page(&req)
+        };
+
+        let page = get(&config, "GET", "/").unwrap();
+        let body = String::from_utf8(page.body).unwrap();
+        assert!(body.contains("<li><code>GET /users/{id}</code></li>"));
+        assert!(body.contains("<li><code>POST /&lt;script&gt;</code></li>"));
+        assert!(body.contains("<li><code>/static</code> from <code>./src</code></li>"));
+
+        assert!(get(&config, "HEAD", "/").is_some());
+        assert!(get(&config, "POST", "/").is_none());
+        assert!(get(&config, "GET", "/users").is_none());
+        assert!(get(&ServerConfig::new(), "GET", "/").is_none());
+
+        // The callback for unhandled requests handles `/` instead
+        let config = config.unhandled(|_req| Response::text("unhandled"));
+        assert!(get(&config, "GET", "/").is_none());
+
+        // Routes for `/` handle it first
+        let config = ServerConfig::new()
+            .index_page()
+            .on_get(["/"], |_req, _params| Response::text("home"));
+        let server = crate::start(config, "localhost:0").unwrap();
+        let req = Request {
+            method: "GET".into(),
+            path: "/".into(),
+            ..Request::default()
+        };
+        let response = crate::Client::new(server.address()).send(&req).unwrap();
+        assert_eq!(response.body, b"home");
+        server.stop();
+    }
+
+    #[test]
+    fn custom_router() {
+        // Matches `/users/{name}` whatever the case of `users`, for `GET` only
+        struct CaseInsensitive(crate::router::RouterCallback);
+
+        impl Route for CaseInsensitive {
+            fn find(&self, method: &str, path: &str) -> Option<RouteMatch<'_>> {
+                let (prefix, name) = path.get(1..)?.split_once('/')?;
+                if method != "GET" || !prefix.eq_ignore_ascii_case("users") {
+                    return None;
+                }
+                let params = RouteParams::from([("name".to_string(), name.to_string())]);
+                Some(RouteMatch::new("/users/{name}", params, &*self.0))
+            }
+
+            fn allowed_methods(&self, path: &str) -> Vec<&str> {
+                match self.find("GET", path) {
+                    Some(_) => vec!["GET"],
+                    None => vec![],
+                }
+            }
+
+            fn routes(&self) -> Vec<(&str, &str)> {
+                vec![("GET", "/users/{name}")]
+            }
+        }
+
+        // Matches a single path, for `GET` only
+        struct Exact(&'static str, crate::router::RouterCallback);
+
+        impl Route for Exact {
+            fn find(&self, method: &str, path: &str) -> Option<RouteMatch<'_>> {
+                (method == "GET" && path == self.0)
+                    .then(|| RouteMatch::new(self.0, RouteParams::new(), &*self.1))
+            }
+
+            fn allowed_methods(&self, path: &str) -> Vec<&str> {
+                match path == self.0 {
+                    true => vec!["GET"],
+                    false => vec![],
+                }
+            }
+
+            fn routes(&self) -> Vec<(&str, &str)> {
+                vec![("GET", self.0)]
+            }
+        }
+
+        let config = ServerConfig::new()
+            .on_post(["/users/{name}"], |_req, params| {
+                Response::text(format!("created {}", params["name"]))
+            })
+            .router(CaseInsensitive(Arc::new(|_req, params| {
+                Response::text(format!("hello {}", params["name"]))
+            })))
+            // Backends add up, and the first one to match wins
+            .router(Exact(
+                "/ping",
+                Arc::new(|_req, _params| Response::text("pong")),
+            ))
+            .router(Exact(
+                "/users/ada",
+                Arc::new(|_req, _params| Response::text("shadowed")),
+            ));
+        assert_eq!(
+            config.routes(),
+            [
+                ("GET", "/ping"),
+                ("GET", "/users/ada"),
+                ("GET", "/users/{name}"),
+                ("POST", "/users/{name}")
+            ]
+        );
+
+        let server = crate::start(config, "localhost:0").unwrap();
+        let client = crate::Client::new(server.address());
+        let send = |method: &str, path: &str| {
+            let req = Request {
+                method: method.into(),
+                path: path.into(),
+                ..Request::default()
+            };
+            client.send(&req).unwrap()
+        };
+
+        assert_eq!(send("GET", "/USERS/ada").body, b"hello ada");
+        assert_eq!(send("GET", "/users/ada").body, b"hello ada");
+        assert_eq!(send("GET", "/ping").body, b"pong");
+        assert_eq!(send("POST", "/users/ada").body, b"created ada");
+        let response = send("DELETE", "/users/ada");
+        assert_eq!(response.status, status::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers["Allow"], "GET, POST");
+        assert_eq!(server.route_stats()["/users/{name}"].requests, 3);
+        server.stop();
+    }
 }
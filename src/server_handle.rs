@@ -1,7 +1,19 @@
+use crate::circuit_breaker::CircuitState;
+use crate::diagnostics::Diagnostics;
+use crate::httpdate;
+use crate::listener::ListenerInfo;
+use crate::log_filter::{self, InvalidLogFilter};
+use crate::panic_report::PanicReport;
+use crate::stats::{Counters, RouteStats, Stats};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
 use std::io;
 use std::net::SocketAddr;
-use std::sync::mpsc::Receiver;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
 
 /// The reason the server exited
 #[derive(Debug, Default)]
@@ -9,18 +21,94 @@ pub enum ServerExitReason {
     /// It was gracefully shutdown
     #[default]
     Normal,
-    /// Polling the server socket for new connections failed somehow.
-    Err(io::Error),
-    /// The server panicked. The payload will contain the panic message.
-    Panic(String),
+    /// It was forcefully stopped with [`abort()`](crate::ServerHandle::abort).
+    /// In-flight requests were not waited on.
+    Aborted,
+    /// An I/O operation on the server socket failed.
+    Err {
+        /// The operation that failed
+        operation: ServerOperation,
+        /// The address the server was listening on
+        address: SocketAddr,
+        /// When the failure happened
+        timestamp: SystemTime,
+        /// The underlying error
+        error: io::Error,
+    },
+    /// The server panicked. The report contains the panic message, and where it happened.
+    Panic(PanicReport),
+}
+
+/// An operation of the server loop that can fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerOperation {
+    /// Polling the server socket for new connections
+    Poll,
+    /// Accepting a new connection on the server socket
+    Accept,
+}
+
+impl Display for ServerOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Poll => write!(f, "poll"),
+            Self::Accept => write!(f, "accept"),
+        }
+    }
+}
+
+impl Display for ServerExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Normal => write!(f, "The server was shutdown gracefully"),
+            Self::Aborted => write!(f, "The server was aborted"),
+            Self::Err {
+                operation,
+                address,
+                timestamp,
+                error,
+            } => {
+                write!(f, "The server at {address} failed to {operation}")?;
+                write!(f, " at {}", httpdate::format_rfc3339(*timestamp))?;
+                write!(f, ": {error}")
+            }
+            Self::Panic(report) if report.message().is_empty() => {
+                write!(f, "The server panicked")
+            }
+            Self::Panic(report) => write!(f, "The server panicked: {report}"),
+        }
+    }
+}
+
+// What a server is listening on
+pub(crate) struct Listening {
+    pub address: SocketAddr,
+    pub info: ListenerInfo,
+    // The listeners added with `ServerConfig::listen`
+    pub others: Vec<ListenerInfo>,
 }
 
 /// Handle to a running FastCGI server
 pub struct ServerHandle {
-    pub(crate) address: SocketAddr,
+    // Boxed to keep the handle small, since it is returned by `join_timeout()`
+    pub(crate) listener: Box<Listening>,
     pub(crate) server_loop: JoinHandle<ServerExitReason>,
-    pub(crate) server_waker: mio::Waker,
+    pub(crate) server_waker: Arc<mio::Waker>,
+    pub(crate) shutdown_requested: Arc<AtomicBool>,
+    pub(crate) abort_requested: Arc<AtomicBool>,
     pub(crate) observe_shutdown: Receiver<()>,
+    pub(crate) observe_exit: Receiver<()>,
+    pub(crate) stats: Arc<Stats>,
+    pub(crate) maintenance: Arc<AtomicBool>,
+    pub(crate) diagnostics: Box<Diagnostics>,
+}
+
+impl fmt::Debug for ServerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerHandle")
+            .field("listener", &self.listener.info)
+            .finish_non_exhaustive()
+    }
 }
 
 impl ServerHandle {
@@ -32,30 +120,56 @@ impl ServerHandle {
     pub fn join(self) -> ServerExitReason {
         match self.server_loop.join() {
             Ok(r) => r,
-            Err(any) => match any.as_ref().downcast_ref::<String>() {
-                Some(s) => ServerExitReason::Panic(s.clone()),
-                None => match any.as_ref().downcast_ref::<&str>() {
-                    Some(s) => ServerExitReason::Panic(s.to_string()),
-                    None => ServerExitReason::Panic(String::new()),
-                },
-            },
+            // The server loop catches its own panics, so this one happened outside of it
+            Err(payload) => ServerExitReason::Panic(PanicReport::from_payload(payload.as_ref())),
+        }
+    }
+
+    /// Blocks until the server terminates or `timeout` elapses, whichever comes first.
+    ///
+    /// If the server exited, the reason is returned.
+    /// Otherwise, the handle is given back so that it can be used again.
+    ///
+    /// Like [`join()`](crate::ServerHandle::join), this function does not attempt to stop the
+    /// server.
+    pub fn join_timeout(self, timeout: Duration) -> Result<ServerExitReason, ServerHandle> {
+        // Nothing is ever sent on this channel.
+        // The sending half is owned by the server thread, and gets dropped when that thread
+        // exits (including when it panics), which disconnects the channel.
+        match self.observe_exit.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => Err(self),
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => Ok(self.join()),
         }
     }
 
     /// Stops the FastCGI server
     ///
-    /// The server waits for all in-flight requests to complete before it is shutdown
+    /// The server waits for all in-flight requests to complete before it is shutdown.
+    /// Connections still waiting in the request queue get a `503 Service Unavailable` instead
+    /// (see [`ServerConfig::drain_retry_after`](crate::ServerConfig::drain_retry_after)).
     pub fn stop(self) {
-        // Wake up the server thread.
-        // It will be able to tell that it was woken up by the waker instead of by a new readable Tcp connection.
-        // If this call fails, just return.
+        self.signal_shutdown();
+    }
+
+    /// Forcefully stops the FastCGI server
+    ///
+    /// Unlike [`stop()`](crate::ServerHandle::stop), the server does not wait for in-flight
+    /// requests to complete. They are left to finish on their own in the background.
+    pub fn abort(self) {
+        self.abort_requested.store(true, Ordering::SeqCst);
+        self.signal_shutdown();
+    }
+
+    // Asks the server loop to exit, and waits until it does.
+    pub(crate) fn signal_shutdown(&self) {
+        // If the server could not be woken up, just return.
         // We don't want to attempt to block on the `recv()` call in the next line if its possible
         // we didn't wake the server.
         // This means our graceful shutdown is "best effort".
         // Nothing we can do if some OS-level error happened.
-        let Ok(()) = self.server_waker.wake() else {
+        if !self.request_shutdown() {
             return;
-        };
+        }
 
         // Normally, after the server thread is woken up by the waker, it will eventually
         // rendezvous here.
@@ -64,8 +178,233 @@ impl ServerHandle {
         let _ = self.observe_shutdown.recv();
     }
 
+    // Asks the server loop to exit, without waiting for it to.
+    //
+    // Returns false if the server thread could not be woken up.
+    pub(crate) fn request_shutdown(&self) -> bool {
+        self.shutdown_requested.store(true, Ordering::SeqCst);
+
+        // Wake up the server thread.
+        // It will be able to tell that it was woken up by the waker instead of by a new readable Tcp connection.
+        self.server_waker.wake().is_ok()
+    }
+
+    // Returns true if the server thread has exited
+    pub(crate) fn has_exited(&self) -> bool {
+        matches!(
+            self.observe_exit.try_recv(),
+            Err(TryRecvError::Disconnected)
+        )
+    }
+
     /// Returns the address at which the server is currently listening
+    ///
+    /// A server listening on a unix domain socket it inherited (see
+    /// [`ServerConfig::from_inherited_listener`](crate::ServerConfig::from_inherited_listener))
+    /// has no address, and returns `0.0.0.0:0`.
+    /// See [`ServerHandle::listener`] for more details about the listening socket.
     pub fn address(&self) -> SocketAddr {
-        self.address
+        self.listener.address
+    }
+
+    /// Returns what the server is listening on, and how the socket was set up
+    pub fn listener(&self) -> &ListenerInfo {
+        &self.listener.info
+    }
+
+    /// Returns every socket the server is listening on: the one passed to
+    /// [`start()`](crate::start) first, then those added with
+    /// [`ServerConfig::listen`](crate::ServerConfig::listen), in order
+    pub fn listeners(&self) -> impl Iterator<Item = &ListenerInfo> {
+        std::iter::once(&self.listener.info).chain(&self.listener.others)
+    }
+
+    /// Returns how the server is set up: its version, sockets, worker threads and limits
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let server = vintage::start(ServerConfig::new(), "localhost:0").unwrap();
+    /// let diagnostics = server.diagnostics();
+    /// assert_eq!(diagnostics.listeners[0], *server.listener());
+    /// println!("{diagnostics}");
+    /// ```
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Returns the traffic of each route since the server started, keyed by path pattern
+    ///
+    /// Requests to a route are counted together, whatever their method.
+    /// Only requests that a route handler responded to are counted: static files, the
+    /// [`unhandled`](crate::ServerConfig::unhandled) callback and middleware that responds on
+    /// its own are not.
+    ///
+    /// ```
+    /// use vintage::{Response, ServerConfig};
+    ///
+    /// let config = ServerConfig::new().on_get(["/users/{id}"], |_req, _params| {
+    ///     Response::text("user")
+    /// });
+    /// let server = vintage::start(config, "localhost:0").unwrap();
+    /// assert!(server.route_stats().is_empty());
+    /// ```
+    pub fn route_stats(&self) -> BTreeMap<String, RouteStats> {
+        self.stats.routes()
+    }
+
+    /// Returns how many responses could not be completely sent since the server started, because
+    /// writing to the connection failed (e.g. the web server closed it)
+    ///
+    /// Requests the client aborted are not counted. A rising count usually means the web server
+    /// gives up on responses before they are sent (e.g. its timeouts are too short).
+    pub fn write_errors(&self) -> u64 {
+        self.stats.write_errors()
+    }
+
+    /// Returns counters of the responses by status class, of protocol errors, and of the
+    /// connections being handled right now
+    ///
+    /// They are plain atomic counters, cheap enough to read on every scrape of an existing
+    /// telemetry pipeline. Every response the server produced is counted, including those of
+    /// static files, middleware, and requests it rejected on its own (e.g. with a `431` or a
+    /// `503`).
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let server = vintage::start(ServerConfig::new(), "localhost:0").unwrap();
+    /// let counters = server.counters();
+    /// assert_eq!(counters.responses_5xx, 0);
+    /// assert_eq!(counters.active_connections, 0);
+    /// ```
+    pub fn counters(&self) -> Counters {
+        self.stats.counters()
+    }
+
+    /// Returns the state of the circuits of the breakers registered with
+    /// [`ServerConfig::circuit_breaker`](crate::ServerConfig::circuit_breaker), keyed by path
+    /// pattern
+    ///
+    /// Circuits that did not see a request yet are left out.
+    pub fn circuits(&self) -> BTreeMap<String, CircuitState> {
+        self.stats.circuits()
+    }
+
+    /// Turns maintenance mode on or off
+    ///
+    /// In maintenance mode, every request is answered with a `503 Service Unavailable`, without
+    /// going through routes or middleware. Its `Retry-After` is set by
+    /// [`ServerConfig::drain_retry_after`](crate::ServerConfig::drain_retry_after).
+    ///
+    /// It can also be toggled from the
+    /// [control socket](crate::ServerConfig::control_socket), if there is one.
+    pub fn set_maintenance(&self, on: bool) {
+        self.maintenance.store(on, Ordering::SeqCst);
+    }
+
+    /// Changes which log records are emitted, while the server runs
+    ///
+    /// `filter` is a comma-separated list of directives, like `RUST_LOG` for `env_logger`:
+    /// `target=level` sets the maximum level of a target and the targets under it (e.g.
+    /// `vintage=debug` also covers `vintage::connection`), a level on its own sets the default
+    /// level, and a target on its own enables all of its records. The most specific directive
+    /// wins. If `filter` has no default level, the current one is kept.
+    ///
+    /// This is handy to trace the protocol on a live server (e.g. with `vintage=trace`) and to
+    /// turn it off again (e.g. with `vintage=info`). Log records go to a single logger for the
+    /// whole process, so this affects every server in it.
+    ///
+    /// Per-target levels are only applied if the logger is wrapped in a [`FilteredLogger`].
+    /// Otherwise, only the global maximum level of `log` is changed, to the most verbose level of
+    /// `filter`.
+    ///
+    /// It can also be changed from the [control socket](crate::ServerConfig::control_socket),
+    /// if there is one.
+    ///
+    /// ```
+    /// use vintage::ServerConfig;
+    ///
+    /// let server = vintage::start(ServerConfig::new(), "localhost:0").unwrap();
+    /// server.set_log_filter("warn,vintage=debug").unwrap();
+    /// assert!(server.set_log_filter("vintage=loud").is_err());
+    /// ```
+    ///
+    /// [`FilteredLogger`]: crate::FilteredLogger
+    pub fn set_log_filter(&self, filter: &str) -> Result<(), InvalidLogFilter> {
+        log_filter::set(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::backtrace::Backtrace;
+
+    #[test]
+    fn display_error_reason() {
+        let reason = ServerExitReason::Err {
+            operation: ServerOperation::Accept,
+            address: "127.0.0.1:9000".parse().unwrap(),
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            error: io::Error::other("boom"),
+        };
+
+        assert_eq!(
+            reason.to_string(),
+            "The server at 127.0.0.1:9000 failed to accept at 1970-01-01T00:00:01Z: boom"
+        );
+    }
+
+    #[test]
+    fn display_panic_reason() {
+        assert_eq!(
+            ServerExitReason::Panic(PanicReport::new("", Backtrace::disabled())).to_string(),
+            "The server panicked"
+        );
+        assert_eq!(
+            ServerExitReason::Panic(PanicReport::new("oops", Backtrace::disabled())).to_string(),
+            "The server panicked: oops"
+        );
+    }
+
+    #[test]
+    fn listener_info() {
+        let config = crate::ServerConfig::new().backlog(16);
+        let server = crate::start(config, "localhost:0").unwrap();
+
+        let listener = server.listener().clone();
+        assert_eq!(listener.tcp_address(), Some(server.address()));
+        assert_eq!(listener.options().backlog, Some(16));
+        assert_eq!(listener.to_string(), format!("tcp://{}", server.address()));
+
+        server.stop();
+    }
+
+    #[test]
+    fn abort_server() {
+        let server = crate::start(crate::ServerConfig::new(), "localhost:0").unwrap();
+
+        // Equivalent to `abort()`, without consuming the handle
+        server.abort_requested.store(true, Ordering::SeqCst);
+        server.signal_shutdown();
+
+        assert_matches!(server.join(), ServerExitReason::Aborted);
+    }
+
+    #[test]
+    fn join_timeout() {
+        let server = crate::start(crate::ServerConfig::new(), "localhost:0").unwrap();
+
+        let server = server
+            .join_timeout(Duration::from_millis(10))
+            .expect_err("server should still be running");
+
+        server.abort_requested.store(true, Ordering::SeqCst);
+        server.signal_shutdown();
+
+        let reason = server.join_timeout(Duration::from_secs(5));
+        assert_matches!(reason, Ok(ServerExitReason::Aborted));
     }
 }
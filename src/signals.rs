@@ -0,0 +1,86 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set when one of the signals `serve()` stops on was received
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+// Makes SIGINT and SIGTERM set a flag instead of killing the process.
+//
+// The handler only runs once: a second signal kills the process as usual, which is the way out
+// of a graceful shutdown that takes too long.
+pub fn install() -> io::Result<()> {
+    RECEIVED.store(false, Ordering::SeqCst);
+
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: `sigaction` is plain old data, for which all zeroes is a valid value
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND | libc::SA_RESTART;
+        // SAFETY: `action` is fully initialized, and the handler only stores to an atomic, which
+        // is async-signal-safe
+        let result = unsafe {
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Whether SIGINT or SIGTERM was received since `install()`
+pub fn received() -> bool {
+    RECEIVED.load(Ordering::SeqCst)
+}
+
+extern "C" fn handle(_signal: libc::c_int) {
+    RECEIVED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::Connection;
+    use crate::record::*;
+    use crate::{Response, ServerExitReason};
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn serve_until_signal() {
+        // `serve` binds the address itself, so find one that is free
+        let address = std::net::TcpListener::bind("localhost:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = std::thread::spawn(move || {
+            crate::serve(address, |req| Response::text(req.path().to_string()))
+        });
+
+        // The signal handlers are installed before the server accepts connections
+        let mut connection = loop {
+            match std::net::TcpStream::connect(address) {
+                Ok(socket) => break Connection::try_from(mio::net::TcpStream::from_std(socket)),
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        }
+        .unwrap();
+        let params = Params::default()
+            .add("REQUEST_METHOD", "GET")
+            .add("PATH_INFO", "/hello");
+        for record in [
+            Record::from(BeginRequest::new(Role::Responder, false)),
+            Record::from(params),
+            Record::from(Stdin(vec![])),
+        ] {
+            connection.write_record(&record).unwrap();
+        }
+        let Ok(Record::Stdout(stdout)) = connection.read_record() else {
+            panic!("Expected a response");
+        };
+        assert!(stdout.0.ends_with(b"\r\n\r\n/hello"));
+
+        // SAFETY: the signal is handled by `serve`
+        unsafe { libc::kill(libc::getpid(), libc::SIGINT) };
+        assert_matches!(server.join().unwrap(), Ok(ServerExitReason::Normal));
+    }
+}
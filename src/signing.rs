@@ -0,0 +1,96 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// The keys that sign cookie values, so that clients can't forge or change them.
+//
+// Values are signed with the primary key, and accepted if any of the keys signed them. A new
+// primary key can be rolled out with the previous one as a fallback, so that cookies signed before
+// the rotation stay valid until they expire.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Keys {
+    primary: Vec<u8>,
+    fallbacks: Vec<Vec<u8>>,
+}
+
+impl Keys {
+    pub fn new(primary: Vec<u8>, fallbacks: Vec<Vec<u8>>) -> Self {
+        Self { primary, fallbacks }
+    }
+
+    // Returns `value` followed by a `.` and its signature (as hex) for the cookie `name`.
+    //
+    // The name is part of what is signed, so the value of one cookie can't be passed off as the
+    // value of another.
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let signature = mac(&self.primary, name, value).finalize().into_bytes();
+        let signature: String = signature.iter().map(|b| format!("{b:02x}")).collect();
+        format!("{value}.{signature}")
+    }
+
+    // Returns the value of `signed` if one of the keys signed it for the cookie `name`
+    pub fn verify<'a>(&self, name: &str, signed: &'a str) -> Option<&'a str> {
+        let (value, signature) = signed.rsplit_once('.')?;
+        let signature = decode_hex(signature)?;
+        std::iter::once(&self.primary)
+            .chain(&self.fallbacks)
+            // `verify_slice` compares in constant time
+            .any(|key| mac(key, name, value).verify_slice(&signature).is_ok())
+            .then_some(value)
+    }
+}
+
+// Keys don't show up in logs
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keys")
+            .field("fallbacks", &self.fallbacks.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn mac(key: &[u8], name: &str, value: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(name.as_bytes());
+    mac.update(b"=");
+    mac.update(value.as_bytes());
+    mac
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation() {
+        let old = Keys::new(b"old".to_vec(), vec![]);
+        let new = Keys::new(b"new".to_vec(), vec![b"old".to_vec()]);
+
+        let signed = old.sign("session", "abc");
+        assert!(signed.starts_with("abc."));
+        assert_eq!(new.verify("session", &signed), Some("abc"));
+        assert_eq!(new.verify("flash", &signed), None);
+
+        let signed = new.sign("session", "abc");
+        assert_eq!(new.verify("session", &signed), Some("abc"));
+        assert_eq!(old.verify("session", &signed), None);
+
+        let tampered = signed.replacen("abc", "abd", 1);
+        assert_eq!(new.verify("session", &tampered), None);
+        for invalid in ["abc", "abc.", "abc.zz", "abc.é0"] {
+            assert_eq!(new.verify("session", invalid), None);
+        }
+    }
+}
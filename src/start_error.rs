@@ -0,0 +1,175 @@
+use crate::listener::ListenAddress;
+use std::error::Error;
+use std::fmt;
+use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// The error returned by [`start()`](crate::start) when the server could not be started
+///
+/// Every listening socket is bound before giving up, so when several of them fail (e.g. two
+/// addresses are already in use), each one is listed.
+///
+/// It converts into an [`io::Error`], so `?` works in functions that return one.
+///
+/// ```
+/// use vintage::{ListenAddress, ServerConfig, StartStep};
+///
+/// let taken = vintage::start(ServerConfig::new(), "localhost:0").unwrap();
+/// let config = ServerConfig::new().listen(taken.address(), ServerConfig::new());
+///
+/// let err = vintage::start(config, taken.address()).unwrap_err();
+/// assert_eq!(err.failures().len(), 2);
+/// for failure in err.failures() {
+///     assert_eq!(failure.step, StartStep::Bind(ListenAddress::Tcp(taken.address())));
+///     assert_eq!(failure.error.kind(), std::io::ErrorKind::AddrInUse);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct StartError {
+    failures: Vec<StartFailure>,
+}
+
+/// One of the reasons a server could not be started
+///
+/// See [`StartError`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct StartFailure {
+    /// What was being done
+    pub step: StartStep,
+    /// What went wrong
+    pub error: io::Error,
+}
+
+/// A step of starting a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StartStep {
+    /// Resolving the address given to [`start()`](crate::start)
+    ResolveAddress,
+    /// Running a task registered with [`ServerConfig::on_start`](crate::ServerConfig::on_start)
+    StartTask,
+    /// Binding a listening socket to the address
+    Bind(ListenAddress),
+    /// Taking over the listening socket inherited from the parent process (see
+    /// [`ServerConfig::from_inherited_listener`](crate::ServerConfig::from_inherited_listener))
+    InheritListener,
+    /// Creating the control socket at the path (see
+    /// [`ServerConfig::control_socket`](crate::ServerConfig::control_socket))
+    #[cfg(unix)]
+    ControlSocket(PathBuf),
+    /// Setting up the threads that serve connections
+    Setup,
+    /// Running a callback registered with
+    /// [`ServerConfig::before_serve`](crate::ServerConfig::before_serve)
+    BeforeServe,
+}
+
+impl StartError {
+    pub(crate) fn new(failures: Vec<StartFailure>) -> Self {
+        debug_assert!(!failures.is_empty());
+        Self { failures }
+    }
+
+    // A start-up error with a single cause
+    pub(crate) fn single(step: StartStep, error: io::Error) -> Self {
+        Self::new(vec![StartFailure { step, error }])
+    }
+
+    /// Returns every failure, in the order they happened
+    pub fn failures(&self) -> &[StartFailure] {
+        &self.failures
+    }
+
+    /// Returns the kind of the first failure
+    pub fn kind(&self) -> io::ErrorKind {
+        self.failures[0].error.kind()
+    }
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The server could not be started: ")?;
+        for (i, failure) in self.failures.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{failure}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for StartError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.failures[0].error)
+    }
+}
+
+// A single failure converts into its own error, so that its kind and payload are kept
+impl From<StartError> for io::Error {
+    fn from(mut err: StartError) -> Self {
+        if err.failures.len() == 1 {
+            return err.failures.remove(0).error;
+        }
+        io::Error::new(err.kind(), err)
+    }
+}
+
+impl fmt::Display for StartFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.step, self.error)
+    }
+}
+
+impl fmt::Display for StartStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ResolveAddress => write!(f, "failed to resolve the address"),
+            Self::StartTask => write!(f, "start-up task failed"),
+            Self::Bind(address) => write!(f, "failed to bind {address}"),
+            Self::InheritListener => write!(f, "failed to use the inherited listener"),
+            #[cfg(unix)]
+            Self::ControlSocket(path) => {
+                write!(f, "failed to create the control socket {}", path.display())
+            }
+            Self::Setup => write!(f, "failed to set up the server"),
+            Self::BeforeServe => write!(f, "before_serve callback failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let err = StartError::new(vec![
+            StartFailure {
+                step: StartStep::Bind(ListenAddress::Tcp("127.0.0.1:9000".parse().unwrap())),
+                error: io::Error::from(io::ErrorKind::AddrInUse),
+            },
+            StartFailure {
+                step: StartStep::BeforeServe,
+                error: io::Error::other("no such user"),
+            },
+        ]);
+        assert_eq!(
+            err.to_string(),
+            "The server could not be started: failed to bind tcp://127.0.0.1:9000: address in \
+             use; before_serve callback failed: no such user"
+        );
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let err = io::Error::from(StartError::single(
+            StartStep::StartTask,
+            io::Error::other("templates are missing"),
+        ));
+        assert_eq!(
+            err.into_inner().unwrap().to_string(),
+            "templates are missing"
+        );
+    }
+}
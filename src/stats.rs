@@ -0,0 +1,137 @@
+use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+use crate::sync;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How much traffic a route received since the server started
+///
+/// See [`ServerHandle::route_stats`](crate::ServerHandle::route_stats)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RouteStats {
+    /// How many requests the route handled
+    pub requests: u64,
+    /// How many bytes of params and request body were received, as sent by the web server
+    pub bytes_in: u64,
+    /// How many bytes of response headers and body were sent
+    pub bytes_out: u64,
+}
+
+/// Cheap counters of the traffic of a server since it started
+///
+/// See [`ServerHandle::counters`](crate::ServerHandle::counters)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Counters {
+    /// How many `2xx` responses were produced
+    pub responses_2xx: u64,
+    /// How many `3xx` responses were produced
+    pub responses_3xx: u64,
+    /// How many `4xx` responses were produced
+    pub responses_4xx: u64,
+    /// How many `5xx` responses were produced
+    pub responses_5xx: u64,
+    /// How many connections were closed because the web server did not follow the FastCGI
+    /// protocol (e.g. it sent a malformed record, or took too long to send one)
+    pub protocol_errors: u64,
+    /// How many connections are having their request read or handled right now
+    pub active_connections: u64,
+}
+
+// Counters shared by every thread of a server
+#[derive(Debug, Default)]
+pub struct Stats {
+    routes: Mutex<BTreeMap<String, RouteStats>>,
+    write_errors: AtomicU64,
+    // The responses of each status class, from `2xx` to `5xx`
+    responses: [AtomicU64; 4],
+    protocol_errors: AtomicU64,
+    active_connections: AtomicU64,
+    // The circuit breakers registered with the server
+    breakers: Vec<CircuitBreaker>,
+}
+
+impl Stats {
+    pub fn new(breakers: Vec<CircuitBreaker>) -> Self {
+        Self {
+            routes: Mutex::default(),
+            breakers,
+            ..Self::default()
+        }
+    }
+
+    pub fn record(&self, route: &str, bytes_in: usize, bytes_out: usize) {
+        let mut routes = sync::lock(&self.routes);
+        let stats = match routes.get_mut(route) {
+            Some(stats) => stats,
+            None => routes.entry(route.to_string()).or_default(),
+        };
+        stats.requests += 1;
+        stats.bytes_in += bytes_in as u64;
+        stats.bytes_out += bytes_out as u64;
+    }
+
+    pub fn record_write_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors.load(Ordering::Relaxed)
+    }
+
+    // Counts a response by the class of its status. Status codes outside of `2xx` to `5xx` are
+    // not counted.
+    pub fn record_status(&self, status: u16) {
+        if let Some(counter) = (status / 100)
+            .checked_sub(2)
+            .and_then(|class| self.responses.get(usize::from(class)))
+        {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_protocol_error(&self) {
+        self.protocol_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counters(&self) -> Counters {
+        let [responses_2xx, responses_3xx, responses_4xx, responses_5xx] =
+            self.responses.each_ref().map(|c| c.load(Ordering::Relaxed));
+        Counters {
+            responses_2xx,
+            responses_3xx,
+            responses_4xx,
+            responses_5xx,
+            protocol_errors: self.protocol_errors.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn routes(&self) -> BTreeMap<String, RouteStats> {
+        sync::lock(&self.routes).clone()
+    }
+
+    pub fn circuits(&self) -> BTreeMap<String, CircuitState> {
+        self.breakers
+            .iter()
+            .flat_map(CircuitBreaker::states)
+            .collect()
+    }
+}
+
+// Counts a connection as active until it is dropped
+pub struct ActiveConnection(Arc<Stats>);
+
+impl ActiveConnection {
+    pub fn new(stats: Arc<Stats>) -> Self {
+        stats.active_connections.fetch_add(1, Ordering::Relaxed);
+        Self(stats)
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -1,21 +1,50 @@
 //! HTTP status code constants
 
 macro_rules! status_codes {
-    ($($name:ident  $value:literal),* $(,)?) => {
+    ($($name:ident  $value:literal $reason:literal),* $(,)?) => {
         $(
             pub const $name: u16 = $value;
         )*
+
+        /// Returns the canonical reason phrase of `code` (e.g. `Not Found` for `404`), if it is
+        /// one of the codes defined in this module
+        pub fn reason_phrase(code: u16) -> Option<&'static str> {
+            match code {
+                $($value => Some($reason),)*
+                _ => None,
+            }
+        }
     }
 }
 
 status_codes! {
-    OK                          200,
-    NOT_MODIFIED                304,
-    TEMPORARY_REDIRECT          307,
-    PERMANENT_REDIRECT          308,
-    BAD_REQUEST                 400,
-    NOT_FOUND                   404,
-    METHOD_NOT_ALLOWED          405,
-    TEAPOT                      418,
-    INTERNAL_SERVER_ERROR       500,
+    OK                          200 "OK",
+    CREATED                     201 "Created",
+    ACCEPTED                    202 "Accepted",
+    NO_CONTENT                  204 "No Content",
+    PARTIAL_CONTENT             206 "Partial Content",
+    MULTIPLE_CHOICES            300 "Multiple Choices",
+    MOVED_PERMANENTLY           301 "Moved Permanently",
+    FOUND                       302 "Found",
+    SEE_OTHER                   303 "See Other",
+    NOT_MODIFIED                304 "Not Modified",
+    TEMPORARY_REDIRECT          307 "Temporary Redirect",
+    PERMANENT_REDIRECT          308 "Permanent Redirect",
+    BAD_REQUEST                 400 "Bad Request",
+    UNAUTHORIZED                401 "Unauthorized",
+    FORBIDDEN                   403 "Forbidden",
+    NOT_FOUND                   404 "Not Found",
+    METHOD_NOT_ALLOWED          405 "Method Not Allowed",
+    REQUEST_TIMEOUT             408 "Request Timeout",
+    CONFLICT                    409 "Conflict",
+    PRECONDITION_FAILED         412 "Precondition Failed",
+    CONTENT_TOO_LARGE           413 "Content Too Large",
+    UNSUPPORTED_MEDIA_TYPE      415 "Unsupported Media Type",
+    RANGE_NOT_SATISFIABLE       416 "Range Not Satisfiable",
+    TEAPOT                      418 "I'm a teapot",
+    UNPROCESSABLE_CONTENT       422 "Unprocessable Content",
+    REQUEST_HEADER_FIELDS_TOO_LARGE 431 "Request Header Fields Too Large",
+    INTERNAL_SERVER_ERROR       500 "Internal Server Error",
+    BAD_GATEWAY                 502 "Bad Gateway",
+    SERVICE_UNAVAILABLE         503 "Service Unavailable",
 }
@@ -0,0 +1,167 @@
+use crate::listener::ListenerInfo;
+use crate::server_handle::{ServerExitReason, ServerHandle};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+// How often `Supervisor::join()` checks whether one of its servers exited
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Manages several running FastCGI servers as a single unit
+///
+/// This is useful when an application listens on more than one address.
+/// When any of the servers exits, [`join()`](crate::Supervisor::join) shuts down the others, so
+/// the application never ends up half-running.
+///
+/// ```
+/// use vintage::{Response, ServerConfig, Supervisor};
+///
+/// let config = ServerConfig::new()
+///     .on_get(["/about"], |_req, _params| {
+///         Response::html("<h1>Hello World</h1>")
+///     });
+///
+/// let supervisor = Supervisor::new()
+///     .supervise(vintage::start(config.clone(), "localhost:0").unwrap())
+///     .supervise(vintage::start(config, "localhost:0").unwrap());
+///
+/// // This would block the current thread until one of the servers exits
+/// // supervisor.join()
+///
+/// // Gracefully shutdown all the servers
+/// supervisor.stop();
+/// ```
+#[derive(Debug, Default)]
+pub struct Supervisor {
+    handles: Vec<ServerHandle>,
+}
+
+impl Supervisor {
+    /// Creates a supervisor that does not manage any servers yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a server to be managed by this supervisor
+    pub fn supervise(mut self, handle: ServerHandle) -> Self {
+        self.handles.push(handle);
+        self
+    }
+
+    /// Returns the addresses of the managed servers, in the order they were added
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.handles.iter().map(ServerHandle::address).collect()
+    }
+
+    /// Returns what each managed server is listening on, in the order they were added
+    pub fn listeners(&self) -> Vec<ListenerInfo> {
+        self.handles
+            .iter()
+            .map(|handle| handle.listener().clone())
+            .collect()
+    }
+
+    /// Blocks until any of the managed servers terminates, then stops all the others.
+    ///
+    /// Returns the exit reason of every server, in the order they were added.
+    /// Servers that were stopped by the supervisor report [`ServerExitReason::Normal`].
+    pub fn join(self) -> Vec<(SocketAddr, ServerExitReason)> {
+        if self.handles.is_empty() {
+            return vec![];
+        }
+
+        while !self.handles.iter().any(ServerHandle::has_exited) {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        self.shutdown()
+    }
+
+    /// Stops all the managed servers
+    ///
+    /// Each server waits for its in-flight requests to complete before it is shutdown
+    pub fn stop(self) {
+        self.shutdown();
+    }
+
+    fn shutdown(self) -> Vec<(SocketAddr, ServerExitReason)> {
+        // Signal every server before waiting for any of them.
+        // That way, they all wind down their in-flight requests at the same time.
+        for handle in self.handles.iter() {
+            if !handle.has_exited() {
+                handle.request_shutdown();
+            }
+        }
+
+        self.handles
+            .into_iter()
+            .map(|handle| (handle.address(), handle.join()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerConfig;
+    use assert_matches::assert_matches;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn join_without_servers() {
+        assert!(Supervisor::new().join().is_empty());
+    }
+
+    #[test]
+    fn exiting_server_stops_the_others() {
+        let exited = crate::start(ServerConfig::new(), "localhost:0").unwrap();
+        let running = crate::start(ServerConfig::new(), "localhost:0").unwrap();
+
+        // Equivalent to `abort()`, without consuming the handle
+        exited.abort_requested.store(true, Ordering::SeqCst);
+        exited.signal_shutdown();
+
+        let addresses = [exited.address(), running.address()];
+        let supervisor = Supervisor::new().supervise(exited).supervise(running);
+        assert_eq!(supervisor.addresses(), addresses);
+        let listeners: Vec<_> = supervisor
+            .listeners()
+            .iter()
+            .map(ListenerInfo::tcp_address)
+            .collect();
+        assert_eq!(listeners, addresses.map(Some));
+
+        let mut reasons = supervisor.join().into_iter();
+
+        assert_matches!(reasons.next(), Some((a, ServerExitReason::Aborted)) if a == addresses[0]);
+        assert_matches!(reasons.next(), Some((a, ServerExitReason::Normal)) if a == addresses[1]);
+        assert_matches!(reasons.next(), None);
+    }
+
+    #[test]
+    fn servers_wind_down_together() {
+        let (collect, started) = crate::testing::collector();
+        let busy = ServerConfig::new().unhandled(move |_req| {
+            collect(());
+            thread::sleep(Duration::from_millis(500));
+            crate::Response::text("done")
+        });
+        let busy = crate::start(busy, "localhost:0").unwrap();
+        let idle = crate::start(ServerConfig::new(), "localhost:0").unwrap();
+
+        let client = crate::Client::new(busy.address());
+        let in_flight = thread::spawn(move || client.send(&crate::Request::default()).unwrap());
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let idle_client = crate::Client::new(idle.address());
+        let supervisor = Supervisor::new().supervise(busy).supervise(idle);
+        let stopping = thread::spawn(move || supervisor.stop());
+
+        // The idle server doesn't wait for the busy one to finish its request before stopping
+        thread::sleep(Duration::from_millis(200));
+        assert!(idle_client.send(&crate::Request::default()).is_err());
+
+        stopping.join().unwrap();
+        assert_eq!(in_flight.join().unwrap().body, b"done");
+    }
+}
@@ -0,0 +1,21 @@
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// A callback that can be called from any thread (e.g. a handler, or a sink), and the receiver
+// of the values it is called with
+pub(crate) fn collector<T: Send + 'static>() -> (impl Fn(T) + Send + Sync + 'static, Receiver<T>) {
+    let (sender, received) = channel();
+    let sender = Mutex::new(sender);
+    let collect = move |value| sender.lock().unwrap().send(value).unwrap();
+    (collect, received)
+}
+
+// Waits for `count` values, and checks that no more follow
+pub(crate) fn receive_exactly<T>(received: &Receiver<T>, count: usize) -> Vec<T> {
+    let values = (0..count)
+        .map(|_| received.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    assert!(received.recv_timeout(Duration::from_millis(100)).is_err());
+    values
+}
@@ -0,0 +1,443 @@
+use crate::connection::Stream;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConnection};
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Write as _};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The certificate (and client verification settings) of a listener that serves FastCGI over TLS
+///
+/// FastCGI has no security of its own, and web servers usually reach the application over a
+/// local socket. When they forward requests over a network that is not trusted instead, the
+/// connections can be wrapped in TLS with [`ServerConfig::tls`](crate::ServerConfig::tls).
+///
+/// ```no_run
+/// use vintage::{ClientAuth, ServerConfig, TlsConfig};
+///
+/// let tls = TlsConfig::from_pem_files("/etc/app/cert.pem", "/etc/app/key.pem")
+///     .unwrap()
+///     // Only accept web servers with a certificate signed by the internal CA
+///     .client_auth(
+///         ClientAuth::Required,
+///         &std::fs::read("/etc/app/web-servers-ca.pem").unwrap(),
+///     )
+///     .unwrap();
+///
+/// let config = ServerConfig::new().tls(tls);
+/// let handle = vintage::start(config, "0.0.0.0:9000").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    certificates: Vec<CertificateDer<'static>>,
+    key: Arc<PrivateKeyDer<'static>>,
+    client_auth: Option<(ClientAuth, Arc<RootCertStore>)>,
+    // Built from the fields above, so that each connection doesn't have to
+    server: Arc<rustls::ServerConfig>,
+}
+
+impl TlsConfig {
+    /// Creates a configuration that presents `certificate_chain` (PEM encoded, leaf certificate
+    /// first) and proves it owns it with `private_key` (PEM encoded)
+    ///
+    /// This fails if the certificates or key can't be parsed, or if the key does not match the
+    /// leaf certificate.
+    pub fn from_pem(certificate_chain: &[u8], private_key: &[u8]) -> io::Result<Self> {
+        let certificates = parse_certificates(certificate_chain)?;
+        let key = PrivateKeyDer::from_pem_slice(private_key)
+            .map_err(|err| invalid_input(format!("Invalid private key: {err}")))?;
+        Self::build(certificates, Arc::new(key), None)
+    }
+
+    /// Like [`TlsConfig::from_pem`], but reads the certificate chain and private key from files
+    pub fn from_pem_files(
+        certificate_chain: impl AsRef<Path>,
+        private_key: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        Self::from_pem(
+            &std::fs::read(certificate_chain)?,
+            &std::fs::read(private_key)?,
+        )
+    }
+
+    /// Asks clients for a certificate, which must be signed by one of `ca_certificates` (a PEM
+    /// encoded bundle)
+    ///
+    /// `policy` says whether clients that have no certificate are accepted. Connections of clients
+    /// with a certificate that can't be verified are closed during the handshake, before any
+    /// request is read. Handlers can see who the client is with
+    /// [`Request::client_identity`](crate::Request::client_identity).
+    ///
+    /// Each listener has its own [`TlsConfig`], so the web servers allowed on one listener can be
+    /// different from those allowed on another.
+    pub fn client_auth(self, policy: ClientAuth, ca_certificates: &[u8]) -> io::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for certificate in parse_certificates(ca_certificates)? {
+            roots
+                .add(certificate)
+                .map_err(|err| invalid_input(format!("Invalid CA certificate: {err}")))?;
+        }
+        Self::build(self.certificates, self.key, Some((policy, Arc::new(roots))))
+    }
+
+    fn build(
+        certificates: Vec<CertificateDer<'static>>,
+        key: Arc<PrivateKeyDer<'static>>,
+        client_auth: Option<(ClientAuth, Arc<RootCertStore>)>,
+    ) -> io::Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_input)?;
+        let builder = match &client_auth {
+            Some((policy, roots)) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider);
+                let verifier = match policy {
+                    ClientAuth::Required => verifier,
+                    ClientAuth::Optional => verifier.allow_unauthenticated(),
+                };
+                builder.with_client_cert_verifier(verifier.build().map_err(invalid_input)?)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let server = builder
+            .with_single_cert(certificates.clone(), key.clone_key())
+            .map_err(|err| invalid_input(format!("Invalid certificate or key: {err}")))?;
+
+        Ok(Self {
+            certificates,
+            key,
+            client_auth,
+            server: Arc::new(server),
+        })
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("certificates", &self.certificates.len())
+            .field(
+                "client_auth",
+                &self.client_auth.as_ref().map(|(policy, _)| policy),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether clients must present a certificate. See [`TlsConfig::client_auth`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Clients without a certificate are disconnected during the handshake
+    Required,
+    /// Clients without a certificate are served too. Their requests have no
+    /// [`client_identity`](crate::Request::client_identity).
+    Optional,
+}
+
+/// The certificate a client presented on a TLS connection, once it was verified
+///
+/// See [`Request::client_identity`](crate::Request::client_identity)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    common_name: Option<String>,
+    fingerprint: String,
+    certificate: Vec<u8>,
+}
+
+impl ClientIdentity {
+    fn new(certificate: &[u8]) -> Self {
+        let mut fingerprint = String::with_capacity(64);
+        for byte in Sha256::digest(certificate) {
+            let _ = write!(fingerprint, "{byte:02x}");
+        }
+        Self {
+            common_name: common_name(certificate),
+            fingerprint,
+            certificate: certificate.to_vec(),
+        }
+    }
+
+    /// Returns the common name (`CN`) of the subject of the certificate, if it has one
+    pub fn common_name(&self) -> Option<&str> {
+        self.common_name.as_deref()
+    }
+
+    /// Returns the SHA-256 fingerprint of the certificate, in lowercase hexadecimal
+    ///
+    /// Unlike the common name, it identifies a single certificate, which makes it a good fit for
+    /// audit logs.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// Returns the certificate, DER encoded
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+}
+
+// Finds the common name of the subject of a DER encoded certificate
+fn common_name(certificate: &[u8]) -> Option<String> {
+    const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+    let (_, certificate, _) = der_element(certificate)?;
+    let (_, mut fields, _) = der_element(certificate)?;
+    // The version is optional
+    let (tag, _, rest) = der_element(fields)?;
+    if tag == 0xa0 {
+        fields = rest;
+    }
+    // The serial number, signature algorithm, issuer and validity come before the subject
+    for _ in 0..4 {
+        fields = der_element(fields)?.2;
+    }
+    let (_, mut names, _) = der_element(fields)?;
+
+    while !names.is_empty() {
+        let (_, mut attributes, rest) = der_element(names)?;
+        names = rest;
+        while !attributes.is_empty() {
+            let (_, attribute, rest) = der_element(attributes)?;
+            attributes = rest;
+            let (_, oid, value) = der_element(attribute)?;
+            if oid == COMMON_NAME {
+                let (_, value, _) = der_element(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+// Splits the DER element at the start of `input` into its tag, its contents, and what follows it
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&length, mut rest) = rest.split_first()?;
+    let length = if length < 0x80 {
+        length as usize
+    } else {
+        // The long form: the low bits are the number of bytes of the length
+        let bytes = (length & 0x7f) as usize;
+        if bytes == 0 || bytes > 4 || rest.len() < bytes {
+            return None;
+        }
+        let (length, after) = rest.split_at(bytes);
+        rest = after;
+        length
+            .iter()
+            .fold(0, |length, byte| length << 8 | *byte as usize)
+    };
+    if rest.len() < length {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(length);
+    Some((tag, contents, rest))
+}
+
+fn parse_certificates(pem: &[u8]) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid_input(format!("Invalid certificate: {err}")))?;
+    if certificates.is_empty() {
+        return Err(invalid_input("No certificate found"));
+    }
+    Ok(certificates)
+}
+
+fn invalid_input(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+// A TLS session over an accepted connection, in blocking mode.
+//
+// The handshake happens on the first read.
+#[derive(Debug)]
+pub struct TlsStream {
+    session: ServerConnection,
+    stream: Stream,
+    // Plaintext that was not encrypted yet, so that the small writes that make up a record end up
+    // in the same TLS record
+    write_buffer: Vec<u8>,
+    write_capacity: usize,
+}
+
+impl TlsStream {
+    pub fn new(stream: Stream, config: &TlsConfig, write_capacity: usize) -> io::Result<Self> {
+        let session = ServerConnection::new(config.server.clone()).map_err(io::Error::other)?;
+        Ok(Self {
+            session,
+            stream,
+            write_buffer: Vec::with_capacity(write_capacity),
+            write_capacity,
+        })
+    }
+
+    pub fn stream(&self) -> &Stream {
+        &self.stream
+    }
+
+    // The identity of the client, if it presented a certificate
+    pub fn client_identity(&self) -> Option<ClientIdentity> {
+        let certificates = self.session.peer_certificates()?;
+        certificates
+            .first()
+            .map(|certificate| ClientIdentity::new(certificate))
+    }
+
+    // Decrypts the input that already arrived, without blocking.
+    //
+    // Returns the first chunk of decrypted input, and how many decrypted bytes are waiting to be
+    // read in total. Fails if the client closed the connection and nothing is left to read.
+    pub fn peek_input(&mut self) -> io::Result<(&[u8], usize)> {
+        self.stream.set_nonblocking(true)?;
+        let received = self.receive_available();
+        self.stream.set_nonblocking(false)?;
+        let available = received?;
+
+        match self.session.reader().into_first_chunk() {
+            // The client closed the session cleanly
+            Ok([]) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(chunk) => Ok((chunk, available)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok((&[], 0)),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Reads and decrypts what the socket already received. Returns how many decrypted bytes are
+    // waiting to be read.
+    fn receive_available(&mut self) -> io::Result<usize> {
+        loop {
+            let state = self
+                .session
+                .process_new_packets()
+                .map_err(io::Error::other)?;
+            if !self.session.wants_read() {
+                return Ok(state.plaintext_bytes_to_read());
+            }
+            match self.session.read_tls(&mut self.stream) {
+                Ok(0) => return Ok(state.plaintext_bytes_to_read()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(state.plaintext_bytes_to_read())
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Ends the session, and returns the socket
+    pub fn close(mut self) -> Stream {
+        let _ = self.flush();
+        self.session.send_close_notify();
+        let _ = self.tls().flush();
+        self.stream
+    }
+
+    // Ends the session after `bytes`, and returns the socket with the encrypted bytes it still has
+    // to send
+    pub fn close_after(mut self, bytes: &[u8]) -> io::Result<(Stream, Vec<u8>)> {
+        self.flush()?;
+        self.session.set_buffer_limit(None);
+        self.session.writer().write_all(bytes)?;
+        self.session.send_close_notify();
+
+        let mut encrypted = vec![];
+        while self.session.wants_write() {
+            self.session.write_tls(&mut encrypted)?;
+        }
+        Ok((self.stream, encrypted))
+    }
+
+    fn tls(&mut self) -> rustls::Stream<'_, ServerConnection, Stream> {
+        rustls::Stream::new(&mut self.session, &mut self.stream)
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.tls().read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.write_buffer.len() + buf.len() > self.write_capacity {
+            self.flush()?;
+        }
+        self.write_buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Self {
+            session,
+            stream,
+            write_buffer,
+            ..
+        } = self;
+        let mut tls = rustls::Stream::new(session, stream);
+        tls.write_all(write_buffer)?;
+        write_buffer.clear();
+        tls.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_pem() {
+        let generated = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+        let certificate = generated.cert.pem();
+        let key = generated.key_pair.serialize_pem();
+        assert!(TlsConfig::from_pem(certificate.as_bytes(), key.as_bytes()).is_ok());
+
+        let other = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+        let other_key = other.key_pair.serialize_pem();
+        for (certificate, key) in [
+            ("", key.as_str()),
+            (certificate.as_str(), ""),
+            (key.as_str(), key.as_str()),
+            (certificate.as_str(), other_key.as_str()),
+        ] {
+            let err = TlsConfig::from_pem(certificate.as_bytes(), key.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let tls = TlsConfig::from_pem(certificate.as_bytes(), key.as_bytes()).unwrap();
+        let err = tls
+            .client_auth(ClientAuth::Required, b"not a certificate")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn identity() {
+        let mut params = rcgen::CertificateParams::new(vec!["web-1".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "Example");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "web-1.internal");
+        let key = rcgen::KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key).unwrap();
+
+        let identity = ClientIdentity::new(certificate.der());
+        assert_eq!(identity.common_name(), Some("web-1.internal"));
+        assert_eq!(identity.fingerprint().len(), 64);
+        assert_eq!(identity.certificate(), certificate.der().as_ref());
+
+        let mut anonymous = rcgen::CertificateParams::new(vec!["web-1".to_string()]).unwrap();
+        anonymous.distinguished_name = rcgen::DistinguishedName::new();
+        let certificate = anonymous.self_signed(&key).unwrap();
+        assert_eq!(ClientIdentity::new(certificate.der()).common_name(), None);
+
+        assert_eq!(common_name(b"garbage"), None);
+    }
+}